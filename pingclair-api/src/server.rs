@@ -84,9 +84,15 @@ async fn handle_request(
         },
         (&Method::POST, path) if path.starts_with("/config") => {
             let body_bytes = req.collect().await.unwrap().to_bytes();
-            let config: ServerConfig = match serde_json::from_slice(&body_bytes) {
+            let config: ServerConfig = match serde_json::from_slice::<ServerConfig>(&body_bytes)
+                .map_err(|e| e.to_string())
+                .and_then(|c| c.validate().map(|()| c))
+            {
                 Ok(c) => c,
-                Err(e) => return Ok(response(StatusCode::BAD_REQUEST, &format!("Invalid config: {}", e))),
+                Err(e) => {
+                    pingclair_proxy::metrics::record_config_reload(false);
+                    return Ok(response(StatusCode::BAD_REQUEST, &format!("Invalid config: {}", e)));
+                }
             };
 
             let proxies_guard = proxies.read();
@@ -102,6 +108,7 @@ async fn handle_request(
                 }
             }
             
+            pingclair_proxy::metrics::record_config_reload(updated > 0);
             if updated > 0 {
                 Ok(response(StatusCode::OK, "Config updated"))
             } else {
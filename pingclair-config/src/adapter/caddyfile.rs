@@ -7,6 +7,7 @@
 use crate::parser::ast::*;
 use crate::parser::caddy_ast::{Directive, Block};
 use crate::parser::lexer::Location;
+use pingclair_core::server::{ConfigRegex, Expression, Template, parse_ip_ranges, route_order_key};
 use thiserror::Error;
 use std::collections::HashMap;
 
@@ -36,12 +37,15 @@ pub enum AdapterError {
 
 // MARK: - Snippet Expansion (Pass 1)
 
+/// Snippet bodies by name
+type Snippets = HashMap<String, Vec<Directive>>;
+
 /// Collect snippet `(name) { ... }` definitions from top-level directives
 /// and return (snippets_map, remaining_directives).
 fn collect_snippets(
     directives: Vec<Directive>,
-) -> Result<(HashMap<String, Vec<Directive>>, Vec<Directive>), AdapterError> {
-    let mut snippets: HashMap<String, Vec<Directive>> = HashMap::new();
+) -> Result<(Snippets, Vec<Directive>), AdapterError> {
+    let mut snippets: Snippets = HashMap::new();
     let mut remaining = Vec::new();
 
     for d in directives {
@@ -66,7 +70,7 @@ fn collect_snippets(
 /// from circular snippet references (limit: 16).
 fn expand_imports(
     directives: Vec<Directive>,
-    snippets: &HashMap<String, Vec<Directive>>,
+    snippets: &Snippets,
     depth: usize,
 ) -> Result<Vec<Directive>, AdapterError> {
     if depth > 16 {
@@ -158,6 +162,43 @@ fn adapt_global(d: Directive) -> Result<GlobalBlock, AdapterError> {
                         }
                     }
                 }
+                "trusted_proxies" => {
+                    // Caddy syntax: `trusted_proxies static <ranges...>`
+                    for arg in sub.args.iter().filter(|a| a.as_str() != "static") {
+                        if arg == "private_ranges" {
+                            global.trusted_proxies.extend(pingclair_core::server::PRIVATE_RANGES.iter().map(|r| r.to_string()));
+                        } else {
+                            global.trusted_proxies.push(arg.clone());
+                        }
+                    }
+                }
+                "client_ip_headers" => {
+                    global.client_ip_headers.extend(sub.args.iter().cloned());
+                }
+                "request_id_header" => {
+                    global.request_id_header = sub.args.first().cloned();
+                }
+                "tracing" => {
+                    global.tracing = Some(adapt_tracing(sub)?);
+                }
+                "metrics" => {
+                    global.metrics = Some(adapt_metrics(sub)?);
+                }
+                "statsd" => {
+                    global.statsd = Some(adapt_statsd(sub)?);
+                }
+                "listener_wrappers" => {
+                    global.listeners.push(adapt_listener_wrappers(sub)?);
+                }
+                "log" => {
+                    // Global application log: `log { output file <path>; format json; level DEBUG }`
+                    let log = adapt_log_block(sub.block.unwrap_or_default())?;
+                    global.logging = Some(LoggingConfig {
+                        level: log.level.unwrap_or_default(),
+                        format: log.format,
+                        output: log.output,
+                    });
+                }
                 "protocols" => {
                     for arg in &sub.args {
                         match arg.to_lowercase().as_str() {
@@ -175,6 +216,266 @@ fn adapt_global(d: Directive) -> Result<GlobalBlock, AdapterError> {
     Ok(global)
 }
 
+/// Adapt the global `tracing { ... }` (OTLP export) block.
+///
+/// ```text
+/// tracing {
+///     endpoint http://otel-collector:4317
+///     protocol grpc
+///     sample_ratio 0.1
+///     service_name edge
+///     header Authorization "Bearer token"
+///     batch_size 256
+///     flush_interval 2s
+/// }
+/// ```
+fn adapt_tracing(d: Directive) -> Result<TracingBlock, AdapterError> {
+    let mut tracing = TracingBlock {
+        endpoint: d.args.first().cloned(),
+        ..Default::default()
+    };
+    let invalid = |arg: &str| AdapterError::InvalidArgument("tracing".into(), arg.to_string());
+
+    for sub in d.block.map(|b| b.directives).unwrap_or_default() {
+        let arg = sub.args.first().map(String::as_str).unwrap_or("");
+        match sub.name.as_str() {
+            "endpoint" => tracing.endpoint = Some(arg.to_string()),
+            "protocol" => {
+                tracing.grpc = match arg {
+                    "grpc" => true,
+                    "http" | "http/json" => false,
+                    _ => return Err(invalid(arg)),
+                };
+            }
+            "sample_ratio" => {
+                let ratio = arg.parse::<f64>().ok()
+                    .filter(|r| (0.0..=1.0).contains(r))
+                    .ok_or_else(|| invalid(arg))?;
+                tracing.sample_ratio = Some(ratio);
+            }
+            "service_name" => tracing.service_name = Some(arg.to_string()),
+            "header" => {
+                if sub.args.len() != 2 {
+                    return Err(AdapterError::ArgumentCount("header".into(), 2, sub.args.len()));
+                }
+                tracing.headers.push((sub.args[0].clone(), sub.args[1].clone()));
+            }
+            "batch_size" => tracing.batch_size = Some(arg.parse().map_err(|_| invalid(arg))?),
+            "flush_interval" => tracing.flush_interval_ms = Some(parse_duration_ms(arg).ok_or_else(|| invalid(arg))?),
+            other => return Err(AdapterError::UnknownDirective(format!("tracing.{}", other))),
+        }
+    }
+
+    match tracing.endpoint.as_deref() {
+        None => return Err(AdapterError::InvalidArgument("tracing".into(), "missing endpoint".into())),
+        // The exporter speaks cleartext only; TLS collectors go through a local forwarder
+        Some(endpoint) if endpoint.to_ascii_lowercase().starts_with("https://") => {
+            return Err(AdapterError::InvalidArgument(
+                "tracing".into(),
+                format!("{} (https:// endpoints are not supported; export to a local collector over http://)", endpoint),
+            ));
+        }
+        Some(_) => {}
+    }
+    Ok(tracing)
+}
+
+/// Adapt the global `metrics { ... }` block.
+///
+/// ```text
+/// metrics {
+///     per_route
+///     buckets 0.005 0.05 0.5 5
+///     upstream_buckets 0.001 0.01 0.1 1
+/// }
+/// ```
+fn adapt_metrics(d: Directive) -> Result<MetricsBlock, AdapterError> {
+    let mut metrics = MetricsBlock::default();
+
+    for sub in d.block.map(|b| b.directives).unwrap_or_default() {
+        match sub.name.as_str() {
+            "per_route" => metrics.per_route = true,
+            "buckets" => metrics.buckets = parse_buckets(&sub)?,
+            "upstream_buckets" => metrics.upstream_buckets = parse_buckets(&sub)?,
+            other => return Err(AdapterError::UnknownDirective(format!("metrics.{}", other))),
+        }
+    }
+    Ok(metrics)
+}
+
+/// Adapt the global `statsd [<address>] { ... }` push exporter block.
+///
+/// ```text
+/// statsd 127.0.0.1:8125 {
+///     protocol dogstatsd
+///     prefix edge
+///     tag env prod
+///     flush_interval 1s
+///     sample_rate 0.5
+///     max_packet_size 8932
+/// }
+/// ```
+fn adapt_statsd(d: Directive) -> Result<StatsdBlock, AdapterError> {
+    let mut statsd = StatsdBlock {
+        address: d.args.first().cloned(),
+        ..Default::default()
+    };
+    let invalid = |arg: &str| AdapterError::InvalidArgument("statsd".into(), arg.to_string());
+
+    for sub in d.block.map(|b| b.directives).unwrap_or_default() {
+        let arg = sub.args.first().map(String::as_str).unwrap_or("");
+        match sub.name.as_str() {
+            "address" => statsd.address = Some(arg.to_string()),
+            "protocol" => {
+                statsd.dogstatsd = match arg {
+                    "dogstatsd" => true,
+                    "statsd" => false,
+                    _ => return Err(invalid(arg)),
+                };
+            }
+            "prefix" => statsd.prefix = Some(arg.to_string()),
+            "tag" => {
+                if sub.args.len() != 2 {
+                    return Err(AdapterError::ArgumentCount("tag".into(), 2, sub.args.len()));
+                }
+                statsd.tags.push((sub.args[0].clone(), sub.args[1].clone()));
+            }
+            "flush_interval" => {
+                let ms = parse_duration_ms(arg).filter(|ms| *ms > 0).ok_or_else(|| invalid(arg))?;
+                statsd.flush_interval_ms = Some(ms);
+            }
+            "sample_rate" => {
+                let rate = arg.parse::<f64>().ok()
+                    .filter(|r| *r > 0.0 && *r <= 1.0)
+                    .ok_or_else(|| invalid(arg))?;
+                statsd.sample_rate = Some(rate);
+            }
+            "max_packet_size" => {
+                let size = parse_size_bytes(arg).filter(|n| *n >= 512).ok_or_else(|| invalid(arg))?;
+                statsd.max_packet_size = Some(size as usize);
+            }
+            other => return Err(AdapterError::UnknownDirective(format!("statsd.{}", other))),
+        }
+    }
+
+    if statsd.address.is_none() {
+        return Err(AdapterError::InvalidArgument("statsd".into(), "missing address".into()));
+    }
+    Ok(statsd)
+}
+
+/// Adapt `listener_wrappers` from a `servers [<address>]` block.
+///
+/// ```text
+/// servers :443 {
+///     listener_wrappers {
+///         proxy_protocol {
+///             timeout 2s
+///             allow 10.0.0.0/8
+///         }
+///         tls
+///     }
+/// }
+/// ```
+fn adapt_listener_wrappers(d: Directive) -> Result<ListenerBlock, AdapterError> {
+    let mut listener = ListenerBlock {
+        address: d.args.first().cloned(),
+        ..Default::default()
+    };
+
+    for wrapper in d.block.map(|b| b.directives).unwrap_or_default() {
+        match wrapper.name.as_str() {
+            "proxy_protocol" => {
+                let mut pp = ProxyProtocolBlock::default();
+                for sub in wrapper.block.map(|b| b.directives).unwrap_or_default() {
+                    let arg = sub.args.first().map(String::as_str).unwrap_or("");
+                    match sub.name.as_str() {
+                        "timeout" => {
+                            let ms = parse_duration_ms(arg).filter(|ms| *ms > 0).ok_or_else(|| {
+                                AdapterError::InvalidArgument("proxy_protocol".into(), arg.to_string())
+                            })?;
+                            pp.timeout_ms = Some(ms);
+                        }
+                        "allow" => pp.allow.extend(sub.args.iter().cloned()),
+                        other => return Err(AdapterError::UnknownDirective(format!("proxy_protocol.{}", other))),
+                    }
+                }
+                listener.proxy_protocol = Some(pp);
+            }
+            // TLS is decided per listener elsewhere; the wrapper only fixes ordering in Caddy
+            "tls" | "http_redirect" => {}
+            other => return Err(AdapterError::UnknownDirective(format!("listener_wrappers.{}", other))),
+        }
+    }
+    Ok(listener)
+}
+
+/// Adapt a server's `normalize_path` directive.
+///
+/// ```text
+/// normalize_path off
+/// normalize_path {
+///     decode_unreserved off
+///     resolve_dots off
+///     merge_slashes off
+///     encoded_slashes keep|decode|reject
+///     reject_nul off
+///     forward_original
+/// }
+/// ```
+fn adapt_path_normalization(d: Directive) -> Result<PathNormalizationBlock, AdapterError> {
+    let mut normalization = PathNormalizationBlock::default();
+    match d.args.first().map(String::as_str) {
+        None | Some("on") => {}
+        Some("off") => normalization.enabled = false,
+        Some(other) => return Err(AdapterError::InvalidArgument("normalize_path".into(), other.to_string())),
+    }
+
+    for sub in d.block.map(|b| b.directives).unwrap_or_default() {
+        let switch = || match sub.args.first().map(String::as_str) {
+            None | Some("on") => Ok(true),
+            Some("off") => Ok(false),
+            Some(other) => Err(AdapterError::InvalidArgument(sub.name.clone(), other.to_string())),
+        };
+        match sub.name.as_str() {
+            "decode_unreserved" => normalization.decode_unreserved = Some(switch()?),
+            "resolve_dots" => normalization.resolve_dots = Some(switch()?),
+            "merge_slashes" => normalization.merge_slashes = Some(switch()?),
+            "reject_nul" => normalization.reject_nul = Some(switch()?),
+            "forward_original" => normalization.forward_original = switch()?,
+            "encoded_slashes" => {
+                normalization.encoded_slashes = Some(match sub.args.first().map(String::as_str) {
+                    Some("keep") => EncodedSlashes::Keep,
+                    Some("decode") => EncodedSlashes::Decode,
+                    Some("reject") => EncodedSlashes::Reject,
+                    Some(other) => return Err(AdapterError::InvalidArgument("encoded_slashes".into(), other.to_string())),
+                    None => return Err(AdapterError::ArgumentCount("encoded_slashes".into(), 1, 0)),
+                });
+            }
+            other => return Err(AdapterError::UnknownDirective(format!("normalize_path.{}", other))),
+        }
+    }
+    Ok(normalization)
+}
+
+/// Parse histogram bucket bounds (seconds, or durations like `250ms`).
+/// Bounds must be strictly increasing, as Prometheus requires.
+fn parse_buckets(d: &Directive) -> Result<Vec<f64>, AdapterError> {
+    if d.args.is_empty() {
+        return Err(AdapterError::ArgumentCount(d.name.clone(), 1, 0));
+    }
+    let mut buckets: Vec<f64> = Vec::with_capacity(d.args.len());
+    for arg in &d.args {
+        let bound = arg.parse::<f64>().ok()
+            .or_else(|| parse_duration_ms(arg).map(|ms| ms as f64 / 1000.0))
+            .filter(|b| b.is_finite() && *b > 0.0)
+            .filter(|b| buckets.last().is_none_or(|prev| b > prev))
+            .ok_or_else(|| AdapterError::InvalidArgument(d.name.clone(), arg.clone()))?;
+        buckets.push(bound);
+    }
+    Ok(buckets)
+}
+
 /// Flatten Caddy's nested `servers { ... }` block.
 ///
 /// Caddy allows:
@@ -185,13 +486,19 @@ fn adapt_global(d: Directive) -> Result<GlobalBlock, AdapterError> {
 ///     }
 /// }
 /// ```
-/// We flatten `servers` children up to the parent level.
+/// We flatten `servers` children up to the parent level. `listener_wrappers`
+/// is per listener, so it inherits the `servers <address>` argument.
 fn expand_servers_block(directives: Vec<Directive>) -> Vec<Directive> {
     let mut result = Vec::new();
     for d in directives {
         if d.name == "servers" {
             if let Some(block) = d.block {
-                result.extend(block.directives);
+                result.extend(block.directives.into_iter().map(|mut sub| {
+                    if sub.name == "listener_wrappers" {
+                        sub.args = d.args.clone();
+                    }
+                    sub
+                }));
             }
         } else {
             result.push(d);
@@ -246,10 +553,23 @@ fn adapt_server(d: Directive) -> Result<ServerBlock, AdapterError> {
                 "listen" => {
                     if sub_d.args.is_empty() { return Err(AdapterError::ArgumentCount("listen".into(), 1, 0)); }
                     let addr = &sub_d.args[0];
+                    let (explicit, rest) = if let Some(stripped) = addr.strip_prefix("https://") {
+                        (Some(Scheme::Https), stripped)
+                    } else if let Some(stripped) = addr.strip_prefix("http://") {
+                        (Some(Scheme::Http), stripped)
+                    } else {
+                        (None, addr.as_str())
+                    };
+                    // A port that is written must be valid; it decides the implied scheme
+                    let invalid = || AdapterError::InvalidArgument("listen".into(), addr.clone());
+                    let port = match listen_port(rest).ok_or_else(invalid)? {
+                        Some(port) => Some(port.parse::<u16>().map_err(|_| invalid())?),
+                        None => None,
+                    };
                     server.listens.push(ListenAddr {
-                        scheme: if addr.starts_with("https") { Scheme::Https } else { Scheme::Http },
+                        scheme: implied_scheme(explicit, port),
                         host: "0.0.0.0".to_string(),
-                        port: addr.split(':').last().and_then(|p| p.parse().ok()),
+                        port,
                     });
                 },
                 "compress" | "encode" => {
@@ -268,19 +588,44 @@ fn adapt_server(d: Directive) -> Result<ServerBlock, AdapterError> {
                     }
                 },
                 "log" => {
-                    if let Some(log_block) = sub_d.block {
-                        let log = adapt_log_block(log_block)?;
-                        server.log = Some(Node::new(log, Location { start: 0, end: 0 }));
-                    }
+                    // A bare `log` enables the default (stdout, text) access log
+                    let log = adapt_log_block(sub_d.block.unwrap_or_default())?;
+                    server.log = Some(Node::new(log, Location { start: 0, end: 0 }));
+                },
+                "normalize_path" => {
+                    server.path_normalization = Some(adapt_path_normalization(sub_d)?);
                 },
                 "route" | "handle" => {
-                    let (matcher, inner_block) = parse_matcher_and_block(&sub_d)?;
+                    let (mut matcher, inner_block) = parse_matcher_and_block(&sub_d)?;
+                    // Inline path matcher: handle /api/* { ... }
+                    if matcher.is_none()
+                        && let Some(path) = sub_d.args.first().filter(|a| a.starts_with('/'))
+                    {
+                        matcher = Some(Matcher::Path(PathMatcher { patterns: vec![path.clone()] }));
+                    }
                     if let Some(blk) = inner_block {
                         let mut handlers = Vec::new();
+                        let mut internal = false;
+                        let mut priority = 0;
                         for inner_d in &blk.directives {
-                            handlers.push(adapt_handler(inner_d.clone())?);
+                            match inner_d.name.as_str() {
+                                // `internal` marks the block as an X-Accel-Redirect target only
+                                "internal" => internal = true,
+                                "priority" => priority = parse_priority(inner_d)?,
+                                _ => handlers.push(adapt_handler(inner_d.clone())?),
+                            }
                         }
-                        if matcher.is_none() {
+                        // `route` blocks run in the order written (Caddy semantics)
+                        let source_order = sub_d.name == "route";
+                        if internal || priority != 0 || (source_order && matcher.is_some()) {
+                            add_route_arm(&mut server, RouteArm {
+                                matcher,
+                                handler: Handler::Pipeline(handlers),
+                                internal,
+                                priority,
+                                source_order,
+                            });
+                        } else if matcher.is_none() {
                             default_handlers.push(Handler::Pipeline(handlers));
                         } else {
                             add_route(&mut server, matcher, Handler::Pipeline(handlers));
@@ -316,6 +661,9 @@ fn adapt_server(d: Directive) -> Result<ServerBlock, AdapterError> {
                     let handler = adapt_handler(handler_d)?;
                     if matcher.is_some() {
                         add_route(&mut server, matcher, handler);
+                    } else if matches!(handler, Handler::Map(_) | Handler::Vars(_)) {
+                        // Site-wide variables are set before routing, so matchers can use them
+                        server.variables.push(handler);
                     } else {
                         default_handlers.push(handler);
                     }
@@ -333,6 +681,7 @@ fn adapt_server(d: Directive) -> Result<ServerBlock, AdapterError> {
         }
     }
 
+    sort_route_arms(&mut server);
     Ok(server)
 }
 
@@ -344,24 +693,25 @@ struct ParsedAddress {
 }
 
 /// Parse a Caddy server address like `http://ai.408timeout.com:20615`
-/// or `:8080` or `example.com`.
+/// or `:8080` or `example.com`. Without a scheme, port 443 implies HTTPS.
 fn parse_server_address(addr: &str) -> Option<ParsedAddress> {
-    let (scheme, rest) = if let Some(stripped) = addr.strip_prefix("https://") {
-        (Scheme::Https, stripped)
+    let (explicit, rest) = if let Some(stripped) = addr.strip_prefix("https://") {
+        (Some(Scheme::Https), stripped)
     } else if let Some(stripped) = addr.strip_prefix("http://") {
-        (Scheme::Http, stripped)
+        (Some(Scheme::Http), stripped)
     } else {
-        (Scheme::Http, addr)
+        (None, addr)
     };
+    let scheme = explicit.unwrap_or(Scheme::Http);
 
     // rest is either: "host:port", ":port", "host", ""
     if rest.is_empty() {
         return None;
     }
 
-    let (hostname, port) = if rest.starts_with(':') {
+    let (hostname, port) = if let Some(port) = rest.strip_prefix(':') {
         // :port
-        let p = rest[1..].parse::<u16>().ok();
+        let p = port.parse::<u16>().ok();
         ("0.0.0.0".to_string(), p)
     } else if let Some(colon_pos) = rest.rfind(':') {
         // host:port
@@ -380,76 +730,308 @@ fn parse_server_address(addr: &str) -> Option<ParsedAddress> {
     Some(ParsedAddress {
         hostname: hostname.clone(),
         listen: ListenAddr {
-            scheme,
+            scheme: implied_scheme(explicit, port),
             host: hostname,
             port,
         },
     })
 }
 
+/// Port written in a `listen` address, skipping the colons of a bracketed IPv6 host
+///
+/// - Returns: `None` for an unterminated `[` host or text after it other than `:port`.
+fn listen_port(addr: &str) -> Option<Option<&str>> {
+    match addr.strip_prefix('[') {
+        Some(bracketed) => match bracketed.split_once(']')?.1 {
+            "" => Some(None),
+            rest => rest.strip_prefix(':').map(Some),
+        },
+        None => Some(addr.rsplit_once(':').map(|(_, port)| port)),
+    }
+}
+
+/// Scheme of a listener: the explicit one, else HTTPS on port 443 (as Caddy does)
+fn implied_scheme(explicit: Option<Scheme>, port: Option<u16>) -> Scheme {
+    match (explicit, port) {
+        (Some(scheme), _) => scheme,
+        (None, Some(443)) => Scheme::Https,
+        (None, _) => Scheme::Http,
+    }
+}
+
 // MARK: - Log Block
 
 fn adapt_log_block(block: Block) -> Result<LogBlock, AdapterError> {
     let mut output = LogOutput::Stdout;
     let mut format = LogFormat::default();
+    let mut level = None;
+    let mut skip = Vec::new();
+    let mut sample = Vec::new();
 
     for d in block.directives {
         match d.name.as_str() {
-            "output" => {
-                if let Some(kind) = d.args.first() {
-                    match kind.as_str() {
-                        "file" => {
-                            if let Some(path) = d.args.get(1) {
-                                output = LogOutput::File(path.clone());
-                            }
-                        }
-                        "stdout" => output = LogOutput::Stdout,
-                        "stderr" => output = LogOutput::Stderr,
-                        _ => {}
-                    }
-                }
-            }
+            "output" => output = adapt_log_output(d)?,
             "format" => {
                 if let Some(kind) = d.args.first() {
                     match kind.as_str() {
                         "json" => format.format_type = LogFormatType::Json,
                         "filter" => {
-                            // `format filter { wrap json ... }` → extract "json"
+                            // `format filter { wrap json; fields { ... } }`
                             format.format_type = LogFormatType::Json;
                             if let Some(filter_block) = d.block {
-                                let mut filter = LogFilter::default();
-                                for fb_d in filter_block.directives {
-                                    if fb_d.name == "wrap" {
-                                        if let Some(wrap_type) = fb_d.args.first() {
-                                            match wrap_type.as_str() {
-                                                "json" => format.format_type = LogFormatType::Json,
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                    if fb_d.name == "fields" {
-                                        if let Some(fields_block) = fb_d.block {
-                                            for field_d in fields_block.directives {
-                                                // field_name "delete" → exclude field
-                                                if field_d.args.first().map(|a| a.as_str()) == Some("delete") {
-                                                    filter.exclude.push(field_d.name);
-                                                }
-                                            }
-                                        }
-                                    }
+                                let (wrap, filter) = adapt_log_filter(filter_block)?;
+                                if let Some(wrap) = wrap {
+                                    format.format_type = wrap;
                                 }
                                 format.filter = Some(filter);
                             }
                         }
+                        "common" => format.format_type = LogFormatType::Common,
+                        "combined" => format.format_type = LogFormatType::Combined,
+                        "template" => {
+                            // `format template "{http.request.host} $status ..."`
+                            let template = d.args.get(1)
+                                .ok_or_else(|| AdapterError::ArgumentCount("format template".into(), 2, d.args.len()))?;
+                            format.format_type = LogFormatType::Template;
+                            format.template = Some(template.clone());
+                        }
                         _ => format.format_type = LogFormatType::Text,
                     }
                 }
             }
+            "skip" => {
+                // `skip /health /status/*`
+                if d.args.is_empty() {
+                    return Err(AdapterError::ArgumentCount("skip".into(), 1, 0));
+                }
+                skip.extend(d.args);
+            }
+            "sample" => {
+                // `sample <ratio> [paths...]`
+                let arg = d.args.first().map(String::as_str).unwrap_or("");
+                let ratio = arg.parse::<f64>().ok()
+                    .filter(|r| (0.0..=1.0).contains(r))
+                    .ok_or_else(|| AdapterError::InvalidArgument("sample".into(), arg.to_string()))?;
+                sample.push(LogSample { ratio, paths: d.args[1..].to_vec() });
+            }
+            "level" => {
+                let arg = d.args.first().map(String::as_str).unwrap_or("");
+                level = Some(parse_log_level(arg)
+                    .ok_or_else(|| AdapterError::InvalidArgument("level".into(), arg.to_string()))?);
+            }
             _ => {}
         }
     }
 
-    Ok(LogBlock { output, format })
+    Ok(LogBlock { output, format, level, skip, sample })
+}
+
+/// Adapt a log `output` directive:
+///
+///   output file <path> { roll_size 100MiB; roll_interval 24h; roll_keep 10; roll_uncompressed }
+///   output syslog [udp/host:514 | unix//dev/log] { facility local7; tag pingclair }
+///   output net <tcp/host:port | unix//path>
+///   output stdout | stderr
+fn adapt_log_output(d: Directive) -> Result<LogOutput, AdapterError> {
+    let kind = d.args.first().map(String::as_str).unwrap_or("stdout");
+    let options = d.block.map(|b| b.directives).unwrap_or_default();
+    let arg = |sub: &Directive| -> Result<String, AdapterError> {
+        sub.args.first().cloned().ok_or_else(|| AdapterError::ArgumentCount(sub.name.clone(), 1, 0))
+    };
+
+    Ok(match kind {
+        "file" => {
+            let path = d.args.get(1)
+                .ok_or_else(|| AdapterError::ArgumentCount("output file".into(), 2, d.args.len()))?
+                .clone();
+            let mut rotation = LogRotation::default();
+            let mut rolling = false;
+            let mut disabled = false;
+            for sub in options {
+                rolling = true;
+                match sub.name.as_str() {
+                    "roll_size" => {
+                        let value = arg(&sub)?;
+                        rotation.max_size = Some(parse_size_bytes(&value)
+                            .ok_or_else(|| AdapterError::InvalidArgument("roll_size".into(), value))?);
+                    }
+                    "roll_interval" => {
+                        let value = arg(&sub)?;
+                        rotation.interval_secs = Some(parse_duration_ms(&value)
+                            .filter(|ms| *ms >= 1000)
+                            .ok_or_else(|| AdapterError::InvalidArgument("roll_interval".into(), value))? / 1000);
+                    }
+                    "roll_keep" => {
+                        let value = arg(&sub)?;
+                        rotation.keep = Some(value.parse()
+                            .map_err(|_| AdapterError::InvalidArgument("roll_keep".into(), value))?);
+                    }
+                    "roll_uncompressed" => rotation.uncompressed = true,
+                    "roll_disabled" => disabled = true,
+                    other => return Err(AdapterError::UnknownDirective(format!("output file {}", other))),
+                }
+            }
+            if rolling && !disabled {
+                LogOutput::RollingFile { path, rotation }
+            } else {
+                LogOutput::File(path)
+            }
+        }
+        "syslog" => {
+            let mut facility = None;
+            let mut tag = None;
+            for sub in options {
+                match sub.name.as_str() {
+                    "facility" => {
+                        let value = arg(&sub)?;
+                        facility = Some(parse_syslog_facility(&value)
+                            .ok_or_else(|| AdapterError::InvalidArgument("facility".into(), value))?);
+                    }
+                    "tag" => tag = Some(arg(&sub)?),
+                    other => return Err(AdapterError::UnknownDirective(format!("output syslog {}", other))),
+                }
+            }
+            LogOutput::Syslog { address: d.args.get(1).cloned(), facility, tag }
+        }
+        "net" => LogOutput::Net(d.args.get(1)
+            .ok_or_else(|| AdapterError::ArgumentCount("output net".into(), 2, d.args.len()))?
+            .clone()),
+        "stdout" => LogOutput::Stdout,
+        "stderr" => LogOutput::Stderr,
+        other => return Err(AdapterError::InvalidArgument("output".into(), other.to_string())),
+    })
+}
+
+/// Parse a byte size: `1048576`, `512KiB`, `100MB`, `1GiB`
+fn parse_size_bytes(s: &str) -> Option<u64> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return None,
+    };
+    number.checked_mul(multiplier)
+}
+
+/// Syslog facility by name (`local0`..`local7`, `user`, `daemon`, ...) or number
+fn parse_syslog_facility(s: &str) -> Option<u8> {
+    let code = match s.to_ascii_lowercase().as_str() {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "local0" => 16,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        other => other.parse().ok()?,
+    };
+    (code <= 23).then_some(code)
+}
+
+/// Adapt a `format filter` block into its wrapped format and field filters.
+///
+/// Fields may be listed in a `fields { ... }` block or directly in the filter
+/// block (newer Caddy syntax).
+fn adapt_log_filter(block: Block) -> Result<(Option<LogFormatType>, LogFilter), AdapterError> {
+    let mut wrap = None;
+    let mut filter = LogFilter::default();
+
+    for d in block.directives {
+        match d.name.as_str() {
+            "wrap" => {
+                wrap = match d.args.first().map(String::as_str) {
+                    Some("json") => Some(LogFormatType::Json),
+                    Some("console") => Some(LogFormatType::Text),
+                    Some("common") => Some(LogFormatType::Common),
+                    Some("combined") => Some(LogFormatType::Combined),
+                    other => return Err(AdapterError::InvalidArgument("wrap".into(), other.unwrap_or("").to_string())),
+                };
+            }
+            "fields" => {
+                for field in d.block.map(|b| b.directives).unwrap_or_default() {
+                    let name = field.name.clone();
+                    filter.fields.push((name, adapt_log_field_action(field)?));
+                }
+            }
+            _ => {
+                let name = d.name.clone();
+                filter.fields.push((name, adapt_log_field_action(d)?));
+            }
+        }
+    }
+
+    Ok((wrap, filter))
+}
+
+/// `<field> delete | replace <value> | hash | ip_mask ... | query { ... } | cookie { ... }`
+fn adapt_log_field_action(d: Directive) -> Result<LogFieldAction, AdapterError> {
+    let kind = d.args.first().map(String::as_str).unwrap_or("");
+    Ok(match kind {
+        "delete" => LogFieldAction::Delete,
+        "replace" => LogFieldAction::Replace(d.args.get(1).cloned().unwrap_or_else(|| "REDACTED".to_string())),
+        "hash" => LogFieldAction::Hash,
+        "ip_mask" => {
+            // `ip_mask <v4> [<v6>]` or `ip_mask { ipv4 <n>; ipv6 <n> }`
+            let prefix = |arg: Option<&String>, max: u8, default: u8| -> Result<u8, AdapterError> {
+                match arg {
+                    Some(arg) => arg.parse::<u8>().ok()
+                        .filter(|n| *n <= max)
+                        .ok_or_else(|| AdapterError::InvalidArgument("ip_mask".into(), arg.clone())),
+                    None => Ok(default),
+                }
+            };
+            let mut ipv4 = prefix(d.args.get(1), 32, 32)?;
+            let mut ipv6 = prefix(d.args.get(2), 128, 128)?;
+            for sub in d.block.map(|b| b.directives).unwrap_or_default() {
+                match sub.name.as_str() {
+                    "ipv4" => ipv4 = prefix(sub.args.first(), 32, 32)?,
+                    "ipv6" => ipv6 = prefix(sub.args.first(), 128, 128)?,
+                    other => return Err(AdapterError::UnknownDirective(format!("ip_mask {}", other))),
+                }
+            }
+            LogFieldAction::IpMask { ipv4, ipv6 }
+        }
+        "query" | "cookie" => {
+            let mut params = Vec::new();
+            for sub in d.block.map(|b| b.directives).unwrap_or_default() {
+                let name = sub.args.first()
+                    .ok_or_else(|| AdapterError::ArgumentCount(sub.name.clone(), 1, 0))?
+                    .clone();
+                let action = match sub.name.as_str() {
+                    "delete" => LogParamAction::Delete,
+                    "replace" => LogParamAction::Replace(sub.args.get(1).cloned().unwrap_or_else(|| "REDACTED".to_string())),
+                    "hash" => LogParamAction::Hash,
+                    other => return Err(AdapterError::UnknownDirective(format!("{} {}", kind, other))),
+                };
+                params.push((name, action));
+            }
+            if kind == "query" { LogFieldAction::Query(params) } else { LogFieldAction::Cookie(params) }
+        }
+        other => return Err(AdapterError::InvalidArgument(d.name.clone(), other.to_string())),
+    })
+}
+
+/// Parse a Caddy log level name (case-insensitive)
+fn parse_log_level(level: &str) -> Option<LogLevel> {
+    match level.to_ascii_uppercase().as_str() {
+        "TRACE" => Some(LogLevel::Trace),
+        "DEBUG" => Some(LogLevel::Debug),
+        "INFO" => Some(LogLevel::Info),
+        "WARN" | "WARNING" => Some(LogLevel::Warn),
+        "ERROR" => Some(LogLevel::Error),
+        _ => None,
+    }
 }
 
 // MARK: - Handler Adaptation
@@ -492,6 +1074,42 @@ fn adapt_handler(d: Directive) -> Result<Handler, AdapterError> {
         "header" => {
             adapt_header_directive(&d)
         },
+        "templates" => {
+            // templates { mime <types...>; between <open> <close>; root <path> }
+            let mut config = TemplatesConfig::default();
+            if let Some(block) = d.block {
+                for sub in block.directives {
+                    match sub.name.as_str() {
+                        "mime" => config.mime_types.extend(sub.args.iter().cloned()),
+                        "between" => {
+                            if sub.args.len() != 2 {
+                                return Err(AdapterError::ArgumentCount("between".into(), 2, sub.args.len()));
+                            }
+                            config.between = Some((sub.args[0].clone(), sub.args[1].clone()));
+                        }
+                        "root" => config.root = sub.args.first().cloned(),
+                        other => return Err(AdapterError::UnknownDirective(format!("templates: {}", other))),
+                    }
+                }
+            }
+            Ok(Handler::Templates(config))
+        },
+        "redir" => {
+            // redir <to> [code|permanent|temporary]
+            let to = d.args.iter()
+                .find(|a| !a.starts_with('@'))
+                .cloned()
+                .ok_or_else(|| AdapterError::ArgumentCount("redir".into(), 1, 0))?;
+            let code = match d.args.iter().skip_while(|a| **a != to).nth(1).map(|s| s.as_str()) {
+                Some("permanent") => 301,
+                Some("temporary") | None => 302,
+                Some(other) => other.parse::<u16>()
+                    .map_err(|_| AdapterError::InvalidArgument("redir".into(), other.to_string()))?,
+            };
+            Ok(Handler::Redirect(RedirectConfig { to, code }))
+        },
+        "map" => adapt_map(d),
+        "vars" => adapt_vars(d),
         "handle" => {
             // `handle { ... }` inside another handle — nested exclusive routing
             let mut handlers = Vec::new();
@@ -506,6 +1124,74 @@ fn adapt_handler(d: Directive) -> Result<Handler, AdapterError> {
     }
 }
 
+// MARK: - map / vars
+
+/// Adapt `map <source> <destinations...> { <key> <values...>; default <values...> }`.
+///
+/// Keys are tried in order: exact, `*` wildcard, or `~` / `~*` regex.
+fn adapt_map(d: Directive) -> Result<Handler, AdapterError> {
+    let [source, destinations @ ..] = d.args.as_slice() else {
+        return Err(AdapterError::ArgumentCount("map".into(), 2, 0));
+    };
+    if destinations.is_empty() {
+        return Err(AdapterError::ArgumentCount("map".into(), 2, 1));
+    }
+
+    let mut config = MapConfig {
+        source: source.clone(),
+        destinations: destinations.iter().map(|dest| var_name(dest).to_string()).collect(),
+        entries: Vec::new(),
+        defaults: Vec::new(),
+    };
+    for entry in d.block.map(|b| b.directives).unwrap_or_default() {
+        if entry.args.len() > config.destinations.len() {
+            return Err(AdapterError::InvalidArgument(
+                "map".into(),
+                format!("'{}' has {} outputs for {} destinations", entry.name, entry.args.len(), config.destinations.len()),
+            ));
+        }
+        if entry.name == "default" {
+            config.defaults = entry.args;
+        } else {
+            config.entries.push((entry.name, entry.args));
+        }
+    }
+    Ok(Handler::Map(config))
+}
+
+/// Adapt `vars <name> <value>` or `vars { <name> <value>... }`.
+fn adapt_vars(d: Directive) -> Result<Handler, AdapterError> {
+    let mut lines = Vec::new();
+    if !d.args.is_empty() {
+        lines.push(d.args);
+    }
+    for sub in d.block.map(|b| b.directives).unwrap_or_default() {
+        lines.push(std::iter::once(sub.name).chain(sub.args).collect());
+    }
+
+    let mut vars = HashMap::new();
+    for line in &lines {
+        match line.split_first() {
+            Some((name, value)) if !value.is_empty() => {
+                vars.insert(var_name(name).to_string(), value.join(" "));
+            }
+            _ => return Err(AdapterError::ArgumentCount("vars".into(), 2, line.len())),
+        }
+    }
+    if vars.is_empty() {
+        return Err(AdapterError::ArgumentCount("vars".into(), 2, 0));
+    }
+    Ok(Handler::Vars(vars))
+}
+
+/// Variable name from `name`, `{name}` or `{vars.name}`
+fn var_name(arg: &str) -> &str {
+    let name = arg.strip_prefix('{').and_then(|a| a.strip_suffix('}')).unwrap_or(arg);
+    name.strip_prefix("http.vars.")
+        .or_else(|| name.strip_prefix("vars."))
+        .unwrap_or(name)
+}
+
 // MARK: - reverse_proxy Full Block Parsing
 
 /// Adapt a `reverse_proxy` directive with full sub-block support.
@@ -520,16 +1206,40 @@ fn adapt_reverse_proxy(d: Directive) -> Result<Handler, AdapterError> {
         .cloned()
         .collect();
 
+    // Upstreams with placeholders (e.g. `{vars.backend}` from `map`) are rendered per request
+    for upstream in upstreams.iter().filter(|u| u.contains('{')) {
+        if let Err(e) = Template::parse(upstream) {
+            return Err(AdapterError::InvalidArgument("reverse_proxy".into(), format!("{}: {}", upstream, e)));
+        }
+    }
+
     let mut proxy = ProxyConfig::new(upstreams);
 
     // Parse sub-block if present
     if let Some(block) = d.block {
-        for sub in block.directives {
+        // Response matchers (`@name status 5xx`) may be declared anywhere in
+        // the block, so collect them before resolving `handle_response`.
+        let (matcher_defs, directives): (Vec<_>, Vec<_>) = block.directives
+            .into_iter()
+            .partition(|sub| sub.name.starts_with('@'));
+        let mut response_matchers = HashMap::new();
+        for def in &matcher_defs {
+            response_matchers.insert(def.name.clone(), parse_response_matcher(def)?);
+        }
+
+        for sub in directives {
             match sub.name.as_str() {
+                "handle_response" => {
+                    proxy.handle_response.push(adapt_handle_response(sub, &response_matchers)?);
+                }
                 "header_up" => {
-                    // header_up Key Value
+                    // header_up Key Value  |  header_up -Key (remove, e.g. a default X-Forwarded-*)
                     // Value may be a {placeholder} → preserved as-is for runtime resolution
-                    if sub.args.len() >= 2 {
+                    if let [name] = sub.args.as_slice()
+                        && let Some(removed) = name.strip_prefix('-')
+                    {
+                        proxy.header_up_remove.push(removed.to_string());
+                    } else if sub.args.len() >= 2 {
                         let key = sub.args[0].clone();
                         let value = sub.args[1].clone();
                         proxy.header_up.insert(
@@ -539,8 +1249,27 @@ fn adapt_reverse_proxy(d: Directive) -> Result<Handler, AdapterError> {
                     }
                 }
                 "header_down" => {
-                    // 🐛 TODO: header_down is not yet tracked in ProxyConfig AST.
-                    // For now, silently ignore.
+                    // header_down Key Value
+                    if sub.args.len() < 2 {
+                        return Err(AdapterError::ArgumentCount("header_down".into(), 2, sub.args.len()));
+                    }
+                    proxy.header_down.insert(sub.args[0].clone(), Expr::String(sub.args[1].clone()));
+                }
+                "forwarded_headers" => {
+                    // forwarded_headers x_forwarded rfc7239  |  forwarded_headers off
+                    let mut forwarded = ForwardedHeaders::default();
+                    for arg in &sub.args {
+                        match arg.as_str() {
+                            "x_forwarded" => forwarded.x_forwarded = true,
+                            "rfc7239" => forwarded.rfc7239 = true,
+                            "off" => {}
+                            _ => return Err(AdapterError::InvalidArgument("forwarded_headers".into(), arg.clone())),
+                        }
+                    }
+                    if sub.args.is_empty() {
+                        return Err(AdapterError::ArgumentCount("forwarded_headers".into(), 1, 0));
+                    }
+                    proxy.forwarded_headers = Some(forwarded);
                 }
                 "flush_interval" => {
                     if let Some(val) = sub.args.first() {
@@ -557,6 +1286,7 @@ fn adapt_reverse_proxy(d: Directive) -> Result<Handler, AdapterError> {
                         let mut transport = TransportConfig {
                             read_timeout: None,
                             write_timeout: None,
+                            proxy_protocol: None,
                         };
                         for t_sub in transport_block.directives {
                             match t_sub.name.as_str() {
@@ -568,6 +1298,14 @@ fn adapt_reverse_proxy(d: Directive) -> Result<Handler, AdapterError> {
                                     transport.write_timeout = t_sub.args.first()
                                         .and_then(|s| parse_duration_ms(s));
                                 }
+                                "proxy_protocol" => {
+                                    let arg = t_sub.args.first().map(String::as_str).unwrap_or("");
+                                    transport.proxy_protocol = Some(match arg {
+                                        "v1" => 1,
+                                        "v2" => 2,
+                                        _ => return Err(AdapterError::InvalidArgument("proxy_protocol".into(), arg.to_string())),
+                                    });
+                                }
                                 _ => {}
                             }
                         }
@@ -586,6 +1324,73 @@ fn adapt_reverse_proxy(d: Directive) -> Result<Handler, AdapterError> {
     Ok(Handler::Proxy(Box::new(proxy)))
 }
 
+/// Adapt `handle_response [@matcher] { ... }` inside a `reverse_proxy` block.
+fn adapt_handle_response(
+    d: Directive,
+    response_matchers: &HashMap<String, ResponseMatcher>,
+) -> Result<ResponseHandlerBlock, AdapterError> {
+    let mut block = ResponseHandlerBlock::default();
+
+    if let Some(name) = d.args.first() {
+        let matcher = response_matchers.get(name).ok_or_else(|| {
+            AdapterError::InvalidArgument("handle_response".into(), format!("undefined response matcher {}", name))
+        })?;
+        block.matcher = Some(matcher.clone());
+    }
+
+    if let Some(inner) = d.block {
+        for sub in inner.directives {
+            match sub.name.as_str() {
+                "copy_response_headers" => {
+                    // copy_response_headers A B  |  copy_response_headers { include A B }
+                    block.copy_headers.extend(sub.args.iter().cloned());
+                    if let Some(headers_block) = &sub.block {
+                        for h in &headers_block.directives {
+                            if h.name == "include" {
+                                block.copy_headers.extend(h.args.iter().cloned());
+                            }
+                        }
+                    }
+                }
+                _ => block.handlers.push(adapt_handler(sub)?),
+            }
+        }
+    }
+
+    Ok(block)
+}
+
+/// Parse a response matcher definition: `@name status 5xx` or a block form.
+fn parse_response_matcher(d: &Directive) -> Result<ResponseMatcher, AdapterError> {
+    let mut matcher = ResponseMatcher::default();
+
+    let mut apply = |sub: &Directive| -> Result<(), AdapterError> {
+        match sub.name.as_str() {
+            "status" => matcher.status.extend(sub.args.iter().cloned()),
+            "header" => matcher.headers.push(parse_header_matcher(sub)?),
+            other => return Err(AdapterError::UnknownDirective(format!("response matcher: {}", other))),
+        }
+        Ok(())
+    };
+
+    if let Some(block) = &d.block {
+        for sub in &block.directives {
+            apply(sub)?;
+        }
+    } else {
+        if d.args.is_empty() {
+            return Err(AdapterError::ArgumentCount(d.name.clone(), 1, 0));
+        }
+        apply(&Directive {
+            name: d.args[0].clone(),
+            args: d.args[1..].to_vec(),
+            block: None,
+        })?;
+    }
+
+    Ok(matcher)
+}
+
 /// Parse Caddy duration strings like "300s", "5m", "100ms" into milliseconds.
 fn parse_duration_ms(s: &str) -> Option<u64> {
     if let Some(secs) = s.strip_suffix('s') {
@@ -598,6 +1403,12 @@ fn parse_duration_ms(s: &str) -> Option<u64> {
     if let Some(mins) = s.strip_suffix('m') {
         return mins.parse::<u64>().ok().map(|v| v * 60_000);
     }
+    if let Some(hours) = s.strip_suffix('h') {
+        return hours.parse::<u64>().ok().map(|v| v * 3_600_000);
+    }
+    if let Some(days) = s.strip_suffix('d') {
+        return days.parse::<u64>().ok().map(|v| v * 86_400_000);
+    }
     // Plain number → milliseconds
     s.parse::<u64>().ok()
 }
@@ -690,8 +1501,8 @@ fn adapt_header_directive(d: &Directive) -> Result<Handler, AdapterError> {
             .collect();
 
         if let Some(key) = args.first() {
-            if key.starts_with('-') {
-                config.remove.push(key[1..].to_string());
+            if let Some(name) = key.strip_prefix('-') {
+                config.remove.push(name.to_string());
             } else if let Some(val) = args.get(1) {
                 config.set.insert((*key).clone(), (*val).clone());
             }
@@ -718,87 +1529,295 @@ fn parse_matcher_and_block(d: &Directive) -> Result<(Option<Matcher>, Option<&Bl
 }
 
 fn parse_matcher_definition(d: &Directive) -> Result<Matcher, AdapterError> {
+    // `path_regexp` captures default to the matcher's own name
+    let set_name = d.name.trim_start_matches('@');
     if let Some(block) = &d.block {
-        let mut matchers = Vec::new();
-        for sub in &block.directives {
-            matchers.push(parse_single_matcher(sub)?);
-        }
-
-        if matchers.is_empty() {
-            return Err(AdapterError::InvalidArgument(d.name.clone(), "Empty matcher block".into()));
-        }
-
-        let mut combined = matchers.remove(0);
-        for m in matchers {
-            combined = Matcher::And(Box::new(combined), Box::new(m));
-        }
-        Ok(combined)
+        combine_matchers(&d.name, block.directives.iter()
+            .map(|sub| parse_single_matcher(sub, set_name))
+            .collect::<Result<Vec<_>, _>>()?)
     } else {
         // Inline matcher: @api path /v1/*
         if d.args.is_empty() {
             return Err(AdapterError::ArgumentCount(d.name.clone(), 1, 0));
         }
+        // Expression shorthand: @post `{method} == "POST"`
+        if let [expression] = d.args.as_slice()
+            && (expression.contains(char::is_whitespace) || expression.starts_with('{'))
+        {
+            return parse_expression_matcher(std::slice::from_ref(expression));
+        }
         let sub_directive = Directive {
             name: d.args[0].clone(),
             args: d.args[1..].to_vec(),
             block: None,
         };
-        parse_single_matcher(&sub_directive)
+        parse_single_matcher(&sub_directive, set_name)
+    }
+}
+
+/// AND together the matchers of a matcher set
+fn combine_matchers(name: &str, mut matchers: Vec<Matcher>) -> Result<Matcher, AdapterError> {
+    if matchers.is_empty() {
+        return Err(AdapterError::InvalidArgument(name.to_string(), "Empty matcher block".into()));
+    }
+
+    let mut combined = matchers.remove(0);
+    for m in matchers {
+        combined = Matcher::And(Box::new(combined), Box::new(m));
     }
+    Ok(combined)
 }
 
-fn parse_single_matcher(d: &Directive) -> Result<Matcher, AdapterError> {
+fn parse_single_matcher(d: &Directive, set_name: &str) -> Result<Matcher, AdapterError> {
     match d.name.as_str() {
         "path" => {
             Ok(Matcher::Path(PathMatcher { patterns: d.args.clone() }))
         }
+        "path_regexp" => {
+            // path_regexp [<name>] <regexp>
+            let (name, pattern) = match d.args.as_slice() {
+                [pattern] => (set_name.to_string(), pattern.clone()),
+                [name, pattern] => (name.clone(), pattern.clone()),
+                _ => return Err(AdapterError::ArgumentCount("path_regexp".into(), 1, d.args.len())),
+            };
+            check_regex("path_regexp", &pattern)?;
+            Ok(Matcher::PathRegexp(PathRegexpMatcher { name: Some(name), pattern }))
+        }
+        "host" => {
+            if d.args.is_empty() {
+                return Err(AdapterError::ArgumentCount("host".into(), 1, 0));
+            }
+            Ok(Matcher::Host(d.args.clone()))
+        }
+        "host_regexp" => {
+            // host_regexp [<name>] <regexp>
+            let (name, pattern) = match d.args.as_slice() {
+                [pattern] => (set_name.to_string(), pattern.clone()),
+                [name, pattern] => (name.clone(), pattern.clone()),
+                _ => return Err(AdapterError::ArgumentCount("host_regexp".into(), 1, d.args.len())),
+            };
+            check_regex("host_regexp", &pattern)?;
+            Ok(Matcher::HostRegexp(HostRegexpMatcher { name: Some(name), pattern }))
+        }
         "method" => {
-            let methods = d.args.iter().filter_map(|m| match m.to_uppercase().as_str() {
-                "GET" => Some(HttpMethod::Get),
-                "POST" => Some(HttpMethod::Post),
-                "PUT" => Some(HttpMethod::Put),
-                "DELETE" => Some(HttpMethod::Delete),
-                _ => None,
-            }).collect();
+            let methods = d.args.iter().map(|m| match m.to_uppercase().as_str() {
+                "GET" => Ok(HttpMethod::Get),
+                "POST" => Ok(HttpMethod::Post),
+                "PUT" => Ok(HttpMethod::Put),
+                "DELETE" => Ok(HttpMethod::Delete),
+                "PATCH" => Ok(HttpMethod::Patch),
+                "HEAD" => Ok(HttpMethod::Head),
+                "OPTIONS" => Ok(HttpMethod::Options),
+                other => Err(AdapterError::InvalidArgument("method".into(), other.to_string())),
+            }).collect::<Result<Vec<_>, _>>()?;
+            if methods.is_empty() {
+                return Err(AdapterError::ArgumentCount("method".into(), 1, 0));
+            }
             Ok(Matcher::Method(methods))
         }
         "header" => {
-            if d.args.is_empty() { return Err(AdapterError::ArgumentCount("header".into(), 1, d.args.len())); }
-
-            let condition = if d.args.len() >= 2 {
-                let val = &d.args[1];
-                if val == "*" {
-                    HeaderCondition::Exists
-                } else if val.starts_with("*") && val.ends_with("*") {
-                    HeaderCondition::Contains(val[1..val.len()-1].to_string())
-                } else {
-                    HeaderCondition::Equals(val.clone())
+            Ok(Matcher::Header(parse_header_matcher(d)?))
+        }
+        "header_regexp" => {
+            // header_regexp [<name>] <field> <regexp>
+            let (name, field, pattern) = match d.args.as_slice() {
+                [field, pattern] => (set_name.to_string(), field.clone(), pattern.clone()),
+                [name, field, pattern] => (name.clone(), field.clone(), pattern.clone()),
+                _ => return Err(AdapterError::ArgumentCount("header_regexp".into(), 2, d.args.len())),
+            };
+            check_regex("header_regexp", &pattern)?;
+            Ok(Matcher::HeaderRegexp(HeaderRegexpMatcher { name: Some(name), field, pattern }))
+        }
+        "query" => {
+            // query <key>=<val>... — values of one key are ORed, different keys ANDed
+            if d.args.is_empty() {
+                return Err(AdapterError::ArgumentCount("query".into(), 1, 0));
+            }
+            let mut by_key: Vec<(String, Vec<HeaderCondition>)> = Vec::new();
+            for arg in &d.args {
+                let (name, condition) = match arg.split_once('=') {
+                    Some((name, value)) => (name.to_string(), wildcard_condition(value)),
+                    None => (arg.clone(), HeaderCondition::Exists),
+                };
+                match by_key.iter_mut().find(|(key, _)| *key == name) {
+                    Some((_, conditions)) => conditions.push(condition),
+                    None => by_key.push((name, vec![condition])),
                 }
+            }
+            let per_key = by_key.into_iter().filter_map(|(name, conditions)| {
+                conditions.into_iter()
+                    .map(|condition| Matcher::Query(QueryMatcher { name: name.clone(), condition }))
+                    .reduce(|left, right| Matcher::Or(Box::new(left), Box::new(right)))
+            });
+            combine_matchers("query", per_key.collect())
+        }
+        "cookie" => {
+            // cookie <name> [<value>]
+            let (name, condition) = match d.args.as_slice() {
+                [name] => (name.clone(), HeaderCondition::Exists),
+                [name, value] => (name.clone(), wildcard_condition(value)),
+                _ => return Err(AdapterError::ArgumentCount("cookie".into(), 1, d.args.len())),
+            };
+            Ok(Matcher::Cookie(CookieMatcher { name, condition }))
+        }
+        "remote_ip" => {
+            // `forwarded` (legacy Caddy option) is superseded by `client_ip`
+            let ranges: Vec<String> = d.args.iter().filter(|a| *a != "forwarded").cloned().collect();
+            if ranges.is_empty() {
+                return Err(AdapterError::ArgumentCount("remote_ip".into(), 1, 0));
+            }
+            check_ip_ranges("remote_ip", &ranges)?;
+            Ok(Matcher::RemoteIp(ranges))
+        }
+        "client_ip" => {
+            if d.args.is_empty() {
+                return Err(AdapterError::ArgumentCount("client_ip".into(), 1, 0));
+            }
+            check_ip_ranges("client_ip", &d.args)?;
+            Ok(Matcher::ClientIp(d.args.clone()))
+        }
+        "expression" => parse_expression_matcher(&d.args),
+        "vars" => {
+            // vars <name|{placeholder}> <values...>
+            let [key, values @ ..] = d.args.as_slice() else {
+                return Err(AdapterError::ArgumentCount("vars".into(), 2, 0));
+            };
+            if values.is_empty() {
+                return Err(AdapterError::ArgumentCount("vars".into(), 2, 1));
+            }
+            let key = if key.starts_with('{') { key.clone() } else { format!("{{vars.{}}}", key) };
+            if let Err(e) = Template::parse(&key) {
+                return Err(AdapterError::InvalidArgument("vars".into(), e.to_string()));
+            }
+            Ok(Matcher::Vars(VarsMatcher { key, values: values.to_vec() }))
+        }
+        "protocol" => {
+            if d.args.is_empty() {
+                return Err(AdapterError::ArgumentCount("protocol".into(), 1, 0));
+            }
+            Ok(Matcher::Protocol(d.args.iter().map(|p| p.to_lowercase()).collect()))
+        }
+        "not" => {
+            // not <matcher> | not { <matchers> }
+            let inner = if let Some(block) = &d.block {
+                combine_matchers("not", block.directives.iter()
+                    .map(|sub| parse_single_matcher(sub, set_name))
+                    .collect::<Result<Vec<_>, _>>()?)?
             } else {
-                // Single arg: header exists
-                HeaderCondition::Exists
+                let Some((name, args)) = d.args.split_first() else {
+                    return Err(AdapterError::ArgumentCount("not".into(), 1, 0));
+                };
+                parse_single_matcher(&Directive { name: name.clone(), args: args.to_vec(), block: None }, set_name)?
             };
-
-            Ok(Matcher::Header(HeaderMatcher {
-                name: d.args[0].clone(),
-                condition,
-            }))
+            Ok(Matcher::Not(Box::new(inner)))
         }
         _ => Err(AdapterError::UnknownDirective(format!("matcher: {}", d.name))),
     }
 }
 
+/// Compile a matcher regex now so a typo fails config loading
+fn check_regex(directive: &str, pattern: &str) -> Result<(), AdapterError> {
+    ConfigRegex::parse(pattern)
+        .map(|_| ())
+        .map_err(|e| AdapterError::InvalidArgument(directive.into(), format!("invalid regex {:?}: {}", pattern, e)))
+}
+
+/// Reject entries that are not an IP, a CIDR block or `private_ranges`
+fn check_ip_ranges(directive: &str, entries: &[String]) -> Result<(), AdapterError> {
+    match entries.iter().find(|entry| parse_ip_ranges(entry).is_none()) {
+        Some(entry) => Err(AdapterError::InvalidArgument(directive.into(), format!("invalid IP/CIDR {:?}", entry))),
+        None => Ok(()),
+    }
+}
+
+/// `expression <expr>`, validated now so mistakes fail config loading.
+///
+/// Several tokens are joined with spaces; quote the expression with
+/// backticks to keep its string literals.
+fn parse_expression_matcher(args: &[String]) -> Result<Matcher, AdapterError> {
+    if args.is_empty() {
+        return Err(AdapterError::ArgumentCount("expression".into(), 1, 0));
+    }
+    let source = args.join(" ");
+    if let Err(e) = Expression::parse(&source) {
+        return Err(AdapterError::InvalidArgument("expression".into(), e.render(&source)));
+    }
+    Ok(Matcher::Expression(source))
+}
+
+/// Caddy-style value wildcards: `*` (present), `*x*`, `x*`, `*x`
+fn wildcard_condition(value: &str) -> HeaderCondition {
+    if value == "*" {
+        HeaderCondition::Exists
+    } else if value.len() > 2 && value.starts_with('*') && value.ends_with('*') {
+        HeaderCondition::Contains(value[1..value.len() - 1].to_string())
+    } else if let Some(suffix) = value.strip_prefix('*') {
+        HeaderCondition::EndsWith(suffix.to_string())
+    } else if let Some(prefix) = value.strip_suffix('*') {
+        HeaderCondition::StartsWith(prefix.to_string())
+    } else {
+        HeaderCondition::Equals(value.to_string())
+    }
+}
+
+fn parse_header_matcher(d: &Directive) -> Result<HeaderMatcher, AdapterError> {
+    if d.args.is_empty() { return Err(AdapterError::ArgumentCount("header".into(), 1, d.args.len())); }
+
+    let condition = match d.args.get(1) {
+        Some(val) => wildcard_condition(val),
+        // Single arg: header exists
+        None => HeaderCondition::Exists,
+    };
+
+    Ok(HeaderMatcher {
+        name: d.args[0].clone(),
+        condition,
+    })
+}
+
 // MARK: - Helpers
 
 fn add_route(server: &mut ServerBlock, matcher: Option<Matcher>, handler: Handler) {
+    add_route_arm(server, RouteArm {
+        matcher,
+        handler,
+        internal: false,
+        priority: 0,
+        source_order: false,
+    });
+}
+
+/// `priority <n>` inside a `handle` / `route` block
+fn parse_priority(d: &Directive) -> Result<i32, AdapterError> {
+    match d.args.as_slice() {
+        [value] => value.parse()
+            .map_err(|_| AdapterError::InvalidArgument("priority".into(), value.clone())),
+        _ => Err(AdapterError::ArgumentCount("priority".into(), 1, d.args.len())),
+    }
+}
+
+/// Put route arms in the order the router tries them (priority, then
+/// explicit `route` blocks as written, then path specificity, then source
+/// order), once all named matchers are known
+fn sort_route_arms(server: &mut ServerBlock) {
+    let Some(routes) = server.routes.as_mut() else {
+        return;
+    };
+    let matchers = &server.matchers;
+    routes.inner.arms.sort_by_cached_key(|arm| {
+        let path = arm.inner.matcher.as_ref()
+            .and_then(|m| m.path_pattern(matchers))
+            .unwrap_or_else(|| "/*".to_string());
+        route_order_key(&path, arm.inner.priority, arm.inner.source_order)
+    });
+}
+
+fn add_route_arm(server: &mut ServerBlock, arm: RouteArm) {
     if server.routes.is_none() {
         server.routes = Some(Node::new(RouteBlock { arms: Vec::new() }, Location { start: 0, end: 0 }));
     }
     let routes = server.routes.as_mut().unwrap();
-    routes.inner.arms.push(Node::new(RouteArm {
-        matcher,
-        handler,
-    }, Location { start: 0, end: 0 }));
+    routes.inner.arms.push(Node::new(arm, Location { start: 0, end: 0 }));
 }
 
 // MARK: - Tests
@@ -824,6 +1843,129 @@ mod global_tests {
         assert_eq!(global.debug, Some(true));
     }
 
+    #[test]
+    fn test_trusted_proxies_and_request_id_header() {
+        let source = r#"{
+            servers {
+                trusted_proxies static private_ranges 203.0.113.7
+                client_ip_headers CF-Connecting-IP X-Forwarded-For
+            }
+            request_id_header X-Correlation-Id
+        }"#;
+        let directives = parse(source).unwrap();
+        let ast = adapt(directives).unwrap();
+
+        let global = ast.global.unwrap().inner;
+        assert!(global.trusted_proxies.contains(&"10.0.0.0/8".to_string()));
+        assert_eq!(global.trusted_proxies.last().map(String::as_str), Some("203.0.113.7"));
+        assert_eq!(global.client_ip_headers, vec!["CF-Connecting-IP", "X-Forwarded-For"]);
+        assert_eq!(global.request_id_header.as_deref(), Some("X-Correlation-Id"));
+    }
+
+    #[test]
+    fn test_tracing_block() {
+        let source = r#"{
+            tracing {
+                endpoint http://collector:4317
+                protocol grpc
+                sample_ratio 0.25
+                header Authorization "Bearer abc"
+                flush_interval 2s
+            }
+        }"#;
+        let directives = parse(source).unwrap();
+        let ast = adapt(directives).unwrap();
+
+        let tracing = ast.global.unwrap().inner.tracing.unwrap();
+        assert_eq!(tracing.endpoint.as_deref(), Some("http://collector:4317"));
+        assert!(tracing.grpc);
+        assert_eq!(tracing.sample_ratio, Some(0.25));
+        assert_eq!(tracing.headers, vec![("Authorization".to_string(), "Bearer abc".to_string())]);
+        assert_eq!(tracing.flush_interval_ms, Some(2000));
+
+        let invalid = parse("{\n tracing {\n endpoint http://c:4318\n sample_ratio 2\n }\n}").unwrap();
+        assert!(adapt(invalid).is_err());
+
+        let tls = parse("{\n tracing {\n endpoint https://c:4318\n }\n}").unwrap();
+        assert!(matches!(adapt(tls), Err(AdapterError::InvalidArgument(ref d, _)) if d == "tracing"));
+    }
+
+    #[test]
+    fn test_metrics_block() {
+        let source = r#"{
+            metrics {
+                per_route
+                buckets 0.01 0.1 1 10
+                upstream_buckets 5ms 50ms 500ms
+            }
+        }"#;
+        let directives = parse(source).unwrap();
+        let ast = adapt(directives).unwrap();
+
+        let metrics = ast.global.unwrap().inner.metrics.unwrap();
+        assert!(metrics.per_route);
+        assert_eq!(metrics.buckets, vec![0.01, 0.1, 1.0, 10.0]);
+        assert_eq!(metrics.upstream_buckets, vec![0.005, 0.05, 0.5]);
+
+        let unsorted = parse("{\n metrics {\n buckets 1 0.5\n }\n}").unwrap();
+        assert!(adapt(unsorted).is_err());
+    }
+
+    #[test]
+    fn test_statsd_block() {
+        let source = r#"{
+            statsd 127.0.0.1:8125 {
+                protocol dogstatsd
+                prefix edge
+                tag env prod
+                flush_interval 500ms
+                sample_rate 0.1
+            }
+        }"#;
+        let directives = parse(source).unwrap();
+        let ast = adapt(directives).unwrap();
+
+        let statsd = ast.global.unwrap().inner.statsd.unwrap();
+        assert_eq!(statsd.address.as_deref(), Some("127.0.0.1:8125"));
+        assert!(statsd.dogstatsd);
+        assert_eq!(statsd.prefix.as_deref(), Some("edge"));
+        assert_eq!(statsd.tags, vec![("env".to_string(), "prod".to_string())]);
+        assert_eq!(statsd.flush_interval_ms, Some(500));
+        assert_eq!(statsd.sample_rate, Some(0.1));
+
+        let missing = parse("{\n statsd {\n prefix edge\n }\n}").unwrap();
+        assert!(adapt(missing).is_err());
+    }
+
+    #[test]
+    fn test_listener_proxy_protocol() {
+        let source = r#"{
+            servers :443 {
+                listener_wrappers {
+                    proxy_protocol {
+                        timeout 2s
+                        allow 10.0.0.0/8 192.168.0.1
+                    }
+                    tls
+                }
+                protocols h1 h2
+            }
+        }"#;
+        let directives = parse(source).unwrap();
+        let global = adapt(directives).unwrap().global.unwrap().inner;
+
+        assert_eq!(global.listeners.len(), 1);
+        let listener = &global.listeners[0];
+        assert_eq!(listener.address.as_deref(), Some(":443"));
+        let pp = listener.proxy_protocol.as_ref().unwrap();
+        assert_eq!(pp.timeout_ms, Some(2000));
+        assert_eq!(pp.allow, vec!["10.0.0.0/8", "192.168.0.1"]);
+        assert_eq!(global.protocols, vec![Protocol::H1, Protocol::H2]);
+
+        let bad = parse("{\n servers {\n listener_wrappers {\n proxy_protocol {\n fallback_policy reject\n }\n }\n }\n}").unwrap();
+        assert!(adapt(bad).is_err());
+    }
+
     #[test]
     fn test_multi_listener_adaptation() {
         let source = ":8080 :8081 { respond \"Hello\" }";
@@ -889,10 +2031,13 @@ mod global_tests {
                 reverse_proxy 127.0.0.1:3000 {
                     header_up X-Forwarded-Proto https
                     header_up X-Real-IP {http.request.header.CF-Connecting-IP}
+                    header_up -X-Forwarded-Host
+                    forwarded_headers x_forwarded rfc7239
                     flush_interval -1
                     transport http {
                         read_timeout 300s
                         write_timeout 300s
+                        proxy_protocol v2
                     }
                 }
             }
@@ -907,16 +2052,46 @@ mod global_tests {
             assert_eq!(proxy.upstreams, vec!["127.0.0.1:3000"]);
             assert!(proxy.header_up.contains_key("X-Forwarded-Proto"));
             assert!(proxy.header_up.contains_key("X-Real-IP"));
+            assert_eq!(proxy.header_up_remove, vec!["X-Forwarded-Host"]);
+            assert_eq!(proxy.forwarded_headers, Some(ForwardedHeaders { x_forwarded: true, rfc7239: true }));
             assert!(matches!(proxy.flush_interval, Some(FlushInterval::Immediate)));
             assert!(proxy.transport.is_some());
             let t = proxy.transport.as_ref().unwrap();
             assert_eq!(t.read_timeout, Some(300_000));
             assert_eq!(t.write_timeout, Some(300_000));
+            assert_eq!(t.proxy_protocol, Some(2));
         } else {
             panic!("Expected Proxy handler");
         }
     }
 
+    #[test]
+    fn test_reverse_proxy_placeholder_upstreams() {
+        let source = r#"
+            example.com {
+                map {host} {vars.backend} {
+                    api.example.com 10.0.0.2:8080
+                    default         10.0.0.1:8080
+                }
+                reverse_proxy {vars.backend}
+            }
+        "#;
+        let ast = adapt(parse(source).unwrap()).unwrap();
+        let handler = &ast.servers[0].inner.routes.as_ref().unwrap().inner.arms[0].inner.handler;
+        let Handler::Proxy(proxy) = handler else {
+            panic!("Expected Proxy handler, got {:?}", handler);
+        };
+        assert_eq!(proxy.upstreams, vec!["{vars.backend}".to_string()]);
+
+        let source = r#"
+            example.com {
+                reverse_proxy {time.now.format.%Q}:8080
+            }
+        "#;
+        let err = adapt(parse(source).unwrap()).unwrap_err();
+        assert!(matches!(err, AdapterError::InvalidArgument(ref d, _) if d == "reverse_proxy"));
+    }
+
     #[test]
     fn test_http_url_address_parsing() {
         let source = r#"
@@ -933,6 +2108,43 @@ mod global_tests {
         assert_eq!(server.bind, Some("127.0.0.1".to_string()));
     }
 
+    #[test]
+    fn test_listen_schemes_and_rejected_ports() {
+        let source = r#"
+            example.com {
+                listen https://:8443
+                listen :443
+                listen :8080
+                listen httpsd:9000
+                listen [::1]
+                listen https://[::1]:8443
+            }
+        "#;
+        let ast = adapt(parse(source).unwrap()).unwrap();
+        let schemes: Vec<_> = ast.servers[0].inner.listens.iter()
+            .filter(|l| l.host == "0.0.0.0")
+            .map(|l| (l.scheme, l.port))
+            .collect();
+        assert_eq!(schemes, vec![
+            (Scheme::Https, Some(8443)),
+            (Scheme::Https, Some(443)),
+            (Scheme::Http, Some(8080)),
+            // Only a real `https://` prefix selects TLS
+            (Scheme::Http, Some(9000)),
+            // Bracketed IPv6 hosts keep their colons
+            (Scheme::Http, None),
+            (Scheme::Https, Some(8443)),
+        ]);
+
+        for bad in [":99999", ":http", "https://:", "127.0.0.1:-1", "[::1", "[::1]8080"] {
+            let source = format!("example.com {{\n listen {}\n}}", bad);
+            assert!(
+                matches!(adapt(parse(&source).unwrap()), Err(AdapterError::InvalidArgument(ref d, _)) if d == "listen"),
+                "{} should be rejected", bad
+            );
+        }
+    }
+
     #[test]
     fn test_servers_nested_global() {
         let source = r#"{
@@ -1011,4 +2223,209 @@ mod global_tests {
         let server = &ast.servers[0].inner;
         assert!(server.matchers.contains_key("@cf_access"));
     }
+
+    #[test]
+    fn test_ip_matchers_reject_invalid_ranges() {
+        for matcher in ["not remote_ip 10.0.0.0/33", "client_ip private_ranges 300.1.1.1"] {
+            let source = format!("example.com {{\n    @m {}\n    respond @m \"hi\"\n}}", matcher);
+            let directive = matcher.trim_start_matches("not ").split(' ').next().unwrap();
+            assert!(
+                matches!(adapt(parse(&source).unwrap()), Err(AdapterError::InvalidArgument(ref d, _)) if d == directive),
+                "{} should be rejected", matcher
+            );
+        }
+    }
+
+    #[test]
+    fn test_regex_matchers() {
+        let source = r#"
+            example.com {
+                @tenant header_regexp org X-Tenant ^(?P<id>[a-z]+)$
+                respond @tenant "{re.org.id}"
+            }
+        "#;
+        let ast = adapt(parse(source).unwrap()).unwrap();
+        let matcher = &ast.servers[0].inner.matchers["@tenant"];
+        assert!(matches!(matcher, Matcher::HeaderRegexp(HeaderRegexpMatcher { name: Some(name), field, .. }) if name == "org" && field == "X-Tenant"));
+
+        for matcher in ["header_regexp X-Id (", "path_regexp [0-9", "host_regexp (?P<x"] {
+            let source = format!("example.com {{\n    @m {}\n    respond @m \"hi\"\n}}", matcher);
+            let directive = matcher.split(' ').next().unwrap();
+            assert!(
+                matches!(adapt(parse(&source).unwrap()), Err(AdapterError::InvalidArgument(ref d, _)) if d == directive),
+                "{} should be rejected", matcher
+            );
+        }
+    }
+
+    #[test]
+    fn test_handle_response_block() {
+        let source = r#"
+            example.com {
+                reverse_proxy localhost:3000 {
+                    handle_response @accel {
+                        file_server /srv/protected
+                    }
+                    @accel header X-Accel-Redirect *
+                }
+            }
+        "#;
+        let directives = parse(source).unwrap();
+        let ast = adapt(directives).unwrap();
+        let server = &ast.servers[0].inner;
+        let handler = &server.routes.as_ref().unwrap().inner.arms[0].inner.handler;
+        let Handler::Proxy(proxy) = handler else {
+            panic!("Expected Proxy handler, got {:?}", handler);
+        };
+
+        assert_eq!(proxy.handle_response.len(), 1);
+        let block = &proxy.handle_response[0];
+        let matcher = block.matcher.as_ref().unwrap();
+        assert_eq!(matcher.headers[0].name, "X-Accel-Redirect");
+        assert!(matches!(matcher.headers[0].condition, HeaderCondition::Exists));
+        assert!(matches!(block.handlers[0], Handler::FileServer(_)));
+    }
+
+    #[test]
+    fn test_handle_response_undefined_matcher() {
+        let source = r#"
+            example.com {
+                reverse_proxy localhost:3000 {
+                    handle_response @missing {
+                        respond 502
+                    }
+                }
+            }
+        "#;
+        let directives = parse(source).unwrap();
+        assert!(adapt(directives).is_err());
+    }
+
+    #[test]
+    fn test_internal_handle_block() {
+        let source = r#"
+            example.com {
+                @protected path /protected/*
+                handle @protected {
+                    internal
+                    file_server /srv/downloads
+                }
+                reverse_proxy localhost:3000
+            }
+        "#;
+        let directives = parse(source).unwrap();
+        let ast = adapt(directives).unwrap();
+        let arms = &ast.servers[0].inner.routes.as_ref().unwrap().inner.arms;
+
+        assert!(arms[0].inner.internal);
+        assert!(!arms[1].inner.internal);
+    }
+
+    #[test]
+    fn test_route_arms_in_precedence_order() {
+        let source = r#"
+            example.com {
+                handle /api/* {
+                    respond "api"
+                }
+                handle /api/users/* {
+                    respond "users"
+                }
+                @static path /css/* /js/*
+                handle @static {
+                    respond "static"
+                }
+                handle {
+                    priority 10
+                    respond "maintenance"
+                }
+                reverse_proxy localhost:3000
+            }
+        "#;
+        let directives = parse(source).unwrap();
+        let ast = adapt(directives).unwrap();
+        let server = &ast.servers[0].inner;
+        let arms = &server.routes.as_ref().unwrap().inner.arms;
+
+        let order: Vec<_> = arms.iter()
+            .map(|arm| (
+                arm.inner.priority,
+                arm.inner.matcher.as_ref().and_then(|m| m.path_pattern(&server.matchers)),
+            ))
+            .collect();
+        assert_eq!(order, [
+            (10, None),
+            (0, Some("/api/users/*".to_string())),
+            (0, Some("/api/*".to_string())),
+            (0, None),
+            (0, None),
+        ]);
+        assert!(matches!(&arms[3].inner.matcher, Some(Matcher::Named(name)) if name == "@static"));
+        assert!(matches!(&arms[4].inner.handler, Handler::Proxy(_)));
+    }
+
+    #[test]
+    fn test_route_blocks_keep_source_order() {
+        let source = r#"
+            example.com {
+                handle /api/users/admin {
+                    respond "admin"
+                }
+                route /api/* {
+                    respond "api"
+                }
+                route /api/users/* {
+                    respond "users"
+                }
+            }
+        "#;
+        let ast = adapt(parse(source).unwrap()).unwrap();
+        let server = &ast.servers[0].inner;
+        let paths: Vec<_> = server.routes.as_ref().unwrap().inner.arms.iter()
+            .map(|arm| arm.inner.matcher.as_ref().and_then(|m| m.path_pattern(&server.matchers)).unwrap())
+            .collect();
+        assert_eq!(paths, ["/api/*", "/api/users/*", "/api/users/admin"]);
+    }
+
+    #[test]
+    fn test_map_and_vars_directives() {
+        let source = r#"
+            example.com {
+                map {host} {backend} {vars.tier} {
+                    example.com     main  gold
+                    ~^(\w+)\.apps\.  app-$1
+                    default         fallback
+                }
+                vars region eu-west
+                @gold vars tier gold
+                handle @gold {
+                    vars { lane fast }
+                    respond "gold"
+                }
+            }
+        "#;
+        let ast = adapt(parse(source).unwrap()).unwrap();
+        let server = &ast.servers[0].inner;
+
+        // Site-wide map / vars run before routing instead of in the default route
+        assert_eq!(server.variables.len(), 2);
+        let Handler::Map(map) = &server.variables[0] else { panic!("expected map") };
+        assert_eq!(map.source, "{host}");
+        assert_eq!(map.destinations, vec!["backend", "tier"]);
+        assert_eq!(map.entries[0], ("example.com".to_string(), vec!["main".to_string(), "gold".to_string()]));
+        assert_eq!(map.entries[1].0, r"~^(\w+)\.apps\.");
+        assert_eq!(map.defaults, vec!["fallback"]);
+        assert!(matches!(&server.variables[1], Handler::Vars(vars) if vars["region"] == "eu-west"));
+
+        assert!(matches!(
+            &server.matchers["@gold"],
+            Matcher::Vars(VarsMatcher { key, values }) if key == "{vars.tier}" && values == &["gold"]
+        ));
+        let arms = &server.routes.as_ref().unwrap().inner.arms;
+        assert_eq!(arms.len(), 1);
+        assert!(matches!(&arms[0].inner.handler, Handler::Pipeline(h) if matches!(&h[0], Handler::Vars(_))));
+
+        let too_many = "example.com {\n map {host} {a} {\n x 1 2\n }\n}";
+        assert!(adapt(parse(too_many).unwrap()).is_err());
+    }
 }
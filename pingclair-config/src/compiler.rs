@@ -5,16 +5,22 @@
 use crate::parser::ast::*;
 use pingclair_core::config::{
     PingclairConfig, ServerConfig, RouteConfig, HandlerConfig,
-    TlsConfig, ReverseProxyConfig,
+    TlsConfig, ReverseProxyConfig, ProxyProtocolVersion, ForwardedHeadersConfig,
     LoadBalanceConfig, LogConfig, LogOutput as CoreLogOutput, LogFormat as CoreLogFormat,
-    Matcher as CoreMatcher, MatcherCondition,
+    Matcher as CoreMatcher, MatcherCondition, ResponseHandlerConfig,
+    MapConfig as CoreMapConfig, MapEntry as CoreMapEntry,
+    ResponseMatcher as CoreResponseMatcher, EncodedSlashPolicy,
 };
+use pingclair_core::server::{ConfigRegex, MapKey, Template, parse_ip_ranges};
 use std::collections::HashMap;
 use thiserror::Error;
 
 /// Compiler errors
 #[derive(Debug, Error)]
 pub enum CompileError {
+    #[error("Invalid global configuration: {message}")]
+    InvalidGlobal { message: String },
+
     #[error("Invalid server configuration: {message}")]
     InvalidServer { message: String },
     
@@ -23,6 +29,15 @@ pub enum CompileError {
     
     #[error("Unsupported feature: {feature}")]
     UnsupportedFeature { feature: String },
+
+    #[error("Invalid template {template:?}: {message}")]
+    InvalidTemplate { template: String, message: String },
+
+    #[error("Invalid map key {key:?}: {message}")]
+    InvalidMapKey { key: String, message: String },
+
+    #[error("Invalid regex {pattern:?}: {message}")]
+    InvalidRegex { pattern: String, message: String },
 }
 
 type CompileResult<T> = Result<T, CompileError>;
@@ -66,6 +81,83 @@ fn compile_global(global: &GlobalBlock, config: &mut PingclairConfig) -> Compile
             AutoHttpsMode::DisableRedirects => CoreMode::DisableRedirects,
         };
     }
+
+    // Request metadata trust & correlation
+    config.global.trusted_proxies = global.trusted_proxies.clone();
+    config.global.client_ip_headers = global.client_ip_headers.clone();
+    if let Some(header) = &global.request_id_header {
+        config.global.request_id_header = Some(header.clone());
+    }
+
+    // Global application log
+    if let Some(logging) = &global.logging {
+        config.logging = pingclair_core::config::LoggingConfig {
+            level: log_level_name(logging.level).to_string(),
+            format: match logging.format.format_type {
+                LogFormatType::Json => "json".to_string(),
+                // Request-line formats only apply to access logs
+                _ => "pretty".to_string(),
+            },
+            file: match &logging.output {
+                LogOutput::File(path) | LogOutput::RollingFile { path, .. } => Some(path.clone()),
+                _ => None,
+            },
+        };
+    }
+
+    // OpenTelemetry export
+    if let Some(tracing) = &global.tracing {
+        use pingclair_core::config::{OtlpProtocol, TracingConfig};
+        let defaults = TracingConfig::default();
+        config.global.tracing = Some(TracingConfig {
+            endpoint: tracing.endpoint.clone().unwrap_or(defaults.endpoint),
+            protocol: if tracing.grpc { OtlpProtocol::Grpc } else { OtlpProtocol::Http },
+            sample_ratio: tracing.sample_ratio.unwrap_or(defaults.sample_ratio),
+            service_name: tracing.service_name.clone().unwrap_or(defaults.service_name),
+            headers: tracing.headers.iter().cloned().collect(),
+            batch_size: tracing.batch_size.filter(|n| *n > 0).unwrap_or(defaults.batch_size),
+            flush_interval_ms: tracing.flush_interval_ms.unwrap_or(defaults.flush_interval_ms),
+        });
+    }
+
+    // StatsD push export
+    if let Some(statsd) = &global.statsd {
+        use pingclair_core::config::{StatsdConfig, StatsdFlavor};
+        let defaults = StatsdConfig::default();
+        config.global.statsd = Some(StatsdConfig {
+            address: statsd.address.clone().unwrap_or(defaults.address),
+            flavor: if statsd.dogstatsd { StatsdFlavor::Dogstatsd } else { StatsdFlavor::Statsd },
+            prefix: statsd.prefix.clone().unwrap_or(defaults.prefix),
+            tags: statsd.tags.iter().cloned().collect(),
+            flush_interval_ms: statsd.flush_interval_ms.unwrap_or(defaults.flush_interval_ms),
+            sample_rate: statsd.sample_rate.unwrap_or(defaults.sample_rate),
+            max_packet_size: statsd.max_packet_size.unwrap_or(defaults.max_packet_size),
+        });
+    }
+
+    // Per-listener options
+    for listener in &global.listeners {
+        use pingclair_core::config::{ListenerOptions, ProxyProtocolConfig};
+        config.global.listeners.push(ListenerOptions {
+            address: listener.address.clone(),
+            proxy_protocol: listener.proxy_protocol.as_ref().map(|pp| {
+                let defaults = ProxyProtocolConfig::default();
+                ProxyProtocolConfig {
+                    timeout_ms: pp.timeout_ms.unwrap_or(defaults.timeout_ms),
+                    allow: pp.allow.clone(),
+                }
+            }),
+        });
+    }
+
+    // Prometheus metrics
+    if let Some(metrics) = &global.metrics {
+        config.global.metrics = pingclair_core::config::MetricsConfig {
+            buckets: metrics.buckets.clone(),
+            upstream_buckets: metrics.upstream_buckets.clone(),
+            per_route: metrics.per_route,
+        };
+    }
     
     Ok(())
 }
@@ -76,9 +168,12 @@ fn compile_server(server: &ServerBlock) -> CompileResult<ServerConfig> {
         listen: Vec::new(),
         routes: Vec::new(),
         tls: None,
+        tls_listen: Vec::new(),
         log: None,
         client_max_body_size: 1024 * 1024, // 1MB default
         security: Default::default(),
+        path_normalization: Default::default(),
+        variables: Vec::new(),
     };
     
     // Listen addresses
@@ -88,12 +183,12 @@ fn compile_server(server: &ServerBlock) -> CompileResult<ServerConfig> {
         } else {
             listen.host.clone()
         };
-        config.listen.push(addr);
-        
-        // Set TLS based on scheme
+        // Set TLS based on scheme, per listener
         if listen.scheme == Scheme::Https {
-            config.tls = Some(TlsConfig::default());
+            config.tls.get_or_insert_with(TlsConfig::default);
+            config.tls_listen.push(addr.clone());
         }
+        config.listen.push(addr);
     }
     
     // Bind address (add as first listen if no explicit listens)
@@ -107,7 +202,38 @@ fn compile_server(server: &ServerBlock) -> CompileResult<ServerConfig> {
     if let Some(log) = &server.log {
         config.log = Some(compile_log(&log.inner)?);
     }
+
+    // Path normalisation (unset options keep their defaults)
+    if let Some(block) = &server.path_normalization {
+        let normalization = &mut config.path_normalization;
+        normalization.enabled = block.enabled;
+        normalization.forward_original = block.forward_original;
+        if let Some(decode) = block.decode_unreserved {
+            normalization.decode_unreserved = decode;
+        }
+        if let Some(resolve) = block.resolve_dots {
+            normalization.resolve_dots = resolve;
+        }
+        if let Some(merge) = block.merge_slashes {
+            normalization.merge_slashes = merge;
+        }
+        if let Some(reject) = block.reject_nul {
+            normalization.reject_nul = reject;
+        }
+        if let Some(policy) = block.encoded_slashes {
+            normalization.encoded_slashes = match policy {
+                EncodedSlashes::Keep => EncodedSlashPolicy::Keep,
+                EncodedSlashes::Decode => EncodedSlashPolicy::Decode,
+                EncodedSlashes::Reject => EncodedSlashPolicy::Reject,
+            };
+        }
+    }
     
+    // Site-wide variables, evaluated before routing
+    config.variables = server.variables.iter()
+        .map(compile_handler)
+        .collect::<CompileResult<Vec<_>>>()?;
+
     // Routes
     if let Some(routes) = &server.routes {
         for arm in &routes.inner.arms {
@@ -163,6 +289,28 @@ fn compile_server(server: &ServerBlock) -> CompileResult<ServerConfig> {
 fn compile_log(log: &LogBlock) -> CompileResult<LogConfig> {
     let output = match &log.output {
         LogOutput::File(path) => CoreLogOutput::File(path.clone()),
+        LogOutput::RollingFile { path, rotation } => {
+            let defaults = pingclair_core::config::LogRotation::default();
+            CoreLogOutput::RollingFile {
+                path: path.clone(),
+                rotation: pingclair_core::config::LogRotation {
+                    // An explicit interval alone rotates by time only
+                    max_size: rotation.max_size.or(if rotation.interval_secs.is_some() { None } else { defaults.max_size }),
+                    interval_secs: rotation.interval_secs,
+                    keep: rotation.keep.unwrap_or(defaults.keep),
+                    compress: !rotation.uncompressed,
+                },
+            }
+        }
+        LogOutput::Syslog { address, facility, tag } => {
+            let defaults = pingclair_core::config::SyslogConfig::default();
+            CoreLogOutput::Syslog(pingclair_core::config::SyslogConfig {
+                address: address.clone().unwrap_or(defaults.address),
+                facility: facility.unwrap_or(defaults.facility),
+                tag: tag.clone().unwrap_or(defaults.tag),
+            })
+        }
+        LogOutput::Net(address) => CoreLogOutput::Net(address.clone()),
         LogOutput::Stdout => CoreLogOutput::Stdout,
         LogOutput::Stderr => CoreLogOutput::Stderr,
     };
@@ -170,34 +318,80 @@ fn compile_log(log: &LogBlock) -> CompileResult<LogConfig> {
     let format = match log.format.format_type {
         LogFormatType::Json => CoreLogFormat::Json,
         LogFormatType::Text => CoreLogFormat::Text,
+        LogFormatType::Common => CoreLogFormat::Common,
+        LogFormatType::Combined => CoreLogFormat::Combined,
+        LogFormatType::Template => CoreLogFormat::Template(log.format.template.clone().unwrap_or_default()),
     };
     
     Ok(LogConfig {
         output,
         format,
-        level: None, // Use global level
+        level: log.level.map(|level| log_level_name(level).to_string()), // None: use global level
+        filter: compile_log_filter(log),
     })
 }
 
-fn find_path_pattern(matcher: &Matcher, matchers: &HashMap<String, Matcher>) -> Option<String> {
-    match matcher {
-        Matcher::Path(pm) => pm.patterns.first().cloned(),
-        Matcher::Named(name) => matchers.get(name).and_then(|m| find_path_pattern(m, matchers)),
-        Matcher::And(left, right) | Matcher::Or(left, right) => {
-            find_path_pattern(left, matchers).or_else(|| find_path_pattern(right, matchers))
-        }
-        _ => None,
+fn compile_log_filter(log: &LogBlock) -> pingclair_core::config::LogFilterConfig {
+    use pingclair_core::config::{
+        LogFieldAction as CoreAction, LogFieldFilter, LogFilterConfig, LogParamAction as CoreParamAction,
+        LogParamFilter, LogSampleConfig,
+    };
+
+    let params = |params: &[(String, LogParamAction)]| -> Vec<LogParamFilter> {
+        params.iter()
+            .map(|(name, action)| LogParamFilter {
+                name: name.clone(),
+                action: match action {
+                    LogParamAction::Delete => CoreParamAction::Delete,
+                    LogParamAction::Replace(value) => CoreParamAction::Replace(value.clone()),
+                    LogParamAction::Hash => CoreParamAction::Hash,
+                },
+            })
+            .collect()
+    };
+
+    let fields = log.format.filter.iter()
+        .flat_map(|filter| &filter.fields)
+        .map(|(field, action)| LogFieldFilter {
+            field: field.clone(),
+            action: match action {
+                LogFieldAction::Delete => CoreAction::Delete,
+                LogFieldAction::Replace(value) => CoreAction::Replace(value.clone()),
+                LogFieldAction::Hash => CoreAction::Hash,
+                LogFieldAction::IpMask { ipv4, ipv6 } => CoreAction::IpMask { ipv4: *ipv4, ipv6: *ipv6 },
+                LogFieldAction::Query(list) => CoreAction::Query(params(list)),
+                LogFieldAction::Cookie(list) => CoreAction::Cookie(params(list)),
+            },
+        })
+        .collect();
+
+    LogFilterConfig {
+        fields,
+        skip_paths: log.skip.clone(),
+        sample: log.sample.iter()
+            .map(|rule| LogSampleConfig { ratio: rule.ratio, paths: rule.paths.clone() })
+            .collect(),
+    }
+}
+
+fn log_level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Trace => "trace",
+        LogLevel::Debug => "debug",
+        LogLevel::Info => "info",
+        LogLevel::Warn => "warn",
+        LogLevel::Error => "error",
     }
 }
 
 fn compile_route_arm(arm: &RouteArm, matchers: &HashMap<String, Matcher>) -> CompileResult<RouteConfig> {
     // Compile matcher to path pattern
     let path = arm.matcher.as_ref()
-        .and_then(|m| find_path_pattern(m, matchers))
+        .and_then(|m| m.path_pattern(matchers))
         .unwrap_or_else(|| "/*".to_string());
     
     // Compile matcher conditions
-    let matcher = arm.matcher.as_ref().map(|m| compile_matcher(m, matchers));
+    let matcher = arm.matcher.as_ref().map(|m| compile_matcher(m, matchers)).transpose()?;
     
     // Compile handler
     let handler = compile_handler(&arm.handler)?;
@@ -207,14 +401,17 @@ fn compile_route_arm(arm: &RouteArm, matchers: &HashMap<String, Matcher>) -> Com
         handler,
         methods: None,
         matcher,
+        internal: arm.internal,
+        priority: arm.priority,
+        source_order: arm.source_order,
     })
 }
 
-fn compile_matcher(matcher: &Matcher, matchers: &HashMap<String, Matcher>) -> CoreMatcher {
-    match matcher {
+fn compile_matcher(matcher: &Matcher, matchers: &HashMap<String, Matcher>) -> CompileResult<CoreMatcher> {
+    Ok(match matcher {
         Matcher::Named(name) => {
             if let Some(m) = matchers.get(name) {
-                compile_matcher(m, matchers)
+                compile_matcher(m, matchers)?
             } else {
                 // Fallback or error? CoreMatcher doesn't have a "None" that's safe here 
                 // but we can use an empty And or similar if needed. 
@@ -228,17 +425,16 @@ fn compile_matcher(matcher: &Matcher, matchers: &HashMap<String, Matcher>) -> Co
             }
         }
         Matcher::Header(hm) => {
-            let condition = match &hm.condition {
-                HeaderCondition::Exists => MatcherCondition::Exists,
-                HeaderCondition::Equals(v) => MatcherCondition::Equals(v.clone()),
-                HeaderCondition::Contains(v) => MatcherCondition::Contains(v.clone()),
-                HeaderCondition::StartsWith(v) => MatcherCondition::StartsWith(v.clone()),
-                HeaderCondition::EndsWith(v) => MatcherCondition::EndsWith(v.clone()),
-                HeaderCondition::Regex(v) => MatcherCondition::Regex(v.clone()),
-            };
             CoreMatcher::Header {
                 name: hm.name.clone(),
-                condition,
+                condition: compile_header_condition(&hm.condition)?,
+            }
+        }
+        Matcher::HeaderRegexp(hm) => {
+            CoreMatcher::HeaderRegexp {
+                name: hm.name.clone().unwrap_or_else(|| "header_regexp".to_string()),
+                field: hm.field.clone(),
+                pattern: compile_regex(&hm.pattern)?,
             }
         }
         Matcher::Method(methods) => {
@@ -247,40 +443,164 @@ fn compile_matcher(matcher: &Matcher, matchers: &HashMap<String, Matcher>) -> Co
             }
         }
         Matcher::Query(qm) => {
-            let condition = match &qm.condition {
-                HeaderCondition::Exists => MatcherCondition::Exists,
-                HeaderCondition::Equals(v) => MatcherCondition::Equals(v.clone()),
-                _ => MatcherCondition::Exists,
-            };
             CoreMatcher::Query {
                 name: qm.name.clone(),
-                condition,
+                condition: compile_header_condition(&qm.condition)?,
+            }
+        }
+        Matcher::Cookie(cm) => {
+            CoreMatcher::Cookie {
+                name: cm.name.clone(),
+                condition: compile_header_condition(&cm.condition)?,
+            }
+        }
+        Matcher::PathRegexp(pm) => {
+            CoreMatcher::PathRegexp {
+                name: pm.name.clone().unwrap_or_else(|| "path_regexp".to_string()),
+                pattern: compile_regex(&pm.pattern)?,
             }
         }
         Matcher::Host(hosts) => {
             CoreMatcher::Host(hosts.clone())
         }
+        Matcher::HostRegexp(hm) => {
+            CoreMatcher::HostRegexp {
+                name: hm.name.clone().unwrap_or_else(|| "host_regexp".to_string()),
+                pattern: compile_regex(&hm.pattern)?,
+            }
+        }
         Matcher::RemoteIp(ips) => {
-            CoreMatcher::RemoteIp(ips.clone())
+            CoreMatcher::RemoteIp(compile_ip_ranges(ips)?)
+        }
+        Matcher::ClientIp(ips) => {
+            CoreMatcher::ClientIp(compile_ip_ranges(ips)?)
         }
         Matcher::Protocol(protocols) => {
             CoreMatcher::Protocol(protocols.clone())
         }
+        Matcher::Expression(expression) => {
+            CoreMatcher::Expression { expression: expression.clone() }
+        }
+        Matcher::Vars(vm) => {
+            CoreMatcher::Vars {
+                key: compile_template(&vm.key)?,
+                values: vm.values.clone(),
+            }
+        }
         Matcher::And(left, right) => {
             CoreMatcher::And(
-                Box::new(compile_matcher(left, matchers)),
-                Box::new(compile_matcher(right, matchers)),
+                Box::new(compile_matcher(left, matchers)?),
+                Box::new(compile_matcher(right, matchers)?),
             )
         }
         Matcher::Or(left, right) => {
             CoreMatcher::Or(
-                Box::new(compile_matcher(left, matchers)),
-                Box::new(compile_matcher(right, matchers)),
+                Box::new(compile_matcher(left, matchers)?),
+                Box::new(compile_matcher(right, matchers)?),
             )
         }
         Matcher::Not(inner) => {
-            CoreMatcher::Not(Box::new(compile_matcher(inner, matchers)))
+            CoreMatcher::Not(Box::new(compile_matcher(inner, matchers)?))
         }
+    })
+}
+
+fn compile_header_condition(condition: &HeaderCondition) -> CompileResult<MatcherCondition> {
+    Ok(match condition {
+        HeaderCondition::Exists => MatcherCondition::Exists,
+        HeaderCondition::Equals(v) => MatcherCondition::Equals(v.clone()),
+        HeaderCondition::Contains(v) => MatcherCondition::Contains(v.clone()),
+        HeaderCondition::StartsWith(v) => MatcherCondition::StartsWith(v.clone()),
+        HeaderCondition::EndsWith(v) => MatcherCondition::EndsWith(v.clone()),
+        HeaderCondition::Regex(v) => MatcherCondition::Regex(compile_regex(v)?),
+    })
+}
+
+/// Compile a matcher regex now so a typo fails the load, not every request
+fn compile_regex(pattern: &str) -> CompileResult<ConfigRegex> {
+    ConfigRegex::parse(pattern).map_err(|e| CompileError::InvalidRegex {
+        pattern: pattern.to_string(),
+        message: e.to_string(),
+    })
+}
+
+fn compile_response_handler(block: &ResponseHandlerBlock) -> CompileResult<ResponseHandlerConfig> {
+    let matcher = block.matcher.as_ref().map(|m| -> CompileResult<_> {
+        Ok(CoreResponseMatcher {
+            status: m.status.clone(),
+            headers: m.headers.iter()
+                .map(|hm| Ok((hm.name.clone(), compile_header_condition(&hm.condition)?)))
+                .collect::<CompileResult<_>>()?,
+        })
+    }).transpose()?;
+
+    let handlers = block.handlers.iter()
+        .map(compile_handler)
+        .collect::<CompileResult<Vec<_>>>()?;
+
+    Ok(ResponseHandlerConfig {
+        matcher,
+        handlers,
+        copy_headers: block.copy_headers.clone(),
+    })
+}
+
+/// Check `remote_ip` / `client_ip` entries; a bad one would silently match nothing
+fn compile_ip_ranges(entries: &[String]) -> CompileResult<Vec<String>> {
+    match entries.iter().find(|entry| parse_ip_ranges(entry).is_none()) {
+        Some(entry) => Err(CompileError::InvalidRoute {
+            message: format!("invalid IP/CIDR {:?} in matcher", entry),
+        }),
+        None => Ok(entries.to_vec()),
+    }
+}
+
+/// Parse a configuration string into a placeholder template
+fn compile_template(source: &str) -> CompileResult<Template> {
+    Template::parse(source).map_err(|e| CompileError::InvalidTemplate {
+        template: source.to_string(),
+        message: e.to_string(),
+    })
+}
+
+fn compile_template_map(values: &HashMap<String, String>) -> CompileResult<HashMap<String, Template>> {
+    values.iter()
+        .map(|(key, value)| Ok((key.clone(), compile_template(value)?)))
+        .collect()
+}
+
+/// Template for a string or `${...}` variable expression; other expressions have none
+fn compile_expr_template(expr: &Expr) -> CompileResult<Option<Template>> {
+    match expr {
+        Expr::String(s) => compile_template(s).map(Some),
+        Expr::Variable(v) => compile_template(&variable_placeholder(&v.path)).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Map a `${req...}` variable path onto the placeholder registry.
+///
+/// `req.header["X"]`, `req.query["k"]`, `req.cookie["c"]` and the `req.*`
+/// request fields map to their placeholders; any other name is a custom `{vars.*}`.
+fn variable_placeholder(path: &str) -> String {
+    let indexed = |prefix: &str| {
+        path.strip_prefix(prefix)?
+            .strip_suffix("\"]")
+            .map(str::to_string)
+    };
+    if let Some(name) = indexed("req.header[\"") {
+        return format!("{{header.{}}}", name);
+    }
+    if let Some(key) = indexed("req.query[\"") {
+        return format!("{{query.{}}}", key);
+    }
+    if let Some(name) = indexed("req.cookie[\"") {
+        return format!("{{cookie.{}}}", name);
+    }
+    match path {
+        "req.host" | "req.path" | "req.method" | "req.query" | "req.uri" | "req.scheme"
+        | "req.remote_ip" | "req.client_ip" => format!("{{{}}}", &path[4..]),
+        _ => format!("{{vars.{}}}", path),
     }
 }
 
@@ -292,10 +612,14 @@ fn compile_handler(handler: &Handler) -> CompileResult<HandlerConfig> {
                 load_balance: LoadBalanceConfig::default(),
                 health_check: None,
                 headers_up: HashMap::new(),
+                headers_up_remove: proxy.header_up_remove.clone(),
+                forwarded_headers: Default::default(),
                 headers_down: HashMap::new(),
                 flush_interval: None,
                 read_timeout: None,
                 write_timeout: None,
+                handle_response: Vec::new(),
+                proxy_protocol: None,
             };
             
             // Flush interval
@@ -306,20 +630,39 @@ fn compile_handler(handler: &Handler) -> CompileResult<HandlerConfig> {
                 });
             }
             
-            // Header up
+            // Header up / down
             for (key, value) in &proxy.header_up {
-                let value_str = match value {
-                    Expr::String(s) => s.clone(),
-                    Expr::Variable(v) => format!("${{{}}}", v.path),
-                    _ => continue,
-                };
-                config.headers_up.insert(key.clone(), value_str);
+                if let Some(template) = compile_expr_template(value)? {
+                    config.headers_up.insert(key.clone(), template);
+                }
+            }
+            for (key, value) in &proxy.header_down {
+                if let Some(template) = compile_expr_template(value)? {
+                    config.headers_down.insert(key.clone(), template);
+                }
             }
             
+            // Standard forwarding headers
+            if let Some(forwarded) = &proxy.forwarded_headers {
+                config.forwarded_headers = ForwardedHeadersConfig {
+                    x_forwarded: forwarded.x_forwarded,
+                    forwarded: forwarded.rfc7239,
+                };
+            }
+
             // Transport
             if let Some(transport) = &proxy.transport {
                 config.read_timeout = transport.read_timeout.map(|ms| ms as i64);
                 config.write_timeout = transport.write_timeout.map(|ms| ms as i64);
+                config.proxy_protocol = transport.proxy_protocol.map(|v| match v {
+                    2 => ProxyProtocolVersion::V2,
+                    _ => ProxyProtocolVersion::V1,
+                });
+            }
+
+            // Upstream response handlers
+            for block in &proxy.handle_response {
+                config.handle_response.push(compile_response_handler(block)?);
             }
             
             Ok(HandlerConfig::ReverseProxy(config))
@@ -328,25 +671,25 @@ fn compile_handler(handler: &Handler) -> CompileResult<HandlerConfig> {
         Handler::Respond(resp) => {
             Ok(HandlerConfig::Respond {
                 status: resp.status,
-                body: resp.body.as_ref().and_then(|e| match e {
-                    Expr::String(s) => Some(s.clone()),
-                    _ => None,
-                }),
-                headers: resp.headers.clone(),
+                body: match &resp.body {
+                    Some(body) => compile_expr_template(body)?,
+                    None => None,
+                },
+                headers: compile_template_map(&resp.headers)?,
             })
         }
         
         Handler::Redirect(redir) => {
             Ok(HandlerConfig::Redirect {
-                to: redir.to.clone(),
+                to: compile_template(&redir.to)?,
                 code: redir.code,
             })
         }
         
         Handler::Headers(headers) => {
             Ok(HandlerConfig::Headers {
-                set: headers.set.clone(),
-                add: headers.add.clone(),
+                set: compile_template_map(&headers.set)?,
+                add: compile_template_map(&headers.add)?,
                 remove: headers.remove.clone(),
             })
         }
@@ -376,6 +719,44 @@ fn compile_handler(handler: &Handler) -> CompileResult<HandlerConfig> {
             Ok(HandlerConfig::Handle(compiled))
         }
 
+        Handler::Templates(templates) => {
+            let mime_types = if templates.mime_types.is_empty() {
+                vec!["text/html".to_string(), "text/plain".to_string(), "text/markdown".to_string()]
+            } else {
+                templates.mime_types.clone()
+            };
+            Ok(HandlerConfig::Templates {
+                root: templates.root.clone(),
+                mime_types,
+                between: templates.between.clone(),
+            })
+        }
+
+        Handler::Map(map) => {
+            let compile_values = |values: &[String]| {
+                values.iter().map(|v| compile_template(v)).collect::<CompileResult<Vec<_>>>()
+            };
+            let entries = map.entries.iter()
+                .map(|(key, values)| {
+                    let key = MapKey::parse(key).map_err(|e| CompileError::InvalidMapKey {
+                        key: key.clone(),
+                        message: e.to_string(),
+                    })?;
+                    Ok(CoreMapEntry { key, values: compile_values(values)? })
+                })
+                .collect::<CompileResult<Vec<_>>>()?;
+            Ok(HandlerConfig::Map(CoreMapConfig {
+                source: compile_template(&map.source)?,
+                destinations: map.destinations.clone(),
+                entries,
+                defaults: compile_values(&map.defaults)?,
+            }))
+        }
+
+        Handler::Vars(vars) => {
+            Ok(HandlerConfig::Vars { vars: compile_template_map(vars)? })
+        }
+
         Handler::Plugin { name, args } => {
             let args_str = args.iter().map(|e| match e {
                 Expr::String(s) => s.clone(),
@@ -445,4 +826,563 @@ mod tests {
             panic!("Expected And matcher, got {:?}", route.matcher);
         }
     }
+
+    #[test]
+    fn test_compile_handle_response() {
+        let ast = crate::parser::compile(r#"
+            example.com {
+                listen :8080
+                reverse_proxy localhost:3000 {
+                    @error status 5xx
+                    handle_response @error {
+                        copy_response_headers X-Request-Id
+                        respond "Upstream unavailable" 503
+                    }
+                }
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let HandlerConfig::ReverseProxy(proxy) = &config.servers[0].routes[0].handler else {
+            panic!("Expected ReverseProxy handler");
+        };
+        assert_eq!(proxy.handle_response.len(), 1);
+
+        let block = &proxy.handle_response[0];
+        assert_eq!(block.matcher.as_ref().unwrap().status, vec!["5xx".to_string()]);
+        assert_eq!(block.copy_headers, vec!["X-Request-Id".to_string()]);
+        assert!(matches!(block.handlers[0], HandlerConfig::Respond { status: 503, .. }));
+    }
+
+    #[test]
+    fn test_compile_templates() {
+        let ast = crate::parser::compile(r#"
+            example.com {
+                listen :8080
+                templates {
+                    mime text/html
+                    between <% %>
+                }
+                file_server /srv/www
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let HandlerConfig::Pipeline(handlers) = &config.servers[0].routes[0].handler else {
+            panic!("Expected Pipeline handler");
+        };
+        let HandlerConfig::Templates { mime_types, between, root } = &handlers[0] else {
+            panic!("Expected Templates handler, got {:?}", handlers[0]);
+        };
+        assert_eq!(mime_types, &vec!["text/html".to_string()]);
+        assert_eq!(between, &Some(("<%".to_string(), "%>".to_string())));
+        assert!(root.is_none());
+    }
+
+    #[test]
+    fn test_compile_logging() {
+        let ast = crate::parser::compile(r#"
+            {
+                log {
+                    output file /var/log/pingclair/app.log
+                    format json
+                    level DEBUG
+                }
+            }
+            example.com {
+                listen :8080
+                log {
+                    output file /var/log/pingclair/access.log
+                    level ERROR
+                }
+                respond "ok"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        assert_eq!(config.logging.level, "debug");
+        assert_eq!(config.logging.format, "json");
+        assert_eq!(config.logging.file.as_deref(), Some("/var/log/pingclair/app.log"));
+
+        let log = config.servers[0].log.as_ref().unwrap();
+        assert!(matches!(&log.output, CoreLogOutput::File(path) if path == "/var/log/pingclair/access.log"));
+        assert_eq!(log.level.as_deref(), Some("error"));
+    }
+
+    #[test]
+    fn test_compile_log_formats() {
+        let ast = crate::parser::compile(r#"
+            a.example.com {
+                log {
+                    format combined
+                }
+                respond "a"
+            }
+            b.example.com {
+                log {
+                    format template "$remote_addr {http.request.header.X-Tenant} $status"
+                }
+                respond "b"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let log = config.servers[0].log.as_ref().unwrap();
+        assert!(matches!(log.format, CoreLogFormat::Combined));
+        let log = config.servers[1].log.as_ref().unwrap();
+        assert!(matches!(&log.format, CoreLogFormat::Template(t) if t == "$remote_addr {http.request.header.X-Tenant} $status"));
+    }
+
+    #[test]
+    fn test_compile_log_filters() {
+        use pingclair_core::config::{LogFieldAction as CoreAction, LogParamAction as CoreParamAction};
+
+        let ast = crate::parser::compile(r#"
+            example.com {
+                log {
+                    format filter {
+                        wrap combined
+                        fields {
+                            request>headers>Authorization delete
+                            request>headers>Cookie cookie {
+                                replace session
+                            }
+                            request>uri query {
+                                hash token
+                            }
+                            request>remote_ip ip_mask {
+                                ipv4 24
+                                ipv6 48
+                            }
+                        }
+                    }
+                    skip /health /ready*
+                    sample 0.25 /api/*
+                }
+                respond "ok"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let log = config.servers[0].log.as_ref().unwrap();
+        assert!(matches!(log.format, CoreLogFormat::Combined));
+
+        let fields = &log.filter.fields;
+        assert_eq!(fields.len(), 4);
+        assert_eq!(fields[0].field, "request>headers>Authorization");
+        assert!(matches!(fields[0].action, CoreAction::Delete));
+        assert!(matches!(&fields[1].action, CoreAction::Cookie(p) if p[0].name == "session"
+            && matches!(&p[0].action, CoreParamAction::Replace(v) if v == "REDACTED")));
+        assert!(matches!(&fields[2].action, CoreAction::Query(p) if matches!(p[0].action, CoreParamAction::Hash)));
+        assert!(matches!(fields[3].action, CoreAction::IpMask { ipv4: 24, ipv6: 48 }));
+
+        assert_eq!(log.filter.skip_paths, vec!["/health", "/ready*"]);
+        assert_eq!(log.filter.sample[0].ratio, 0.25);
+        assert_eq!(log.filter.sample[0].paths, vec!["/api/*"]);
+    }
+
+    #[test]
+    fn test_compile_log_outputs() {
+        use pingclair_core::config::LogRotation;
+
+        let ast = crate::parser::compile(r#"
+            a.example.com {
+                log {
+                    output file /var/log/a.log {
+                        roll_size 10MiB
+                        roll_keep 3
+                        roll_uncompressed
+                    }
+                }
+                respond "a"
+            }
+            b.example.com {
+                log {
+                    output file /var/log/b.log {
+                        roll_interval 24h
+                    }
+                }
+                respond "b"
+            }
+            c.example.com {
+                log {
+                    output syslog udp/10.0.0.1:514 {
+                        facility local3
+                        tag edge
+                    }
+                }
+                respond "c"
+            }
+            d.example.com {
+                log {
+                    output net unix//run/vector.sock
+                }
+                respond "d"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let output = |i: usize| config.servers[i].log.as_ref().unwrap().output.clone();
+
+        assert!(matches!(output(0), CoreLogOutput::RollingFile { path, rotation }
+            if path == "/var/log/a.log"
+                && rotation == LogRotation { max_size: Some(10 << 20), interval_secs: None, keep: 3, compress: false }));
+        assert!(matches!(output(1), CoreLogOutput::RollingFile { rotation, .. }
+            if rotation.max_size.is_none() && rotation.interval_secs == Some(86_400) && rotation.compress));
+        assert!(matches!(output(2), CoreLogOutput::Syslog(syslog)
+            if syslog.address == "udp/10.0.0.1:514" && syslog.facility == 19 && syslog.tag == "edge"));
+        assert!(matches!(output(3), CoreLogOutput::Net(address) if address == "unix//run/vector.sock"));
+    }
+
+    #[test]
+    fn test_compile_metrics() {
+        let ast = crate::parser::compile(r#"
+            {
+                metrics {
+                    per_route
+                    buckets 0.05 0.5 5
+                }
+            }
+            example.com {
+                respond "ok"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        assert!(config.global.metrics.per_route);
+        assert_eq!(config.global.metrics.buckets, vec![0.05, 0.5, 5.0]);
+        assert!(config.global.metrics.upstream_buckets.is_empty());
+    }
+
+    #[test]
+    fn test_compile_path_normalization() {
+        let ast = crate::parser::compile(r#"
+            example.com {
+                normalize_path {
+                    merge_slashes off
+                    encoded_slashes reject
+                    forward_original
+                }
+                respond "ok"
+            }
+            other.com {
+                normalize_path off
+                respond "ok"
+            }
+            plain.com {
+                respond "ok"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let normalization = &config.servers[0].path_normalization;
+        assert!(normalization.enabled && normalization.resolve_dots && normalization.forward_original);
+        assert!(!normalization.merge_slashes);
+        assert_eq!(normalization.encoded_slashes, EncodedSlashPolicy::Reject);
+        assert!(!config.servers[1].path_normalization.enabled);
+        assert_eq!(config.servers[2].path_normalization, Default::default());
+    }
+
+    #[test]
+    fn test_compile_statsd() {
+        use pingclair_core::config::StatsdFlavor;
+
+        let ast = crate::parser::compile(r#"
+            {
+                statsd udp.example.net:8125 {
+                    protocol dogstatsd
+                    tag env prod
+                }
+            }
+            example.com {
+                respond "ok"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let statsd = config.global.statsd.unwrap();
+        assert_eq!(statsd.address, "udp.example.net:8125");
+        assert_eq!(statsd.flavor, StatsdFlavor::Dogstatsd);
+        assert_eq!(statsd.prefix, "pingclair");
+        assert_eq!(statsd.tags.get("env").map(String::as_str), Some("prod"));
+        assert_eq!(statsd.max_packet_size, 1432);
+    }
+
+    #[test]
+    fn test_compile_listener_schemes() {
+        let ast = crate::parser::compile(r#"
+            http://example.com:8080 https://example.com:9443 {
+                respond "ok"
+            }
+            example.org:443 {
+                respond "ok"
+            }
+            example.net:8080 {
+                respond "ok"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let mixed = &config.servers[0];
+        assert_eq!(mixed.tls_listen, vec!["example.com:9443"]);
+        assert!(mixed.is_tls_listener("example.com:9443"));
+        assert!(!mixed.is_tls_listener("example.com:8080"));
+        assert_eq!(config.servers[1].tls_listen, vec!["example.org:443"]);
+        assert!(config.servers[1].is_tls_listener("example.org:443"));
+
+        // Plain listeners stay plain, whatever address they are asked about
+        let plain = &config.servers[2];
+        assert!(plain.tls.is_none() && plain.tls_listen.is_empty());
+        assert!(!plain.is_tls_listener("example.net:8080"));
+        assert!(!plain.is_tls_listener("example.net:443"));
+    }
+
+    #[test]
+    fn test_compile_proxy_protocol() {
+        let ast = crate::parser::compile(r#"
+            {
+                servers :443 {
+                    listener_wrappers {
+                        proxy_protocol {
+                            allow 10.0.0.0/8
+                        }
+                    }
+                }
+            }
+            example.com {
+                reverse_proxy 127.0.0.1:8080 {
+                    transport http {
+                        proxy_protocol v1
+                    }
+                }
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let listener = &config.global.listeners[0];
+        assert!(listener.applies_to("0.0.0.0:443"));
+        assert!(!listener.applies_to("0.0.0.0:8443"));
+        let pp = listener.proxy_protocol.as_ref().unwrap();
+        assert_eq!(pp.timeout_ms, 5000);
+        assert_eq!(pp.allow, vec!["10.0.0.0/8"]);
+
+        let HandlerConfig::ReverseProxy(proxy) = &config.servers[0].routes[0].handler else {
+            panic!("Expected ReverseProxy handler");
+        };
+        assert_eq!(proxy.proxy_protocol, Some(ProxyProtocolVersion::V1));
+        assert_eq!(proxy.forwarded_headers, ForwardedHeadersConfig::default());
+    }
+
+    #[test]
+    fn test_compile_forwarded_headers() {
+        let ast = crate::parser::compile(r#"
+            example.com {
+                reverse_proxy 127.0.0.1:8080 {
+                    forwarded_headers off
+                    header_up -X-Request-Id
+                }
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let HandlerConfig::ReverseProxy(proxy) = &config.servers[0].routes[0].handler else {
+            panic!("Expected ReverseProxy handler");
+        };
+        assert_eq!(proxy.forwarded_headers, ForwardedHeadersConfig { x_forwarded: false, forwarded: false });
+        assert_eq!(proxy.headers_up_remove, vec!["X-Request-Id"]);
+        assert!(proxy.headers_up.is_empty());
+    }
+
+    #[test]
+    fn test_compile_placeholder_templates() {
+        let ast = crate::parser::compile(r#"
+            example.com {
+                reverse_proxy 127.0.0.1:8080 {
+                    header_up X-Real-IP {client_ip}
+                    header_down X-Upstream {http.reverse_proxy.upstream.hostport}
+                }
+            }
+            bad.com {
+                respond "{time.now.format.%Q}"
+            }
+        "#).unwrap();
+
+        let err = compile_ast(&ast).unwrap_err();
+        assert!(matches!(err, CompileError::InvalidTemplate { .. }));
+
+        let ast = crate::parser::compile(r#"
+            example.com {
+                reverse_proxy 127.0.0.1:8080 {
+                    header_up X-Real-IP {client_ip}
+                    header_down X-Upstream {http.reverse_proxy.upstream.hostport}
+                }
+            }
+        "#).unwrap();
+        let config = compile_ast(&ast).unwrap();
+        let HandlerConfig::ReverseProxy(proxy) = &config.servers[0].routes[0].handler else {
+            panic!("Expected ReverseProxy handler");
+        };
+        assert_eq!(proxy.headers_up["X-Real-IP"].as_str(), "{client_ip}");
+        assert!(!proxy.headers_down["X-Upstream"].is_literal());
+
+        assert_eq!(variable_placeholder(r#"req.header["X-Tenant"]"#), "{header.X-Tenant}");
+        assert_eq!(variable_placeholder("req.remote_ip"), "{remote_ip}");
+        assert_eq!(variable_placeholder("tenant"), "{vars.tenant}");
+    }
+
+    #[test]
+    fn test_compile_request_matchers() {
+        let ast = crate::parser::compile(r#"
+            example.com {
+                @assets {
+                    path_regexp \.(?P<ext>css|js)$
+                    query v=* lang=en lang=fr*
+                    cookie theme dark
+                    not header_regexp User-Agent (?i)bot
+                    method GET HEAD
+                }
+                respond @assets "asset"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        fn flatten(matcher: &CoreMatcher, out: &mut Vec<CoreMatcher>) {
+            match matcher {
+                CoreMatcher::And(left, right) => {
+                    flatten(left, out);
+                    flatten(right, out);
+                }
+                other => out.push(other.clone()),
+            }
+        }
+        let mut parts = Vec::new();
+        flatten(config.servers[0].routes[0].matcher.as_ref().unwrap(), &mut parts);
+
+        assert!(matches!(&parts[0], CoreMatcher::PathRegexp { name, pattern } if name == "assets" && pattern.as_str() == r"\.(?P<ext>css|js)$"));
+        assert!(matches!(&parts[1], CoreMatcher::Query { name, condition: MatcherCondition::Exists } if name == "v"));
+        let CoreMatcher::Or(en, fr) = &parts[2] else { panic!("Expected ORed lang values, got {:?}", parts[2]) };
+        assert!(matches!(en.as_ref(), CoreMatcher::Query { condition: MatcherCondition::Equals(v), .. } if v == "en"));
+        assert!(matches!(fr.as_ref(), CoreMatcher::Query { condition: MatcherCondition::StartsWith(v), .. } if v == "fr"));
+        assert!(matches!(&parts[3], CoreMatcher::Cookie { name, condition: MatcherCondition::Equals(v) } if name == "theme" && v == "dark"));
+        let CoreMatcher::Not(inner) = &parts[4] else { panic!("Expected Not, got {:?}", parts[4]) };
+        assert!(matches!(inner.as_ref(), CoreMatcher::HeaderRegexp { name, field, .. } if name == "assets" && field == "User-Agent"));
+        assert!(matches!(&parts[5], CoreMatcher::Method { methods } if methods == &["GET", "HEAD"]));
+    }
+
+    #[test]
+    fn test_compile_ip_matchers() {
+        let ast = crate::parser::compile(r#"
+            example.com {
+                @office {
+                    remote_ip forwarded 10.0.0.0/8 2001:db8::/32
+                    client_ip private_ranges
+                }
+                respond @office "hi"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let Some(CoreMatcher::And(remote, client)) = &config.servers[0].routes[0].matcher else {
+            panic!("Expected combined matcher, got {:?}", config.servers[0].routes[0].matcher);
+        };
+        assert!(matches!(remote.as_ref(), CoreMatcher::RemoteIp(ips) if ips == &["10.0.0.0/8", "2001:db8::/32"]));
+        assert!(matches!(client.as_ref(), CoreMatcher::ClientIp(ips) if ips == &["private_ranges"]));
+
+        // The Caddyfile adapter rejects this too; the compiler must not rely on it
+        let mut ast = ast;
+        let outside = Matcher::Not(Box::new(Matcher::RemoteIp(vec!["10.0.0.0/33".to_string()])));
+        ast.servers[0].inner.matchers.insert("@office".to_string(), outside);
+        assert!(matches!(compile_ast(&ast), Err(CompileError::InvalidRoute { message }) if message.contains("10.0.0.0/33")));
+    }
+
+    #[test]
+    fn test_compile_host_matchers() {
+        let ast = crate::parser::compile(r#"
+            *.example.com {
+                @tenant {
+                    host *.example.com
+                    host_regexp ^(?P<name>[a-z]+)\.example\.com$
+                }
+                respond @tenant "tenant"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        let Some(CoreMatcher::And(host, regexp)) = &config.servers[0].routes[0].matcher else {
+            panic!("Expected combined matcher, got {:?}", config.servers[0].routes[0].matcher);
+        };
+        assert!(matches!(host.as_ref(), CoreMatcher::Host(hosts) if hosts == &["*.example.com"]));
+        assert!(matches!(regexp.as_ref(), CoreMatcher::HostRegexp { name, pattern } if name == "tenant" && pattern.as_str() == r"^(?P<name>[a-z]+)\.example\.com$"));
+    }
+
+    #[test]
+    fn test_compile_map_and_vars() {
+        use pingclair_core::server::{apply_vars, PlaceholderContext};
+
+        let ast = crate::parser::compile(r#"
+            example.com {
+                map {host} {backend} {tier} {
+                    example.com         main      gold
+                    ~^(?P<app>[a-z]+)\.apps\.  app-$app  -
+                    *.example.com       {host}    silver
+                    default             fallback  bronze
+                }
+                vars upstream "{vars.backend}:8080"
+                @gold vars tier gold
+                respond @gold "gold"
+            }
+        "#).unwrap();
+        let server = &compile_ast(&ast).unwrap().servers[0];
+        assert_eq!(server.variables.len(), 2);
+
+        let run = |host: &str| {
+            let mut vars = HashMap::new();
+            apply_vars(&server.variables, &PlaceholderContext { host, ..Default::default() }, &mut vars);
+            vars
+        };
+        let vars = run("example.com");
+        assert_eq!(vars["upstream"], "main:8080");
+        assert_eq!(vars["tier"], "gold");
+        let vars = run("shop.apps.example.com");
+        assert_eq!(vars["backend"], "app-shop");
+        assert!(!vars.contains_key("tier"));
+        assert_eq!(run("www.example.com")["backend"], "www.example.com");
+        assert_eq!(run("other.org")["tier"], "bronze");
+
+        assert!(matches!(
+            &server.routes[0].matcher,
+            Some(CoreMatcher::Vars { key, values }) if key.as_str() == "{vars.tier}" && values == &["gold"]
+        ));
+
+        let ast = crate::parser::compile("example.com {\n map {path} {a} {\n ~( x\n }\n}").unwrap();
+        let err = compile_ast(&ast).unwrap_err();
+        assert!(err.to_string().contains("Invalid map key"), "{}", err);
+    }
+
+    #[test]
+    fn test_compile_expression_matcher() {
+        let ast = crate::parser::compile(r#"
+            example.com {
+                @json_post `{method} == "POST" && {header.Content-Type}.startsWith("application/json")`
+                respond @json_post "ok"
+            }
+        "#).unwrap();
+
+        let config = compile_ast(&ast).unwrap();
+        assert!(matches!(
+            &config.servers[0].routes[0].matcher,
+            Some(CoreMatcher::Expression { expression })
+                if expression == r#"{method} == "POST" && {header.Content-Type}.startsWith("application/json")"#
+        ));
+
+        // Invalid expressions fail at load time, pointing at the problem
+        let err = crate::parser::compile(r#"
+            example.com {
+                @bad expression `{method} == "POST" && {nope}`
+                respond @bad "no"
+            }
+        "#).unwrap_err();
+        assert!(err.to_string().contains("Unknown placeholder {nope}"), "{}", err);
+        assert!(err.to_string().contains("^^^^^^"), "{}", err);
+    }
 }
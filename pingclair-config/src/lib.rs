@@ -29,7 +29,6 @@ pub use parser::{
     parse, compile as parse_and_analyze, 
     Ast, ParseError, CompileError as AnalyzeError,
     Token, tokenize, LexError,
-    VariableResolver, ResolvedVariable,
     SemanticAnalyzer, SemanticError,
 };
 
@@ -55,9 +54,18 @@ pub fn compile_file(path: impl AsRef<Path>) -> Result<PingclairConfig, FullCompi
     let source = std::fs::read_to_string(path)
         .map_err(|e| FullCompileError::Io(e.to_string()))?;
         
-    if path.extension().map_or(false, |ext| ext == "json") {
-        serde_json::from_str(&source)
-            .map_err(|e| FullCompileError::Io(format!("JSON parse error: {}", e)))
+    if path.extension().is_some_and(|ext| ext == "json") {
+        let config: PingclairConfig = serde_json::from_str(&source)
+            .map_err(|e| FullCompileError::Io(format!("JSON parse error: {}", e)))?;
+        if let Some(statsd) = &config.global.statsd {
+            statsd.validate()
+                .map_err(|message| CompileError::InvalidGlobal { message })?;
+        }
+        for server in &config.servers {
+            server.validate()
+                .map_err(|message| CompileError::InvalidServer { message })?;
+        }
+        Ok(config)
     } else {
         compile(&source)
     }
@@ -86,6 +94,13 @@ pub fn compile_multiple_files(paths: &[impl AsRef<Path>]) -> Result<PingclairCon
         if config.global.auto_https != pingclair_core::config::AutoHttpsMode::On {
             final_config.global.auto_https = config.global.auto_https;
         }
+        final_config.global.trusted_proxies.extend(config.global.trusted_proxies);
+        if let Some(header) = config.global.request_id_header {
+            final_config.global.request_id_header = Some(header);
+        }
+        if let Some(tracing) = config.global.tracing {
+            final_config.global.tracing = Some(tracing);
+        }
         
         // Merge logging config (use the last one if multiple exist)
         if !config.logging.level.is_empty() {
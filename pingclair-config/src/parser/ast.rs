@@ -43,9 +43,62 @@ pub struct GlobalBlock {
     pub logging: Option<LoggingConfig>,
     pub email: Option<String>,
     pub auto_https: Option<AutoHttpsMode>,
+    pub trusted_proxies: Vec<String>,
+    pub client_ip_headers: Vec<String>,
+    pub request_id_header: Option<String>,
+    pub tracing: Option<TracingBlock>,
+    pub metrics: Option<MetricsBlock>,
+    pub statsd: Option<StatsdBlock>,
+    pub listeners: Vec<ListenerBlock>,
     pub directives: Vec<Directive>,
 }
 
+/// Per-listener options (`servers [<address>] { listener_wrappers { ... } }`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListenerBlock {
+    pub address: Option<String>,
+    pub proxy_protocol: Option<ProxyProtocolBlock>,
+}
+
+/// Listener `proxy_protocol` wrapper
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProxyProtocolBlock {
+    pub timeout_ms: Option<u64>,
+    pub allow: Vec<String>,
+}
+
+/// StatsD push exporter block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsdBlock {
+    pub address: Option<String>,
+    pub dogstatsd: bool,
+    pub prefix: Option<String>,
+    pub tags: Vec<(String, String)>,
+    pub flush_interval_ms: Option<u64>,
+    pub sample_rate: Option<f64>,
+    pub max_packet_size: Option<usize>,
+}
+
+/// Prometheus metrics block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsBlock {
+    pub per_route: bool,
+    pub buckets: Vec<f64>,
+    pub upstream_buckets: Vec<f64>,
+}
+
+/// OpenTelemetry span export block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TracingBlock {
+    pub endpoint: Option<String>,
+    pub grpc: bool,
+    pub sample_ratio: Option<f64>,
+    pub service_name: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub batch_size: Option<usize>,
+    pub flush_interval_ms: Option<u64>,
+}
+
 /// Auto-HTTPS modes
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AutoHttpsMode {
@@ -123,11 +176,51 @@ pub struct ServerBlock {
 
     /// Named matcher definitions
     pub matchers: HashMap<String, Matcher>,
+
+    /// Request path normalisation (`normalize_path`)
+    pub path_normalization: Option<PathNormalizationBlock>,
+
+    /// Site-wide `map` / `vars` handlers, run before routing
+    pub variables: Vec<Handler>,
     
     /// Other directives (including macro calls)
     pub directives: Vec<Directive>,
 }
 
+/// Request path normalisation block; unset options keep their defaults
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathNormalizationBlock {
+    pub enabled: bool,
+    pub decode_unreserved: Option<bool>,
+    pub resolve_dots: Option<bool>,
+    pub merge_slashes: Option<bool>,
+    pub encoded_slashes: Option<EncodedSlashes>,
+    pub reject_nul: Option<bool>,
+    pub forward_original: bool,
+}
+
+impl Default for PathNormalizationBlock {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            decode_unreserved: None,
+            resolve_dots: None,
+            merge_slashes: None,
+            encoded_slashes: None,
+            reject_nul: None,
+            forward_original: false,
+        }
+    }
+}
+
+/// Handling of `%2F` / `%5C` in request paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodedSlashes {
+    Keep,
+    Decode,
+    Reject,
+}
+
 /// Listen address
 #[derive(Debug, Clone)]
 pub struct ListenAddr {
@@ -160,6 +253,7 @@ pub enum CompressionAlgo {
 pub struct LoggingConfig {
     pub level: LogLevel,
     pub format: LogFormat,
+    pub output: LogOutput,
 }
 
 /// Log level
@@ -178,21 +272,51 @@ pub enum LogLevel {
 pub struct LogBlock {
     pub output: LogOutput,
     pub format: LogFormat,
+    /// Per-server level override
+    pub level: Option<LogLevel>,
+    /// Request paths that are not logged
+    pub skip: Vec<String>,
+    /// Sampling rules
+    pub sample: Vec<LogSample>,
 }
 
 /// Log output destination
 #[derive(Debug, Clone)]
 pub enum LogOutput {
     File(String),
+    /// File with `roll_*` options
+    RollingFile {
+        path: String,
+        rotation: LogRotation,
+    },
+    /// `output syslog [address] { facility ...; tag ... }`
+    Syslog {
+        address: Option<String>,
+        facility: Option<u8>,
+        tag: Option<String>,
+    },
+    /// `output net <address>`
+    Net(String),
     Stdout,
     Stderr,
 }
 
+/// Log file rotation (`roll_size`, `roll_interval`, `roll_keep`, `roll_uncompressed`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogRotation {
+    pub max_size: Option<u64>,
+    pub interval_secs: Option<u64>,
+    pub keep: Option<usize>,
+    pub uncompressed: bool,
+}
+
 /// Log format
 #[derive(Debug, Clone, Default)]
 pub struct LogFormat {
     pub format_type: LogFormatType,
     pub filter: Option<LogFilter>,
+    /// Line template for `LogFormatType::Template`
+    pub template: Option<String>,
 }
 
 /// Log format type
@@ -201,12 +325,42 @@ pub enum LogFormatType {
     #[default]
     Text,
     Json,
+    Common,
+    Combined,
+    Template,
 }
 
-/// Log filter
+/// Log filter (`format filter { fields { ... } }`)
 #[derive(Debug, Clone, Default)]
 pub struct LogFilter {
-    pub exclude: Vec<String>,
+    /// `(field path, action)` in declaration order
+    pub fields: Vec<(String, LogFieldAction)>,
+}
+
+/// Rewrite applied to a log field
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogFieldAction {
+    Delete,
+    Replace(String),
+    Hash,
+    IpMask { ipv4: u8, ipv6: u8 },
+    Query(Vec<(String, LogParamAction)>),
+    Cookie(Vec<(String, LogParamAction)>),
+}
+
+/// Rewrite applied to a query parameter or cookie
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogParamAction {
+    Delete,
+    Replace(String),
+    Hash,
+}
+
+/// Sampling rule (`sample <ratio> [paths...]`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSample {
+    pub ratio: f64,
+    pub paths: Vec<String>,
 }
 
 // ============================================================
@@ -227,6 +381,16 @@ pub struct RouteArm {
     
     /// Handler for this route
     pub handler: Handler,
+
+    /// Only reachable through internal redirects (`internal` directive)
+    pub internal: bool,
+
+    /// Route precedence (`priority` directive); higher is tried first
+    pub priority: i32,
+
+    /// Explicit `route` block: keeps its source position instead of being
+    /// sorted by path specificity
+    pub source_order: bool,
 }
 
 /// Route matcher
@@ -238,20 +402,41 @@ pub enum Matcher {
     /// Match by header: header("X-Foo", exists) or header("X-Foo", "value")
     Header(HeaderMatcher),
     
+    /// Match by header regex: header_regexp(tenant, "X-Tenant", "^(\w+)$")
+    HeaderRegexp(HeaderRegexpMatcher),
+
     /// Match by method: method(GET | POST)
     Method(Vec<HttpMethod>),
     
     /// Match by query parameter
     Query(QueryMatcher),
+
+    /// Match by cookie: cookie("session", "abc*")
+    Cookie(CookieMatcher),
+
+    /// Match by path regex: path_regexp(static, "\.css$")
+    PathRegexp(PathRegexpMatcher),
     
     /// Match by host: host("example.com" | "*.example.com")
     Host(Vec<String>),
+
+    /// Match by host regex: host_regexp(tenant, "^(\w+)\.example\.com$")
+    HostRegexp(HostRegexpMatcher),
     
-    /// Match by remote IP: remote_ip("1.2.3.4" | "192.168.1.0/24")
+    /// Match by socket peer: remote_ip("1.2.3.4" | "192.168.1.0/24" | "private_ranges")
     RemoteIp(Vec<String>),
+
+    /// Match by client IP resolved through trusted proxies: client_ip("10.0.0.0/8")
+    ClientIp(Vec<String>),
     
     /// Match by protocol: protocol("https" | "http")
     Protocol(Vec<String>),
+
+    /// Match by boolean expression: expression(`{method} == "POST"`)
+    Expression(String),
+
+    /// Match by variable: vars({vars.tier}, "gold" | "silver")
+    Vars(VarsMatcher),
     
     /// Combined matchers with AND
     And(Box<Matcher>, Box<Matcher>),
@@ -291,6 +476,15 @@ pub enum HeaderCondition {
     Regex(String),
 }
 
+/// Header regex matcher
+#[derive(Debug, Clone)]
+pub struct HeaderRegexpMatcher {
+    /// Capture name prefix (defaults to the named matcher's name)
+    pub name: Option<String>,
+    pub field: String,
+    pub pattern: String,
+}
+
 /// Query parameter matcher
 #[derive(Debug, Clone)]
 pub struct QueryMatcher {
@@ -298,6 +492,37 @@ pub struct QueryMatcher {
     pub condition: HeaderCondition,  // Reuse same conditions
 }
 
+/// Cookie matcher
+#[derive(Debug, Clone)]
+pub struct CookieMatcher {
+    pub name: String,
+    pub condition: HeaderCondition,
+}
+
+/// Path regex matcher
+#[derive(Debug, Clone)]
+pub struct PathRegexpMatcher {
+    /// Capture name prefix (defaults to the named matcher's name)
+    pub name: Option<String>,
+    pub pattern: String,
+}
+
+/// Host regex matcher
+#[derive(Debug, Clone)]
+pub struct HostRegexpMatcher {
+    /// Capture name prefix (defaults to the named matcher's name)
+    pub name: Option<String>,
+    pub pattern: String,
+}
+
+/// Variable matcher
+#[derive(Debug, Clone)]
+pub struct VarsMatcher {
+    /// Placeholder to compare, e.g. `{vars.tier}`
+    pub key: String,
+    pub values: Vec<String>,
+}
+
 /// HTTP methods
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpMethod {
@@ -338,6 +563,15 @@ pub enum Handler {
     /// Exclusive routing group
     Handle(Vec<Handler>),
 
+    /// Server-side templates
+    Templates(TemplatesConfig),
+
+    /// Derive variables from a placeholder
+    Map(MapConfig),
+
+    /// Set variables
+    Vars(HashMap<String, String>),
+
     /// Plugin invocation
     Plugin { name: String, args: Vec<Expr> },
 }
@@ -353,12 +587,53 @@ pub struct ProxyConfig {
     
     /// Headers to add to upstream request
     pub header_up: HashMap<String, Expr>,
+
+    /// Headers to remove from upstream request (`header_up -Name`)
+    pub header_up_remove: Vec<String>,
+
+    /// Headers to set on the downstream response (`header_down`)
+    pub header_down: HashMap<String, Expr>,
+
+    /// Standard forwarding headers (`forwarded_headers`); defaults when None
+    pub forwarded_headers: Option<ForwardedHeaders>,
     
     /// Transport configuration
     pub transport: Option<TransportConfig>,
     
     /// Macro calls (use xxx!())
     pub macro_calls: Vec<MacroCall>,
+
+    /// Upstream response handlers (`handle_response`)
+    pub handle_response: Vec<ResponseHandlerBlock>,
+}
+
+/// Forwarding header families sent upstream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ForwardedHeaders {
+    /// `X-Forwarded-For` / `-Proto` / `-Host`
+    pub x_forwarded: bool,
+    /// RFC 7239 `Forwarded`
+    pub rfc7239: bool,
+}
+
+/// Upstream response handler block (`handle_response [@matcher] { ... }`)
+#[derive(Debug, Clone, Default)]
+pub struct ResponseHandlerBlock {
+    /// Response matcher (None matches every response)
+    pub matcher: Option<ResponseMatcher>,
+    /// Handlers producing the replacement response
+    pub handlers: Vec<Handler>,
+    /// Upstream headers copied onto the replacement (`copy_response_headers`)
+    pub copy_headers: Vec<String>,
+}
+
+/// Upstream response matcher (`@name status 5xx`)
+#[derive(Debug, Clone, Default)]
+pub struct ResponseMatcher {
+    /// Status codes or classes (`404`, `5xx`)
+    pub status: Vec<String>,
+    /// Response header conditions
+    pub headers: Vec<HeaderMatcher>,
 }
 
 /// Flush interval
@@ -373,6 +648,7 @@ pub enum FlushInterval {
 pub struct TransportConfig {
     pub read_timeout: Option<u64>,   // milliseconds
     pub write_timeout: Option<u64>,  // milliseconds
+    pub proxy_protocol: Option<u8>,  // PROXY header version (1 or 2)
 }
 
 /// Static response configuration
@@ -407,6 +683,26 @@ pub struct FileServerConfig {
     pub compress: bool,
 }
 
+/// Templates configuration (`templates { mime ...; between ...; root ... }`)
+#[derive(Debug, Clone, Default)]
+pub struct TemplatesConfig {
+    pub root: Option<String>,
+    /// Empty = default MIME types
+    pub mime_types: Vec<String>,
+    pub between: Option<(String, String)>,
+}
+
+/// Map configuration (`map <source> <destinations...> { <key> <values...> }`)
+#[derive(Debug, Clone)]
+pub struct MapConfig {
+    pub source: String,
+    /// Variable names, without braces or `vars.` prefix
+    pub destinations: Vec<String>,
+    /// `(key, values)` in source order
+    pub entries: Vec<(String, Vec<String>)>,
+    pub defaults: Vec<String>,
+}
+
 // ============================================================
 // Expressions
 // ============================================================
@@ -493,19 +789,44 @@ impl ServerBlock {
             log: None,
             routes: None,
             matchers: HashMap::new(),
+            path_normalization: None,
+            variables: Vec::new(),
             directives: Vec::new(),
         }
     }
 }
 
+impl Matcher {
+    /// Route path for this matcher: the one `path` pattern every match must satisfy.
+    ///
+    /// Alternatives (several patterns, `Or`) leave the route a catch-all and let
+    /// the matcher filter, so no alternative is lost.
+    ///
+    /// - Parameter matchers: Named matchers that `Named` references resolve against.
+    pub fn path_pattern(&self, matchers: &HashMap<String, Matcher>) -> Option<String> {
+        match self {
+            Matcher::Path(pm) if pm.patterns.len() == 1 => pm.patterns.first().cloned(),
+            Matcher::Named(name) => matchers.get(name).and_then(|m| m.path_pattern(matchers)),
+            Matcher::And(left, right) => {
+                left.path_pattern(matchers).or_else(|| right.path_pattern(matchers))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl ProxyConfig {
     pub fn new(upstreams: Vec<String>) -> Self {
         Self {
             upstreams,
             flush_interval: None,
             header_up: HashMap::new(),
+            header_up_remove: Vec::new(),
+            header_down: HashMap::new(),
+            forwarded_headers: None,
             transport: None,
             macro_calls: Vec::new(),
+            handle_response: Vec::new(),
         }
     }
 }
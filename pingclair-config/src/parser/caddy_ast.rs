@@ -16,7 +16,7 @@ pub struct Directive {
     pub block: Option<Block>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Block {
     pub directives: Vec<Directive>,
 }
@@ -6,7 +6,7 @@
 //! - Whitespace sensitive (Newlines invoke statement termination)
 //! - Directives are just Words
 //! - { } for blocks
-//! - "..." for quoted strings
+//! - "..." for quoted strings, `...` for raw strings
 //! - # for comments (skipped)
 //! - {placeholder} for Caddy-style runtime placeholders
 
@@ -45,7 +45,7 @@ pub enum Token {
     BlockClose,
     /// Newline
     Newline,
-    /// Quoted string: "..."
+    /// Quoted string: "..." or raw `...`
     QuotedString(String),
     /// Environment variable: {$VAR}
     EnvVar(String),
@@ -78,6 +78,8 @@ impl fmt::Display for Token {
 pub enum LexError {
     #[error("Unexpected character at position {position}")]
     UnexpectedChar { position: usize },
+    #[error("Unterminated raw string at {}..{}", .span.start, .span.end)]
+    UnterminatedString { span: Location },
 }
 
 /// Lexer result type
@@ -182,6 +184,23 @@ pub fn tokenize(source: &str) -> LexResult {
             continue;
         }
 
+        // ── Backtick strings: `...` (raw, may contain `"`) ───────────
+        if c == '`' {
+            let start = pos;
+            pos += 1;
+            let content_start = pos;
+            while pos < chars.len() && chars[pos] != '`' {
+                pos += 1;
+            }
+            if pos == chars.len() {
+                return Err(LexError::UnterminatedString { span: Location { start, end: pos } });
+            }
+            let s: String = chars[content_start..pos].iter().collect();
+            pos += 1; // skip closing backtick
+            tokens.push(Spanned::new(Token::QuotedString(s), Location { start, end: pos }));
+            continue;
+        }
+
         // ── Braces ────────────────────────────────────────────────────
         // 🛑 SAFETY: We must check for {$VAR} and {placeholder} BEFORE
         // emitting a bare BlockOpen. The disambiguation rule:
@@ -288,6 +307,16 @@ mod tests {
         assert_eq!(tokens[1].value, Token::Word("127.0.0.1".to_string()));
     }
 
+    #[test]
+    fn test_backtick_string() {
+        let tokens = tokenize(r#"@post `{method} == "POST"`"#).unwrap();
+        assert_eq!(tokens[1].value, Token::QuotedString(r#"{method} == "POST""#.to_string()));
+        assert_eq!(tokens[1].span, Location { start: 6, end: 26 });
+
+        let err = tokenize("@post `{method} == \"POST\"\n").unwrap_err();
+        assert!(matches!(err, LexError::UnterminatedString { span: Location { start: 6, end: 26 } }));
+    }
+
     #[test]
     fn test_block() {
         let tokens = tokenize("example.com {\n  root *\n}").unwrap();
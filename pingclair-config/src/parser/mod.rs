@@ -5,14 +5,13 @@
 pub mod ast;
 pub mod caddy_ast;
 pub mod lexer;
+#[allow(clippy::module_inception)]
 pub mod parser;
-pub mod variables;
 pub mod semantic;
 
 pub use ast::*;
 pub use lexer::{tokenize, Token, LexError, Spanned, Location};
 pub use parser::{parse, ParseError, Parser};
-pub use variables::{VariableResolver, ResolvedVariable};
 pub use semantic::{SemanticAnalyzer, SemanticError};
 
 pub use crate::adapter::caddyfile::{adapt, AdapterError};
@@ -102,7 +102,8 @@ impl<'a> Parser<'a> {
         let name_token = self.consume().ok_or(ParseError::UnexpectedEof { expected: "directive name".to_string() })?;
         
         let name = match &name_token.value {
-            Token::Word(s) | Token::QuotedString(s) | Token::EnvVar(s) => s.clone(),
+            Token::Word(s) | Token::QuotedString(s) => s.clone(),
+            Token::EnvVar(s) => expand_env(s),
             other => return Err(ParseError::UnexpectedToken { 
                 token: format!("{}", other), 
                 location: name_token.span, 
@@ -113,12 +114,8 @@ impl<'a> Parser<'a> {
         let mut args = Vec::new();
         let mut block = None;
 
-        // 2. Loop args
-        loop {
-            let token = match self.peek() {
-                Some(t) => t,
-                None => break, // EOF ends directive
-            };
+        // 2. Loop args (EOF ends the directive)
+        while let Some(token) = self.peek() {
 
             match &token.value {
                 Token::Newline => {
@@ -156,8 +153,9 @@ impl<'a> Parser<'a> {
                     self.consume();
                 },
                 Token::EnvVar(s) => {
-                    // Preserve ${VAR} syntax for later expansion
-                    args.push(format!("${{{}}}", s));
+                    // {$VAR} / {$VAR:default} is substituted now, like Caddy;
+                    // {env.VAR} is the per-request placeholder
+                    args.push(expand_env(s));
                     self.consume();
                 },
                 Token::Placeholder(s) => {
@@ -246,6 +244,14 @@ pub fn parse(source: &str) -> Result<Vec<Directive>, ParseError> {
     parser.parse_config()
 }
 
+/// Substitute a `{$VAR}` / `{$VAR:default}` token from the environment.
+///
+/// - Returns: The variable's value, the default when it is unset, or an empty string.
+fn expand_env(token: &str) -> String {
+    let (name, default) = token.split_once(':').unwrap_or((token, ""));
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -290,4 +296,14 @@ mod tests {
         assert_eq!(d.name, "route");
         assert_eq!(d.block.as_ref().unwrap().directives.len(), 1);
     }
+
+    #[test]
+    fn test_env_vars_substituted_at_parse_time() {
+        let path = std::env::var("PATH").unwrap_or_default();
+        let source = "root {$PATH}\nbind {$PINGCLAIR_TEST_UNSET:127.0.0.1}\nheader X-Path {env.PATH}";
+        let directives = parse(source).unwrap();
+        assert_eq!(directives[0].args[0], path);
+        assert_eq!(directives[1].args[0], "127.0.0.1");
+        assert_eq!(directives[2].args[1], "{env.PATH}");
+    }
 }
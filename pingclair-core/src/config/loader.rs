@@ -26,14 +26,27 @@ impl ConfigLoader {
 
     /// Parse JSON configuration
     pub fn from_json(content: &str) -> Result<PingclairConfig> {
-        serde_json::from_str(content)
-            .map_err(|e| Error::Config(format!("Invalid JSON: {}", e)))
+        let config = serde_json::from_str(content)
+            .map_err(|e| Error::Config(format!("Invalid JSON: {}", e)))?;
+        Self::validate(config)
     }
 
     /// Parse TOML configuration
     pub fn from_toml(content: &str) -> Result<PingclairConfig> {
-        toml::from_str(content)
-            .map_err(|e| Error::Config(format!("Invalid TOML: {}", e)))
+        let config = toml::from_str(content)
+            .map_err(|e| Error::Config(format!("Invalid TOML: {}", e)))?;
+        Self::validate(config)
+    }
+
+    /// Reject servers that deserialize but cannot run
+    fn validate(config: PingclairConfig) -> Result<PingclairConfig> {
+        if let Some(statsd) = &config.global.statsd {
+            statsd.validate().map_err(Error::Config)?;
+        }
+        for server in &config.servers {
+            server.validate().map_err(Error::Config)?;
+        }
+        Ok(config)
     }
 
     /// Parse Pingclairfile configuration
@@ -53,4 +66,14 @@ mod tests {
         let config = ConfigLoader::from_json(json).unwrap();
         assert!(config.servers.is_empty());
     }
+
+    #[test]
+    fn test_statsd_sample_rate_range() {
+        let json = |rate: &str| format!(r#"{{"global": {{"statsd": {{"address": "127.0.0.1:8125", "sample_rate": {}}}}}, "servers": []}}"#, rate);
+        assert!(ConfigLoader::from_json(&json("0.25")).is_ok());
+        assert!(ConfigLoader::from_json(&json("1")).is_ok());
+        assert!(ConfigLoader::from_json(&json("0")).is_err());
+        assert!(ConfigLoader::from_json(&json("1.5")).is_err());
+        assert!(ConfigLoader::from_json(&json("-0.5")).is_err());
+    }
 }
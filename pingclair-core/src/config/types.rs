@@ -2,8 +2,9 @@
 //!
 //! These types represent the runtime configuration for Pingclair.
 
+use crate::server::{MapKey, ConfigRegex, Template};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// Root configuration for Pingclair
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -42,6 +43,260 @@ pub struct GlobalConfig {
     /// Blocked IP addresses (CIDR supported)
     #[serde(default)]
     pub blocked_ips: Vec<String>,
+
+    /// Proxies trusted to supply client request metadata (CIDR supported)
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+
+    /// Headers carrying the real client IP from trusted proxies, in priority
+    /// order (defaults to `X-Forwarded-For`)
+    #[serde(default)]
+    pub client_ip_headers: Vec<String>,
+
+    /// Request ID header name (defaults to `X-Request-Id`)
+    #[serde(default)]
+    pub request_id_header: Option<String>,
+
+    /// OpenTelemetry span export (disabled when absent)
+    #[serde(default)]
+    pub tracing: Option<TracingConfig>,
+
+    /// Prometheus metrics options
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
+    /// StatsD / DogStatsD push export (disabled when absent)
+    #[serde(default)]
+    pub statsd: Option<StatsdConfig>,
+
+    /// Per-listener options (`servers <address> { ... }`)
+    #[serde(default)]
+    pub listeners: Vec<ListenerOptions>,
+}
+
+/// Options applied to matching listeners
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ListenerOptions {
+    /// Listener address (`:443`, `0.0.0.0:443`); applies to every listener when absent
+    #[serde(default)]
+    pub address: Option<String>,
+
+    /// Accept PROXY protocol headers (disabled when absent)
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
+}
+
+impl ListenerOptions {
+    /// Whether these options apply to a listener address.
+    ///
+    /// - Parameter listen: The listener address, e.g. `0.0.0.0:443`.
+    /// - Returns: `true` for an exact match, a matching `:port`, or when no address is set.
+    pub fn applies_to(&self, listen: &str) -> bool {
+        match self.address.as_deref() {
+            None => true,
+            Some(addr) if addr.starts_with(':') => listen.ends_with(addr),
+            Some(addr) => addr == listen,
+        }
+    }
+}
+
+/// Listener-side PROXY protocol (v1 and v2 are both detected)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProxyProtocolConfig {
+    /// Time allowed for the header to arrive (milliseconds)
+    #[serde(default = "default_proxy_protocol_timeout_ms")]
+    pub timeout_ms: u64,
+
+    /// Sources allowed to send a header (CIDR supported, falls back to `trusted_proxies`)
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl Default for ProxyProtocolConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: default_proxy_protocol_timeout_ms(),
+            allow: Vec::new(),
+        }
+    }
+}
+
+fn default_proxy_protocol_timeout_ms() -> u64 {
+    5000
+}
+
+/// PROXY protocol header version sent to upstreams
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+/// Prometheus metrics configuration
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct MetricsConfig {
+    /// Request duration histogram buckets in seconds (Prometheus defaults when empty)
+    #[serde(default)]
+    pub buckets: Vec<f64>,
+
+    /// Upstream connect/TTFB/duration histogram buckets in seconds (falls back to `buckets`)
+    #[serde(default)]
+    pub upstream_buckets: Vec<f64>,
+
+    /// Label request metrics with the matched route path.
+    /// Off by default to keep label cardinality bounded.
+    #[serde(default)]
+    pub per_route: bool,
+}
+
+/// StatsD push exporter configuration
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatsdConfig {
+    /// UDP endpoint, e.g. `127.0.0.1:8125`
+    pub address: String,
+
+    /// Line protocol flavor
+    #[serde(default)]
+    pub flavor: StatsdFlavor,
+
+    /// Metric name prefix
+    #[serde(default = "default_service_name")]
+    pub prefix: String,
+
+    /// Constant tags added to every metric (DogStatsD only)
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+
+    /// Push interval (milliseconds)
+    #[serde(default = "default_statsd_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+
+    /// Sampling rate for timings (0.0 - 1.0); counters are aggregated and always exact
+    #[serde(default = "default_sample_ratio")]
+    pub sample_rate: f64,
+
+    /// Maximum UDP payload size in bytes
+    #[serde(default = "default_statsd_max_packet_size")]
+    pub max_packet_size: usize,
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:8125".to_string(),
+            flavor: StatsdFlavor::default(),
+            prefix: default_service_name(),
+            tags: BTreeMap::new(),
+            flush_interval_ms: default_statsd_flush_interval_ms(),
+            sample_rate: default_sample_ratio(),
+            max_packet_size: default_statsd_max_packet_size(),
+        }
+    }
+}
+
+impl StatsdConfig {
+    /// Check what serde cannot, like the range of `sample_rate`.
+    ///
+    /// - Returns: Why the configuration is invalid.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.sample_rate > 0.0 && self.sample_rate <= 1.0) {
+            return Err(format!("statsd sample_rate must be in (0, 1], got {}", self.sample_rate));
+        }
+        Ok(())
+    }
+}
+
+/// StatsD line protocol flavor
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsdFlavor {
+    /// Plain StatsD: labels are appended to the metric name
+    #[default]
+    Statsd,
+    /// DogStatsD: labels are sent as `|#key:value` tags
+    Dogstatsd,
+}
+
+fn default_statsd_flush_interval_ms() -> u64 {
+    1000
+}
+
+/// Fits a 1500-byte Ethernet MTU after IP and UDP headers
+fn default_statsd_max_packet_size() -> usize {
+    1432
+}
+
+/// OpenTelemetry (OTLP) span export configuration
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TracingConfig {
+    /// Collector endpoint, e.g. `http://localhost:4318/v1/traces` or `http://localhost:4317`
+    pub endpoint: String,
+
+    /// Export protocol
+    #[serde(default)]
+    pub protocol: OtlpProtocol,
+
+    /// Head sampling ratio for new traces (0.0 - 1.0)
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+
+    /// `service.name` resource attribute
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+
+    /// Extra request headers sent to the collector (e.g. auth tokens)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Maximum spans per export request
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+
+    /// Maximum delay before a partial batch is exported (milliseconds)
+    #[serde(default = "default_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:4318/v1/traces".to_string(),
+            protocol: OtlpProtocol::default(),
+            sample_ratio: default_sample_ratio(),
+            service_name: default_service_name(),
+            headers: HashMap::new(),
+            batch_size: default_batch_size(),
+            flush_interval_ms: default_flush_interval_ms(),
+        }
+    }
+}
+
+/// OTLP transport
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OtlpProtocol {
+    /// OTLP/HTTP with JSON encoding
+    #[default]
+    Http,
+    /// OTLP/gRPC (protobuf over HTTP/2 cleartext)
+    Grpc,
+}
+
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+fn default_service_name() -> String {
+    "pingclair".to_string()
+}
+
+fn default_batch_size() -> usize {
+    512
+}
+
+fn default_flush_interval_ms() -> u64 {
+    5000
 }
 
 /// Auto-HTTPS modes
@@ -68,6 +323,11 @@ pub struct ServerConfig {
     #[serde(default)]
     pub tls: Option<TlsConfig>,
 
+    /// Listen addresses that terminate TLS (`https://` site addresses).
+    /// Other listeners serve plain HTTP even when `tls` is configured.
+    #[serde(default)]
+    pub tls_listen: Vec<String>,
+
     /// Routes for this server
     #[serde(default)]
     pub routes: Vec<RouteConfig>,
@@ -83,12 +343,100 @@ pub struct ServerConfig {
     /// Security headers configuration
     #[serde(default)]
     pub security: SecurityConfig,
+
+    /// Request path normalisation applied before routing
+    #[serde(default)]
+    pub path_normalization: PathNormalizationConfig,
+
+    /// Site-wide `map` / `vars` handlers, run before routing so matchers see their variables
+    #[serde(default)]
+    pub variables: Vec<HandlerConfig>,
+}
+
+impl ServerConfig {
+    /// Whether a listener of this server terminates TLS.
+    ///
+    /// - Parameter addr: A listen address of this server.
+    /// - Returns: `true` when `tls` is configured and `addr` is in `tls_listen`.
+    pub fn is_tls_listener(&self, addr: &str) -> bool {
+        self.tls.is_some() && self.tls_listen.iter().any(|listen| listen == addr)
+    }
+
+    /// Check what serde cannot, like the IP ranges of route matchers.
+    ///
+    /// - Returns: Why the configuration is invalid.
+    pub fn validate(&self) -> Result<(), String> {
+        crate::server::Router::validate(&self.routes)
+    }
 }
 
 fn default_body_limit() -> u64 {
     1024 * 1024 // 1MB
 }
 
+/// Request path normalisation.
+///
+/// Matchers, handlers and the file server all see the normalised path, so
+/// `/api/../admin` or `/%2e%2e/admin` cannot slip past a path matcher that a
+/// backend would later resolve differently.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PathNormalizationConfig {
+    /// Normalise at all (off leaves paths exactly as received)
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Decode percent-escapes of unreserved characters (`A-Z a-z 0-9 - . _ ~`)
+    #[serde(default = "default_true")]
+    pub decode_unreserved: bool,
+
+    /// Resolve `.` and `..` segments (RFC 3986 §5.2.4)
+    #[serde(default = "default_true")]
+    pub resolve_dots: bool,
+
+    /// Collapse runs of `/` into one
+    #[serde(default = "default_true")]
+    pub merge_slashes: bool,
+
+    /// What to do with `%2F` / `%5C` inside a segment
+    #[serde(default)]
+    pub encoded_slashes: EncodedSlashPolicy,
+
+    /// Reject paths containing `%00`
+    #[serde(default = "default_true")]
+    pub reject_nul: bool,
+
+    /// Send the path upstream as received instead of the normalised one
+    #[serde(default)]
+    pub forward_original: bool,
+}
+
+impl Default for PathNormalizationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            decode_unreserved: true,
+            resolve_dots: true,
+            merge_slashes: true,
+            encoded_slashes: EncodedSlashPolicy::default(),
+            reject_nul: true,
+            forward_original: false,
+        }
+    }
+}
+
+/// Handling of encoded path separators (`%2F`, `%5C`)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EncodedSlashPolicy {
+    /// Leave them encoded: they stay part of the segment
+    #[default]
+    Keep,
+    /// Decode them into separators before dot-segment resolution
+    Decode,
+    /// Refuse the request
+    Reject,
+}
+
 /// TLS configuration
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TlsConfig {
@@ -126,11 +474,30 @@ pub struct RouteConfig {
     /// Matcher for this route
     #[serde(default)]
     pub matcher: Option<Matcher>,
+
+    /// Internal-only route: never matched for client requests, only as the
+    /// target of an internal redirect (`X-Accel-Redirect`)
+    #[serde(default)]
+    pub internal: bool,
+
+    /// Precedence over other routes; higher is tried first, before path
+    /// specificity (see `server::Router`)
+    #[serde(default)]
+    pub priority: i32,
+
+    /// Tried in source order instead of by path specificity (explicit
+    /// `route` blocks), ahead of sorted routes of the same priority
+    #[serde(default)]
+    pub source_order: bool,
 }
 
 /// Route matcher
+///
+/// Serialized externally tagged (`{"remote_ip": ["10.0.0.0/8"]}`): several
+/// variants share a shape, and an untagged `Not` would recurse forever on
+/// input that matches no variant.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
+#[serde(rename_all = "snake_case")]
 pub enum Matcher {
     /// Match by path
     Path {
@@ -142,27 +509,65 @@ pub enum Matcher {
         name: String,
         condition: MatcherCondition,
     },
+
+    /// Match a header against a regex; captures are exposed as `{name}.{group}`
+    HeaderRegexp {
+        name: String,
+        field: String,
+        pattern: ConfigRegex,
+    },
     
     /// Match by HTTP method
     Method {
         methods: Vec<String>,
     },
     
-    /// Match by query parameter
+    /// Match by query parameter (any value of a repeated key may match)
     Query {
         name: String,
         condition: MatcherCondition,
     },
 
-    /// Match by host
+    /// Match by cookie (any cookie with this name may match)
+    Cookie {
+        name: String,
+        condition: MatcherCondition,
+    },
+
+    /// Match the path against a regex; captures are exposed as `{name}.{group}`
+    PathRegexp {
+        name: String,
+        pattern: ConfigRegex,
+    },
+
+    /// Match by host; `*` matches one label (`*.example.com`)
     Host(Vec<String>),
+
+    /// Match the host against a regex; captures are exposed as `{name}.{group}`
+    HostRegexp {
+        name: String,
+        pattern: ConfigRegex,
+    },
     
-    /// Match by remote IP
+    /// Match the socket peer against IPs / CIDR blocks (`private_ranges` allowed)
     RemoteIp(Vec<String>),
+
+    /// Match the client IP resolved through trusted proxies against IPs / CIDR blocks
+    ClientIp(Vec<String>),
     
     /// Match by protocol
     Protocol(Vec<String>),
-    
+
+    /// Match a boolean expression over placeholders (see `server::Expression`)
+    Expression {
+        expression: String,
+    },
+
+    /// Match when `key` (usually `{vars.<name>}`) renders to one of `values`
+    Vars {
+        key: Template,
+        values: Vec<String>,
+    },
     
     /// AND combination
     And(Box<Matcher>, Box<Matcher>),
@@ -183,7 +588,7 @@ pub enum MatcherCondition {
     Contains(String),
     StartsWith(String),
     EndsWith(String),
-    Regex(String),
+    Regex(ConfigRegex),
 }
 
 /// Handler configuration
@@ -206,7 +611,8 @@ pub enum HandlerConfig {
 
     /// Redirect
     Redirect {
-        to: String,
+        /// Target URL (supports {placeholders})
+        to: Template,
         #[serde(default = "default_redirect_code")]
         code: u16,
     },
@@ -214,7 +620,7 @@ pub enum HandlerConfig {
     /// URI rewrite (internal - does not send redirect to client)
     /// Similar to Caddy's uri and rewrite directives
     Rewrite {
-        /// Strip prefix from path (e.g., "/api" removes "/api/users" -> "/users")
+        /// Strip prefix from path on a segment boundary (e.g., "/api" turns "/api/users" into "/users", leaves "/apiary")
         #[serde(default)]
         strip_prefix: Option<String>,
         /// Strip suffix from path
@@ -222,10 +628,10 @@ pub enum HandlerConfig {
         strip_suffix: Option<String>,
         /// Replace path entirely with this value (supports {placeholders})
         #[serde(default)]
-        replace: Option<String>,
-        /// Regex pattern to match
+        replace: Option<Template>,
+        /// Regex pattern to match (compiled when the configuration is loaded)
         #[serde(default)]
-        regex: Option<String>,
+        regex: Option<ConfigRegex>,
         /// Replacement string for regex (supports capture groups $1, $2, etc)
         #[serde(default)]
         regex_replace: Option<String>,
@@ -235,21 +641,30 @@ pub enum HandlerConfig {
     Respond {
         #[serde(default = "default_status_code")]
         status: u16,
-        body: Option<String>,
+        body: Option<Template>,
         #[serde(default)]
-        headers: HashMap<String, String>,
+        headers: HashMap<String, Template>,
     },
 
-    /// Headers modification
+    /// Headers modification (values support {placeholders})
     Headers {
         #[serde(default)]
-        set: HashMap<String, String>,
+        set: HashMap<String, Template>,
         #[serde(default)]
-        add: HashMap<String, String>,
+        add: HashMap<String, Template>,
         #[serde(default)]
         remove: Vec<String>,
     },
 
+    /// Derive variables from a placeholder (`{vars.<name>}`)
+    /// Similar to Caddy's map directive
+    Map(MapConfig),
+
+    /// Set variables (values support {placeholders})
+    Vars {
+        vars: HashMap<String, Template>,
+    },
+
     /// Pipeline of handlers
     Pipeline(Vec<HandlerConfig>),
 
@@ -335,6 +750,20 @@ pub enum HandlerConfig {
         fallback: Option<Box<HandlerConfig>>,
     },
 
+    /// Server-side templates — renders file server and upstream responses
+    /// Similar to Caddy's templates directive
+    Templates {
+        /// Site root that `include` resolves against (defaults to the file server root)
+        #[serde(default)]
+        root: Option<String>,
+        /// Response MIME types to render
+        #[serde(default = "default_template_mime_types")]
+        mime_types: Vec<String>,
+        /// Custom action delimiters (default `{{` and `}}`)
+        #[serde(default)]
+        between: Option<(String, String)>,
+    },
+
     /// Plugin invocation
     Plugin { name: String, args: Vec<String> },
 }
@@ -359,6 +788,10 @@ fn default_cors_max_age() -> u64 {
     86400 // 24 hours
 }
 
+fn default_template_mime_types() -> Vec<String> {
+    vec!["text/html".into(), "text/plain".into(), "text/markdown".into()]
+}
+
 fn default_status_code() -> u16 {
     200
 }
@@ -387,10 +820,33 @@ pub struct BasicAuthCredential {
     pub hashed: bool,
 }
 
+/// `map` handler: the first entry whose key matches `source` sets the destinations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MapConfig {
+    /// Input to match, e.g. `{host}`
+    pub source: Template,
+    /// Variable names the outputs are stored under, in order
+    pub destinations: Vec<String>,
+    /// Entries, tried in order
+    #[serde(default)]
+    pub entries: Vec<MapEntry>,
+    /// Outputs when no entry matches
+    #[serde(default)]
+    pub defaults: Vec<Template>,
+}
+
+/// A `map` entry: one output per destination, `-` leaves a destination unset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MapEntry {
+    /// Exact value, `*` wildcard, or `~` / `~*` regex (outputs may use `$1`, `$name`)
+    pub key: MapKey,
+    pub values: Vec<Template>,
+}
+
 /// Reverse proxy configuration
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ReverseProxyConfig {
-    /// Upstream URLs
+    /// Upstream URLs (`{placeholders}` such as `map` outputs are rendered per request)
     pub upstreams: Vec<String>,
 
     /// Load balancing configuration
@@ -401,13 +857,21 @@ pub struct ReverseProxyConfig {
     #[serde(default)]
     pub health_check: Option<HealthCheckConfig>,
 
-    /// Headers to add to upstream request
+    /// Headers to add to upstream request (values support {placeholders})
+    #[serde(default)]
+    pub headers_up: HashMap<String, Template>,
+
+    /// Headers to remove from upstream request (`header_up -Name`), applied last
+    #[serde(default)]
+    pub headers_up_remove: Vec<String>,
+
+    /// Standard forwarding headers sent upstream
     #[serde(default)]
-    pub headers_up: HashMap<String, String>,
+    pub forwarded_headers: ForwardedHeadersConfig,
 
-    /// Headers to add to downstream response
+    /// Headers to add to downstream response (values support {placeholders})
     #[serde(default)]
-    pub headers_down: HashMap<String, String>,
+    pub headers_down: HashMap<String, Template>,
 
     /// Flush interval in milliseconds (-1 for immediate)
     pub flush_interval: Option<i64>,
@@ -417,6 +881,64 @@ pub struct ReverseProxyConfig {
 
     /// Write timeout in milliseconds
     pub write_timeout: Option<i64>,
+
+    /// Upstream response interception (`handle_response`), first match wins
+    #[serde(default)]
+    pub handle_response: Vec<ResponseHandlerConfig>,
+
+    /// Send a PROXY protocol header on new upstream connections
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+}
+
+/// Standard forwarding headers added to upstream requests
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ForwardedHeadersConfig {
+    /// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`
+    #[serde(default = "default_true")]
+    pub x_forwarded: bool,
+
+    /// RFC 7239 `Forwarded`
+    #[serde(default)]
+    pub forwarded: bool,
+}
+
+impl Default for ForwardedHeadersConfig {
+    fn default() -> Self {
+        Self { x_forwarded: true, forwarded: false }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Runs handlers against an upstream response instead of passing it through
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ResponseHandlerConfig {
+    /// Response matcher (None matches every response)
+    #[serde(default)]
+    pub matcher: Option<ResponseMatcher>,
+
+    /// Handlers producing the replacement response
+    #[serde(default)]
+    pub handlers: Vec<HandlerConfig>,
+
+    /// Upstream response headers copied onto the replacement response
+    #[serde(default)]
+    pub copy_headers: Vec<String>,
+}
+
+/// Matches an upstream response by status code and headers
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ResponseMatcher {
+    /// Status codes or classes, e.g. `404`, `5xx`
+    #[serde(default)]
+    pub status: Vec<String>,
+
+    /// Response header conditions (all must match)
+    #[serde(default)]
+    pub headers: HashMap<String, MatcherCondition>,
 }
 
 /// Load balancing configuration
@@ -611,24 +1133,191 @@ pub struct LogConfig {
 
     /// Log level (overrides global)
     pub level: Option<String>,
+
+    /// Field redaction, skipped paths and sampling
+    #[serde(default)]
+    pub filter: LogFilterConfig,
 }
 
-/// Log output destination
+/// Access log filters, applied before entries reach the writer
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct LogFilterConfig {
+    /// Field filters keyed by Caddy field path (`request>headers>Authorization`)
+    #[serde(default)]
+    pub fields: Vec<LogFieldFilter>,
+
+    /// Request paths that are never logged (`/health`, `/status/*`)
+    #[serde(default)]
+    pub skip_paths: Vec<String>,
+
+    /// Sampling rules; the first rule matching the request path applies
+    #[serde(default)]
+    pub sample: Vec<LogSampleConfig>,
+}
+
+impl LogFilterConfig {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.skip_paths.is_empty() && self.sample.is_empty()
+    }
+}
+
+/// Filter applied to one log field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogFieldFilter {
+    /// Field path, `>`-separated (`request>remote_ip`)
+    pub field: String,
+    pub action: LogFieldAction,
+}
+
+/// How a log field is rewritten
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFieldAction {
+    /// Remove the field
+    Delete,
+    /// Replace the value with a fixed string
+    Replace(String),
+    /// Replace the value with the first 4 bytes of its SHA-256, hex encoded
+    Hash,
+    /// Keep only a prefix of IP addresses
+    IpMask { ipv4: u8, ipv6: u8 },
+    /// Filter individual query parameters of a URI
+    Query(Vec<LogParamFilter>),
+    /// Filter individual cookies of a `Cookie` header
+    Cookie(Vec<LogParamFilter>),
+}
+
+/// Filter applied to one query parameter or cookie
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogParamFilter {
+    pub name: String,
+    pub action: LogParamAction,
+}
+
+/// How a query parameter or cookie is rewritten
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogParamAction {
+    Delete,
+    Replace(String),
+    Hash,
+}
+
+/// Log only a fraction of the requests matching `paths`
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogSampleConfig {
+    /// Fraction of requests logged (0.0–1.0)
+    pub ratio: f64,
+    /// Request paths the rule applies to (all paths when empty)
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+/// Log output destination
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum LogOutput {
     File(String),
+    /// File rotated by size and/or time
+    RollingFile {
+        path: String,
+        rotation: LogRotation,
+    },
+    /// RFC 5424 syslog over UDP or a unix datagram socket
+    Syslog(SyslogConfig),
+    /// Newline-delimited lines over a stream socket (`tcp/host:port`, `unix//path`)
+    Net(String),
     Stdout,
     Stderr,
 }
 
+/// Rotation policy of a rolling log file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRotation {
+    /// Rotate once the file reaches this size (bytes)
+    #[serde(default)]
+    pub max_size: Option<u64>,
+
+    /// Rotate at every multiple of this interval (seconds, aligned to UTC)
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+
+    /// Rotated files kept (older ones are deleted)
+    #[serde(default = "default_roll_keep")]
+    pub keep: usize,
+
+    /// Gzip rotated files
+    #[serde(default = "default_bool_true")]
+    pub compress: bool,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_size: Some(100 * 1024 * 1024),
+            interval_secs: None,
+            keep: default_roll_keep(),
+            compress: true,
+        }
+    }
+}
+
+fn default_roll_keep() -> usize {
+    10
+}
+
+/// Syslog destination
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyslogConfig {
+    /// `udp/host:port`, `host:port` (UDP) or `unix//dev/log`
+    #[serde(default = "default_syslog_address")]
+    pub address: String,
+
+    /// Facility code (`local7` = 23)
+    #[serde(default = "default_syslog_facility")]
+    pub facility: u8,
+
+    /// APP-NAME field
+    #[serde(default = "default_syslog_tag")]
+    pub tag: String,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            address: default_syslog_address(),
+            facility: default_syslog_facility(),
+            tag: default_syslog_tag(),
+        }
+    }
+}
+
+fn default_syslog_address() -> String {
+    "unix//dev/log".to_string()
+}
+
+fn default_syslog_facility() -> u8 {
+    23
+}
+
+fn default_syslog_tag() -> String {
+    "pingclair".to_string()
+}
+
 /// Log format
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum LogFormat {
     #[default]
     Text,
+    /// Caddy-compatible `http.log.access` JSON
     Json,
+    /// NCSA Common Log Format
+    Common,
+    /// NCSA Combined Log Format (Common + referer and user agent)
+    Combined,
+    /// User-defined line of `{placeholders}` and nginx-style `$variables`
+    Template(String),
 }
 
 #[cfg(test)]
@@ -658,12 +1347,32 @@ mod tests {
             name: Some("example.com".to_string()),
             listen: vec!["127.0.0.1:8080".to_string()],
             tls: None,
+            tls_listen: vec![],
             routes: vec![],
             log: None,
             client_max_body_size: 1024 * 1024,
             security: Default::default(),
+            path_normalization: Default::default(),
+            variables: vec![],
         };
         assert_eq!(config.name, Some("example.com".to_string()));
+        assert!(!config.is_tls_listener("127.0.0.1:8080"));
+
+        let tls = ServerConfig {
+            listen: vec!["0.0.0.0:80".to_string(), "0.0.0.0:8443".to_string()],
+            tls: Some(TlsConfig::default()),
+            ..Default::default()
+        };
+        // `tls` alone does not turn every listener into a TLS one
+        assert!(!tls.is_tls_listener("0.0.0.0:80"));
+        assert!(!tls.is_tls_listener("0.0.0.0:8443"));
+
+        let per_listener = ServerConfig { tls_listen: vec!["0.0.0.0:8443".to_string()], ..tls };
+        assert!(per_listener.is_tls_listener("0.0.0.0:8443"));
+        assert!(!per_listener.is_tls_listener("0.0.0.0:80"));
+
+        let without_tls = ServerConfig { tls: None, ..per_listener };
+        assert!(!without_tls.is_tls_listener("0.0.0.0:8443"));
     }
 
     #[test]
@@ -32,6 +32,10 @@ pub enum Error {
     #[error("Plugin error: {0}")]
     Plugin(String),
 
+    /// Template rendering error
+    #[error("Template error: {0}")]
+    Template(String),
+
     /// Internal error
     #[error("Internal error: {0}")]
     Internal(String),
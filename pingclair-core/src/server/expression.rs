@@ -0,0 +1,735 @@
+//! Expression matcher language
+//!
+//! A small CEL-like boolean language over request placeholders, parsed and
+//! type-checked once per matcher:
+//!
+//! ```text
+//! {method} == "POST" && {header.Content-Type}.startsWith("application/json")
+//! {client_ip} in ["10.0.0.0/8", "private_ranges"] || time_between("22:00", "06:00")
+//! ```
+//!
+//! - literals: `"str"` / `'str'`, integers, `true` / `false`, lists (`in` only)
+//! - operators: `||`, `&&`, `!`, `==`, `!=`, `<`, `<=`, `>`, `>=`, `in`, `+`
+//! - methods: `.startsWith(s)`, `.endsWith(s)`, `.contains(s)`, `.matches("regex")`,
+//!   `.lower()`, `.size()`
+//! - functions: `cidr(ip, "10.0.0.0/8", ...)`,
+//!   `time_between("22:00", "06:00")` (UTC, wraps past midnight)
+//!
+//! Evaluation never touches the filesystem: matchers run on the request path,
+//! so there is no `file_exists` (use `try_files` / `file_server` instead).
+//!
+//! Placeholders are strings from the shared [registry](super::Placeholder):
+//! `{method}`, `{path}`, `{host}`, `{client_ip}`, `{header.<Name>}`, `{query.<key>}`,
+//! `{cookie.<name>}`, regex captures `{re.<name>.<group>}` and so on. Placeholders
+//! that only exist after routing (upstream, response) evaluate to empty strings.
+//! Comparing a string with an integer parses the string.
+
+use super::placeholder::{Placeholder, PlaceholderContext};
+use super::router::{parse_ip_net, Captures, RequestInfo, PRIVATE_RANGES};
+use ipnet::IpNet;
+use std::borrow::Cow;
+use std::net::IpAddr;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Invalid expression, with the byte range of the offending source
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{message} at {}..{}", .span.start, .span.end)]
+pub struct ExpressionError {
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+impl ExpressionError {
+    fn new(message: impl Into<String>, span: Range<usize>) -> Self {
+        Self { message: message.into(), span }
+    }
+
+    /// Render the error under the source with a `^^^` marker
+    pub fn render(&self, source: &str) -> String {
+        let start = source[..self.span.start.min(source.len())].chars().count();
+        let width = source.get(self.span.clone()).map_or(1, |s| s.chars().count().max(1));
+        format!("{}\n  {}\n  {}{}", self.message, source, " ".repeat(start), "^".repeat(width))
+    }
+}
+
+/// A compiled expression
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    root: Expr,
+}
+
+impl Expression {
+    /// Parse and type-check an expression; it must evaluate to a boolean.
+    pub fn parse(source: &str) -> Result<Self, ExpressionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0, end: source.len() };
+        let (root, ty, span) = parser.parse_or()?;
+        if let Some((_, span)) = parser.tokens.get(parser.pos) {
+            return Err(ExpressionError::new("Unexpected token", span.clone()));
+        }
+        if ty != Type::Bool {
+            return Err(ExpressionError::new(format!("Expression must be a boolean, found {}", ty), span));
+        }
+        Ok(Self { source: source.to_string(), root })
+    }
+
+    /// The expression source
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate against a request and the captures of previously matched matchers
+    pub fn evaluate(&self, request: &RequestInfo, captures: &Captures) -> bool {
+        let scope = PlaceholderContext::from_request(request, captures);
+        matches!(self.root.eval(&scope), Value::Bool(true))
+    }
+}
+
+// MARK: - AST
+
+/// Static type of a sub-expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Str,
+    Int,
+    Bool,
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Type::Str => "string",
+            Type::Int => "int",
+            Type::Bool => "bool",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value<'a> {
+    Str(Cow<'a, str>),
+    Int(i64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StrTest {
+    StartsWith,
+    EndsWith,
+    Contains,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Value<'static>),
+    Placeholder(Placeholder),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    /// `value in [...]`; IP values are also tested against CIDR elements
+    In { value: Box<Expr>, items: Vec<Value<'static>>, nets: Vec<IpNet> },
+    StrTest(StrTest, Box<Expr>, Box<Expr>),
+    Matches(Box<Expr>, Arc<regex::Regex>),
+    Lower(Box<Expr>),
+    Size(Box<Expr>),
+    Cidr(Box<Expr>, Vec<IpNet>),
+    /// Seconds since midnight UTC, `from..to` (wrapping when `from > to`)
+    TimeBetween(u32, u32),
+}
+
+impl Expr {
+    fn eval<'a>(&'a self, scope: &PlaceholderContext<'a>) -> Value<'a> {
+        match self {
+            Expr::Literal(value) => match value {
+                Value::Str(s) => Value::Str(Cow::Borrowed(s.as_ref())),
+                Value::Int(i) => Value::Int(*i),
+                Value::Bool(b) => Value::Bool(*b),
+            },
+            Expr::Placeholder(placeholder) => Value::Str(placeholder.resolve(scope)),
+            Expr::Not(inner) => Value::Bool(!inner.eval_bool(scope)),
+            Expr::And(left, right) => Value::Bool(left.eval_bool(scope) && right.eval_bool(scope)),
+            Expr::Or(left, right) => Value::Bool(left.eval_bool(scope) || right.eval_bool(scope)),
+            Expr::Compare(op, left, right) => Value::Bool(compare(*op, &left.eval(scope), &right.eval(scope))),
+            Expr::Add(left, right) => match (left.eval(scope), right.eval(scope)) {
+                (Value::Int(a), Value::Int(b)) => Value::Int(a.saturating_add(b)),
+                (a, b) => Value::Str(Cow::Owned(format!("{}{}", a.as_str(), b.as_str()))),
+            },
+            Expr::In { value, items, nets } => {
+                let value = value.eval(scope);
+                let found = items.iter().any(|item| compare(CompareOp::Eq, &value, item))
+                    || (!nets.is_empty() && ip_in(&value.as_str(), nets));
+                Value::Bool(found)
+            }
+            Expr::StrTest(test, value, needle) => {
+                let (value, needle) = (value.eval(scope), needle.eval(scope));
+                let (value, needle) = (value.as_str(), needle.as_str());
+                Value::Bool(match test {
+                    StrTest::StartsWith => value.starts_with(needle.as_ref()),
+                    StrTest::EndsWith => value.ends_with(needle.as_ref()),
+                    StrTest::Contains => value.contains(needle.as_ref()),
+                })
+            }
+            Expr::Matches(value, re) => Value::Bool(re.is_match(&value.eval(scope).as_str())),
+            Expr::Lower(value) => Value::Str(Cow::Owned(value.eval(scope).as_str().to_lowercase())),
+            Expr::Size(value) => Value::Int(value.eval(scope).as_str().chars().count() as i64),
+            Expr::Cidr(value, nets) => Value::Bool(ip_in(&value.eval(scope).as_str(), nets)),
+            Expr::TimeBetween(from, to) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| (d.as_secs() % 86_400) as u32);
+                Value::Bool(time_between(*from, *to, now))
+            }
+        }
+    }
+
+    fn eval_bool(&self, scope: &PlaceholderContext<'_>) -> bool {
+        matches!(self.eval(scope), Value::Bool(true))
+    }
+}
+
+impl Value<'_> {
+    fn as_str(&self) -> Cow<'_, str> {
+        match self {
+            Value::Str(s) => Cow::Borrowed(s.as_ref()),
+            Value::Int(i) => Cow::Owned(i.to_string()),
+            Value::Bool(b) => Cow::Borrowed(if *b { "true" } else { "false" }),
+        }
+    }
+}
+
+/// Compare two values; a string compared with an integer is parsed first
+fn compare(op: CompareOp, left: &Value, right: &Value) -> bool {
+    let ordering = match (left, right) {
+        (Value::Str(a), Value::Str(b)) => a.as_ref().cmp(b.as_ref()),
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Str(s), Value::Int(i)) => match s.trim().parse::<i64>() {
+            Ok(n) => n.cmp(i),
+            Err(_) => return false,
+        },
+        (Value::Int(i), Value::Str(s)) => match s.trim().parse::<i64>() {
+            Ok(n) => i.cmp(&n),
+            Err(_) => return false,
+        },
+        _ => return false,
+    };
+    match op {
+        CompareOp::Eq => ordering.is_eq(),
+        CompareOp::Ne => ordering.is_ne(),
+        CompareOp::Lt => ordering.is_lt(),
+        CompareOp::Le => ordering.is_le(),
+        CompareOp::Gt => ordering.is_gt(),
+        CompareOp::Ge => ordering.is_ge(),
+    }
+}
+
+fn ip_in(value: &str, nets: &[IpNet]) -> bool {
+    value.parse::<IpAddr>()
+        .map(|ip| ip.to_canonical())
+        .is_ok_and(|ip| nets.iter().any(|net| net.contains(&ip)))
+}
+
+// MARK: - Lexer
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Placeholder(String),
+    Str(String),
+    Int(i64),
+    Ident(String),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Dot,
+    Plus,
+    Bang,
+    AndAnd,
+    OrOr,
+    Op(CompareOp),
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, Range<usize>)>, ExpressionError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        let two = bytes.get(pos..pos + 2);
+        let token = match (c, two) {
+            (_, Some(b"&&")) => { pos += 2; Token::AndAnd }
+            (_, Some(b"||")) => { pos += 2; Token::OrOr }
+            (_, Some(b"==")) => { pos += 2; Token::Op(CompareOp::Eq) }
+            (_, Some(b"!=")) => { pos += 2; Token::Op(CompareOp::Ne) }
+            (_, Some(b"<=")) => { pos += 2; Token::Op(CompareOp::Le) }
+            (_, Some(b">=")) => { pos += 2; Token::Op(CompareOp::Ge) }
+            (b'<', _) => { pos += 1; Token::Op(CompareOp::Lt) }
+            (b'>', _) => { pos += 1; Token::Op(CompareOp::Gt) }
+            (b'!', _) => { pos += 1; Token::Bang }
+            (b'+', _) => { pos += 1; Token::Plus }
+            (b'(', _) => { pos += 1; Token::LParen }
+            (b')', _) => { pos += 1; Token::RParen }
+            (b'[', _) => { pos += 1; Token::LBracket }
+            (b']', _) => { pos += 1; Token::RBracket }
+            (b',', _) => { pos += 1; Token::Comma }
+            (b'.', _) => { pos += 1; Token::Dot }
+            (b'{', _) => {
+                let Some(len) = source[pos..].find('}') else {
+                    return Err(ExpressionError::new("Unterminated placeholder", start..source.len()));
+                };
+                pos += len + 1;
+                Token::Placeholder(source[start + 1..pos - 1].trim().to_string())
+            }
+            (b'"' | b'\'', _) => {
+                let (value, end) = lex_string(source, pos)?;
+                pos = end;
+                Token::Str(value)
+            }
+            (b'0'..=b'9', _) => {
+                while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                    pos += 1;
+                }
+                match source[start..pos].parse() {
+                    Ok(n) => Token::Int(n),
+                    Err(_) => return Err(ExpressionError::new("Integer out of range", start..pos)),
+                }
+            }
+            (c, _) if c.is_ascii_alphabetic() || c == b'_' => {
+                while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+                    pos += 1;
+                }
+                Token::Ident(source[start..pos].to_string())
+            }
+            _ => {
+                let len = source[pos..].chars().next().map_or(1, char::len_utf8);
+                return Err(ExpressionError::new("Unexpected character", start..start + len));
+            }
+        };
+        tokens.push((token, start..pos));
+    }
+
+    Ok(tokens)
+}
+
+/// Lex a quoted string starting at `start`, returning its value and end offset
+fn lex_string(source: &str, start: usize) -> Result<(String, usize), ExpressionError> {
+    let mut chars = source[start..].char_indices();
+    let (_, quote) = chars.next().expect("called on a quote");
+    let mut value = String::new();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((value, start + offset + 1)),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, escaped)) => value.push(escaped),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err(ExpressionError::new("Unterminated string", start..source.len()))
+}
+
+// MARK: - Parser
+
+/// Recursive descent parser; every rule returns the node, its type and its span.
+///
+/// Precedence, loosest first: `||`, `&&`, comparisons / `in`, `+`, `!`, method calls.
+struct Parser {
+    tokens: Vec<(Token, Range<usize>)>,
+    pos: usize,
+    end: usize,
+}
+
+type Parsed = (Expr, Type, Range<usize>);
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn next(&mut self) -> Result<(Token, Range<usize>), ExpressionError> {
+        let token = self.tokens.get(self.pos).cloned()
+            .ok_or_else(|| ExpressionError::new("Unexpected end of expression", self.end..self.end))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<Range<usize>, ExpressionError> {
+        let (token, span) = self.next()?;
+        if token != expected {
+            return Err(ExpressionError::new(format!("Expected {}", what), span));
+        }
+        Ok(span)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn parse_or(&mut self) -> Result<Parsed, ExpressionError> {
+        let mut left = self.parse_and()?;
+        while self.eat(&Token::OrOr) {
+            let right = self.parse_and()?;
+            left = logical(left, right, Expr::Or)?;
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Parsed, ExpressionError> {
+        let mut left = self.parse_comparison()?;
+        while self.eat(&Token::AndAnd) {
+            let right = self.parse_comparison()?;
+            left = logical(left, right, Expr::And)?;
+        }
+        Ok(left)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Parsed, ExpressionError> {
+        let left = self.parse_additive()?;
+        match self.peek() {
+            Some(Token::Op(op)) => {
+                let op = *op;
+                self.pos += 1;
+                let right = self.parse_additive()?;
+                let span = left.2.start..right.2.end;
+                let comparable = match (left.1, right.1) {
+                    (a, b) if a == b => a != Type::Bool || matches!(op, CompareOp::Eq | CompareOp::Ne),
+                    (Type::Str, Type::Int) | (Type::Int, Type::Str) => true,
+                    _ => false,
+                };
+                if !comparable {
+                    return Err(ExpressionError::new(format!("Cannot compare {} with {}", left.1, right.1), span));
+                }
+                Ok((Expr::Compare(op, Box::new(left.0), Box::new(right.0)), Type::Bool, span))
+            }
+            Some(Token::Ident(ident)) if ident == "in" => {
+                self.pos += 1;
+                if left.1 == Type::Bool {
+                    return Err(ExpressionError::new("`in` expects a string or int", left.2));
+                }
+                let (items, nets, end) = self.parse_list()?;
+                let span = left.2.start..end;
+                Ok((Expr::In { value: Box::new(left.0), items, nets }, Type::Bool, span))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    /// `[lit, ...]` for `in`; string elements that are IPs / CIDRs also match addresses
+    fn parse_list(&mut self) -> Result<(Vec<Value<'static>>, Vec<IpNet>, usize), ExpressionError> {
+        self.expect(Token::LBracket, "a `[...]` list after `in`")?;
+        let mut items = Vec::new();
+        let mut nets = Vec::new();
+        loop {
+            let (token, span) = self.next()?;
+            match token {
+                Token::RBracket if items.is_empty() => return Ok((items, nets, span.end)),
+                Token::Str(s) => {
+                    if s == "private_ranges" {
+                        nets.extend(PRIVATE_RANGES.iter().filter_map(|r| parse_ip_net(r)));
+                    } else {
+                        nets.extend(parse_ip_net(&s));
+                    }
+                    items.push(Value::Str(Cow::Owned(s)));
+                }
+                Token::Int(i) => items.push(Value::Int(i)),
+                _ => return Err(ExpressionError::new("List elements must be string or int literals", span)),
+            }
+            let (token, span) = self.next()?;
+            match token {
+                Token::Comma => {}
+                Token::RBracket => return Ok((items, nets, span.end)),
+                _ => return Err(ExpressionError::new("Expected `,` or `]`", span)),
+            }
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Parsed, ExpressionError> {
+        let mut left = self.parse_unary()?;
+        while self.eat(&Token::Plus) {
+            let right = self.parse_unary()?;
+            let span = left.2.start..right.2.end;
+            if left.1 != right.1 || left.1 == Type::Bool {
+                return Err(ExpressionError::new(format!("Cannot add {} and {}", left.1, right.1), span));
+            }
+            left = (Expr::Add(Box::new(left.0), Box::new(right.0)), left.1, span);
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Parsed, ExpressionError> {
+        if let Some((Token::Bang, span)) = self.tokens.get(self.pos).cloned() {
+            self.pos += 1;
+            let (inner, ty, inner_span) = self.parse_unary()?;
+            if ty != Type::Bool {
+                return Err(ExpressionError::new(format!("`!` expects a bool, found {}", ty), inner_span));
+            }
+            return Ok((Expr::Not(Box::new(inner)), Type::Bool, span.start..inner_span.end));
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Result<Parsed, ExpressionError> {
+        let mut receiver = self.parse_primary()?;
+        while self.eat(&Token::Dot) {
+            let (token, name_span) = self.next()?;
+            let Token::Ident(method) = token else {
+                return Err(ExpressionError::new("Expected a method name", name_span));
+            };
+            let (args, end) = self.parse_args()?;
+            let span = receiver.2.start..end;
+            if receiver.1 != Type::Str {
+                return Err(ExpressionError::new(format!("`{}` expects a string receiver, found {}", method, receiver.1), receiver.2));
+            }
+            let value = Box::new(receiver.0);
+            receiver = match (method.as_str(), <[Parsed; 1]>::try_from(args)) {
+                ("startsWith" | "endsWith" | "contains", Ok([(needle, Type::Str, _)])) => {
+                    let test = match method.as_str() {
+                        "startsWith" => StrTest::StartsWith,
+                        "endsWith" => StrTest::EndsWith,
+                        _ => StrTest::Contains,
+                    };
+                    (Expr::StrTest(test, value, Box::new(needle)), Type::Bool, span)
+                }
+                ("matches", Ok([(Expr::Literal(Value::Str(pattern)), _, pattern_span)])) => {
+                    let re = regex::Regex::new(&pattern)
+                        .map_err(|e| ExpressionError::new(format!("Invalid regex: {}", e), pattern_span))?;
+                    (Expr::Matches(value, Arc::new(re)), Type::Bool, span)
+                }
+                ("matches", _) => return Err(ExpressionError::new("`matches` expects one regex string literal", span)),
+                ("lower", Err(args)) if args.is_empty() => (Expr::Lower(value), Type::Str, span),
+                ("size", Err(args)) if args.is_empty() => (Expr::Size(value), Type::Int, span),
+                ("startsWith" | "endsWith" | "contains" | "lower" | "size", _) => {
+                    return Err(ExpressionError::new(format!("Wrong arguments for `{}`", method), span));
+                }
+                _ => return Err(ExpressionError::new(format!("Unknown method `{}`", method), name_span)),
+            };
+        }
+        Ok(receiver)
+    }
+
+    /// `( expr, ... )`, returning the arguments and the end offset
+    fn parse_args(&mut self) -> Result<(Vec<Parsed>, usize), ExpressionError> {
+        self.expect(Token::LParen, "`(`")?;
+        let mut args = Vec::new();
+        if let Some((Token::RParen, span)) = self.tokens.get(self.pos).cloned() {
+            self.pos += 1;
+            return Ok((args, span.end));
+        }
+        loop {
+            args.push(self.parse_or()?);
+            let (token, span) = self.next()?;
+            match token {
+                Token::Comma => {}
+                Token::RParen => return Ok((args, span.end)),
+                _ => return Err(ExpressionError::new("Expected `,` or `)`", span)),
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Parsed, ExpressionError> {
+        let (token, span) = self.next()?;
+        match token {
+            Token::Placeholder(name) => match Placeholder::parse(&name) {
+                Ok(Some(placeholder)) => Ok((Expr::Placeholder(placeholder), Type::Str, span)),
+                Ok(None) => Err(ExpressionError::new(format!("Unknown placeholder {{{}}}", name), span)),
+                Err(err) => Err(ExpressionError::new(err.to_string(), span)),
+            },
+            Token::Str(s) => Ok((Expr::Literal(Value::Str(Cow::Owned(s))), Type::Str, span)),
+            Token::Int(i) => Ok((Expr::Literal(Value::Int(i)), Type::Int, span)),
+            Token::LParen => {
+                let (inner, ty, _) = self.parse_or()?;
+                let end = self.expect(Token::RParen, "`)`")?;
+                Ok((inner, ty, span.start..end.end))
+            }
+            Token::Ident(ident) => match ident.as_str() {
+                "true" => Ok((Expr::Literal(Value::Bool(true)), Type::Bool, span)),
+                "false" => Ok((Expr::Literal(Value::Bool(false)), Type::Bool, span)),
+                _ => self.parse_function(ident, span),
+            },
+            _ => Err(ExpressionError::new("Expected a value", span)),
+        }
+    }
+
+    fn parse_function(&mut self, name: String, name_span: Range<usize>) -> Result<Parsed, ExpressionError> {
+        if self.peek() != Some(&Token::LParen) {
+            return Err(ExpressionError::new(format!("Unknown identifier `{}`", name), name_span));
+        }
+        let (args, end) = self.parse_args()?;
+        let span = name_span.start..end;
+        let mut args = args.into_iter();
+        match name.as_str() {
+            "cidr" => {
+                let Some((value, Type::Str, _)) = args.next() else {
+                    return Err(ExpressionError::new("`cidr` expects an IP string and ranges", span));
+                };
+                let mut nets = Vec::new();
+                for (arg, _, arg_span) in args {
+                    let Expr::Literal(Value::Str(range)) = arg else {
+                        return Err(ExpressionError::new("`cidr` ranges must be string literals", arg_span));
+                    };
+                    if range == "private_ranges" {
+                        nets.extend(PRIVATE_RANGES.iter().filter_map(|r| parse_ip_net(r)));
+                    } else {
+                        nets.push(parse_ip_net(&range)
+                            .ok_or_else(|| ExpressionError::new(format!("Invalid IP/CIDR {:?}", range), arg_span))?);
+                    }
+                }
+                if nets.is_empty() {
+                    return Err(ExpressionError::new("`cidr` expects at least one range", span));
+                }
+                Ok((Expr::Cidr(Box::new(value), nets), Type::Bool, span))
+            }
+            "time_between" => {
+                let mut bound = || match args.next() {
+                    Some((Expr::Literal(Value::Str(time)), _, time_span)) => parse_time_of_day(&time)
+                        .ok_or_else(|| ExpressionError::new(format!("Invalid time {:?}, expected HH:MM[:SS]", time), time_span)),
+                    _ => Err(ExpressionError::new("`time_between` expects two \"HH:MM\" literals", span.clone())),
+                };
+                let (from, to) = (bound()?, bound()?);
+                if args.next().is_some() {
+                    return Err(ExpressionError::new("`time_between` expects two \"HH:MM\" literals", span));
+                }
+                Ok((Expr::TimeBetween(from, to), Type::Bool, span))
+            }
+            _ => Err(ExpressionError::new(format!("Unknown function `{}`", name), name_span)),
+        }
+    }
+}
+
+/// Combine two boolean operands
+fn logical(left: Parsed, right: Parsed, op: fn(Box<Expr>, Box<Expr>) -> Expr) -> Result<Parsed, ExpressionError> {
+    for (_, ty, span) in [&left, &right] {
+        if *ty != Type::Bool {
+            return Err(ExpressionError::new(format!("Expected a bool, found {}", ty), span.clone()));
+        }
+    }
+    let span = left.2.start..right.2.end;
+    Ok((op(Box::new(left.0), Box::new(right.0)), Type::Bool, span))
+}
+
+/// Whether `now` falls in `from..to` (seconds since midnight), wrapping past midnight when `from > to`
+///
+/// Equal bounds form an empty window.
+fn time_between(from: u32, to: u32, now: u32) -> bool {
+    if from <= to { (from..to).contains(&now) } else { now >= from || now < to }
+}
+
+/// `HH:MM` / `HH:MM:SS` to seconds since midnight
+fn parse_time_of_day(time: &str) -> Option<u32> {
+    let mut parts = time.split(':').map(|p| p.parse::<u32>().ok());
+    let hours = parts.next()??;
+    let minutes = parts.next()??;
+    let seconds = parts.next().unwrap_or(Some(0))?;
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request<'a>(headers: &'a http::HeaderMap) -> RequestInfo<'a> {
+        RequestInfo {
+            path: "/api/users",
+            query: "page=2&debug",
+            method: "POST",
+            headers,
+            host: "example.com",
+            remote_ip: "10.1.2.3",
+            client_ip: "203.0.113.9",
+            protocol: "https",
+            vars: None,
+        }
+    }
+
+    fn eval(source: &str) -> bool {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("content-type", "application/json; charset=utf-8".parse().unwrap());
+        headers.insert("cookie", "session=abc".parse().unwrap());
+        let mut captures = Captures::new();
+        captures.insert("tenant.name".to_string(), "acme".to_string());
+        Expression::parse(source).unwrap().evaluate(&request(&headers), &captures)
+    }
+
+    #[test]
+    fn test_operators_and_placeholders() {
+        assert!(eval(r#"{method} == "POST" && {header.Content-Type}.startsWith("application/json")"#));
+        assert!(eval(r#"{remote_ip} in ["10.0.0.0/8"] && !({client_ip} in ["private_ranges"])"#));
+        assert!(eval(r#"{query.page} >= 2 && {query.page} < 10 && {cookie.session} == 'abc'"#));
+        assert!(eval(r#"{re.tenant.name} + "." + {host} == "acme.example.com""#));
+        assert!(eval(r#"{path}.matches("^/api/") && {path}.size() == 10 || false"#));
+        assert!(!eval(r#"{method} != "POST" || {http.request.scheme} == "http""#));
+        assert!(eval(r#"cidr({client_ip}, "203.0.113.0/24") && {uri}.endsWith("?page=2&debug")"#));
+    }
+
+    #[test]
+    fn test_time_between() {
+        let at = |time: &str| parse_time_of_day(time).unwrap();
+        let (night, day) = ((at("22:00"), at("06:00")), (at("09:00"), at("17:30")));
+
+        assert!(time_between(night.0, night.1, at("23:15")));
+        assert!(time_between(night.0, night.1, at("00:00")));
+        assert!(time_between(night.0, night.1, at("22:00")));
+        assert!(!time_between(night.0, night.1, at("06:00")));
+        assert!(!time_between(night.0, night.1, at("12:00")));
+
+        assert!(time_between(day.0, day.1, at("09:00")));
+        assert!(!time_between(day.0, day.1, at("17:30")));
+        assert!(!time_between(day.0, day.1, at("08:59:59")));
+        assert!(!time_between(at("00:00"), at("00:00"), at("00:00")));
+
+        assert!(Expression::parse(r#"time_between("22:00", "06:00")"#).is_ok());
+    }
+
+    #[test]
+    fn test_errors_have_spans() {
+        let err = Expression::parse(r#"{method} == "POST" &&"#).unwrap_err();
+        assert_eq!(err.span, 21..21);
+
+        let err = Expression::parse(r#"{nope} == "x""#).unwrap_err();
+        assert_eq!(err.message, "Unknown placeholder {nope}");
+        assert_eq!(err.span, 0..6);
+
+        let err = Expression::parse(r#"{path}.matches("(")"#).unwrap_err();
+        assert_eq!(err.span, 15..18);
+
+        let err = Expression::parse(r#"{path} && true"#).unwrap_err();
+        assert_eq!(err.message, "Expected a bool, found string");
+        assert_eq!(err.render(r#"{path} && true"#), "Expected a bool, found string\n  {path} && true\n  ^^^^^^");
+
+        assert!(Expression::parse(r#"{path}"#).is_err());
+        assert!(Expression::parse(r#"time_between("25:00", "06:00")"#).is_err());
+        assert!(Expression::parse(r#"file_exists("/srv" + {path})"#).is_err());
+        assert!(Expression::parse(r#"cidr({client_ip}, "not-an-ip")"#).is_err());
+    }
+}
@@ -3,9 +3,14 @@
 //! Provides handlers for respond, redirect, and headers operations.
 
 use crate::config::HandlerConfig;
+use super::placeholder::PlaceholderContext;
 use http::StatusCode;
 use bytes::Bytes;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 
 /// Handler result
 pub type HandlerResult = Result<HandlerResponse, HandlerError>;
@@ -80,23 +85,24 @@ impl HandlerResponse {
     }
 }
 
-/// Execute a handler configuration
-pub fn execute_handler(config: &HandlerConfig) -> HandlerResult {
+/// Execute a handler configuration, rendering its templates against `ctx`
+pub fn execute_handler(config: &HandlerConfig, ctx: &PlaceholderContext) -> HandlerResult {
     match config {
         HandlerConfig::Respond { status, body, headers } => {
             let mut response = if let Some(body_content) = body {
-                // Clone the body content to get owned data
-                HandlerResponse::with_body(*status, Bytes::from(body_content.clone()))
+                HandlerResponse::with_body(*status, Bytes::from(body_content.render(ctx).into_owned()))
             } else {
                 HandlerResponse::status(*status)
             };
             
-            response.headers = headers.clone();
+            response.headers = headers.iter()
+                .map(|(k, v)| (k.clone(), v.render(ctx).into_owned()))
+                .collect();
             Ok(response)
         }
         
         HandlerConfig::Redirect { to, code } => {
-            Ok(HandlerResponse::redirect(to, *code))
+            Ok(HandlerResponse::redirect(&to.render(ctx), *code))
         }
         
         HandlerConfig::Headers { set, add, remove: _ } => {
@@ -104,10 +110,10 @@ pub fn execute_handler(config: &HandlerConfig) -> HandlerResult {
             // Return a passthrough response
             let mut response = HandlerResponse::status(200);
             for (k, v) in set {
-                response.headers.insert(k.clone(), v.clone());
+                response.headers.insert(k.clone(), v.render(ctx).into_owned());
             }
             for (k, v) in add {
-                response.headers.insert(k.clone(), v.clone());
+                response.headers.insert(k.clone(), v.render(ctx).into_owned());
             }
             Ok(response)
         }
@@ -131,7 +137,7 @@ pub fn execute_handler(config: &HandlerConfig) -> HandlerResult {
             let mut final_response = HandlerResponse::status(200);
             
             for handler in handlers {
-                match execute_handler(handler) {
+                match execute_handler(handler, ctx) {
                     Ok(response) => {
                         final_response.status = response.status;
                         final_response.headers.extend(response.headers);
@@ -148,7 +154,7 @@ pub fn execute_handler(config: &HandlerConfig) -> HandlerResult {
 
         HandlerConfig::Handle(handlers) => {
             // Treat Handle as a pipeline for now
-            execute_handler(&HandlerConfig::Pipeline(handlers.clone()))
+            execute_handler(&HandlerConfig::Pipeline(handlers.clone()), ctx)
         }
 
         HandlerConfig::Rewrite { strip_prefix, strip_suffix, replace, regex: _, regex_replace: _ } => {
@@ -165,7 +171,7 @@ pub fn execute_handler(config: &HandlerConfig) -> HandlerResult {
                 response.headers.insert("X-Pingclair-Strip-Suffix".to_string(), suffix.clone());
             }
             if let Some(replacement) = replace {
-                response.headers.insert("X-Pingclair-Replace-Path".to_string(), replacement.clone());
+                response.headers.insert("X-Pingclair-Replace-Path".to_string(), replacement.render(ctx).into_owned());
             }
             // Note: regex support would need the regex crate here
             // For now, regex rewrites are handled separately
@@ -225,7 +231,7 @@ pub fn execute_handler(config: &HandlerConfig) -> HandlerResult {
 
         HandlerConfig::HandlePath { prefix, handlers } => {
             // execute inner handlers
-            let mut response = execute_handler(&HandlerConfig::Pipeline(handlers.clone()))?;
+            let mut response = execute_handler(&HandlerConfig::Pipeline(handlers.clone()), ctx)?;
             
             // Add instruction to strip prefix
             // Note: In a real execution engine, we would modify the path before inner execution,
@@ -250,37 +256,184 @@ pub fn execute_handler(config: &HandlerConfig) -> HandlerResult {
             Ok(HandlerResponse::status(200))
         }
 
+        HandlerConfig::Templates { .. } => {
+            // Templates render the response body, handled at the proxy layer.
+            Ok(HandlerResponse::status(200))
+        }
+
+        HandlerConfig::Map(_) | HandlerConfig::Vars { .. } => {
+            // Variables live in the request context, set at the proxy layer (see `apply_vars`).
+            Ok(HandlerResponse::status(200))
+        }
+
         HandlerConfig::Plugin { name, args: _ } => {
             Err(HandlerError::Config(format!("Plugin {} is not yet implemented", name)))
         }
     }
 }
 
+/// A `Rewrite` or matcher regex, compiled once when the configuration is loaded
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ConfigRegex {
+    source: String,
+    regex: Arc<Regex>,
+}
+
+impl ConfigRegex {
+    /// Compile a pattern.
+    ///
+    /// - Returns: The compiled pattern, or the regex error.
+    pub fn parse(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self { source: pattern.to_string(), regex: Arc::new(Regex::new(pattern)?) })
+    }
+
+    /// The pattern as written in the configuration
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// The compiled regex
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+}
+
+impl TryFrom<String> for ConfigRegex {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Self::parse(&pattern)
+    }
+}
+
+impl From<ConfigRegex> for String {
+    fn from(regex: ConfigRegex) -> Self {
+        regex.source
+    }
+}
+
+impl PartialEq for ConfigRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl fmt::Debug for ConfigRegex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ConfigRegex").field(&self.source).finish()
+    }
+}
+
+/// Apply a `Rewrite` handler to a request path.
+///
+/// Steps run in order: `replace` (with placeholders), `strip_prefix`,
+/// `strip_suffix`, then the `regex` substitution (`$1` / `$name` groups).
+/// The prefix is only stripped on a segment boundary: `/api` strips `/api`
+/// and `/api/users`, not `/apiary`.
+///
+/// - Parameter config: The handler; anything but `Rewrite` returns `None`.
+/// - Parameter path: The current request path.
+/// - Parameter ctx: Request the `replace` template is rendered against.
+/// - Returns: The rewritten path, which may carry its own `?query`.
+pub fn rewrite_path(config: &HandlerConfig, path: &str, ctx: &PlaceholderContext) -> Option<String> {
+    let HandlerConfig::Rewrite { strip_prefix, strip_suffix, replace, regex, regex_replace } = config else {
+        return None;
+    };
+
+    let mut path = match replace {
+        Some(template) => template.render(ctx).into_owned(),
+        None => path.to_string(),
+    };
+    if let Some(prefix) = strip_prefix
+        && let Some(rest) = path.strip_prefix(prefix.trim_end_matches('/'))
+        && (rest.is_empty() || rest.starts_with(['/', '?']))
+    {
+        path = if rest.starts_with('/') { rest.to_string() } else { format!("/{}", rest) };
+    }
+    if let Some(suffix) = strip_suffix
+        && let Some(rest) = path.strip_suffix(suffix.as_str())
+    {
+        path = rest.to_string();
+    }
+    if let (Some(pattern), Some(replacement)) = (regex, regex_replace) {
+        path = pattern.regex.replace(&path, replacement.as_str()).into_owned();
+    }
+    Some(path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::server::{Captures, Template};
+
+    fn rewrite(strip_prefix: Option<&str>, replace: Option<&str>, regex: Option<(&str, &str)>) -> HandlerConfig {
+        HandlerConfig::Rewrite {
+            strip_prefix: strip_prefix.map(str::to_string),
+            strip_suffix: None,
+            replace: replace.map(|t| Template::parse(t).unwrap()),
+            regex: regex.map(|(p, _)| ConfigRegex::parse(p).unwrap()),
+            regex_replace: regex.map(|(_, r)| r.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_rewrite_path() {
+        let captures = Captures::from([("path.id".to_string(), "42".to_string())]);
+        let ctx = &PlaceholderContext { captures: Some(&captures), ..Default::default() };
+
+        let config = rewrite(Some("/api"), None, None);
+        assert_eq!(rewrite_path(&config, "/api/users", ctx).as_deref(), Some("/users"));
+        assert_eq!(rewrite_path(&config, "/api", ctx).as_deref(), Some("/"));
+        assert_eq!(rewrite_path(&config, "/api?x=1", ctx).as_deref(), Some("/?x=1"));
+        assert_eq!(rewrite_path(&config, "/apiary", ctx).as_deref(), Some("/apiary"));
+        let config = rewrite(Some("/api/"), None, None);
+        assert_eq!(rewrite_path(&config, "/api/users", ctx).as_deref(), Some("/users"));
+        assert_eq!(rewrite_path(&config, "/apiary", ctx).as_deref(), Some("/apiary"));
+
+        let config = rewrite(None, Some("/profile?id={re.path.id}"), None);
+        assert_eq!(rewrite_path(&config, "/u/42", ctx).as_deref(), Some("/profile?id=42"));
+
+        let config = rewrite(None, None, Some((r"^/old/(?P<rest>.*)$", "/new/$rest")));
+        assert_eq!(rewrite_path(&config, "/old/a/b", ctx).as_deref(), Some("/new/a/b"));
+
+        assert!(rewrite_path(&HandlerConfig::Pipeline(Vec::new()), "/", ctx).is_none());
+    }
+
+    #[test]
+    fn test_rewrite_regex_compiled_at_load() {
+        let config: HandlerConfig = serde_json::from_str(
+            r#"{"type": "rewrite", "regex": "^/v1/(.*)$", "regex_replace": "/api/$1"}"#,
+        ).unwrap();
+        let ctx = &PlaceholderContext::default();
+        assert_eq!(rewrite_path(&config, "/v1/users", ctx).as_deref(), Some("/api/users"));
+
+        let invalid = serde_json::from_str::<HandlerConfig>(r#"{"type": "rewrite", "regex": "(", "regex_replace": "/"}"#);
+        assert!(invalid.is_err());
+    }
     
     #[test]
     fn test_respond_handler() {
         let config = HandlerConfig::Respond {
             status: 200,
-            body: Some("Hello, World!".to_string()),
+            body: Some(Template::parse("Hello from {path}!").unwrap()),
             headers: HashMap::new(),
         };
         
-        let response = execute_handler(&config).unwrap();
+        let ctx = PlaceholderContext { path: "/hello", ..Default::default() };
+        let response = execute_handler(&config, &ctx).unwrap();
         assert_eq!(response.status, StatusCode::OK);
-        assert!(response.body.is_some());
+        assert_eq!(response.body.as_deref(), Some(&b"Hello from /hello!"[..]));
     }
     
     #[test]
     fn test_redirect_handler() {
         let config = HandlerConfig::Redirect {
-            to: "https://example.com".to_string(),
+            to: Template::parse("https://example.com").unwrap(),
             code: 301,
         };
         
-        let response = execute_handler(&config).unwrap();
+        let response = execute_handler(&config, &PlaceholderContext::default()).unwrap();
         assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY);
         assert_eq!(response.headers.get("Location"), Some(&"https://example.com".to_string()));
     }
@@ -288,7 +441,7 @@ mod tests {
     #[test]
     fn test_headers_handler() {
         let mut headers = HashMap::new();
-        headers.insert("X-Custom".to_string(), "value".to_string());
+        headers.insert("X-Custom".to_string(), Template::parse("value").unwrap());
         
         let config = HandlerConfig::Headers {
             set: headers,
@@ -296,7 +449,7 @@ mod tests {
             remove: Vec::new(),
         };
         
-        let response = execute_handler(&config).unwrap();
+        let response = execute_handler(&config, &PlaceholderContext::default()).unwrap();
         assert_eq!(response.headers.get("X-Custom"), Some(&"value".to_string()));
     }
 }
@@ -0,0 +1,191 @@
+//! Host name normalisation and matching
+//!
+//! 🏗️ ARCHITECTURE: Request hosts are normalised once per request and every
+//! configured host pattern once at load time, so comparisons are plain
+//! string or label compares:
+//!   - lowercase, port removed, trailing dot removed (`Example.COM.:443` → `example.com`)
+//!   - internationalised names converted to punycode (`bücher.de` → `xn--bcher-kva.de`)
+//!
+//! Patterns may use `*` for exactly one label (`*.example.com`, `api.*.internal`).
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Normalise a `Host` header / `:authority` / configured host name.
+///
+/// - Parameter host: Raw host, optionally with a port (`[::1]:8443`).
+/// - Returns: The lowercase ASCII host without port or trailing dot.
+pub fn normalize_host(host: &str) -> Cow<'_, str> {
+    let host = strip_port(host.trim());
+    let host = host.strip_suffix('.').unwrap_or(host);
+
+    // ⚡ OPTIMIZATION: Most hosts are already lowercase ASCII
+    if !host.bytes().any(|b| b.is_ascii_uppercase() || !b.is_ascii()) {
+        return Cow::Borrowed(host);
+    }
+    if host.is_ascii() {
+        return Cow::Owned(host.to_ascii_lowercase());
+    }
+    match idna::domain_to_ascii(host) {
+        Ok(ascii) => Cow::Owned(ascii),
+        Err(_) => Cow::Owned(host.to_lowercase()),
+    }
+}
+
+/// Normalise a host pattern, leaving `*` labels in place.
+pub fn normalize_host_pattern(pattern: &str) -> String {
+    if !pattern.contains('*') {
+        return normalize_host(pattern).into_owned();
+    }
+    let pattern = pattern.trim();
+    let pattern = pattern.strip_suffix('.').unwrap_or(pattern);
+    pattern.split('.')
+        .map(|label| if label == "*" { Cow::Borrowed("*") } else { normalize_host(label) })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Match a normalised host against a normalised pattern.
+///
+/// A `*` label matches exactly one non-empty label.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    if !pattern.contains('*') {
+        return pattern == host;
+    }
+    let mut pattern_labels = pattern.split('.');
+    let mut host_labels = host.split('.');
+    loop {
+        match (pattern_labels.next(), host_labels.next()) {
+            (None, None) => return true,
+            (Some("*"), Some(label)) if !label.is_empty() => {}
+            (Some(expected), Some(label)) if expected == label => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Remove a `:port` suffix, keeping bracketed IPv6 literals intact
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        };
+    }
+    match host.split_once(':') {
+        // A bare IPv6 literal has several colons and no port
+        Some((name, port)) if !port.contains(':') => name,
+        _ => host,
+    }
+}
+
+// MARK: - Virtual Host Index
+
+/// Virtual host lookup by exact name or `*.suffix` wildcard
+///
+/// ⚡ OPTIMIZATION: Wildcards are indexed by suffix, so a lookup probes each
+/// parent domain of the request host once instead of scanning every pattern.
+/// The most specific wildcard wins (`*.api.example.com` over `*.example.com`).
+///
+/// ⚠️ Unlike route `host` matchers, a wildcard site also serves deeper
+/// subdomains (`a.b.example.com`), as virtual-host lookup always has.
+#[derive(Debug, Clone)]
+pub struct HostIndex<T> {
+    exact: HashMap<String, T>,
+    wildcard: HashMap<String, T>,
+}
+
+impl<T> Default for HostIndex<T> {
+    fn default() -> Self {
+        Self {
+            exact: HashMap::new(),
+            wildcard: HashMap::new(),
+        }
+    }
+}
+
+impl<T> HostIndex<T> {
+    /// Create an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a host name or `*.suffix` wildcard
+    pub fn insert(&mut self, pattern: &str, value: T) {
+        let pattern = normalize_host_pattern(pattern);
+        match pattern.strip_prefix("*.") {
+            Some(suffix) => self.wildcard.insert(suffix.to_string(), value),
+            None => self.exact.insert(pattern, value),
+        };
+    }
+
+    /// Look up a normalised request host.
+    ///
+    /// - Parameter host: Host as returned by [`normalize_host`].
+    /// - Returns: The exact entry, else the most specific wildcard entry.
+    pub fn get(&self, host: &str) -> Option<&T> {
+        if let Some(value) = self.exact.get(host) {
+            return Some(value);
+        }
+        if self.wildcard.is_empty() {
+            return None;
+        }
+        host.match_indices('.')
+            .find_map(|(dot, _)| self.wildcard.get(&host[dot + 1..]))
+    }
+
+    /// Iterate over all registered values
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.exact.values().chain(self.wildcard.values())
+    }
+
+    /// Number of registered hosts
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.wildcard.len()
+    }
+
+    /// Whether no hosts are registered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("example.com"), "example.com");
+        assert_eq!(normalize_host("Example.COM.:8443"), "example.com");
+        assert_eq!(normalize_host("[::1]:443"), "[::1]");
+        assert_eq!(normalize_host("bücher.de"), "xn--bcher-kva.de");
+        assert_eq!(normalize_host_pattern("*.Bücher.de."), "*.xn--bcher-kva.de");
+    }
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("*.example.com", "api.example.com"));
+        assert!(!host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(host_matches("api.*.internal", "api.eu.internal"));
+        assert!(host_matches("example.com", "example.com"));
+        assert!(!host_matches("example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn test_host_index() {
+        let mut index = HostIndex::new();
+        index.insert("example.com", "apex");
+        index.insert("*.example.com", "wildcard");
+        index.insert("*.api.example.com", "api");
+        index.insert("Bücher.de.", "idn");
+
+        assert_eq!(index.get("example.com"), Some(&"apex"));
+        assert_eq!(index.get("www.example.com"), Some(&"wildcard"));
+        assert_eq!(index.get("a.b.example.com"), Some(&"wildcard"));
+        assert_eq!(index.get("v1.api.example.com"), Some(&"api"));
+        assert_eq!(index.get(&normalize_host("BÜCHER.de")), Some(&"idn"));
+        assert_eq!(index.get("example.org"), None);
+    }
+}
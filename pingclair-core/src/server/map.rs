@@ -0,0 +1,271 @@
+//! `map` and `vars` handlers
+//!
+//! 🏗️ ARCHITECTURE: Both only produce `(name, value)` pairs; the caller stores
+//! them in its per-request variables, where `{vars.<name>}` and the `vars`
+//! matcher read them. A `map` tries its entries in order and the first key
+//! that matches the rendered source wins:
+//!   - exact: `example.com`
+//!   - wildcard: `*.example.com`, `/api/*` (`*` matches any run of characters)
+//!   - regex: `~^/user/(\d+)$`, or `~*` for case-insensitive; outputs may use `$1` / `$name`
+//!
+//! Without a match the defaults apply. An output of `-` leaves its destination unset.
+//! Capture references are expanded in the output as written, before its
+//! placeholders are filled in, so a `$` in request data stays literal.
+//!
+//! Variables do not pick upstreams directly (`reverse_proxy {vars.backend}` is
+//! rejected): route on them with the `vars` matcher, one `reverse_proxy` per
+//! `handle` block, so each pool keeps its load balancing and health checks.
+
+use crate::config::{HandlerConfig, MapConfig};
+use super::placeholder::PlaceholderContext;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Output that leaves its destination unset
+const SKIP: &str = "-";
+
+/// A `map` entry key, compiled once when the configuration is loaded
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum MapKey {
+    Exact(String),
+    Wildcard(String),
+    Regex {
+        source: String,
+        regex: Arc<Regex>,
+    },
+}
+
+impl MapKey {
+    /// Parse a key: `~` / `~*` prefix for a regex, any `*` for a wildcard, else exact.
+    ///
+    /// - Parameter key: The key as written in the configuration.
+    /// - Returns: The compiled key, or the regex error.
+    pub fn parse(key: &str) -> Result<Self, regex::Error> {
+        if let Some(pattern) = key.strip_prefix("~*") {
+            let regex = Regex::new(&format!("(?i){}", pattern))?;
+            Ok(Self::Regex { source: key.to_string(), regex: Arc::new(regex) })
+        } else if let Some(pattern) = key.strip_prefix('~') {
+            let regex = Regex::new(pattern)?;
+            Ok(Self::Regex { source: key.to_string(), regex: Arc::new(regex) })
+        } else if key.contains('*') {
+            Ok(Self::Wildcard(key.to_string()))
+        } else {
+            Ok(Self::Exact(key.to_string()))
+        }
+    }
+
+    /// The key as written in the configuration
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Exact(key) | Self::Wildcard(key) => key,
+            Self::Regex { source, .. } => source,
+        }
+    }
+
+    fn matches(&self, input: &str) -> bool {
+        match self {
+            Self::Exact(key) => key == input,
+            Self::Wildcard(pattern) => wildcard_matches(pattern, input),
+            Self::Regex { regex, .. } => regex.is_match(input),
+        }
+    }
+}
+
+impl TryFrom<String> for MapKey {
+    type Error = regex::Error;
+
+    fn try_from(key: String) -> Result<Self, Self::Error> {
+        Self::parse(&key)
+    }
+}
+
+impl From<MapKey> for String {
+    fn from(key: MapKey) -> Self {
+        key.as_str().to_string()
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl fmt::Debug for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MapKey").field(&self.as_str()).finish()
+    }
+}
+
+/// `*` matches any run of characters (including none)
+fn wildcard_matches(pattern: &str, input: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = input.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Evaluate a `map`.
+///
+/// - Parameter config: The map to evaluate.
+/// - Parameter ctx: Request placeholders, including the variables set so far.
+/// - Returns: The variables to set, in destination order.
+pub fn map_vars(config: &MapConfig, ctx: &PlaceholderContext) -> Vec<(String, String)> {
+    let input = config.source.render(ctx);
+    let (outputs, captures) = match config.entries.iter().find(|e| e.key.matches(&input)) {
+        Some(entry) => {
+            let captures = match &entry.key {
+                MapKey::Regex { regex, .. } => regex.captures(&input),
+                _ => None,
+            };
+            (&entry.values, captures)
+        }
+        None => (&config.defaults, None),
+    };
+
+    config.destinations.iter()
+        .zip(outputs)
+        .filter(|(_, output)| output.as_str() != SKIP)
+        .map(|(name, output)| {
+            let value = match &captures {
+                Some(captures) => output.render_with(ctx, |text, out| captures.expand(text, out)),
+                None => output.render(ctx).into_owned(),
+            };
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+/// Variables a `map` or `vars` handler sets; other handlers set none.
+///
+/// - Parameter handler: The handler to evaluate.
+/// - Parameter ctx: Request placeholders, including the variables set so far.
+/// - Returns: The variables to set.
+pub fn handler_vars(handler: &HandlerConfig, ctx: &PlaceholderContext) -> Vec<(String, String)> {
+    match handler {
+        HandlerConfig::Map(config) => map_vars(config, ctx),
+        HandlerConfig::Vars { vars } => vars.iter()
+            .map(|(name, value)| (name.clone(), value.render(ctx).into_owned()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Run `map` / `vars` handlers in order, each seeing the variables set before it.
+///
+/// - Parameter handlers: The handlers to run; others are skipped.
+/// - Parameter ctx: Request placeholders; its `vars` are ignored in favour of `vars`.
+/// - Parameter vars: The request's variables, updated in place.
+pub fn apply_vars(handlers: &[HandlerConfig], ctx: &PlaceholderContext, vars: &mut HashMap<String, String>) {
+    for handler in handlers {
+        let set = handler_vars(handler, &PlaceholderContext { vars: Some(vars), ..ctx.clone() });
+        vars.extend(set);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MapEntry;
+    use crate::server::Template;
+
+    fn template(source: &str) -> Template {
+        Template::parse(source).unwrap()
+    }
+
+    fn host_map() -> MapConfig {
+        let entry = |key: &str, values: &[&str]| MapEntry {
+            key: MapKey::parse(key).unwrap(),
+            values: values.iter().map(|v| template(v)).collect(),
+        };
+        MapConfig {
+            source: template("{host}"),
+            destinations: vec!["backend".into(), "tier".into()],
+            entries: vec![
+                entry("example.com", &["main", "gold"]),
+                entry("~^(?P<tenant>[a-z]+)\\.apps\\.example\\.com$", &["app-$tenant", "-"]),
+                entry("*.example.com", &["{host}", "silver"]),
+            ],
+            defaults: vec![template("fallback"), template("bronze")],
+        }
+    }
+
+    fn evaluate(config: &MapConfig, host: &str) -> HashMap<String, String> {
+        map_vars(config, &PlaceholderContext { host, ..Default::default() }).into_iter().collect()
+    }
+
+    #[test]
+    fn test_map_entries_in_order() {
+        let map = host_map();
+
+        let vars = evaluate(&map, "example.com");
+        assert_eq!(vars["backend"], "main");
+        assert_eq!(vars["tier"], "gold");
+
+        // Regex before wildcard; `-` leaves the destination unset
+        let vars = evaluate(&map, "acme.apps.example.com");
+        assert_eq!(vars["backend"], "app-acme");
+        assert!(!vars.contains_key("tier"));
+
+        let vars = evaluate(&map, "www.example.com");
+        assert_eq!(vars["backend"], "www.example.com");
+        assert_eq!(vars["tier"], "silver");
+
+        let vars = evaluate(&map, "other.org");
+        assert_eq!(vars["backend"], "fallback");
+        assert_eq!(vars["tier"], "bronze");
+    }
+
+    #[test]
+    fn test_map_key_forms() {
+        assert!(MapKey::parse("~*^/API/").unwrap().matches("/api/users"));
+        assert!(!MapKey::parse("~^/API/").unwrap().matches("/api/users"));
+        assert!(MapKey::parse("/static/*.css").unwrap().matches("/static/css/site.css"));
+        assert!(!MapKey::parse("/static/*.css").unwrap().matches("/static/site.js"));
+        assert!(!MapKey::parse("*a*a").unwrap().matches("a"));
+        assert!(MapKey::parse("~(").is_err());
+    }
+
+    #[test]
+    fn test_map_captures_do_not_expand_request_data() {
+        let map = MapConfig {
+            source: template("{path}"),
+            destinations: vec!["target".into()],
+            entries: vec![MapEntry {
+                key: MapKey::parse("~^/u/(?P<id>[^/]+)").unwrap(),
+                values: vec![template("$id:{query}")],
+            }],
+            defaults: vec![template("-")],
+        };
+        let ctx = PlaceholderContext { path: "/u/$1x", query: "a=$id&b=${1}", ..Default::default() };
+        let vars: HashMap<_, _> = map_vars(&map, &ctx).into_iter().collect();
+        assert_eq!(vars["target"], "$1x:a=$id&b=${1}");
+    }
+
+    #[test]
+    fn test_vars_see_earlier_vars() {
+        let handlers = vec![
+            HandlerConfig::Map(host_map()),
+            HandlerConfig::Vars {
+                vars: HashMap::from([("upstream".to_string(), template("{vars.backend}:8080"))]),
+            },
+        ];
+        let mut vars = HashMap::new();
+        apply_vars(&handlers, &PlaceholderContext { host: "example.com", ..Default::default() }, &mut vars);
+        assert_eq!(vars["upstream"], "main:8080");
+    }
+}
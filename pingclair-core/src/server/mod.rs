@@ -2,10 +2,20 @@
 
 mod tls;
 mod router;
+mod host;
+mod expression;
+mod path;
+mod placeholder;
+mod map;
 mod handlers;
 mod redirect;
 
 pub use self::tls::TlsServer;
-pub use self::router::{Router, CompiledRoute, CompiledMatcher};
-pub use self::handlers::{HandlerResponse, HandlerError, execute_handler};
+pub use self::router::{Router, CompiledRoute, CompiledMatcher, CompiledResponseMatcher, RequestInfo, RouteMatch, Captures, PathSpecificity, RouteOrderKey, route_order_key, parse_ip_ranges, path_glob_matches, PRIVATE_RANGES};
+pub use self::host::{HostIndex, normalize_host, normalize_host_pattern, host_matches};
+pub use self::expression::{Expression, ExpressionError};
+pub use self::path::{PathError, normalize_path};
+pub use self::placeholder::{Placeholder, PlaceholderContext, ProxyProtocolField, ProxyProtocolValues, Template, TemplateError, TimeFormat};
+pub use self::map::{MapKey, apply_vars, handler_vars, map_vars};
+pub use self::handlers::{HandlerResponse, HandlerError, ConfigRegex, execute_handler, rewrite_path};
 pub use self::redirect::{HttpRedirectServer, RedirectConfig};
@@ -0,0 +1,168 @@
+//! Request path normalisation
+//!
+//! 🏗️ ARCHITECTURE: Runs once per request before routing, so every matcher,
+//! handler and the file server agree on one spelling of the path:
+//!   - percent-escapes of unreserved characters are decoded (`%7Euser` → `~user`)
+//!   - remaining escapes are upper-cased (`%2f` → `%2F`)
+//!   - runs of `/` are merged (`/api//v1` → `/api/v1`)
+//!   - `.` and `..` segments are resolved and clamped at the root (`/a/../../b` → `/b`)
+//!
+//! 🛑 SAFETY: Decoding happens before dot-segment resolution, so `%2e%2e`
+//! is resolved like `..` instead of reaching a backend that decodes it later.
+
+use std::borrow::Cow;
+
+use crate::config::{EncodedSlashPolicy, PathNormalizationConfig};
+
+/// A path refused by the normalisation policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PathError {
+    #[error("encoded NUL byte in path")]
+    Nul,
+    #[error("encoded path separator in path")]
+    EncodedSlash,
+}
+
+/// Normalise a request path according to `config`.
+///
+/// - Parameter path: The path component of the request URI, without query.
+/// - Parameter config: The server's normalisation policy.
+/// - Returns: The normalised path (borrowed when unchanged), or the policy violation.
+pub fn normalize_path<'a>(path: &'a str, config: &PathNormalizationConfig) -> Result<Cow<'a, str>, PathError> {
+    // Only origin-form paths are normalised (`*` for OPTIONS passes through)
+    if !config.enabled || !path.starts_with('/') {
+        return Ok(Cow::Borrowed(path));
+    }
+
+    let decoded = if path.contains('%') { decode(path, config)? } else { Cow::Borrowed(path) };
+
+    // ⚡ OPTIMIZATION: Most paths have nothing left to resolve
+    let needs_merge = config.merge_slashes && decoded.contains("//");
+    let needs_dots = config.resolve_dots && decoded.split('/').any(|s| s == "." || s == "..");
+    if !needs_merge && !needs_dots {
+        return Ok(match decoded {
+            Cow::Owned(owned) if owned != path => Cow::Owned(owned),
+            _ => Cow::Borrowed(path),
+        });
+    }
+
+    let segments: Vec<&str> = decoded[1..].split('/').collect();
+    let last = segments.len() - 1;
+    let mut output: Vec<&str> = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.into_iter().enumerate() {
+        match segment {
+            // A trailing `.` or `..` leaves a directory path (`/a/b/..` → `/a/`)
+            "." if config.resolve_dots => {
+                if i == last { output.push(""); }
+            }
+            ".." if config.resolve_dots => {
+                output.pop();
+                if i == last { output.push(""); }
+            }
+            "" if config.merge_slashes && i != last => {}
+            segment => output.push(segment),
+        }
+    }
+
+    let normalized = format!("/{}", output.join("/"));
+    Ok(if normalized == path { Cow::Borrowed(path) } else { Cow::Owned(normalized) })
+}
+
+/// Decode escapes per policy, upper-casing the ones that stay encoded
+fn decode<'a>(path: &'a str, config: &PathNormalizationConfig) -> Result<Cow<'a, str>, PathError> {
+    let bytes = path.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = match (bytes[i], bytes.get(i + 1..i + 3)) {
+            (b'%', Some(&[hi, lo])) => hex(hi).zip(hex(lo)).map(|(hi, lo)| hi << 4 | lo),
+            _ => None,
+        };
+        let Some(byte) = escape else {
+            output.push(bytes[i]);
+            i += 1;
+            continue;
+        };
+        match byte {
+            0 if config.reject_nul => return Err(PathError::Nul),
+            b'/' | b'\\' => match config.encoded_slashes {
+                EncodedSlashPolicy::Reject => return Err(PathError::EncodedSlash),
+                EncodedSlashPolicy::Decode => output.push(b'/'),
+                EncodedSlashPolicy::Keep => push_escape(&mut output, byte),
+            },
+            b if config.decode_unreserved && is_unreserved(b) => output.push(b),
+            b => push_escape(&mut output, b),
+        }
+        i += 3;
+    }
+
+    // 🛑 SAFETY: Only ASCII bytes are ever decoded, so the output stays valid UTF-8
+    let decoded = String::from_utf8(output).expect("decoding preserves UTF-8");
+    Ok(if decoded == path { Cow::Borrowed(path) } else { Cow::Owned(decoded) })
+}
+
+fn push_escape(output: &mut Vec<u8>, byte: u8) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    output.extend_from_slice(&[b'%', HEX[(byte >> 4) as usize], HEX[(byte & 0xF) as usize]]);
+}
+
+fn hex(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
+
+/// RFC 3986 §2.3 unreserved characters
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(path: &str) -> Result<Cow<'_, str>, PathError> {
+        normalize_path(path, &PathNormalizationConfig::default())
+    }
+
+    #[test]
+    fn test_dot_segments_and_slashes() {
+        assert_eq!(normalize("/api/users").unwrap(), "/api/users");
+        assert!(matches!(normalize("/api/users").unwrap(), Cow::Borrowed(_)));
+        assert_eq!(normalize("/api/../admin").unwrap(), "/admin");
+        assert_eq!(normalize("/a/./b/../c").unwrap(), "/a/c");
+        assert_eq!(normalize("/../../etc/passwd").unwrap(), "/etc/passwd");
+        assert_eq!(normalize("/a/b/..").unwrap(), "/a/");
+        assert_eq!(normalize("//api///v1//").unwrap(), "/api/v1/");
+        assert_eq!(normalize("/").unwrap(), "/");
+        assert_eq!(normalize("*").unwrap(), "*");
+    }
+
+    #[test]
+    fn test_percent_decoding() {
+        assert_eq!(normalize("/%2e%2e/admin").unwrap(), "/admin");
+        assert_eq!(normalize("/api/%2E./admin").unwrap(), "/admin");
+        assert_eq!(normalize("/%7Euser/%41").unwrap(), "/~user/A");
+        assert_eq!(normalize("/a%20b/%e2%82%ac").unwrap(), "/a%20b/%E2%82%AC");
+        assert_eq!(normalize("/100%").unwrap(), "/100%");
+        assert_eq!(normalize("/%zz").unwrap(), "/%zz");
+    }
+
+    #[test]
+    fn test_policies() {
+        assert_eq!(normalize("/a%00b"), Err(PathError::Nul));
+        assert_eq!(normalize("/a%2fb/..").unwrap(), "/");
+        assert_eq!(normalize("/a%2Fb").unwrap(), "/a%2Fb");
+
+        let decode = PathNormalizationConfig { encoded_slashes: EncodedSlashPolicy::Decode, ..Default::default() };
+        assert_eq!(normalize_path("/a%2F..%2Fadmin", &decode).unwrap(), "/admin");
+        assert_eq!(normalize_path("/a%5cb", &decode).unwrap(), "/a/b");
+
+        let reject = PathNormalizationConfig { encoded_slashes: EncodedSlashPolicy::Reject, ..Default::default() };
+        assert_eq!(normalize_path("/a%2Fb", &reject), Err(PathError::EncodedSlash));
+
+        let keep_dots = PathNormalizationConfig { resolve_dots: false, merge_slashes: false, ..Default::default() };
+        assert_eq!(normalize_path("/a//../b", &keep_dots).unwrap(), "/a//../b");
+
+        let off = PathNormalizationConfig { enabled: false, ..Default::default() };
+        assert_eq!(normalize_path("/a/../%00", &off).unwrap(), "/a/../%00");
+    }
+}
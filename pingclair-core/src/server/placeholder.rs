@@ -0,0 +1,650 @@
+//! Placeholder registry and templates
+//!
+//! 🏗️ ARCHITECTURE: Every `{placeholder}` Pingclair understands is named here,
+//! once. Configuration strings (respond bodies, redirect targets, `header_up` /
+//! `header_down`, `header`, rewrites) are parsed into a `Template` when the
+//! configuration is loaded, so rendering only walks pre-resolved segments.
+//! The expression matcher and access log formats use the same registry.
+//!
+//! - request: `{method}`, `{host}`, `{hostport}`, `{port}`, `{path}`, `{query}`,
+//!   `{uri}`, `{scheme}`, `{proto}`, `{remote_ip}`, `{remote_port}`, `{client_ip}`,
+//!   `{request_id}`, `{header.<Name>}`, `{query.<key>}`, `{cookie.<name>}`
+//! - TLS: `{tls.version}`, `{tls.cipher}`, PROXY protocol `{http.request.proxy_protocol.*}`
+//! - upstream and response: `{upstream.address}`, `{status}`, `{http.response.header.<Name>}`
+//! - time (UTC): `{time.now}`, `{time.now.unix}`, `{time.now.unix_ms}`, `{time.now.http}`,
+//!   `{time.now.common_log}`, `{time.now.format.<strftime>}`
+//! - environment: `{env.<NAME>}` (read per request; `{$NAME}` in a config file is
+//!   substituted when the file is parsed)
+//! - route captures `{re.<name>.<group>}` and custom variables `{vars.<name>}`
+//!
+//! The Caddy `http.request.*`, `http.response.*` and `http.reverse_proxy.*`
+//! spellings are accepted too. Braces that do not name a placeholder
+//! (`{"ok": true}`, CSS rules) are kept literally, like Caddy does.
+
+use super::router::{cookie_values, query_values, Captures, RequestInfo};
+use http::{HeaderMap, HeaderName};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A placeholder whose argument is invalid (bad header name, time format)
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid placeholder {{{placeholder}}}: {reason}")]
+pub struct TemplateError {
+    pub placeholder: String,
+    pub reason: String,
+}
+
+impl TemplateError {
+    fn new(placeholder: &str, reason: impl Into<String>) -> Self {
+        Self { placeholder: placeholder.to_string(), reason: reason.into() }
+    }
+}
+
+// MARK: - Registry
+
+/// A value a `{placeholder}` resolves to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Placeholder {
+    Method,
+    /// Normalised host, without port
+    Host,
+    /// `Host` header as received
+    HostPort,
+    Port,
+    Path,
+    Query,
+    /// Path and query
+    Uri,
+    Scheme,
+    Proto,
+    /// Direct peer
+    RemoteIp,
+    RemotePort,
+    /// Client resolved through trusted proxies
+    ClientIp,
+    RequestId,
+    Header(HeaderName),
+    QueryParam(String),
+    Cookie(String),
+    TlsVersion,
+    TlsCipher,
+    ProxyProtocol(ProxyProtocolField),
+    /// `<name>.<group>` route capture
+    Capture(String),
+    /// Custom per-request variable
+    Var(String),
+    Env(String),
+    UpstreamAddr,
+    Status,
+    ResponseHeader(HeaderName),
+    Time(TimeFormat),
+}
+
+/// PROXY protocol v2 details of the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocolField {
+    Alpn,
+    Authority,
+    TlsVersion,
+    TlsCipher,
+    TlsClientCn,
+}
+
+/// Rendering of `{time.now.*}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// `2000-10-10T13:55:36Z`
+    Rfc3339,
+    Unix,
+    UnixMs,
+    /// `Tue, 10 Oct 2000 13:55:36 GMT`
+    Http,
+    /// `10/Oct/2000:13:55:36 +0000`
+    CommonLog,
+    /// strftime subset: `%Y %y %m %d %H %M %S %j %a %b %s %z %%`
+    Custom(String),
+}
+
+impl Placeholder {
+    /// Look up a placeholder name (without braces).
+    ///
+    /// - Returns: `None` for names the registry does not know, or an error
+    ///   when a known placeholder has an invalid argument.
+    pub fn parse(name: &str) -> Result<Option<Self>, TemplateError> {
+        let prefixed = |prefixes: &[&str]| {
+            prefixes.iter()
+                .find_map(|prefix| name.strip_prefix(prefix))
+                .filter(|rest| !rest.is_empty())
+        };
+        let header = |header: &str| {
+            HeaderName::from_bytes(header.as_bytes()).map_err(|_| TemplateError::new(name, "invalid header name"))
+        };
+
+        if let Some(name) = prefixed(&["header.", "http.request.header."]) {
+            return Ok(Some(Self::Header(header(name)?)));
+        }
+        if let Some(name) = prefixed(&["http.response.header.", "response.header."]) {
+            return Ok(Some(Self::ResponseHeader(header(name)?)));
+        }
+        if let Some(key) = prefixed(&["query.", "http.request.uri.query."]) {
+            return Ok(Some(Self::QueryParam(key.to_string())));
+        }
+        if let Some(cookie) = prefixed(&["cookie.", "http.request.cookie."]) {
+            return Ok(Some(Self::Cookie(cookie.to_string())));
+        }
+        if let Some(capture) = prefixed(&["re.", "http.regexp."]) {
+            return Ok(Some(Self::Capture(capture.to_string())));
+        }
+        if let Some(format) = prefixed(&["time.now.format."]) {
+            validate_time_format(format).map_err(|reason| TemplateError::new(name, reason))?;
+            return Ok(Some(Self::Time(TimeFormat::Custom(format.to_string()))));
+        }
+        if let Some(var) = prefixed(&["env."]) {
+            return Ok(Some(Self::Env(var.to_string())));
+        }
+        // `http.vars.client_ip` is Caddy's resolved client address, not a custom variable
+        if name != "http.vars.client_ip"
+            && let Some(var) = prefixed(&["vars.", "http.vars."])
+        {
+            return Ok(Some(Self::Var(var.to_string())));
+        }
+        if let Some(field) = name.strip_prefix("http.request.proxy_protocol.") {
+            return Ok(match field {
+                "alpn" => Some(Self::ProxyProtocol(ProxyProtocolField::Alpn)),
+                "authority" => Some(Self::ProxyProtocol(ProxyProtocolField::Authority)),
+                "tls_version" => Some(Self::ProxyProtocol(ProxyProtocolField::TlsVersion)),
+                "tls_cipher" => Some(Self::ProxyProtocol(ProxyProtocolField::TlsCipher)),
+                "tls_client_cn" => Some(Self::ProxyProtocol(ProxyProtocolField::TlsClientCn)),
+                _ => None,
+            });
+        }
+
+        Ok(Some(match name {
+            "method" | "http.request.method" => Self::Method,
+            "host" | "http.request.host" => Self::Host,
+            "hostport" | "http.request.hostport" => Self::HostPort,
+            "port" | "http.request.port" => Self::Port,
+            "path" | "http.request.uri.path" => Self::Path,
+            "query" | "http.request.uri.query" => Self::Query,
+            "uri" | "http.request.uri" => Self::Uri,
+            "scheme" | "http.request.scheme" => Self::Scheme,
+            "proto" | "http.request.proto" => Self::Proto,
+            "remote_ip" | "http.request.remote.host" => Self::RemoteIp,
+            "remote_port" | "http.request.remote.port" => Self::RemotePort,
+            "client_ip" | "http.vars.client_ip" => Self::ClientIp,
+            "request_id" | "http.request.uuid" | "pingclair.request_id" => Self::RequestId,
+            "tls.version" | "http.request.tls.version" => Self::TlsVersion,
+            "tls.cipher" | "http.request.tls.cipher_suite" => Self::TlsCipher,
+            "upstream.address" | "http.reverse_proxy.upstream.hostport" => Self::UpstreamAddr,
+            "status" | "http.response.status" | "http.response.status_code"
+            | "http.reverse_proxy.status_code" => Self::Status,
+            "time.now" | "time.now.rfc3339" => Self::Time(TimeFormat::Rfc3339),
+            "time.now.unix" => Self::Time(TimeFormat::Unix),
+            "time.now.unix_ms" => Self::Time(TimeFormat::UnixMs),
+            "time.now.http" => Self::Time(TimeFormat::Http),
+            "time.now.common_log" => Self::Time(TimeFormat::CommonLog),
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Resolve against a request; missing values are empty.
+    pub fn resolve<'c>(&self, ctx: &PlaceholderContext<'c>) -> Cow<'c, str> {
+        let borrowed = |value: Option<&'c str>| Cow::Borrowed(value.unwrap_or(""));
+        match self {
+            Self::Method => Cow::Borrowed(ctx.method),
+            Self::Host => Cow::Borrowed(ctx.host),
+            Self::HostPort => borrowed(ctx.host_header()),
+            Self::Port => borrowed(ctx.host_header().and_then(port_of)),
+            Self::Path => Cow::Borrowed(ctx.path),
+            Self::Query => Cow::Borrowed(ctx.query),
+            Self::Uri if ctx.query.is_empty() => Cow::Borrowed(ctx.path),
+            Self::Uri => Cow::Owned(format!("{}?{}", ctx.path, ctx.query)),
+            Self::Scheme => Cow::Borrowed(ctx.scheme),
+            Self::Proto => Cow::Borrowed(ctx.proto),
+            Self::RemoteIp => Cow::Borrowed(ctx.remote_ip),
+            Self::RemotePort => ctx.remote_port.map_or(Cow::Borrowed(""), |port| Cow::Owned(port.to_string())),
+            Self::ClientIp => Cow::Borrowed(ctx.client_ip),
+            Self::RequestId => Cow::Borrowed(ctx.request_id),
+            Self::Header(name) => ctx.headers.map_or(Cow::Borrowed(""), |headers| header_value(headers, name)),
+            Self::QueryParam(key) => Cow::Owned(query_values(ctx.query, key).into_iter().next().unwrap_or_default()),
+            Self::Cookie(name) => Cow::Owned(
+                ctx.headers.and_then(|headers| cookie_values(headers, name).into_iter().next()).unwrap_or_default(),
+            ),
+            Self::TlsVersion => borrowed(ctx.tls_version),
+            Self::TlsCipher => borrowed(ctx.tls_cipher),
+            Self::ProxyProtocol(field) => borrowed(ctx.proxy_protocol.as_ref().and_then(|pp| match field {
+                ProxyProtocolField::Alpn => pp.alpn,
+                ProxyProtocolField::Authority => pp.authority,
+                ProxyProtocolField::TlsVersion => pp.tls_version,
+                ProxyProtocolField::TlsCipher => pp.tls_cipher,
+                ProxyProtocolField::TlsClientCn => pp.tls_client_cn,
+            })),
+            Self::Capture(name) => borrowed(ctx.captures.and_then(|c| c.get(name)).map(String::as_str)),
+            Self::Var(name) => borrowed(ctx.vars.and_then(|v| v.get(name)).map(String::as_str)),
+            Self::Env(name) => Cow::Owned(std::env::var(name).unwrap_or_default()),
+            Self::UpstreamAddr => borrowed(ctx.upstream),
+            Self::Status => ctx.status.map_or(Cow::Borrowed(""), |status| Cow::Owned(status.to_string())),
+            Self::ResponseHeader(name) => {
+                ctx.response_headers.map_or(Cow::Borrowed(""), |headers| header_value(headers, name))
+            }
+            Self::Time(format) => Cow::Owned(format_time(ctx.time.unwrap_or_else(SystemTime::now), format)),
+        }
+    }
+}
+
+// MARK: - Context
+
+/// Request, connection and response data placeholders resolve against.
+///
+/// Callers fill in what they have; anything left unset resolves to an empty string.
+#[derive(Debug, Clone, Default)]
+pub struct PlaceholderContext<'a> {
+    pub method: &'a str,
+    /// Normalised host, without port
+    pub host: &'a str,
+    pub path: &'a str,
+    /// Raw query string (without `?`)
+    pub query: &'a str,
+    /// `http` / `https`
+    pub scheme: &'a str,
+    /// `HTTP/1.1`, `HTTP/2.0`, ...
+    pub proto: &'a str,
+    /// Direct peer
+    pub remote_ip: &'a str,
+    pub remote_port: Option<u16>,
+    /// Client resolved through trusted proxies
+    pub client_ip: &'a str,
+    pub request_id: &'a str,
+    pub headers: Option<&'a HeaderMap>,
+    pub tls_version: Option<&'a str>,
+    pub tls_cipher: Option<&'a str>,
+    pub proxy_protocol: Option<ProxyProtocolValues<'a>>,
+    pub captures: Option<&'a Captures>,
+    pub vars: Option<&'a HashMap<String, String>>,
+    /// Selected upstream address
+    pub upstream: Option<&'a str>,
+    /// Response status, once known
+    pub status: Option<u16>,
+    pub response_headers: Option<&'a HeaderMap>,
+    /// Time `{time.now.*}` renders (the current time when unset)
+    pub time: Option<SystemTime>,
+}
+
+/// PROXY protocol v2 TLVs of the connection
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProxyProtocolValues<'a> {
+    pub alpn: Option<&'a str>,
+    pub authority: Option<&'a str>,
+    pub tls_version: Option<&'a str>,
+    pub tls_cipher: Option<&'a str>,
+    pub tls_client_cn: Option<&'a str>,
+}
+
+impl<'a> PlaceholderContext<'a> {
+    /// Context of a routed request and its matcher captures
+    pub fn from_request(request: &RequestInfo<'a>, captures: &'a Captures) -> Self {
+        Self {
+            method: request.method,
+            host: request.host,
+            path: request.path,
+            query: request.query,
+            scheme: request.protocol,
+            remote_ip: request.remote_ip,
+            client_ip: request.client_ip,
+            headers: Some(request.headers),
+            captures: Some(captures),
+            vars: request.vars,
+            ..Default::default()
+        }
+    }
+
+    fn host_header(&self) -> Option<&'a str> {
+        self.headers?.get(http::header::HOST)?.to_str().ok()
+    }
+}
+
+/// All values of a header; several are comma-joined
+fn header_value<'c>(headers: &'c HeaderMap, name: &HeaderName) -> Cow<'c, str> {
+    let mut values = headers.get_all(name).iter().filter_map(|v| v.to_str().ok());
+    let Some(first) = values.next() else {
+        return Cow::Borrowed("");
+    };
+    match values.next() {
+        None => Cow::Borrowed(first),
+        Some(second) => {
+            let mut joined = format!("{}, {}", first, second);
+            for value in values {
+                joined.push_str(", ");
+                joined.push_str(value);
+            }
+            Cow::Owned(joined)
+        }
+    }
+}
+
+/// Port of a `Host` header value (bracketed IPv6 literals included)
+fn port_of(hostport: &str) -> Option<&str> {
+    let (host, port) = hostport.rsplit_once(':')?;
+    (!host.is_empty() && !port.contains(']') && (!host.contains(':') || host.ends_with(']'))).then_some(port)
+}
+
+// MARK: - Template
+
+/// A configuration string with `{placeholders}`, parsed once.
+///
+/// (De)serialises as its source string; parsing happens on deserialisation.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    source: Arc<str>,
+    segments: Arc<[Segment]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+impl Template {
+    /// Parse a template.
+    ///
+    /// Unknown names are kept literally.
+    ///
+    /// - Returns: The template, or an error for a known placeholder with an invalid argument.
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = source;
+
+        while let Some(open) = rest.find('{') {
+            literal.push_str(&rest[..open]);
+            let inner = &rest[open + 1..];
+            // `{{path}` keeps the first brace literally and retries from the second
+            let Some(close) = inner.find(['{', '}']).filter(|&i| inner.as_bytes()[i] == b'}') else {
+                literal.push('{');
+                rest = inner;
+                continue;
+            };
+            let name = &inner[..close];
+            match Placeholder::parse(name)? {
+                Some(placeholder) => {
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                }
+                None => {
+                    if !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b)) {
+                        tracing::warn!("⚠️ Unknown placeholder {{{}}} kept literally", name);
+                    }
+                    literal.push_str(&rest[open..open + close + 2]);
+                }
+            }
+            rest = &inner[close + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self { source: Arc::from(source), segments: segments.into() })
+    }
+
+    /// Render against a request.
+    pub fn render<'c>(&'c self, ctx: &PlaceholderContext<'c>) -> Cow<'c, str> {
+        match &*self.segments {
+            [] => Cow::Borrowed(""),
+            [Segment::Literal(text)] => Cow::Borrowed(text),
+            [Segment::Placeholder(placeholder)] => placeholder.resolve(ctx),
+            segments => {
+                let mut out = String::with_capacity(self.source.len());
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(text) => out.push_str(text),
+                        Segment::Placeholder(placeholder) => out.push_str(&placeholder.resolve(ctx)),
+                    }
+                }
+                Cow::Owned(out)
+            }
+        }
+    }
+
+    /// Render against a request, passing the template's own text through `literal`.
+    ///
+    /// Placeholder values are inserted as they are, so request data never reaches `literal`.
+    ///
+    /// - Parameter literal: Appends a literal segment to the output (e.g. expanding `$1`).
+    pub fn render_with(&self, ctx: &PlaceholderContext<'_>, mut literal: impl FnMut(&str, &mut String)) -> String {
+        let mut out = String::with_capacity(self.source.len());
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(text) => literal(text, &mut out),
+                Segment::Placeholder(placeholder) => out.push_str(&placeholder.resolve(ctx)),
+            }
+        }
+        out
+    }
+
+    /// The template as written
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether rendering never depends on the request
+    pub fn is_literal(&self) -> bool {
+        self.segments.iter().all(|s| matches!(s, Segment::Literal(_)))
+    }
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Self::parse(source)
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = TemplateError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Self::parse(&source)
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> Self {
+        template.source.to_string()
+    }
+}
+
+impl PartialEq for Template {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl fmt::Debug for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Template").field(&&*self.source).finish()
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+// MARK: - Time
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+fn validate_time_format(format: &str) -> Result<(), String> {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.next() {
+                Some('Y' | 'y' | 'm' | 'd' | 'H' | 'M' | 'S' | 'j' | 'a' | 'b' | 's' | 'z' | '%') => {}
+                Some(other) => return Err(format!("unsupported time specifier %{}", other)),
+                None => return Err("trailing %".to_string()),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn format_time(time: SystemTime, format: &TimeFormat) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    match format {
+        TimeFormat::Unix => since_epoch.as_secs().to_string(),
+        TimeFormat::UnixMs => since_epoch.as_millis().to_string(),
+        TimeFormat::Rfc3339 => strftime(time, "%Y-%m-%dT%H:%M:%SZ"),
+        TimeFormat::Http => strftime(time, "%a, %d %b %Y %H:%M:%S GMT"),
+        TimeFormat::CommonLog => strftime(time, "%d/%b/%Y:%H:%M:%S %z"),
+        TimeFormat::Custom(format) => strftime(time, format),
+    }
+}
+
+/// Format a UTC time with the validated strftime subset
+fn strftime(time: SystemTime, format: &str) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days = (secs / 86_400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let rem = secs % 86_400;
+    let day_of_year = days - days_from_civil(year, 1, 1) + 1;
+
+    let mut out = String::with_capacity(format.len() + 16);
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", year)),
+            Some('y') => out.push_str(&format!("{:02}", year % 100)),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('H') => out.push_str(&format!("{:02}", rem / 3600)),
+            Some('M') => out.push_str(&format!("{:02}", rem % 3600 / 60)),
+            Some('S') => out.push_str(&format!("{:02}", rem % 60)),
+            Some('j') => out.push_str(&format!("{:03}", day_of_year)),
+            Some('a') => out.push_str(WEEKDAYS[days.rem_euclid(7) as usize]),
+            Some('b') => out.push_str(MONTHS[month as usize - 1]),
+            Some('s') => out.push_str(&secs.to_string()),
+            Some('z') => out.push_str("+0000"),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Days since 1970-01-01 → (year, month, day), proleptic Gregorian
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// (year, month, day) → days since 1970-01-01
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn render(source: &str, ctx: &PlaceholderContext) -> String {
+        Template::parse(source).unwrap().render(ctx).into_owned()
+    }
+
+    #[test]
+    fn test_request_placeholders() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "Example.com:8443".parse().unwrap());
+        headers.insert("x-tenant", "acme".parse().unwrap());
+        headers.append("accept", "text/html".parse().unwrap());
+        headers.append("accept", "*/*".parse().unwrap());
+        headers.insert("cookie", "session=abc; theme=dark".parse().unwrap());
+        let captures = Captures::from([("path.id".to_string(), "42".to_string())]);
+        let vars = HashMap::from([("tier".to_string(), "gold".to_string())]);
+        let ctx = PlaceholderContext {
+            method: "GET",
+            host: "example.com",
+            path: "/users/42",
+            query: "page=2&sort=name",
+            scheme: "https",
+            client_ip: "203.0.113.7",
+            headers: Some(&headers),
+            captures: Some(&captures),
+            vars: Some(&vars),
+            ..Default::default()
+        };
+
+        assert_eq!(render("{method} {scheme}://{host}{uri}", &ctx), "GET https://example.com/users/42?page=2&sort=name");
+        assert_eq!(render("{http.request.hostport} port={port}", &ctx), "Example.com:8443 port=8443");
+        assert_eq!(render("{header.X-Tenant}/{http.request.header.accept}", &ctx), "acme/text/html, */*");
+        assert_eq!(render("{query.page}{cookie.theme}{re.path.id}{vars.tier}", &ctx), "2dark42gold");
+        assert_eq!(render("{http.vars.client_ip} {header.missing}|{upstream.address}|", &ctx), "203.0.113.7 ||");
+    }
+
+    #[test]
+    fn test_literal_braces() {
+        let ctx = PlaceholderContext { path: "/x", ..Default::default() };
+        assert_eq!(render(r#"{"path": "{path}"}"#, &ctx), r#"{"path": "/x"}"#);
+        assert_eq!(render("a{b c}d{{path}}{", &ctx), "a{b c}d{/x}{");
+        assert_eq!(render("{unknown.thing}", &ctx), "{unknown.thing}");
+        assert!(Template::parse("plain").unwrap().is_literal());
+        assert!(!Template::parse("{path}").unwrap().is_literal());
+    }
+
+    #[test]
+    fn test_time_and_env() {
+        let ctx = PlaceholderContext {
+            time: Some(UNIX_EPOCH + Duration::from_secs(971_186_136)),
+            ..Default::default()
+        };
+        assert_eq!(render("{time.now}", &ctx), "2000-10-10T13:55:36Z");
+        assert_eq!(render("{time.now.http}", &ctx), "Tue, 10 Oct 2000 13:55:36 GMT");
+        assert_eq!(render("{time.now.common_log}", &ctx), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(render("{time.now.unix} {time.now.format.%Y/%j}", &ctx), "971186136 2000/284");
+
+        let path = std::env::var("PATH").unwrap_or_default();
+        assert_eq!(render("{env.PATH}", &ctx), path);
+        assert!(!Template::parse("{env.PATH}").unwrap().is_literal());
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(Template::parse("{time.now.format.%Q}").is_err());
+        assert!(Template::parse("{header.bad name}").is_err());
+        let template: Template = serde_json::from_str(r#""Hello {path}""#).unwrap();
+        assert_eq!(template, Template::parse("Hello {path}").unwrap());
+        assert_eq!(serde_json::to_string(&template).unwrap(), r#""Hello {path}""#);
+        assert!(serde_json::from_str::<Template>(r#""{time.now.format.%Q}""#).is_err());
+    }
+}
@@ -1,51 +1,186 @@
-//! High-performance route matcher using radix tree
+//! High-performance route matcher using radix trees
 //!
-//! Provides O(log n) path matching with support for wildcards and parameters.
+//! Route paths and `path` matcher patterns come in three forms:
+//!   - globs: `/api/users`, `/static/*`
+//!   - segment parameters: `/users/{id}/posts/{*rest}`
+//!   - regexes (nginx `location ~`): `~^/user/(?P<id>\d+)$`, or `~*` for case-insensitive
+//!
+//! Parameters and regex captures are returned in [`RouteMatch::captures`]
+//! under the name `path` (`path.id`, `path.1`), alongside `path_regexp` captures.
+//!
+//! # Precedence
+//!
+//! Routes are tried in a fixed order, so rearranging config blocks never
+//! changes which route wins:
+//!   1. higher `priority` first (default `0`)
+//!   2. explicit `route` blocks (`source_order`), in source order
+//!   3. path specificity: exact (`/api/users`) > parameters (`/users/{id}`)
+//!      > prefix (`/api/*`, `/files/{*path}`, longest literal first)
+//!      > regex (`~...`) > catch-all (`/`, `/*`, `/{*path}`)
+//!   4. source order
+//!
+//! The first route whose path, method and matchers all accept the request wins;
+//! a more specific route that rejects it falls through to the next one.
 
-use crate::config::{RouteConfig, Matcher, MatcherCondition};
+use super::expression::Expression;
+use super::host::{host_matches, normalize_host_pattern};
+use super::placeholder::PlaceholderContext;
+use crate::config::{RouteConfig, Matcher, MatcherCondition, ResponseMatcher};
+use ipnet::IpNet;
 use matchit::Router as RadixRouter;
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
 
+/// Ranges expanded from Caddy's `private_ranges` shorthand
+pub const PRIVATE_RANGES: [&str; 6] = [
+    "192.168.0.0/16",
+    "172.16.0.0/12",
+    "10.0.0.0/8",
+    "127.0.0.0/8",
+    "fd00::/8",
+    "::1",
+];
+
 /// Pre-compiled matcher with cached regex
 #[derive(Debug, Clone)]
 pub struct CompiledMatcher {
     /// Original matcher
     pub matcher: Matcher,
-    /// Pre-compiled regex patterns (keyed by pattern string)
+    /// Pre-compiled `~regex` path patterns (keyed by pattern string)
     pub compiled_regexes: HashMap<String, Arc<regex::Regex>>,
+    /// Parsed `remote_ip` / `client_ip` networks (keyed by entry string)
+    pub ip_ranges: HashMap<String, Vec<IpNet>>,
+    /// Normalised `host` patterns (keyed by entry string)
+    pub hosts: HashMap<String, String>,
+    /// Parsed `expression` matchers (keyed by source)
+    pub expressions: HashMap<String, Arc<Expression>>,
 }
 
 impl CompiledMatcher {
-    /// Compile a matcher, pre-compiling any regex patterns
-    pub fn compile(matcher: &Matcher) -> Self {
+    /// Compile a matcher, pre-compiling any regex patterns and IP ranges
+    ///
+    /// - Returns: The compiled matcher, or why an entry is invalid.
+    pub fn compile(matcher: &Matcher) -> Result<Self, String> {
         let mut compiled_regexes = HashMap::new();
-        Self::collect_regexes(matcher, &mut compiled_regexes);
-        Self {
+        Self::collect_regexes(matcher, &mut compiled_regexes)?;
+        let mut ip_ranges = HashMap::new();
+        Self::collect_ip_ranges(matcher, &mut ip_ranges)?;
+        let mut hosts = HashMap::new();
+        Self::collect_hosts(matcher, &mut hosts);
+        let mut expressions = HashMap::new();
+        Self::collect_expressions(matcher, &mut expressions)?;
+        Ok(Self {
             matcher: matcher.clone(),
             compiled_regexes,
+            ip_ranges,
+            hosts,
+            expressions,
+        })
+    }
+
+    /// Recursively parse all expressions in a matcher
+    ///
+    /// 🛑 SAFETY: An invalid expression fails the whole matcher, as with IP ranges.
+    fn collect_expressions(matcher: &Matcher, expressions: &mut HashMap<String, Arc<Expression>>) -> Result<(), String> {
+        match matcher {
+            Matcher::Expression { expression } => {
+                let parsed = Expression::parse(expression)
+                    .map_err(|e| format!("invalid expression: {}", e.render(expression)))?;
+                expressions.insert(expression.clone(), Arc::new(parsed));
+            }
+            Matcher::And(left, right) | Matcher::Or(left, right) => {
+                Self::collect_expressions(left, expressions)?;
+                Self::collect_expressions(right, expressions)?;
+            }
+            Matcher::Not(inner) => {
+                Self::collect_expressions(inner, expressions)?;
+            }
+            _ => {}
         }
+        Ok(())
+    }
+
+    /// Recursively normalise all host patterns in a matcher
+    fn collect_hosts(matcher: &Matcher, hosts: &mut HashMap<String, String>) {
+        match matcher {
+            Matcher::Host(patterns) => {
+                for pattern in patterns {
+                    hosts.insert(pattern.clone(), normalize_host_pattern(pattern));
+                }
+            }
+            Matcher::And(left, right) | Matcher::Or(left, right) => {
+                Self::collect_hosts(left, hosts);
+                Self::collect_hosts(right, hosts);
+            }
+            Matcher::Not(inner) => {
+                Self::collect_hosts(inner, hosts);
+            }
+            _ => {}
+        }
+    }
+
+    /// Recursively parse all IP / CIDR entries in a matcher
+    ///
+    /// 🛑 SAFETY: An invalid entry fails the whole matcher; treating it as
+    /// "matches nothing" would make `not remote_ip 10.0.0.0/33` match everyone.
+    fn collect_ip_ranges(matcher: &Matcher, ranges: &mut HashMap<String, Vec<IpNet>>) -> Result<(), String> {
+        match matcher {
+            Matcher::RemoteIp(entries) | Matcher::ClientIp(entries) => {
+                for entry in entries {
+                    let nets = parse_ip_ranges(entry)
+                        .ok_or_else(|| format!("invalid IP/CIDR {:?}", entry))?;
+                    ranges.insert(entry.clone(), nets);
+                }
+            }
+            Matcher::And(left, right) | Matcher::Or(left, right) => {
+                Self::collect_ip_ranges(left, ranges)?;
+                Self::collect_ip_ranges(right, ranges)?;
+            }
+            Matcher::Not(inner) => {
+                Self::collect_ip_ranges(inner, ranges)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Whether an address falls in any of the given entries
+    fn ip_matches(&self, entries: &[String], ip: &str) -> bool {
+        let Ok(ip) = ip.parse::<IpAddr>().map(|ip| ip.to_canonical()) else {
+            return false;
+        };
+        entries.iter()
+            .filter_map(|entry| self.ip_ranges.get(entry))
+            .flatten()
+            .any(|net| net.contains(&ip))
     }
     
-    /// Recursively collect and compile all regex patterns in a matcher
-    fn collect_regexes(matcher: &Matcher, regexes: &mut HashMap<String, Arc<regex::Regex>>) {
+    /// Recursively compile all `~regex` path patterns in a matcher
+    ///
+    /// Header, query, cookie, path and host regexes are compiled when the
+    /// configuration is deserialized (see `ConfigRegex`).
+    fn collect_regexes(matcher: &Matcher, regexes: &mut HashMap<String, Arc<regex::Regex>>) -> Result<(), String> {
         match matcher {
-            Matcher::Header { condition, .. } | Matcher::Query { condition, .. } => {
-                if let MatcherCondition::Regex(pattern) = condition {
-                    if let Ok(re) = regex::Regex::new(pattern) {
+            Matcher::Path { patterns } => {
+                for pattern in patterns {
+                    if let Some(re) = compile_path_regex(pattern) {
+                        let re = re.map_err(|e| format!("invalid path regex {:?}: {}", pattern, e))?;
                         regexes.insert(pattern.clone(), Arc::new(re));
                     }
                 }
             }
             Matcher::And(left, right) | Matcher::Or(left, right) => {
-                Self::collect_regexes(left, regexes);
-                Self::collect_regexes(right, regexes);
+                Self::collect_regexes(left, regexes)?;
+                Self::collect_regexes(right, regexes)?;
             }
             Matcher::Not(inner) => {
-                Self::collect_regexes(inner, regexes);
+                Self::collect_regexes(inner, regexes)?;
             }
             _ => {}
         }
+        Ok(())
     }
     
     /// Get a pre-compiled regex by pattern
@@ -54,6 +189,87 @@ impl CompiledMatcher {
     }
 }
 
+/// Pre-compiled upstream response matcher (`handle_response`)
+#[derive(Debug, Clone)]
+pub struct CompiledResponseMatcher {
+    /// Original matcher
+    pub matcher: ResponseMatcher,
+}
+
+impl CompiledResponseMatcher {
+    /// Compile a response matcher (header regexes are compiled at load)
+    pub fn compile(matcher: &ResponseMatcher) -> Self {
+        Self {
+            matcher: matcher.clone(),
+        }
+    }
+
+    /// Check an upstream response against the matcher.
+    ///
+    /// Status entries are exact codes (`404`) or classes (`5xx`); any one
+    /// of them must match. Every header condition must hold.
+    pub fn matches(&self, status: u16, headers: &http::HeaderMap) -> bool {
+        let status_ok = self.matcher.status.is_empty()
+            || self.matcher.status.iter().any(|s| Self::status_matches(status, s));
+        if !status_ok {
+            return false;
+        }
+
+        self.matcher.headers.iter().all(|(name, condition)| {
+            let value = headers.get(name.as_str()).and_then(|v| v.to_str().ok());
+            Router::evaluate_condition(value, condition)
+        })
+    }
+
+    fn status_matches(status: u16, pattern: &str) -> bool {
+        let pattern = pattern.trim();
+        match pattern.as_bytes() {
+            [class, b'x', b'x'] | [class, b'X', b'X'] if class.is_ascii_digit() => {
+                status / 100 == (class - b'0') as u16
+            }
+            _ => pattern.parse::<u16>().map(|code| code == status).unwrap_or(false),
+        }
+    }
+}
+
+/// Request details a route is matched against
+#[derive(Debug, Clone, Copy)]
+pub struct RequestInfo<'a> {
+    /// Request path (without query)
+    pub path: &'a str,
+    /// Raw query string (without `?`, empty when absent)
+    pub query: &'a str,
+    /// Request method
+    pub method: &'a str,
+    /// Request headers
+    pub headers: &'a http::HeaderMap,
+    /// Request host, normalised with [`normalize_host`](super::normalize_host)
+    pub host: &'a str,
+    /// Socket peer address
+    pub remote_ip: &'a str,
+    /// Client address resolved through trusted proxies
+    pub client_ip: &'a str,
+    /// Request scheme (`http` / `https`)
+    pub protocol: &'a str,
+    /// Variables set by `map` / `vars` before routing
+    pub vars: Option<&'a HashMap<String, String>>,
+}
+
+/// Regex captures from a route's matchers, keyed `{name}.{group}` and `{name}.{index}`
+pub type Captures = HashMap<String, String>;
+
+/// Capture name for route path parameters and path regexes
+pub const PATH_CAPTURES: &str = "path";
+
+/// A matched route with the captures its matchers produced
+#[derive(Debug, Clone)]
+pub struct RouteMatch<'a> {
+    /// The matched route
+    pub route: &'a CompiledRoute,
+    /// Regex captures (`path_regexp`)
+    pub captures: Captures,
+}
+
 /// Route entry with precompiled matchers
 #[derive(Debug, Clone)]
 pub struct CompiledRoute {
@@ -65,179 +281,402 @@ pub struct CompiledRoute {
     pub compiled_matcher: Option<CompiledMatcher>,
 }
 
-/// High-performance router using radix tree
+/// Precedence class of a route path, most specific first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PathSpecificity {
+    /// `/api/users`
+    Exact,
+    /// `/users/{id}`
+    Parameter,
+    /// `/api/*`, `/api*`, `/files/{*path}`
+    Prefix,
+    /// `~^/user/\d+$`, `~*^/docs/`
+    Regex,
+    /// `/`, `/*`, `/{*path}` or empty
+    CatchAll,
+}
+
+impl PathSpecificity {
+    /// Classify a route path, returning the class and its literal prefix length
+    ///
+    /// A `{*rest}` parameter makes the path a prefix of its literal part, or
+    /// a catch-all when nothing but `/` precedes the first parameter.
+    pub fn of(path: &str) -> (Self, usize) {
+        if matches!(path, "" | "/" | "/*" | "*") {
+            (Self::CatchAll, 0)
+        } else if path.starts_with('~') {
+            (Self::Regex, 0)
+        } else if let Some(brace) = path.find('{') {
+            if !path.contains("{*") {
+                (Self::Parameter, brace)
+            } else if brace <= 1 {
+                (Self::CatchAll, 0)
+            } else {
+                (Self::Prefix, brace)
+            }
+        } else if let Some(prefix) = path.strip_suffix('*') {
+            (Self::Prefix, prefix.len())
+        } else {
+            (Self::Exact, path.len())
+        }
+    }
+}
+
+/// Sort key for route precedence (see the module docs); ties keep source order
+pub type RouteOrderKey = (Reverse<i32>, bool, PathSpecificity, Reverse<usize>);
+
+/// Compute the precedence key of a route path
+///
+/// - Parameter source_order: Keep the route in source order, ahead of
+///   path-sorted routes of the same priority (explicit `route` blocks).
+pub fn route_order_key(path: &str, priority: i32, source_order: bool) -> RouteOrderKey {
+    if source_order {
+        return (Reverse(priority), false, PathSpecificity::Exact, Reverse(0));
+    }
+    let (specificity, literal_len) = PathSpecificity::of(path);
+    (Reverse(priority), true, specificity, Reverse(literal_len))
+}
+
+/// Router: radix lookups for literal and parameter paths, then regex and catch-all routes
+///
+/// 🏗️ ARCHITECTURE: Every route path class has its own lookup, and each
+/// lookup yields positions in the precedence-ordered route table:
+///   - exact paths: one radix lookup
+///   - parameter paths: one radix lookup per layer; a layer only holds
+///     patterns that can never match the same path, so each lookup has at
+///     most one candidate and `/{a}/{b}` is still tried when `/users/{id}` rejects
+///     the request
+///   - prefixes: the radix hit is the longest matching prefix; looking up
+///     what precedes it finds the next one, down to the shortest
+///   - regex and catch-all routes are checked directly
+///
+/// The candidates are then tried in table order, which is precedence order.
 pub struct Router {
-    /// Radix tree for path matching
-    path_router: RadixRouter<Vec<CompiledRoute>>,
-    /// Default routes (no specific path)
-    default_routes: Vec<CompiledRoute>,
+    /// Routes in precedence order; the lookups below hold positions in it
+    routes: Vec<CompiledRoute>,
+    /// Exact paths
+    exact: RadixRouter<Vec<usize>>,
+    /// Parameter paths, layered so no two patterns of a layer overlap
+    parameters: Vec<RadixRouter<Vec<usize>>>,
+    /// Literal prefixes, inserted as themselves and as `{prefix}{*__glob}`
+    prefixes: RadixRouter<(String, Vec<usize>)>,
+    /// Regex routes (`~pattern`)
+    regexes: Vec<(Arc<regex::Regex>, usize)>,
+    /// Catch-all routes (`/`, `/*`)
+    catch_all: Vec<usize>,
     /// All routes for iteration
     all_routes: Vec<RouteConfig>,
 }
 
 impl Router {
+    /// Check that every route path regex and matcher compiles.
+    ///
+    /// Config loaders call this so a bad matcher fails the load instead of
+    /// being dropped by [`Router::new`].
+    ///
+    /// - Returns: The first route path and why its matcher is invalid.
+    pub fn validate(routes: &[RouteConfig]) -> Result<(), String> {
+        for route in routes {
+            if let Some(Err(e)) = compile_path_regex(&route.path) {
+                return Err(format!("route {}: invalid path regex: {}", route.path, e));
+            }
+            if let Some(matcher) = &route.matcher {
+                CompiledMatcher::compile(matcher)
+                    .map_err(|e| format!("route {}: {}", route.path, e))?;
+            }
+        }
+        Ok(())
+    }
+
     /// Create a new router from route configurations
+    ///
+    /// ⚠️ A route whose matcher does not compile is left out, so it can
+    /// never match (see [`Router::validate`]).
     pub fn new(routes: Vec<RouteConfig>) -> Self {
-        let mut path_router = RadixRouter::new();
-        let mut default_routes = Vec::new();
-        let mut path_groups: HashMap<String, Vec<CompiledRoute>> = HashMap::new();
-        
-        for (index, config) in routes.iter().enumerate() {
-            // Pre-compile matcher if present
-            let compiled_matcher = config.matcher.as_ref().map(CompiledMatcher::compile);
-            
-            let compiled = CompiledRoute {
-                config: config.clone(),
-                index,
-                compiled_matcher,
-            };
-            
-            // Normalize path for radix tree
-            let path = Self::normalize_path(&config.path);
-            
-            if path == "/*" || path == "/" {
-                default_routes.push(compiled);
-            } else {
-                path_groups
-                    .entry(path)
-                    .or_default()
-                    .push(compiled);
+        let mut table: Vec<CompiledRoute> = routes.iter().enumerate()
+            .filter_map(|(index, config)| {
+                // Pre-compile matcher if present
+                let compiled_matcher = match config.matcher.as_ref().map(CompiledMatcher::compile).transpose() {
+                    Ok(compiled) => compiled,
+                    Err(e) => {
+                        tracing::error!("❌ Skipping route {}: {}", config.path, e);
+                        return None;
+                    }
+                };
+                Some(CompiledRoute { config: config.clone(), index, compiled_matcher })
+            })
+            .collect();
+
+        // Stable sort: equal keys keep source order
+        table.sort_by_key(|route| route_order_key(&route.config.path, route.config.priority, route.config.source_order));
+
+        let mut exact: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut parameters: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut prefixes: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut regexes = Vec::new();
+        let mut catch_all = Vec::new();
+
+        for (position, route) in table.iter().enumerate() {
+            let path = route.config.path.as_str();
+            match PathSpecificity::of(path).0 {
+                _ if !path.starts_with('~') && path.contains('{') => parameters.entry(path).or_default().push(position),
+                PathSpecificity::Exact => exact.entry(path).or_default().push(position),
+                PathSpecificity::Parameter => parameters.entry(path).or_default().push(position),
+                PathSpecificity::Prefix => prefixes.entry(path.trim_end_matches('*')).or_default().push(position),
+                PathSpecificity::Regex => match compile_path_regex(path) {
+                    Some(Ok(re)) => regexes.push((Arc::new(re), position)),
+                    _ => tracing::warn!("Failed to compile regex route {}", path),
+                },
+                PathSpecificity::CatchAll => catch_all.push(position),
             }
         }
-        
-        // Insert path groups into radix router
-        for (path, routes) in path_groups {
-            // Convert glob patterns to matchit format
-            let matchit_path = Self::glob_to_matchit(&path);
-            if let Err(e) = path_router.insert(&matchit_path, routes) {
+
+        let mut exact_router = RadixRouter::new();
+        for (path, positions) in exact {
+            if let Err(e) = exact_router.insert(path, positions) {
                 tracing::warn!("Failed to insert route {}: {}", path, e);
             }
         }
-        
+
+        // Layers are filled in precedence order, so the result does not depend on hashing
+        let mut parameters: Vec<(&str, Vec<usize>)> = parameters.into_iter().collect();
+        parameters.sort_by_key(|(_, positions)| positions[0]);
+        let mut parameter_layers: Vec<(Vec<&str>, RadixRouter<Vec<usize>>)> = Vec::new();
+        for (path, positions) in parameters {
+            // First layer with no overlapping pattern that takes it without a conflict, else a new one
+            let mut inserted = None;
+            for (paths, layer) in parameter_layers.iter_mut() {
+                if paths.iter().any(|other| patterns_overlap(path, other)) {
+                    continue;
+                }
+                match layer.insert(path, positions.clone()) {
+                    Err(matchit::InsertError::Conflict { .. }) => continue,
+                    result => {
+                        if result.is_ok() {
+                            paths.push(path);
+                        }
+                        inserted = Some(result);
+                        break;
+                    }
+                }
+            }
+            let inserted = inserted.unwrap_or_else(|| {
+                let mut layer = RadixRouter::new();
+                let result = layer.insert(path, positions);
+                if result.is_ok() {
+                    parameter_layers.push((vec![path], layer));
+                }
+                result
+            });
+            if let Err(e) = inserted {
+                tracing::warn!("Failed to insert route {}: {}", path, e);
+            }
+        }
+
+        let parameters = parameter_layers.into_iter().map(|(_, layer)| layer).collect();
+
+        let mut prefix_router = RadixRouter::new();
+        for (prefix, positions) in prefixes {
+            let inserted = prefix_router.insert(prefix, (prefix.to_string(), positions.clone()))
+                .and_then(|()| prefix_router.insert(format!("{}{{*{}}}", prefix, GLOB_PARAM), (prefix.to_string(), positions)));
+            if let Err(e) = inserted {
+                tracing::warn!("Failed to insert route {}*: {}", prefix, e);
+            }
+        }
+
         Self {
-            path_router,
-            default_routes,
+            routes: table,
+            exact: exact_router,
+            parameters,
+            prefixes: prefix_router,
+            regexes,
+            catch_all,
             all_routes: routes,
         }
     }
     
-    /// Match a request path and return matching routes
+    /// Routes whose path matches, in precedence order
     pub fn match_path(&self, path: &str) -> Vec<&CompiledRoute> {
-        let mut matches = Vec::new();
-        
-        // Try radix tree match first
-        if let Ok(matched) = self.path_router.at(path) {
-            for route in matched.value.iter() {
-                matches.push(route);
+        self.path_candidates(path).into_iter().map(|(route, _)| route).collect()
+    }
+
+    /// Routes whose path matches, with the parameters / captures of that path
+    fn path_candidates(&self, path: &str) -> Vec<(&CompiledRoute, Captures)> {
+        let mut hits: Vec<(usize, Captures)> = Vec::new();
+
+        if let Ok(matched) = self.exact.at(path) {
+            hits.extend(matched.value.iter().map(|&position| (position, Captures::new())));
+        }
+
+        for layer in &self.parameters {
+            if let Ok(matched) = layer.at(path) {
+                let params: Captures = matched.params.iter()
+                    .map(|(name, value)| (format!("{}.{}", PATH_CAPTURES, name), value.to_string()))
+                    .collect();
+                hits.extend(matched.value.iter().map(|&position| (position, params.clone())));
             }
         }
-        
-        // Add default routes
-        for route in &self.default_routes {
-            matches.push(route);
+
+        // Longest prefix first, then the longest one of the text before it, ...
+        let mut rest = path;
+        while let Ok(matched) = self.prefixes.at(rest) {
+            let (prefix, positions) = matched.value;
+            hits.extend(positions.iter().map(|&position| (position, Captures::new())));
+            match prefix.char_indices().next_back() {
+                Some((last, _)) => rest = &path[..last],
+                None => break,
+            }
         }
-        
-        matches
+
+        for (re, position) in &self.regexes {
+            let mut captures = Captures::new();
+            if capture_regex(re, path, PATH_CAPTURES, &mut captures) {
+                hits.push((*position, captures));
+            }
+        }
+
+        hits.extend(self.catch_all.iter().map(|&position| (position, Captures::new())));
+
+        hits.sort_by_key(|(position, _)| *position);
+        hits.into_iter().map(|(position, captures)| (&self.routes[position], captures)).collect()
     }
     
-    /// Match request with full context (path, headers, method)
-    pub fn match_request(
-        &self,
-        path: &str,
-        method: &str,
-        headers: &http::HeaderMap,
-        host: &str,
-        remote_ip: &str,
-        protocol: &str,
-    ) -> Option<&CompiledRoute> {
-        let candidates = self.match_path(path);
-        
-        for route in candidates {
+    /// Match request with full context (path, query, headers, method)
+    ///
+    /// Routes marked `internal` are never returned; see [`Router::match_internal_request`].
+    pub fn match_request(&self, request: &RequestInfo) -> Option<RouteMatch<'_>> {
+        self.match_request_inner(request, false)
+    }
+
+    /// Match an internal redirect target (e.g. `X-Accel-Redirect`).
+    ///
+    /// Same as [`Router::match_request`] but internal routes are eligible.
+    pub fn match_internal_request(&self, request: &RequestInfo) -> Option<RouteMatch<'_>> {
+        self.match_request_inner(request, true)
+    }
+
+    fn match_request_inner(&self, request: &RequestInfo, allow_internal: bool) -> Option<RouteMatch<'_>> {
+        for (route, mut captures) in self.path_candidates(request.path) {
+            // Internal routes are only reachable through internal redirects
+            if route.config.internal && !allow_internal {
+                continue;
+            }
+
             // Check method constraint
             if let Some(methods) = &route.config.methods {
-                if !methods.iter().any(|m| m.eq_ignore_ascii_case(method)) {
+                if !methods.iter().any(|m| m.eq_ignore_ascii_case(request.method)) {
                     continue;
                 }
             }
             
             // Check additional matchers (using pre-compiled version)
             if let Some(compiled) = &route.compiled_matcher {
-                if !Self::evaluate_matcher_compiled(compiled, path, method, headers, host, remote_ip, protocol) {
+                if !Self::evaluate_matcher_inner(&compiled.matcher, compiled, request, &mut captures) {
                     continue;
                 }
             }
             
-            return Some(route);
+            return Some(RouteMatch { route, captures });
         }
         
         None
     }
     
-    /// Evaluate a pre-compiled matcher against request context
-    fn evaluate_matcher_compiled(
-        compiled: &CompiledMatcher,
-        path: &str,
-        method: &str,
-        headers: &http::HeaderMap,
-        host: &str,
-        remote_ip: &str,
-        protocol: &str,
-    ) -> bool {
-        Self::evaluate_matcher_inner(&compiled.matcher, compiled, path, method, headers, host, remote_ip, protocol)
-    }
-    
     /// Inner matcher evaluation with access to pre-compiled regexes
+    ///
+    /// Captures are only kept from branches that matched.
     fn evaluate_matcher_inner(
         matcher: &Matcher,
         compiled: &CompiledMatcher,
-        path: &str,
-        method: &str,
-        headers: &http::HeaderMap,
-        host: &str,
-        remote_ip: &str,
-        protocol: &str,
+        request: &RequestInfo,
+        captures: &mut Captures,
     ) -> bool {
         match matcher {
             Matcher::Path { patterns } => {
-                patterns.iter().any(|p| Self::path_matches(path, p))
+                patterns.iter().any(|p| match compiled.get_regex(p).filter(|_| p.starts_with('~')) {
+                    Some(re) => capture_regex(re, request.path, PATH_CAPTURES, captures),
+                    None => Self::path_matches(request.path, p, captures),
+                })
             }
             Matcher::Header { name, condition } => {
-                let header_value = headers.get(name)
+                let header_value = request.headers.get(name)
                     .and_then(|v| v.to_str().ok());
-                Self::evaluate_condition(header_value, condition, compiled)
+                Self::evaluate_condition(header_value, condition)
+            }
+            Matcher::HeaderRegexp { name, field, pattern } => {
+                request.headers.get_all(field).iter()
+                    .filter_map(|v| v.to_str().ok())
+                    .any(|v| capture_regex(pattern.regex(), v, name, captures))
             }
             Matcher::Method { methods } => {
-                methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+                methods.iter().any(|m| m.eq_ignore_ascii_case(request.method))
+            }
+            Matcher::Query { name, condition } => {
+                Self::evaluate_values(query_values(request.query, name), condition)
             }
-            Matcher::Query { name: _, condition: _ } => {
-                // Query matching would need query string parsing
-                true
+            Matcher::Cookie { name, condition } => {
+                Self::evaluate_values(cookie_values(request.headers, name), condition)
+            }
+            Matcher::PathRegexp { name, pattern } => {
+                capture_regex(pattern.regex(), request.path, name, captures)
             }
             Matcher::Host(hosts) => {
-                hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+                hosts.iter()
+                    .filter_map(|h| compiled.hosts.get(h))
+                    .any(|pattern| host_matches(pattern, request.host))
             }
-            Matcher::RemoteIp(ips) => {
-                ips.iter().any(|ip| remote_ip == ip)
+            Matcher::HostRegexp { name, pattern } => {
+                capture_regex(pattern.regex(), request.host, name, captures)
             }
+            Matcher::RemoteIp(ips) => compiled.ip_matches(ips, request.remote_ip),
+            Matcher::ClientIp(ips) => compiled.ip_matches(ips, request.client_ip),
             Matcher::Protocol(protocols) => {
-                protocols.iter().any(|p| p.eq_ignore_ascii_case(protocol))
+                protocols.iter().any(|p| p.eq_ignore_ascii_case(request.protocol))
+            }
+            Matcher::Expression { expression } => {
+                compiled.expressions.get(expression)
+                    .is_some_and(|e| e.evaluate(request, captures))
+            }
+            Matcher::Vars { key, values } => {
+                let value = key.render(&PlaceholderContext::from_request(request, captures));
+                values.iter().any(|v| *v == value)
             }
             Matcher::And(left, right) => {
-                Self::evaluate_matcher_inner(left, compiled, path, method, headers, host, remote_ip, protocol)
-                    && Self::evaluate_matcher_inner(right, compiled, path, method, headers, host, remote_ip, protocol)
+                let mut scratch = captures.clone();
+                let matched = Self::evaluate_matcher_inner(left, compiled, request, &mut scratch)
+                    && Self::evaluate_matcher_inner(right, compiled, request, &mut scratch);
+                if matched {
+                    *captures = scratch;
+                }
+                matched
             }
             Matcher::Or(left, right) => {
-                Self::evaluate_matcher_inner(left, compiled, path, method, headers, host, remote_ip, protocol)
-                    || Self::evaluate_matcher_inner(right, compiled, path, method, headers, host, remote_ip, protocol)
+                let mut scratch = captures.clone();
+                if Self::evaluate_matcher_inner(left, compiled, request, &mut scratch) {
+                    *captures = scratch;
+                    return true;
+                }
+                Self::evaluate_matcher_inner(right, compiled, request, captures)
             }
             Matcher::Not(inner) => {
-                !Self::evaluate_matcher_inner(inner, compiled, path, method, headers, host, remote_ip, protocol)
+                !Self::evaluate_matcher_inner(inner, compiled, request, &mut Captures::new())
             }
         }
     }
+
+    /// Evaluate a condition against every value of a repeated field.
+    ///
+    /// `Exists` holds when the field is present (even with an empty value);
+    /// other conditions hold when any value satisfies them.
+    fn evaluate_values(values: Vec<String>, condition: &MatcherCondition) -> bool {
+        match condition {
+            MatcherCondition::Exists => !values.is_empty(),
+            _ => values.iter().any(|v| Self::evaluate_condition(Some(v), condition)),
+        }
+    }
     
-    /// Evaluate a condition against a value (using pre-compiled regex)
-    fn evaluate_condition(value: Option<&str>, condition: &MatcherCondition, compiled: &CompiledMatcher) -> bool {
+    /// Evaluate a condition against a value
+    fn evaluate_condition(value: Option<&str>, condition: &MatcherCondition) -> bool {
         match condition {
             MatcherCondition::Exists => value.is_some(),
             MatcherCondition::Equals(expected) => {
@@ -253,45 +692,25 @@ impl Router {
                 value.map(|v| v.ends_with(suffix)).unwrap_or(false)
             }
             MatcherCondition::Regex(pattern) => {
-                // Use pre-compiled regex for performance
-                if let Some(re) = compiled.get_regex(pattern) {
-                    value.map(|v| re.is_match(v)).unwrap_or(false)
-                } else {
-                    // Fallback (shouldn't happen normally)
-                    false
-                }
+                value.is_some_and(|v| pattern.regex().is_match(v))
             }
         }
     }
     
-    /// Check if path matches a glob pattern
-    fn path_matches(path: &str, pattern: &str) -> bool {
-        if pattern.ends_with("/*") {
-            let prefix = &pattern[..pattern.len() - 2];
-            path.starts_with(prefix)
-        } else if pattern.ends_with("*") {
-            let prefix = &pattern[..pattern.len() - 1];
-            path.starts_with(prefix)
-        } else {
-            path == pattern
-        }
-    }
-    
-    /// Normalize path for consistent matching
-    fn normalize_path(path: &str) -> String {
-        let path = if path.is_empty() { "/" } else { path };
-        path.to_string()
-    }
-    
-    /// Convert glob pattern to matchit format
-    fn glob_to_matchit(path: &str) -> String {
-        if path.ends_with("/*") {
-            format!("{}/{{*rest}}", &path[..path.len() - 2])
-        } else if path.ends_with("*") {
-            format!("{}{{*rest}}", &path[..path.len() - 1])
-        } else {
-            path.to_string()
+    /// Check if path matches a glob or `{param}` pattern
+    fn path_matches(path: &str, pattern: &str, captures: &mut Captures) -> bool {
+        if pattern.contains('{') {
+            return match match_segments(path, pattern) {
+                Some(params) => {
+                    for (name, value) in params {
+                        captures.insert(format!("{}.{}", PATH_CAPTURES, name), value);
+                    }
+                    true
+                }
+                None => false,
+            };
         }
+        path_glob_matches(path, pattern)
     }
     
     /// Get all routes
@@ -300,6 +719,157 @@ impl Router {
     }
 }
 
+/// Whether a path matches a glob: exact, `/prefix/*` or `/prefix*`
+///
+/// `/prefix/*` stops at a segment boundary: it matches `/prefix` and
+/// `/prefix/...` but not `/prefixes`, while `/prefix*` matches any suffix.
+pub fn path_glob_matches(path: &str, pattern: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix("/*") {
+        path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    } else if let Some(prefix) = pattern.strip_suffix('*') {
+        path.starts_with(prefix)
+    } else {
+        path == pattern
+    }
+}
+
+/// Catch-all parameter generated for prefix routes (not exposed as a capture)
+const GLOB_PARAM: &str = "__glob";
+
+/// Whether two parameter patterns can match the same path
+///
+/// Segments are compared pairwise: two literals must be equal, a segment
+/// with a parameter may match anything and `{*rest}` matches the remainder.
+/// Erring towards "overlap" only costs an extra radix layer.
+fn patterns_overlap(a: &str, b: &str) -> bool {
+    let mut a = a.split('/');
+    let mut b = b.split('/');
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return true,
+            (Some(x), _) | (_, Some(x)) if x.starts_with("{*") => return true,
+            (Some(x), Some(y)) if x == y || x.contains('{') || y.contains('{') => continue,
+            _ => return false,
+        }
+    }
+}
+
+/// Parse a `remote_ip` / `client_ip` entry: an IP, a CIDR block or `private_ranges`
+///
+/// - Returns: The networks the entry covers, or `None` when it is invalid.
+pub fn parse_ip_ranges(entry: &str) -> Option<Vec<IpNet>> {
+    if entry == "private_ranges" {
+        PRIVATE_RANGES.iter().map(|r| parse_ip_net(r)).collect()
+    } else {
+        parse_ip_net(entry).map(|net| vec![net])
+    }
+}
+
+/// Parse an IP / CIDR entry; bare addresses become single-host networks
+pub(super) fn parse_ip_net(entry: &str) -> Option<IpNet> {
+    entry.parse::<IpNet>().ok()
+        .or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Compile a `~regex` / `~*regex` path pattern (None for other patterns)
+fn compile_path_regex(pattern: &str) -> Option<Result<regex::Regex, regex::Error>> {
+    let source = match pattern.strip_prefix("~*") {
+        Some(rest) => format!("(?i){}", rest),
+        None => pattern.strip_prefix('~')?.to_string(),
+    };
+    Some(regex::Regex::new(&source))
+}
+
+/// Match a regex and record its numbered and named groups as `{name}.{group}`
+fn capture_regex(re: &regex::Regex, value: &str, name: &str, captures: &mut Captures) -> bool {
+    let Some(caps) = re.captures(value) else {
+        return false;
+    };
+    for (index, group) in re.capture_names().enumerate() {
+        let Some(m) = caps.get(index) else { continue };
+        captures.insert(format!("{}.{}", name, index), m.as_str().to_string());
+        if let Some(group) = group {
+            captures.insert(format!("{}.{}", name, group), m.as_str().to_string());
+        }
+    }
+    true
+}
+
+/// Match `/users/{id}` style patterns segment by segment.
+///
+/// `{name}` matches one segment and a final `{*name}` the rest of the path.
+fn match_segments(path: &str, pattern: &str) -> Option<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    let mut path_rest = path.trim_start_matches('/');
+    let mut segments = pattern.trim_start_matches('/').split('/').peekable();
+
+    while let Some(segment) = segments.next() {
+        if let Some(name) = segment.strip_prefix("{*").and_then(|s| s.strip_suffix('}')) {
+            params.push((name.to_string(), path_rest.to_string()));
+            return segments.peek().is_none().then_some(params);
+        }
+        let (value, rest) = path_rest.split_once('/').unwrap_or((path_rest, ""));
+        match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            Some(name) if !value.is_empty() => params.push((name.to_string(), value.to_string())),
+            Some(_) => return None,
+            None if segment == value => {}
+            None => return None,
+        }
+        if segments.peek().is_some() && !path_rest.contains('/') {
+            return None;
+        }
+        path_rest = rest;
+    }
+    path_rest.is_empty().then_some(params)
+}
+
+/// Decoded values of a query parameter, in order of appearance
+pub(super) fn query_values(query: &str, name: &str) -> Vec<String> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_query_component(key) == name).then(|| decode_query_component(value))
+        })
+        .collect()
+}
+
+/// Values of the cookies named `name` across all `Cookie` headers
+pub(super) fn cookie_values(headers: &http::HeaderMap, name: &str) -> Vec<String> {
+    headers.get_all(http::header::COOKIE).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .filter(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"').to_string())
+        .collect()
+}
+
+/// Percent-decode a query component (`+` is a space)
+fn decode_query_component(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = |b: u8| (b as char).to_digit(16);
+                match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                    (Some(hi), Some(lo)) => {
+                        out.push((hi * 16 + lo) as u8);
+                        i += 2;
+                    }
+                    _ => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
 impl Default for Router {
     fn default() -> Self {
         Self::new(Vec::new())
@@ -310,6 +880,7 @@ impl Default for Router {
 mod tests {
     use super::*;
     use crate::config::HandlerConfig;
+    use crate::server::ConfigRegex;
     
     fn make_route(path: &str) -> RouteConfig {
         RouteConfig {
@@ -321,6 +892,9 @@ mod tests {
             },
             methods: None,
             matcher: None,
+            internal: false,
+            priority: 0,
+            source_order: false,
         }
     }
     
@@ -360,4 +934,473 @@ mod tests {
         let matched = router.match_path("/unknown");
         assert!(!matched.is_empty());
     }
+
+    #[test]
+    fn test_internal_route_hidden_from_clients() {
+        let mut protected = make_route("/protected/*");
+        protected.internal = true;
+        let router = Router::new(vec![protected]);
+        let headers = http::HeaderMap::new();
+        let request = make_request("/protected/file.zip", "", &headers);
+
+        assert!(router.match_request(&request).is_none());
+        let matched = router.match_internal_request(&request);
+        assert_eq!(matched.map(|m| m.route.index), Some(0));
+    }
+
+    fn make_request<'a>(path: &'a str, query: &'a str, headers: &'a http::HeaderMap) -> RequestInfo<'a> {
+        RequestInfo {
+            path,
+            query,
+            method: "GET",
+            headers,
+            host: "example.com",
+            remote_ip: "127.0.0.1",
+            client_ip: "127.0.0.1",
+            protocol: "http",
+            vars: None,
+        }
+    }
+
+    fn matcher_route(matcher: Matcher) -> Router {
+        let mut route = make_route("/*");
+        route.matcher = Some(matcher);
+        Router::new(vec![route])
+    }
+
+    #[test]
+    fn test_regex_route_captures() {
+        let routes = vec![
+            make_route("/static/*"),
+            make_route(r"~^/user/(?P<id>\d+)/(\w+)$"),
+            make_route("~*^/DOCS/"),
+            make_route("/*"),
+        ];
+        let router = Router::new(routes);
+        let headers = http::HeaderMap::new();
+
+        let matched = router.match_request(&make_request("/user/42/settings", "", &headers)).unwrap();
+        assert_eq!(matched.route.index, 1);
+        assert_eq!(matched.captures.get("path.id").map(String::as_str), Some("42"));
+        assert_eq!(matched.captures.get("path.1").map(String::as_str), Some("42"));
+        assert_eq!(matched.captures.get("path.2").map(String::as_str), Some("settings"));
+
+        let matched = router.match_request(&make_request("/docs/intro", "", &headers)).unwrap();
+        assert_eq!(matched.route.index, 2);
+
+        // Globs do not leak their catch-all parameter
+        let matched = router.match_request(&make_request("/static/app.js", "", &headers)).unwrap();
+        assert_eq!(matched.route.index, 0);
+        assert!(matched.captures.is_empty());
+
+        let matched = router.match_request(&make_request("/user/abc/settings", "", &headers)).unwrap();
+        assert_eq!(matched.route.index, 3);
+    }
+
+    #[test]
+    fn test_segment_params() {
+        let router = Router::new(vec![make_route("/users/{id}/files/{*file}")]);
+        let headers = http::HeaderMap::new();
+
+        let matched = router.match_request(&make_request("/users/7/files/a/b.txt", "", &headers)).unwrap();
+        assert_eq!(matched.captures.get("path.id").map(String::as_str), Some("7"));
+        assert_eq!(matched.captures.get("path.file").map(String::as_str), Some("a/b.txt"));
+
+        // The same patterns work inside `path` matchers
+        let matcher = matcher_route(Matcher::Path { patterns: vec!["/orders/{order}".to_string()] });
+        let matched = matcher.match_request(&make_request("/orders/991", "", &headers)).unwrap();
+        assert_eq!(matched.captures.get("path.order").map(String::as_str), Some("991"));
+        assert!(matcher.match_request(&make_request("/orders/991/items", "", &headers)).is_none());
+        assert!(matcher.match_request(&make_request("/orders", "", &headers)).is_none());
+
+        assert_eq!(match_segments("/a/x/c", "/a/{b}/c"), Some(vec![("b".to_string(), "x".to_string())]));
+        assert_eq!(match_segments("/a/x", "/a/{b}/c"), None);
+        assert_eq!(match_segments("/a//c", "/a/{b}/c"), None);
+    }
+
+    #[test]
+    fn test_query_matcher() {
+        let router = matcher_route(Matcher::Query {
+            name: "tag".to_string(),
+            condition: MatcherCondition::Equals("rust lang".to_string()),
+        });
+        let headers = http::HeaderMap::new();
+
+        // Repeated keys: any value may match; values are percent-decoded
+        assert!(router.match_request(&make_request("/", "tag=go&tag=rust+lang", &headers)).is_some());
+        assert!(router.match_request(&make_request("/", "tag=rust%20lang", &headers)).is_some());
+        assert!(router.match_request(&make_request("/", "tag=go", &headers)).is_none());
+        assert!(router.match_request(&make_request("/", "", &headers)).is_none());
+
+        let presence = matcher_route(Matcher::Query {
+            name: "debug".to_string(),
+            condition: MatcherCondition::Exists,
+        });
+        assert!(presence.match_request(&make_request("/", "a=1&debug", &headers)).is_some());
+        assert!(presence.match_request(&make_request("/", "debugging=1", &headers)).is_none());
+
+        let prefix = matcher_route(Matcher::Query {
+            name: "v".to_string(),
+            condition: MatcherCondition::Regex(ConfigRegex::parse("^[0-9]+$").unwrap()),
+        });
+        assert!(prefix.match_request(&make_request("/", "v=42", &headers)).is_some());
+        assert!(prefix.match_request(&make_request("/", "v=4x", &headers)).is_none());
+    }
+
+    #[test]
+    fn test_cookie_matcher() {
+        let router = matcher_route(Matcher::Cookie {
+            name: "session".to_string(),
+            condition: MatcherCondition::StartsWith("adm".to_string()),
+        });
+        let mut headers = http::HeaderMap::new();
+        assert!(router.match_request(&make_request("/", "", &headers)).is_none());
+
+        headers.append("cookie", "theme=dark; session=user1".parse().unwrap());
+        assert!(router.match_request(&make_request("/", "", &headers)).is_none());
+        headers.append("cookie", "session=admin".parse().unwrap());
+        assert!(router.match_request(&make_request("/", "", &headers)).is_some());
+    }
+
+    #[test]
+    fn test_path_globs_stop_at_segment_boundaries() {
+        assert!(path_glob_matches("/status", "/status/*"));
+        assert!(path_glob_matches("/status/live", "/status/*"));
+        assert!(!path_glob_matches("/statuses", "/status/*"));
+        assert!(path_glob_matches("/statuses", "/status*"));
+        assert!(path_glob_matches("/", "/*"));
+        assert!(!path_glob_matches("/status/", "/status"));
+    }
+
+    #[test]
+    fn test_path_regexp_captures() {
+        let router = matcher_route(Matcher::Or(
+            Box::new(Matcher::PathRegexp {
+                name: "static".to_string(),
+                pattern: ConfigRegex::parse(r"^/assets/(?P<file>.+)\.(css|js)$").unwrap(),
+            }),
+            Box::new(Matcher::Not(Box::new(Matcher::Path { patterns: vec!["/private/*".to_string()] }))),
+        ));
+        let headers = http::HeaderMap::new();
+
+        let matched = router.match_request(&make_request("/assets/app.min.css", "", &headers)).unwrap();
+        assert_eq!(matched.captures.get("static.file").map(String::as_str), Some("app.min"));
+        assert_eq!(matched.captures.get("static.1").map(String::as_str), Some("app.min"));
+        assert_eq!(matched.captures.get("static.2").map(String::as_str), Some("css"));
+        assert_eq!(matched.captures.get("static.0").map(String::as_str), Some("/assets/app.min.css"));
+
+        // Matched through the other branch: no captures
+        let matched = router.match_request(&make_request("/index.html", "", &headers)).unwrap();
+        assert!(matched.captures.is_empty());
+        assert!(router.match_request(&make_request("/private/key", "", &headers)).is_none());
+    }
+
+    #[test]
+    fn test_response_matcher_status() {
+        let matcher = CompiledResponseMatcher::compile(&ResponseMatcher {
+            status: vec!["5xx".to_string(), "404".to_string()],
+            headers: HashMap::new(),
+        });
+        let headers = http::HeaderMap::new();
+
+        assert!(matcher.matches(502, &headers));
+        assert!(matcher.matches(404, &headers));
+        assert!(!matcher.matches(200, &headers));
+        assert!(!matcher.matches(403, &headers));
+    }
+
+    #[test]
+    fn test_response_matcher_headers() {
+        let mut conditions = HashMap::new();
+        conditions.insert(
+            "X-Accel-Redirect".to_string(),
+            MatcherCondition::Regex(ConfigRegex::parse("^/internal/").unwrap()),
+        );
+        let matcher = CompiledResponseMatcher::compile(&ResponseMatcher {
+            status: Vec::new(),
+            headers: conditions,
+        });
+
+        let mut headers = http::HeaderMap::new();
+        assert!(!matcher.matches(200, &headers));
+        headers.insert("x-accel-redirect", "/internal/file.bin".parse().unwrap());
+        assert!(matcher.matches(200, &headers));
+        headers.insert("x-accel-redirect", "/public/file.bin".parse().unwrap());
+        assert!(!matcher.matches(200, &headers));
+    }
+
+    #[test]
+    fn test_remote_ip_cidr() {
+        let router = matcher_route(Matcher::RemoteIp(vec![
+            "192.168.1.0/24".to_string(),
+            "2001:db8::/32".to_string(),
+            "203.0.113.7".to_string(),
+        ]));
+        let headers = http::HeaderMap::new();
+        let from = |ip: &'static str| RequestInfo { remote_ip: ip, ..make_request("/", "", &headers) };
+
+        assert!(router.match_request(&from("192.168.1.77")).is_some());
+        assert!(router.match_request(&from("2001:db8::1")).is_some());
+        assert!(router.match_request(&from("203.0.113.7")).is_some());
+        assert!(router.match_request(&from("::ffff:192.168.1.5")).is_some());
+        assert!(router.match_request(&from("192.168.2.1")).is_none());
+        assert!(router.match_request(&from("203.0.113.8")).is_none());
+        assert!(router.match_request(&from("not-an-ip")).is_none());
+    }
+
+    #[test]
+    fn test_client_ip_private_ranges() {
+        let router = matcher_route(Matcher::ClientIp(vec!["private_ranges".to_string()]));
+        let headers = http::HeaderMap::new();
+
+        // Behind a trusted proxy: the socket peer is private, the client is not
+        let proxied = RequestInfo {
+            remote_ip: "10.0.0.2",
+            client_ip: "198.51.100.4",
+            ..make_request("/", "", &headers)
+        };
+        assert!(router.match_request(&proxied).is_none());
+
+        let internal = RequestInfo { client_ip: "172.20.1.1", ..proxied };
+        assert!(router.match_request(&internal).is_some());
+        let loopback_v6 = RequestInfo { client_ip: "::1", ..proxied };
+        assert!(router.match_request(&loopback_v6).is_some());
+
+        let remote = matcher_route(Matcher::RemoteIp(vec!["private_ranges".to_string()]));
+        assert!(remote.match_request(&proxied).is_some());
+    }
+
+    #[test]
+    fn test_invalid_ip_ranges_are_rejected() {
+        assert_eq!(parse_ip_ranges("127.0.0.0/8"), Some(vec!["127.0.0.0/8".parse().unwrap()]));
+        assert!(parse_ip_ranges("private_ranges").unwrap().contains(&"127.0.0.0/8".parse().unwrap()));
+        assert_eq!(parse_ip_ranges("10.0.0.0/33"), None);
+
+        let negated = Matcher::Not(Box::new(Matcher::RemoteIp(vec!["10.0.0.0/33".to_string()])));
+        let route = RouteConfig { matcher: Some(negated), ..make_route("/") };
+        let error = Router::validate(std::slice::from_ref(&route)).unwrap_err();
+        assert!(error.contains("10.0.0.0/33"), "{}", error);
+
+        // Built without validation, the route is dropped rather than matching everyone
+        let router = Router::new(vec![route]);
+        let headers = http::HeaderMap::new();
+        assert!(router.match_request(&make_request("/", "", &headers)).is_none());
+    }
+
+    #[test]
+    fn test_host_matchers() {
+        let headers = http::HeaderMap::new();
+        let on = |host: &'static str| RequestInfo { host, ..make_request("/", "", &headers) };
+
+        let router = matcher_route(Matcher::Host(vec!["*.Example.com.".to_string(), "bücher.de".to_string()]));
+        assert!(router.match_request(&on("api.example.com")).is_some());
+        assert!(router.match_request(&on("xn--bcher-kva.de")).is_some());
+        assert!(router.match_request(&on("a.b.example.com")).is_none());
+        assert!(router.match_request(&on("example.com")).is_none());
+
+        let router = matcher_route(Matcher::HostRegexp {
+            name: "tenant".to_string(),
+            pattern: ConfigRegex::parse(r"^(?P<name>[a-z0-9-]+)\.tenants\.example\.com$").unwrap(),
+        });
+        let matched = router.match_request(&on("acme.tenants.example.com")).unwrap();
+        assert_eq!(matched.captures.get("tenant.name").map(String::as_str), Some("acme"));
+        assert_eq!(matched.captures.get("tenant.1").map(String::as_str), Some("acme"));
+        assert!(router.match_request(&on("acme.example.com")).is_none());
+    }
+
+    #[test]
+    fn test_header_regexp_captures() {
+        let mut headers = http::HeaderMap::new();
+        headers.append("x-tenant", "-".parse().unwrap());
+        headers.append("x-tenant", "acme-eu".parse().unwrap());
+        let router = matcher_route(Matcher::HeaderRegexp {
+            name: "tenant".to_string(),
+            field: "X-Tenant".to_string(),
+            pattern: ConfigRegex::parse(r"^(?P<org>[a-z]+)-(?P<region>[a-z]+)$").unwrap(),
+        });
+        let matched = router.match_request(&make_request("/", "", &headers)).unwrap();
+        assert_eq!(matched.captures.get("tenant.org").map(String::as_str), Some("acme"));
+        assert_eq!(matched.captures.get("tenant.region").map(String::as_str), Some("eu"));
+    }
+
+    #[test]
+    fn test_invalid_regexes_fail_to_load() {
+        let header = r#"{"path": "/", "handler": {"type": "respond", "status": 200},
+            "matcher": {"header": {"name": "X-Id", "condition": {"regex": "("}}}}"#;
+        let path_regexp = r#"{"path": "/", "handler": {"type": "respond", "status": 200},
+            "matcher": {"path_regexp": {"name": "id", "pattern": "[0-9"}}}"#;
+        for json in [header, path_regexp] {
+            assert!(serde_json::from_str::<RouteConfig>(json).is_err(), "{}", json);
+            let valid = json.replace(r#""(""#, r#""x""#).replace(r#""[0-9""#, r#""[0-9]""#);
+            assert!(serde_json::from_str::<RouteConfig>(&valid).is_ok(), "{}", valid);
+        }
+
+        let route = RouteConfig { matcher: Some(Matcher::Path { patterns: vec!["~(".to_string()] }), ..make_route("/") };
+        assert!(Router::validate(&[route]).is_err());
+        assert!(Router::validate(&[make_route("~^/(api")]).is_err());
+    }
+
+    #[test]
+    fn test_expression_matcher() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+        let router = matcher_route(Matcher::And(
+            Box::new(Matcher::PathRegexp { name: "user".to_string(), pattern: ConfigRegex::parse(r"^/users/(\d+)$").unwrap() }),
+            Box::new(Matcher::Expression {
+                expression: r#"{method} == "GET" && {header.Content-Type}.startsWith("application/json") && {re.user.1} < 100"#.to_string(),
+            }),
+        ));
+        assert!(router.match_request(&make_request("/users/42", "", &headers)).is_some());
+        assert!(router.match_request(&make_request("/users/420", "", &headers)).is_none());
+
+        // Invalid expressions fail validation, and the route is skipped
+        let invalid = Matcher::Expression { expression: "{method} ==".to_string() };
+        let route = RouteConfig { matcher: Some(invalid.clone()), ..make_route("/") };
+        assert!(Router::validate(&[route]).is_err());
+        let router = matcher_route(invalid);
+        assert!(router.match_request(&make_request("/", "", &headers)).is_none());
+    }
+
+    #[test]
+    fn test_vars_matcher() {
+        let headers = http::HeaderMap::new();
+        let router = matcher_route(Matcher::Vars {
+            key: "{vars.tier}".parse().unwrap(),
+            values: vec!["gold".to_string(), "silver".to_string()],
+        });
+        let vars = HashMap::from([("tier".to_string(), "silver".to_string())]);
+        let request = RequestInfo { vars: Some(&vars), ..make_request("/", "", &headers) };
+        assert!(router.match_request(&request).is_some());
+
+        // Unset variables render empty
+        assert!(router.match_request(&make_request("/", "", &headers)).is_none());
+    }
+
+    /// Path of the route that wins for a GET of `path`
+    fn winner(router: &Router, path: &str) -> Option<String> {
+        let headers = http::HeaderMap::new();
+        router.match_request(&make_request(path, "", &headers)).map(|m| m.route.config.path.clone())
+    }
+
+    #[test]
+    fn test_precedence_by_specificity() {
+        let paths = ["/*", "~^/api", "/api/*", "/api/users/*", "/api/users/{id}", "/api/users/me"];
+        let forward = Router::new(paths.iter().map(|p| make_route(p)).collect());
+        let reversed = Router::new(paths.iter().rev().map(|p| make_route(p)).collect());
+
+        for router in [&forward, &reversed] {
+            assert_eq!(winner(router, "/api/users/me").as_deref(), Some("/api/users/me"));
+            assert_eq!(winner(router, "/api/users/7").as_deref(), Some("/api/users/{id}"));
+            assert_eq!(winner(router, "/api/users/7/posts").as_deref(), Some("/api/users/*"));
+            assert_eq!(winner(router, "/api/other").as_deref(), Some("/api/*"));
+            assert_eq!(winner(router, "/apix").as_deref(), Some("~^/api"));
+            assert_eq!(winner(router, "/other").as_deref(), Some("/*"));
+        }
+
+        let order: Vec<_> = forward.match_path("/api/users/me").iter().map(|r| r.config.path.as_str()).collect();
+        assert_eq!(order, ["/api/users/me", "/api/users/{id}", "/api/users/*", "/api/*", "~^/api", "/*"]);
+    }
+
+    #[test]
+    fn test_priority_and_source_order() {
+        let mut maintenance = make_route("/*");
+        maintenance.priority = 10;
+        let mut demoted = make_route("/api/health");
+        demoted.priority = -1;
+        let router = Router::new(vec![make_route("/api/*"), maintenance, make_route("/api/*"), demoted]);
+        let headers = http::HeaderMap::new();
+
+        // Explicit priority beats specificity, in both directions
+        assert_eq!(router.match_request(&make_request("/api/x", "", &headers)).unwrap().route.index, 1);
+        let order: Vec<_> = router.match_path("/api/health").iter().map(|r| r.index).collect();
+        assert_eq!(order, [1, 0, 2, 3]);
+    }
+
+    #[test]
+    fn test_rejected_specific_route_falls_through() {
+        let mut posts_only = make_route("/api/users/*");
+        posts_only.methods = Some(vec!["POST".to_string()]);
+        let mut json_only = make_route("/api/users/{id}");
+        json_only.matcher = Some(Matcher::Header {
+            name: "accept".to_string(),
+            condition: MatcherCondition::Equals("application/json".to_string()),
+        });
+        let router = Router::new(vec![make_route("/api/*"), posts_only, json_only]);
+
+        assert_eq!(winner(&router, "/api/users/7/avatar").as_deref(), Some("/api/*"));
+        assert_eq!(winner(&router, "/api/users/7").as_deref(), Some("/api/*"));
+    }
+
+    #[test]
+    fn test_radix_lookups_return_every_candidate() {
+        let paths = ["/a*", "/a/b/*", "/a/*", "/é/*", "/users/{id}", "/users/{name}", "/users/me"];
+        let router = Router::new(paths.iter().map(|p| make_route(p)).collect());
+        let order = |path: &str| -> Vec<String> {
+            router.match_path(path).iter().map(|r| r.config.path.clone()).collect()
+        };
+
+        // Prefixes, longest first, down to the exact literal (`/a/` matches `/a/*`)
+        assert_eq!(order("/a/b/c"), ["/a/b/*", "/a/*", "/a*"]);
+        assert_eq!(order("/a/"), ["/a/*", "/a*"]);
+        assert_eq!(order("/ab"), ["/a*"]);
+        assert_eq!(order("/é/x"), ["/é/*"]);
+
+        // Conflicting parameter patterns both stay reachable
+        assert_eq!(order("/users/7"), ["/users/{id}", "/users/{name}"]);
+        assert_eq!(order("/users/me"), ["/users/me", "/users/{id}", "/users/{name}"]);
+        let headers = http::HeaderMap::new();
+        let matched = router.match_request(&make_request("/users/7", "", &headers)).unwrap();
+        assert_eq!(matched.captures.get("path.id").map(String::as_str), Some("7"));
+    }
+
+    #[test]
+    fn test_path_specificity() {
+        assert_eq!(PathSpecificity::of("/api/users"), (PathSpecificity::Exact, 10));
+        assert_eq!(PathSpecificity::of("/users/{id}/x"), (PathSpecificity::Parameter, 7));
+        assert_eq!(PathSpecificity::of("/api/*"), (PathSpecificity::Prefix, 5));
+        assert_eq!(PathSpecificity::of("~*^/docs"), (PathSpecificity::Regex, 0));
+        assert_eq!(PathSpecificity::of("/"), (PathSpecificity::CatchAll, 0));
+        assert_eq!(PathSpecificity::of("/files/{*path}"), (PathSpecificity::Prefix, 7));
+        assert_eq!(PathSpecificity::of("/users/{id}/files/{*path}"), (PathSpecificity::Prefix, 7));
+        assert_eq!(PathSpecificity::of("/{*path}"), (PathSpecificity::CatchAll, 0));
+        assert!(route_order_key("/api/*", 0, false) < route_order_key("/api*", 0, false));
+        assert!(route_order_key("/*", 1, false) < route_order_key("/exact", 0, false));
+        assert!(route_order_key("/*", 0, true) < route_order_key("/exact", 0, false));
+        assert_eq!(route_order_key("/*", 0, true), route_order_key("/exact", 0, true));
+    }
+
+    #[test]
+    fn test_catch_all_parameters_rank_as_prefixes() {
+        let paths = ["/{*rest}", "~^/api", "/api/*", "/api/v1/{*rest}"];
+        let forward = Router::new(paths.iter().map(|p| make_route(p)).collect());
+        let reversed = Router::new(paths.iter().rev().map(|p| make_route(p)).collect());
+
+        for router in [&forward, &reversed] {
+            let order: Vec<_> = router.match_path("/api/v1/x").iter().map(|r| r.config.path.as_str()).collect();
+            assert_eq!(order, ["/api/v1/{*rest}", "/api/*", "~^/api", "/{*rest}"]);
+            assert_eq!(winner(router, "/other").as_deref(), Some("/{*rest}"));
+        }
+    }
+
+    #[test]
+    fn test_overlapping_parameter_patterns_are_all_candidates() {
+        let mut users = make_route("/users/{id}");
+        users.methods = Some(vec!["POST".to_string()]);
+        let router = Router::new(vec![users, make_route("/{a}/{b}"), make_route("/teams/{id}")]);
+
+        let order: Vec<_> = router.match_path("/users/7").iter().map(|r| r.config.path.as_str()).collect();
+        assert_eq!(order, ["/users/{id}", "/{a}/{b}"]);
+        assert_eq!(winner(&router, "/users/7").as_deref(), Some("/{a}/{b}"));
+        assert!(patterns_overlap("/files/{*path}", "/files/a/b"));
+        assert!(!patterns_overlap("/users/{id}", "/teams/{id}"));
+        assert!(!patterns_overlap("/users/{id}", "/users/{id}/posts"));
+    }
+
+    #[test]
+    fn test_source_order_routes_are_not_sorted() {
+        let ordered = |path: &str| RouteConfig { source_order: true, ..make_route(path) };
+        let router = Router::new(vec![make_route("/api/users"), ordered("/api/*"), ordered("/api/users")]);
+        let order: Vec<_> = router.match_path("/api/users").iter().map(|r| r.index).collect();
+        assert_eq!(order, [1, 2, 0]);
+    }
 }
@@ -0,0 +1,194 @@
+//! nginx-compatible `X-Accel-*` upstream response controls
+//!
+//! 🏗️ ARCHITECTURE: An upstream can steer how Pingclair delivers its response:
+//!   - `X-Accel-Redirect`   internal redirect to another (possibly `internal`) route
+//!   - `X-Accel-Buffering`  `no` disables gzip, which holds the whole body until the
+//!     upstream finishes; other responses are already forwarded chunk by chunk
+//!     (`templates` still buffers, since it needs the whole document)
+//!   - `X-Accel-Expires`    client caching lifetime (`off`, seconds, or `@unix_ts`)
+//!   - `X-Accel-Limit-Rate` response rate limit in bytes per second, applied as a
+//!     delay before each body chunk; a replacement body is one chunk, so it is
+//!     held back for its whole duration and then sent at once
+//!
+//! These headers are consumed by the proxy and never reach the client.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// MARK: - Headers
+
+/// Upstream headers interpreted (and stripped) by the proxy
+pub const ACCEL_HEADERS: [&str; 4] = [
+    "X-Accel-Redirect",
+    "X-Accel-Buffering",
+    "X-Accel-Expires",
+    "X-Accel-Limit-Rate",
+];
+
+/// Upstream headers carried over to an `X-Accel-Redirect` response (nginx semantics)
+pub const REDIRECT_PRESERVED_HEADERS: [&str; 5] = [
+    "Content-Type",
+    "Content-Disposition",
+    "Set-Cookie",
+    "Cache-Control",
+    "Expires",
+];
+
+// MARK: - Directives
+
+/// `X-Accel-*` directives parsed from an upstream response
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccelDirectives {
+    /// Internal redirect target URI
+    pub redirect: Option<String>,
+    /// Whether the response may be buffered (`X-Accel-Buffering`)
+    pub buffering: Option<bool>,
+    /// Client caching lifetime
+    pub expires: Option<AccelExpires>,
+    /// Rate limit in bytes per second
+    pub limit_rate: Option<u64>,
+}
+
+impl AccelDirectives {
+    /// Parse the directives present on an upstream response.
+    ///
+    /// - Parameter headers: The upstream response headers.
+    /// - Returns: The parsed directives (invalid values are ignored).
+    pub fn from_headers(headers: &http::HeaderMap) -> Self {
+        let get = |name: &str| {
+            headers.get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+
+        Self {
+            redirect: get("x-accel-redirect")
+                .filter(|uri| uri.starts_with('/'))
+                .map(str::to_string),
+            buffering: get("x-accel-buffering").and_then(|v| {
+                match v.to_ascii_lowercase().as_str() {
+                    "yes" => Some(true),
+                    "no" => Some(false),
+                    _ => None,
+                }
+            }),
+            expires: get("x-accel-expires").and_then(AccelExpires::parse),
+            limit_rate: get("x-accel-limit-rate")
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|rate| *rate > 0),
+        }
+    }
+
+    /// Whether the upstream sent no `X-Accel-*` directive at all
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `X-Accel-Expires` value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccelExpires {
+    /// Leave the upstream caching headers untouched
+    Off,
+    /// Cacheable for this many seconds (`0` disables caching)
+    Seconds(u64),
+    /// Cacheable until this unix timestamp (`@1700000000`)
+    At(u64),
+}
+
+impl AccelExpires {
+    /// Parse an `X-Accel-Expires` header value
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.eq_ignore_ascii_case("off") {
+            return Some(Self::Off);
+        }
+        if let Some(ts) = value.strip_prefix('@') {
+            return ts.parse().ok().map(Self::At);
+        }
+        value.parse().ok().map(Self::Seconds)
+    }
+
+    /// Compute the `Cache-Control` and `Expires` values for the client response.
+    ///
+    /// - Parameter now: The current time.
+    /// - Returns: `(cache_control, expires)`, or `None` for `off`.
+    pub fn cache_headers(&self, now: SystemTime) -> Option<(String, Option<String>)> {
+        match *self {
+            Self::Off => None,
+            Self::Seconds(0) => Some(("no-cache".to_string(), None)),
+            Self::Seconds(secs) => Some((
+                format!("max-age={}", secs),
+                Some(httpdate::fmt_http_date(now + Duration::from_secs(secs))),
+            )),
+            Self::At(ts) => {
+                let at = UNIX_EPOCH + Duration::from_secs(ts);
+                let remaining = at.duration_since(now).unwrap_or_default().as_secs();
+                Some((
+                    format!("max-age={}", remaining),
+                    Some(httpdate::fmt_http_date(at)),
+                ))
+            }
+        }
+    }
+}
+
+/// Delay to apply around sending `len` bytes at `rate` bytes per second
+pub fn limit_rate_delay(len: usize, rate: u64) -> Option<Duration> {
+    if len == 0 || rate == 0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(len as f64 / rate as f64))
+}
+
+// MARK: - Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_directives() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("x-accel-redirect", "/protected/file.zip".parse().unwrap());
+        headers.insert("x-accel-buffering", "no".parse().unwrap());
+        headers.insert("x-accel-expires", "60".parse().unwrap());
+        headers.insert("x-accel-limit-rate", "1024".parse().unwrap());
+
+        let directives = AccelDirectives::from_headers(&headers);
+        assert_eq!(directives.redirect.as_deref(), Some("/protected/file.zip"));
+        assert_eq!(directives.buffering, Some(false));
+        assert_eq!(directives.expires, Some(AccelExpires::Seconds(60)));
+        assert_eq!(directives.limit_rate, Some(1024));
+    }
+
+    #[test]
+    fn test_ignores_invalid_values() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("x-accel-redirect", "https://evil.example/".parse().unwrap());
+        headers.insert("x-accel-limit-rate", "0".parse().unwrap());
+
+        assert!(AccelDirectives::from_headers(&headers).is_empty());
+    }
+
+    #[test]
+    fn test_expires_headers() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+
+        assert_eq!(AccelExpires::parse("off"), Some(AccelExpires::Off));
+        assert_eq!(AccelExpires::Off.cache_headers(now), None);
+        assert_eq!(
+            AccelExpires::Seconds(0).cache_headers(now),
+            Some(("no-cache".to_string(), None))
+        );
+
+        let (cache_control, expires) = AccelExpires::parse("@1060").unwrap().cache_headers(now).unwrap();
+        assert_eq!(cache_control, "max-age=60");
+        assert!(expires.is_some());
+    }
+
+    #[test]
+    fn test_limit_rate_delay() {
+        assert_eq!(limit_rate_delay(2048, 1024), Some(Duration::from_secs(2)));
+        assert_eq!(limit_rate_delay(0, 1024), None);
+    }
+}
@@ -0,0 +1,698 @@
+//! Per-server access logs
+//!
+//! 🏗️ ARCHITECTURE: Each site with a `log` block gets an `AccessLogger` in its
+//! `ProxyState`. Loggers write through a shared `LogWriter` per destination, so
+//! several sites (or the global application log) logging to the same file
+//! share one file handle and one writer thread. Destinations (rolling files,
+//! syslog, sockets) are implemented in `log_sink`.
+//!
+//! Lines are written as text, Caddy-compatible JSON, the Common/Combined Log
+//! Formats or a user-defined template (see `log_format`), after the site's
+//! redaction, skip and sampling filters (see `log_filter`).
+//!
+//! ⚡ OPTIMIZATION: Writes are handed to a bounded queue and flushed by a
+//! background thread through a `BufWriter`; the request path never blocks on
+//! disk or network I/O. When the queue is full, lines are dropped and counted.
+
+use crate::log_filter::LogFilters;
+use crate::log_format::{self, LogTemplate};
+use crate::log_sink::Sink;
+use base64::Engine;
+use pingclair_core::config::{LogConfig, LogFormat, LogOutput, LogRotation};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::Level;
+
+/// Pending lines per destination before new lines are dropped
+const QUEUE_CAPACITY: usize = 8192;
+
+/// Maximum delay before buffered lines reach the destination
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+// MARK: - Writer
+
+/// Non-blocking, buffered log destination
+#[derive(Debug)]
+pub struct LogWriter {
+    sender: SyncSender<Command>,
+    dropped: Arc<AtomicU64>,
+    /// Settings the sink currently runs with
+    output: Mutex<LogOutput>,
+}
+
+/// Work for a writer thread
+enum Command {
+    /// Write a line with its severity
+    Line(Level, Vec<u8>),
+    /// Apply a reloaded rotation policy to the open file
+    Rotation(Option<LogRotation>),
+}
+
+/// Open writers keyed by destination, shared across sites and reloads
+static WRITERS: Mutex<Option<HashMap<String, Weak<LogWriter>>>> = Mutex::new(None);
+
+/// Bumped by `reopen_all`; writer threads reopen their files when it changes
+static REOPEN_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+impl LogWriter {
+    /// Get the writer for a destination, opening it if needed.
+    ///
+    /// A destination that is already open keeps its file handle and thread;
+    /// changed settings (a reload with a new rotation policy) are applied to it
+    /// in place.
+    ///
+    /// - Parameter output: The log destination.
+    /// - Returns: A writer shared with every other user of the same destination.
+    pub fn open(output: &LogOutput) -> io::Result<Arc<Self>> {
+        let key = match output {
+            LogOutput::File(path) | LogOutput::RollingFile { path, .. } => format!("file:{}", path),
+            LogOutput::Syslog(syslog) => format!("syslog:{}:{}:{}", syslog.address, syslog.facility, syslog.tag),
+            LogOutput::Net(address) => format!("net:{}", address),
+            LogOutput::Stdout => "stdout".to_string(),
+            LogOutput::Stderr => "stderr".to_string(),
+        };
+
+        let mut writers = WRITERS.lock().unwrap_or_else(|e| e.into_inner());
+        let writers = writers.get_or_insert_with(HashMap::new);
+        if let Some(writer) = writers.get(&key).and_then(Weak::upgrade) {
+            writer.reconfigure(output);
+            return Ok(writer);
+        }
+
+        let writer = Arc::new(Self::spawn(key.clone(), output.clone(), Sink::open(output)?)?);
+        writers.retain(|_, w| w.strong_count() > 0);
+        writers.insert(key, Arc::downgrade(&writer));
+        Ok(writer)
+    }
+
+    /// Hand changed settings for the same destination to the writer thread
+    fn reconfigure(&self, output: &LogOutput) {
+        let mut current = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        if *current == *output {
+            return;
+        }
+        let rotation = match output {
+            LogOutput::RollingFile { rotation, .. } => Some(rotation.clone()),
+            _ => None,
+        };
+        // Config reloads only; waits for queue space instead of dropping the change
+        if self.sender.send(Command::Rotation(rotation)).is_ok() {
+            tracing::info!("📝 Log settings for {:?} changed, applied to the open writer", output);
+            *current = output.clone();
+        }
+    }
+
+    /// Start the writer thread for a sink
+    fn spawn(name: String, output: LogOutput, mut sink: Sink) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Command>(QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let failed = dropped.clone();
+
+        std::thread::Builder::new()
+            .name(format!("log-writer {}", name))
+            .spawn(move || {
+                let mut generation = REOPEN_GENERATION.load(Ordering::Relaxed);
+                loop {
+                    match receiver.recv_timeout(FLUSH_INTERVAL) {
+                        Ok(Command::Line(level, line)) => {
+                            if sink.write_line(level, &line).is_err() {
+                                failed.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                        Ok(Command::Rotation(rotation)) => sink.set_rotation(rotation),
+                        Err(RecvTimeoutError::Timeout) => {
+                            let _ = sink.flush();
+                        }
+                        Err(RecvTimeoutError::Disconnected) => {
+                            let _ = sink.flush();
+                            break;
+                        }
+                    }
+
+                    let current = REOPEN_GENERATION.load(Ordering::Relaxed);
+                    if current != generation {
+                        generation = current;
+                        if let Err(e) = sink.reopen() {
+                            tracing::warn!("⚠️ Failed to reopen log {}: {}", name, e);
+                        }
+                    }
+                }
+            })?;
+
+        Ok(Self { sender, dropped, output: Mutex::new(output) })
+    }
+
+    /// Queue bytes for writing at `INFO` severity (never blocks)
+    pub fn write_bytes(&self, bytes: Vec<u8>) {
+        self.write_record(Level::INFO, bytes);
+    }
+
+    /// Queue a line with its severity (used by syslog; never blocks)
+    pub fn write_record(&self, level: Level, bytes: Vec<u8>) {
+        match self.sender.try_send(Command::Line(level, bytes)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Number of lines dropped because the queue was full or the sink failed
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Reopen every log file (SIGUSR1), e.g. after an external logrotate.
+///
+/// Writer threads pick the request up within the flush interval.
+pub fn reopen_all() {
+    REOPEN_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// `io::Write` handle onto a shared `LogWriter` (one `write` per log event)
+#[derive(Debug, Clone)]
+pub struct LogWriterHandle(pub Arc<LogWriter>);
+
+impl Write for LogWriterHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_bytes(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// MARK: - Access Logger
+
+/// A completed request, as recorded in the access log
+#[derive(Debug, Clone, Default)]
+pub struct AccessLogEntry<'a> {
+    pub time: Option<SystemTime>,
+    pub request_id: &'a str,
+    pub trace_id: &'a str,
+    pub span_id: &'a str,
+    /// Direct peer address
+    pub remote_ip: &'a str,
+    /// Client address resolved through trusted proxies (empty: same as `remote_ip`)
+    pub client_ip: &'a str,
+    pub method: &'a str,
+    pub host: &'a str,
+    pub uri: &'a str,
+    pub proto: &'a str,
+    /// Request scheme (`http` / `https`), from the listener
+    pub scheme: &'a str,
+    pub status: u16,
+    pub bytes: u64,
+    pub duration: Duration,
+    pub user_agent: &'a str,
+    pub referer: &'a str,
+    pub upstream: Option<String>,
+    pub error: Option<String>,
+    pub remote_port: Option<u16>,
+    pub request_headers: Option<&'a http::HeaderMap>,
+    pub response_headers: Option<&'a http::HeaderMap>,
+    /// Request body bytes received
+    pub bytes_in: u64,
+    pub upstream_timing: UpstreamTiming,
+    pub tls: Option<TlsInfo>,
+    /// Path of the matched route
+    pub route: Option<&'a str>,
+    /// Rate limiter outcome, when the route is rate limited
+    pub rate_limited: Option<bool>,
+    /// Authenticated user (see `basic_auth_user`)
+    pub user_id: Option<String>,
+}
+
+/// Negotiated downstream TLS parameters
+#[derive(Debug, Clone, Default)]
+pub struct TlsInfo {
+    /// Protocol version (`TLSv1.3`)
+    pub version: String,
+    /// Cipher suite name
+    pub cipher: String,
+}
+
+/// Upstream timings, each measured from the start of the upstream attempt
+/// (nginx `$upstream_*_time` semantics)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpstreamTiming {
+    pub started: Option<Instant>,
+    /// Connection ready (new or reused)
+    pub connect: Option<Duration>,
+    /// Response headers received
+    pub header: Option<Duration>,
+    /// Response body fully received
+    pub response: Option<Duration>,
+}
+
+impl UpstreamTiming {
+    /// Start timing an upstream attempt (retries restart the clock)
+    pub fn start(&mut self) {
+        *self = Self { started: Some(Instant::now()), ..Default::default() };
+    }
+
+    pub fn connected(&mut self) {
+        self.connect = self.started.map(|t| t.elapsed());
+    }
+
+    pub fn headers_received(&mut self) {
+        self.header = self.started.map(|t| t.elapsed());
+    }
+
+    pub fn finished(&mut self) {
+        self.response = self.started.map(|t| t.elapsed());
+    }
+}
+
+impl AccessLogEntry<'_> {
+    /// Real client address (the peer unless resolved through a trusted proxy)
+    pub fn client(&self) -> &str {
+        if self.client_ip.is_empty() { self.remote_ip } else { self.client_ip }
+    }
+
+    /// Severity of the entry: failed requests and server errors are `ERROR`
+    pub fn level(&self) -> Level {
+        if self.error.is_some() || self.status >= 500 {
+            Level::ERROR
+        } else {
+            Level::INFO
+        }
+    }
+}
+
+/// Authenticated user: the Basic auth user name (nginx `$remote_user`)
+pub fn basic_auth_user(headers: &http::HeaderMap) -> Option<String> {
+    let auth = headers.get(http::header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = auth.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, _) = decoded.split_once(':')?;
+    Some(user.to_string()).filter(|u| !u.is_empty())
+}
+
+/// Access log for one site
+#[derive(Debug)]
+pub struct AccessLogger {
+    writer: Arc<LogWriter>,
+    format: LogFormat,
+    /// Compiled line template (Common, Combined and custom formats)
+    template: Option<LogTemplate>,
+    /// Field redaction, skipped paths and sampling
+    filters: LogFilters,
+    /// Per-server level override (falls back to the global level)
+    level: Option<Level>,
+}
+
+impl AccessLogger {
+    /// Create a logger for a site's `log` block.
+    ///
+    /// - Parameter config: The per-server log configuration.
+    /// - Returns: The logger, or the error opening its destination or compiling its template.
+    pub fn new(config: &LogConfig) -> io::Result<Self> {
+        let template = match &config.format {
+            LogFormat::Common => Some(log_format::COMMON),
+            LogFormat::Combined => Some(log_format::COMBINED),
+            LogFormat::Template(source) => Some(source.as_str()),
+            LogFormat::Text | LogFormat::Json => None,
+        };
+        let template = template
+            .map(LogTemplate::parse)
+            .transpose()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        Ok(Self {
+            writer: LogWriter::open(&config.output)?,
+            format: config.format.clone(),
+            template,
+            filters: LogFilters::new(&config.filter),
+            level: config.level.as_deref().and_then(parse_level),
+        })
+    }
+
+    /// Write an entry if its level passes this logger's threshold.
+    ///
+    /// - Parameter entry: The completed request.
+    /// - Parameter default_level: The global level, used without a per-server override.
+    pub fn log(&self, entry: &AccessLogEntry, default_level: Level) {
+        let threshold = self.level.unwrap_or(default_level);
+        // tracing orders levels by verbosity: ERROR < WARN < INFO < ...
+        if entry.level() > threshold {
+            return;
+        }
+
+        if !self.filters.should_log(entry) {
+            return;
+        }
+
+        let mut line = match (&self.template, &self.format) {
+            (None, LogFormat::Json) => {
+                let mut value = json_entry(entry);
+                self.filters.apply_json(&mut value);
+                value.to_string()
+            }
+            (template, _) => {
+                let redacted = self.filters.redact(entry);
+                let redacted = redacted.as_ref().map(|fields| fields.entry(entry));
+                let entry = redacted.as_ref().unwrap_or(entry);
+                match template {
+                    Some(template) => template.render(entry),
+                    None => format_text(entry),
+                }
+            }
+        };
+        line.push('\n');
+        self.writer.write_record(entry.level(), line.into_bytes());
+    }
+}
+
+/// Parse a level name (`debug`, `INFO`, `warn`, ...)
+pub fn parse_level(level: &str) -> Option<Level> {
+    match level.to_ascii_lowercase().as_str() {
+        "warning" => Some(Level::WARN),
+        other => other.parse().ok(),
+    }
+}
+
+// MARK: - Formats
+
+/// Caddy `http.log.access` JSON schema, plus Pingclair's correlation fields.
+///
+/// ⚠️ `tls.resumed`, `tls.proto` and `tls.server_name` are not exposed by the
+/// TLS digest and are omitted; `cipher_suite` is omitted for unknown suites.
+fn json_entry(entry: &AccessLogEntry) -> serde_json::Value {
+    let ts = entry.time.unwrap_or_else(SystemTime::now)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+
+    let mut request = serde_json::json!({
+        "remote_ip": entry.remote_ip,
+        "remote_port": entry.remote_port.map(|p| p.to_string()).unwrap_or_default(),
+        "client_ip": entry.client(),
+        "proto": entry.proto,
+        "method": entry.method,
+        "host": entry.host,
+        "uri": entry.uri,
+        "headers": entry.request_headers.map(|h| json_headers(h, true)).unwrap_or_default(),
+    });
+    if let Some(tls) = &entry.tls {
+        let mut value = serde_json::json!({});
+        if let Some(version) = tls_version_code(&tls.version) {
+            value["version"] = serde_json::json!(version);
+        }
+        if let Some(suite) = cipher_suite_code(&tls.cipher) {
+            value["cipher_suite"] = serde_json::json!(suite);
+        }
+        request["tls"] = value;
+    }
+
+    let mut value = serde_json::json!({
+        "level": entry.level().as_str().to_ascii_lowercase(),
+        "ts": ts,
+        "logger": "http.log.access",
+        "msg": "handled request",
+        "request": request,
+        "bytes_read": entry.bytes_in,
+        "user_id": entry.user_id.clone().unwrap_or_default(),
+        "duration": entry.duration.as_secs_f64(),
+        "size": entry.bytes,
+        "status": entry.status,
+        "resp_headers": entry.response_headers.map(|h| json_headers(h, false)).unwrap_or_default(),
+        "request_id": entry.request_id,
+        "trace_id": entry.trace_id,
+        "span_id": entry.span_id,
+    });
+    if let Some(upstream) = &entry.upstream {
+        value["upstream"] = serde_json::json!(upstream);
+    }
+    if let Some(error) = &entry.error {
+        value["error"] = serde_json::json!(error);
+    }
+    value
+}
+
+/// Headers as `{"Canonical-Name": ["value", ...]}`; credentials are redacted
+/// like Caddy does unless `log_credentials` is set.
+fn json_headers(headers: &http::HeaderMap, request: bool) -> serde_json::Map<String, serde_json::Value> {
+    let mut map = serde_json::Map::new();
+    for name in headers.keys() {
+        let sensitive = if request {
+            matches!(name.as_str(), "authorization" | "proxy-authorization" | "cookie")
+        } else {
+            name.as_str() == "set-cookie"
+        };
+        let values = headers.get_all(name)
+            .iter()
+            .map(|v| serde_json::Value::String(if sensitive {
+                "REDACTED".to_string()
+            } else {
+                String::from_utf8_lossy(v.as_bytes()).into_owned()
+            }))
+            .collect();
+        map.insert(canonical_header_name(name.as_str()), serde_json::Value::Array(values));
+    }
+    map
+}
+
+/// `x-forwarded-for` → `X-Forwarded-For` (Go's canonical form, as Caddy logs it)
+fn canonical_header_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = true;
+    for c in name.chars() {
+        out.push(if upper { c.to_ascii_uppercase() } else { c });
+        upper = c == '-';
+    }
+    out
+}
+
+/// TLS protocol version as its wire value (`TLSv1.3` → 772)
+fn tls_version_code(version: &str) -> Option<u16> {
+    let number = version.trim_start_matches("TLSv").trim_start_matches("TLS").trim().replace('_', ".");
+    match number.as_str() {
+        "1" | "1.0" => Some(0x0301),
+        "1.1" => Some(0x0302),
+        "1.2" => Some(0x0303),
+        "1.3" => Some(0x0304),
+        _ => None,
+    }
+}
+
+/// IANA cipher suite number from an IANA or OpenSSL suite name
+fn cipher_suite_code(name: &str) -> Option<u16> {
+    Some(match name {
+        "TLS_AES_128_GCM_SHA256" | "TLS13_AES_128_GCM_SHA256" => 0x1301,
+        "TLS_AES_256_GCM_SHA384" | "TLS13_AES_256_GCM_SHA384" => 0x1302,
+        "TLS_CHACHA20_POLY1305_SHA256" | "TLS13_CHACHA20_POLY1305_SHA256" => 0x1303,
+        "ECDHE-ECDSA-AES128-GCM-SHA256" | "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256" => 0xc02b,
+        "ECDHE-RSA-AES128-GCM-SHA256" | "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256" => 0xc02f,
+        "ECDHE-ECDSA-AES256-GCM-SHA384" | "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384" => 0xc02c,
+        "ECDHE-RSA-AES256-GCM-SHA384" | "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384" => 0xc030,
+        "ECDHE-ECDSA-CHACHA20-POLY1305" | "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256" => 0xcca9,
+        "ECDHE-RSA-CHACHA20-POLY1305" | "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256" => 0xcca8,
+        _ => return None,
+    })
+}
+
+fn format_text(entry: &AccessLogEntry) -> String {
+    let mut line = format!(
+        "{} {} {} \"{} {} {}\" {} {} {}ms host={} request_id={} trace_id={} ua={:?} referer={:?}",
+        rfc3339(entry.time.unwrap_or_else(SystemTime::now)),
+        entry.level(),
+        entry.client(),
+        entry.method,
+        entry.uri,
+        entry.proto,
+        entry.status,
+        entry.bytes,
+        entry.duration.as_millis(),
+        entry.host,
+        entry.request_id,
+        entry.trace_id,
+        entry.user_agent,
+        entry.referer,
+    );
+    if let Some(upstream) = &entry.upstream {
+        line.push_str(&format!(" upstream={}", upstream));
+    }
+    if let Some(error) = &entry.error {
+        line.push_str(&format!(" error={:?}", error));
+    }
+    line
+}
+
+/// Format a timestamp as RFC 3339 UTC with millisecond precision
+pub fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day,
+        rem / 3600, (rem % 3600) / 60, rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Days since 1970-01-01 to (year, month, day) — Howard Hinnant's algorithm
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// MARK: - Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(status: u16) -> AccessLogEntry<'static> {
+        AccessLogEntry {
+            time: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            request_id: "req-1",
+            remote_ip: "203.0.113.9",
+            method: "GET",
+            host: "example.com",
+            uri: "/index.html?q=1",
+            proto: "HTTP/1.1",
+            status,
+            bytes: 512,
+            duration: Duration::from_millis(12),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            "2023-11-14T22:13:20.123Z"
+        );
+    }
+
+    #[test]
+    fn test_formats() {
+        let json = json_entry(&entry(200));
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["level"], "info");
+        assert_eq!(json["request"]["uri"], "/index.html?q=1");
+        assert_eq!(json["request"]["client_ip"], "203.0.113.9");
+        assert!(json.get("error").is_none());
+
+        let text = format_text(&entry(502));
+        assert!(text.starts_with("2023-11-14T22:13:20.123Z ERROR 203.0.113.9 \"GET /index.html?q=1 HTTP/1.1\" 502 512 12ms"));
+    }
+
+    #[test]
+    fn test_caddy_json_schema() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("user-agent", "curl/8.0".parse().unwrap());
+        headers.insert("authorization", "Basic ZnJhbms6c2VjcmV0".parse().unwrap());
+        let mut entry = entry(200);
+        entry.remote_port = Some(51234);
+        entry.request_headers = Some(&headers);
+        entry.user_id = basic_auth_user(&headers);
+        entry.bytes_in = 42;
+        entry.tls = Some(TlsInfo { version: "TLSv1.3".into(), cipher: "TLS_AES_128_GCM_SHA256".into() });
+
+        let json = json_entry(&entry);
+        assert_eq!(json["logger"], "http.log.access");
+        assert_eq!(json["msg"], "handled request");
+        assert_eq!(json["request"]["remote_port"], "51234");
+        assert_eq!(json["request"]["headers"]["User-Agent"][0], "curl/8.0");
+        assert_eq!(json["request"]["headers"]["Authorization"][0], "REDACTED");
+        assert_eq!(json["request"]["tls"]["version"], 772);
+        assert_eq!(json["request"]["tls"]["cipher_suite"], 4865);
+        assert_eq!(json["bytes_read"], 42);
+        assert_eq!(json["user_id"], "frank");
+        assert_eq!(json["size"], 512);
+        assert_eq!(json["duration"], 0.012);
+    }
+
+    #[test]
+    fn test_level_threshold_and_file_output() {
+        let dir = std::env::temp_dir().join(format!("pingclair-access-log-{}", std::process::id()));
+        let path = dir.join("access.log");
+        let config = LogConfig {
+            output: LogOutput::File(path.to_string_lossy().to_string()),
+            format: LogFormat::Json,
+            level: Some("error".to_string()),
+            filter: Default::default(),
+        };
+
+        let logger = AccessLogger::new(&config).unwrap();
+        // Same destination shares one writer
+        let other = AccessLogger::new(&config).unwrap();
+        assert!(Arc::ptr_eq(&logger.writer, &other.writer));
+
+        logger.log(&entry(200), Level::INFO);
+        logger.log(&entry(503), Level::INFO);
+        drop(other);
+        drop(logger);
+
+        // Writer thread flushes on disconnect
+        let mut contents = String::new();
+        for _ in 0..50 {
+            contents = std::fs::read_to_string(&path).unwrap_or_default();
+            if !contents.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("\"status\":503"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_changed_settings_reconfigure_the_writer() {
+        let dir = std::env::temp_dir().join(format!("pingclair-log-settings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log").to_string_lossy().to_string();
+        let rolling = |max_size| LogOutput::RollingFile {
+            path: path.clone(),
+            rotation: LogRotation { max_size, interval_secs: None, keep: 5, compress: false },
+        };
+
+        let plain = LogWriter::open(&LogOutput::File(path.clone())).unwrap();
+        let rolled = LogWriter::open(&rolling(Some(20))).unwrap();
+        assert!(Arc::ptr_eq(&plain, &rolled));
+        assert_eq!(*rolled.output.lock().unwrap(), rolling(Some(20)));
+
+        // The open file picks up the new size limit
+        rolled.write_bytes(b"first line of the log\n".to_vec());
+        rolled.write_bytes(b"second line\n".to_vec());
+        let mut files = 0;
+        for _ in 0..50 {
+            files = std::fs::read_dir(&dir).unwrap().count();
+            if files == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(files, 2);
+        drop((plain, rolled));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+}
@@ -0,0 +1,156 @@
+//! Real client IP resolution behind trusted proxies
+//!
+//! 🏗️ ARCHITECTURE: When the direct peer is a trusted proxy (load balancer,
+//! CDN), the client address is read from the configured headers in order:
+//!   - List headers (`X-Forwarded-For`, and single-value headers such as
+//!     `X-Real-IP` / `CF-Connecting-IP`) are walked right to left
+//!   - `Forwarded` (RFC 7239) contributes its `for=` parameters, walked the same way
+//!
+//! 🛑 SAFETY: Walking right to left skips only addresses that are themselves
+//! trusted, so a client cannot spoof its address by prepending entries.
+
+use http::{HeaderMap, HeaderName};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// Default client IP header when none are configured
+pub const DEFAULT_CLIENT_IP_HEADER: &str = "X-Forwarded-For";
+
+/// Resolve the client IP for a request.
+///
+/// - Parameter peer: The direct peer address.
+/// - Parameter headers: The request headers.
+/// - Parameter header_names: Headers carrying the client address, in priority order.
+/// - Parameter trusted: Proxies allowed to supply those headers.
+/// - Returns: The client address, or `peer` when it is untrusted or no header applies.
+pub fn resolve(peer: IpAddr, headers: &HeaderMap, header_names: &[HeaderName], trusted: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+
+    for name in header_names {
+        let forwarded = name.as_str() == "forwarded";
+        let entries: Vec<&str> = headers.get_all(name).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|entry| if forwarded { forwarded_for(entry) } else { Some(entry) })
+            .collect();
+        if let Some(ip) = walk(&entries, peer, &is_trusted) {
+            return ip;
+        }
+    }
+    peer
+}
+
+/// Right-to-left walk: the first untrusted address is the client.
+///
+/// If every address is trusted, the leftmost one is used. An unparseable
+/// entry before any untrusted address stops the walk at the direct peer,
+/// since nothing from there on can be verified.
+fn walk(entries: &[&str], peer: IpAddr, is_trusted: &impl Fn(&IpAddr) -> bool) -> Option<IpAddr> {
+    let mut candidate = None;
+    for entry in entries.iter().rev() {
+        let Some(ip) = parse_ip(entry) else {
+            return Some(peer);
+        };
+        candidate = Some(ip);
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+    candidate
+}
+
+/// `for=` parameter of one `Forwarded` element
+fn forwarded_for(element: &str) -> Option<&str> {
+    element.split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+        .map(|(_, value)| value)
+}
+
+/// Parse `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1`, `[2001:db8::1]:80` (optionally quoted)
+fn parse_ip(token: &str) -> Option<IpAddr> {
+    let token = token.trim().trim_matches('"');
+    if let Some(rest) = token.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    token.parse().ok().or_else(|| {
+        let (host, port) = token.rsplit_once(':')?;
+        port.parse::<u16>().ok()?;
+        host.parse::<std::net::Ipv4Addr>().ok().map(IpAddr::V4)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (k, v) in pairs {
+            map.append(HeaderName::from_bytes(k.as_bytes()).unwrap(), v.parse().unwrap());
+        }
+        map
+    }
+
+    fn names(list: &[&str]) -> Vec<HeaderName> {
+        list.iter().map(|n| HeaderName::from_bytes(n.as_bytes()).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_untrusted_peer_is_client() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let peer: IpAddr = "198.51.100.1".parse().unwrap();
+        let h = headers(&[("x-forwarded-for", "1.1.1.1")]);
+        assert_eq!(resolve(peer, &h, &names(&["x-forwarded-for"]), &trusted), peer);
+    }
+
+    #[test]
+    fn test_xff_right_to_left() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+        let xff = names(&["x-forwarded-for"]);
+
+        // Spoofed leftmost entry is ignored
+        let h = headers(&[("x-forwarded-for", "6.6.6.6, 203.0.113.7, 10.1.1.1")]);
+        assert_eq!(resolve(peer, &h, &xff, &trusted), "203.0.113.7".parse::<IpAddr>().unwrap());
+
+        // Multiple header lines concatenate; ports are stripped
+        let h = headers(&[("x-forwarded-for", "203.0.113.8:5555"), ("x-forwarded-for", "10.2.2.2")]);
+        assert_eq!(resolve(peer, &h, &xff, &trusted), "203.0.113.8".parse::<IpAddr>().unwrap());
+
+        // All trusted: leftmost wins
+        let h = headers(&[("x-forwarded-for", "10.3.3.3, 10.4.4.4")]);
+        assert_eq!(resolve(peer, &h, &xff, &trusted), "10.3.3.3".parse::<IpAddr>().unwrap());
+
+        // Garbage before any untrusted hop falls back to the direct peer
+        let h = headers(&[("x-forwarded-for", "203.0.113.9, bogus, 10.5.5.5")]);
+        assert_eq!(resolve(peer, &h, &xff, &trusted), peer);
+
+        // Garbage left of the client is never reached
+        let h = headers(&[("x-forwarded-for", "bogus, 203.0.113.9, 10.5.5.5")]);
+        assert_eq!(resolve(peer, &h, &xff, &trusted), "203.0.113.9".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_header_priority_and_forwarded() {
+        let trusted: Vec<IpNet> = vec!["127.0.0.1/32".parse().unwrap()];
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let h = headers(&[
+            ("cf-connecting-ip", "2001:db8::7"),
+            ("forwarded", r#"for=192.0.2.60;proto=https, for="[2001:db8:cafe::17]:4711""#),
+        ]);
+
+        assert_eq!(
+            resolve(peer, &h, &names(&["x-real-ip", "cf-connecting-ip"]), &trusted),
+            "2001:db8::7".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            resolve(peer, &h, &names(&["forwarded"]), &trusted),
+            "2001:db8:cafe::17".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(resolve(peer, &h, &names(&["x-real-ip"]), &trusted), peer);
+    }
+}
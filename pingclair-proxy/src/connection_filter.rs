@@ -17,21 +17,7 @@ impl PingclairConnectionFilter {
     /// - Parameter blocked_ips: A list of strings representing IP addresses or CIDR blocks to deny.
     /// - Returns: A configured `PingclairConnectionFilter`.
     pub fn new(blocked_ips: &[String]) -> Self {
-        let mut blocked_cidrs = Vec::new();
-        
-        for ip_str in blocked_ips {
-            match ip_str.parse::<IpNet>() {
-                Ok(cidr) => blocked_cidrs.push(cidr),
-                Err(_) => {
-                    // Try parsing as single IP
-                    if let Ok(ip) = ip_str.parse::<IpAddr>() {
-                        blocked_cidrs.push(IpNet::from(ip));
-                    } else {
-                        tracing::warn!("⚠️ Invalid blocked IP/CIDR: {}", ip_str);
-                    }
-                }
-            }
-        }
+        let blocked_cidrs = parse_ip_ranges(blocked_ips, "blocked");
         
         if !blocked_cidrs.is_empty() {
             tracing::info!("🛡️ Initialized L4 connection filter with {} blocked CIDR(s)", blocked_cidrs.len());
@@ -41,6 +27,31 @@ impl PingclairConnectionFilter {
     }
 }
 
+/// Parse a list of IP addresses / CIDR blocks, skipping invalid entries.
+///
+/// - Parameter entries: IP addresses or CIDR blocks.
+/// - Parameter kind: Label used in the warning for invalid entries.
+/// - Returns: The parsed networks.
+pub(crate) fn parse_ip_ranges(entries: &[String], kind: &str) -> Vec<IpNet> {
+    let mut cidrs = Vec::new();
+
+    for ip_str in entries {
+        match ip_str.parse::<IpNet>() {
+            Ok(cidr) => cidrs.push(cidr),
+            Err(_) => {
+                // Try parsing as single IP
+                if let Ok(ip) = ip_str.parse::<IpAddr>() {
+                    cidrs.push(IpNet::from(ip));
+                } else {
+                    tracing::warn!("⚠️ Invalid {} IP/CIDR: {}", kind, ip_str);
+                }
+            }
+        }
+    }
+
+    cidrs
+}
+
 // MARK: - ConnectionFilter Trait
 
 #[async_trait]
@@ -0,0 +1,211 @@
+//! Standard forwarding headers for upstream requests
+//!
+//! 🏗️ ARCHITECTURE: Shared by `upstream_request_filter` (TCP) and the HTTP/3
+//! forwarder so both paths send the same headers:
+//!   - `X-Forwarded-For`: existing values are kept (and the peer appended) only
+//!     when the peer is a trusted proxy; otherwise the peer replaces them
+//!   - `X-Forwarded-Proto` / `X-Forwarded-Host`: passed through from trusted
+//!     proxies, otherwise derived from this request
+//!   - `Forwarded` (RFC 7239, opt-in): same trust rule as `X-Forwarded-For`
+//!
+//! Hop-by-hop headers (RFC 9110 §7.6.1) are stripped before forwarding.
+
+use http::HeaderMap;
+use pingclair_core::config::ForwardedHeadersConfig;
+use std::net::IpAddr;
+
+/// Headers that only apply to a single connection
+///
+/// ⚠️ `Transfer-Encoding` is hop-by-hop too, but Pingora frames the upstream
+/// body from it, so it is left to the proxy engine rather than stripped here.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+/// The downstream side of a forwarded request
+#[derive(Debug, Clone, Copy)]
+pub struct Forwarding<'a> {
+    /// Direct peer address
+    pub peer: IpAddr,
+    /// Whether the peer may supply forwarding headers
+    pub trusted: bool,
+    /// Request scheme (`http` / `https`)
+    pub proto: &'a str,
+    /// Original `Host` (or `:authority`)
+    pub host: &'a str,
+}
+
+/// Hop-by-hop headers to remove from a request before forwarding.
+///
+/// Protocol upgrades (WebSocket) keep `Connection` and `Upgrade`, and
+/// `TE: trailers` is kept for gRPC.
+///
+/// - Parameter headers: The downstream request headers.
+/// - Returns: Lower-case names to remove, including those listed in `Connection`.
+pub fn hop_by_hop(headers: &HeaderMap) -> Vec<String> {
+    let connection: Vec<String> = headers.get_all(http::header::CONNECTION).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect();
+    let upgrade = headers.contains_key(http::header::UPGRADE)
+        && connection.iter().any(|token| token == "upgrade");
+    let te_trailers = headers.get_all(http::header::TE).iter()
+        .filter_map(|v| v.to_str().ok())
+        .all(|v| v.trim().eq_ignore_ascii_case("trailers"));
+
+    let mut names: Vec<String> = HOP_BY_HOP.iter()
+        .map(|name| name.to_string())
+        .chain(connection)
+        .filter(|name| name != "transfer-encoding")
+        .filter(|name| !(upgrade && (name == "connection" || name == "upgrade")))
+        .filter(|name| !(te_trailers && name == "te"))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Forwarding headers for an upstream request.
+///
+/// - Parameter config: Which header families to send.
+/// - Parameter incoming: The downstream request headers.
+/// - Parameter fwd: Downstream connection details.
+/// - Returns: `(name, value)` pairs that replace any existing values.
+pub fn forwarded_headers(
+    config: &ForwardedHeadersConfig,
+    incoming: &HeaderMap,
+    fwd: &Forwarding,
+) -> Vec<(&'static str, String)> {
+    let mut out = Vec::new();
+    let existing = |name: &str| -> Option<String> {
+        if !fwd.trusted {
+            return None;
+        }
+        let values: Vec<&str> = incoming.get_all(name).iter().filter_map(|v| v.to_str().ok()).collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    };
+
+    if config.x_forwarded {
+        let xff = match existing("x-forwarded-for") {
+            Some(prior) => format!("{}, {}", prior, fwd.peer),
+            None => fwd.peer.to_string(),
+        };
+        out.push(("X-Forwarded-For", xff));
+        out.push(("X-Forwarded-Proto", existing("x-forwarded-proto").unwrap_or_else(|| fwd.proto.to_string())));
+        out.push(("X-Forwarded-Host", existing("x-forwarded-host").unwrap_or_else(|| fwd.host.to_string())));
+    }
+
+    if config.forwarded {
+        let node = match fwd.peer {
+            IpAddr::V4(v4) => v4.to_string(),
+            IpAddr::V6(v6) => format!("\"[{}]\"", v6),
+        };
+        let mut element = format!("for={};proto={}", node, fwd.proto);
+        if !fwd.host.is_empty() {
+            element.push_str(&format!(";host={}", quote(fwd.host)));
+        }
+        let value = match existing("forwarded") {
+            Some(prior) => format!("{}, {}", prior, element),
+            None => element,
+        };
+        out.push(("Forwarded", value));
+    }
+    out
+}
+
+/// RFC 7239 values are tokens or quoted strings; `host:port` needs quoting
+fn quote(value: &str) -> String {
+    let is_token = value.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+    if is_token {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (k, v) in pairs {
+            map.append(http::HeaderName::from_bytes(k.as_bytes()).unwrap(), v.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn test_x_forwarded_trust() {
+        let incoming = headers(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "public.example.com"),
+        ]);
+        let mut fwd = Forwarding {
+            peer: "10.0.0.2".parse().unwrap(),
+            trusted: true,
+            proto: "http",
+            host: "internal:8080",
+        };
+        let config = ForwardedHeadersConfig::default();
+
+        assert_eq!(forwarded_headers(&config, &incoming, &fwd), vec![
+            ("X-Forwarded-For", "203.0.113.7, 10.0.0.2".to_string()),
+            ("X-Forwarded-Proto", "https".to_string()),
+            ("X-Forwarded-Host", "public.example.com".to_string()),
+        ]);
+
+        // Untrusted peers cannot inject a client chain
+        fwd.trusted = false;
+        assert_eq!(forwarded_headers(&config, &incoming, &fwd), vec![
+            ("X-Forwarded-For", "10.0.0.2".to_string()),
+            ("X-Forwarded-Proto", "http".to_string()),
+            ("X-Forwarded-Host", "internal:8080".to_string()),
+        ]);
+
+        let off = ForwardedHeadersConfig { x_forwarded: false, forwarded: false };
+        assert!(forwarded_headers(&off, &incoming, &fwd).is_empty());
+    }
+
+    #[test]
+    fn test_rfc7239() {
+        let incoming = headers(&[("forwarded", "for=192.0.2.43")]);
+        let fwd = Forwarding {
+            peer: "2001:db8::1".parse().unwrap(),
+            trusted: true,
+            proto: "https",
+            host: "example.com:8443",
+        };
+        let config = ForwardedHeadersConfig { x_forwarded: false, forwarded: true };
+        assert_eq!(forwarded_headers(&config, &incoming, &fwd), vec![(
+            "Forwarded",
+            r#"for=192.0.2.43, for="[2001:db8::1]";proto=https;host="example.com:8443""#.to_string(),
+        )]);
+    }
+
+    #[test]
+    fn test_hop_by_hop() {
+        let plain = headers(&[("connection", "keep-alive, X-Secret"), ("te", "gzip")]);
+        let names = hop_by_hop(&plain);
+        assert!(names.contains(&"x-secret".to_string()));
+        assert!(names.contains(&"te".to_string()));
+        assert!(names.contains(&"upgrade".to_string()));
+
+        let websocket = headers(&[("connection", "Upgrade"), ("upgrade", "websocket"), ("te", "trailers")]);
+        let names = hop_by_hop(&websocket);
+        assert!(!names.contains(&"connection".to_string()));
+        assert!(!names.contains(&"upgrade".to_string()));
+        assert!(!names.contains(&"te".to_string()));
+        assert!(names.contains(&"keep-alive".to_string()));
+    }
+}
@@ -168,4 +168,14 @@ impl HealthCheck for HealthChecker {
             self.config.negative_threshold
         }
     }
+
+    /// Publishes health transitions to the `pingclair_upstream_healthy` gauge.
+    ///
+    /// - Parameter target: The backend whose status flipped.
+    /// - Parameter healthy: The new health status.
+    async fn health_status_change(&self, target: &Backend, healthy: bool) {
+        crate::metrics::UPSTREAM_HEALTHY
+            .with_label_values(&[&target.addr.to_string()])
+            .set(healthy as i64);
+    }
 }
@@ -11,10 +11,21 @@
 pub mod health_check;
 pub mod rate_limit;
 pub mod metrics;
+pub mod statsd;
 pub mod quic;
 mod load_balancer;
 mod upstream;
 pub mod connection_filter;
+pub mod client_ip;
+pub mod forwarded;
+pub mod proxy_protocol;
+pub mod accel;
+pub mod trace;
+pub mod otel;
+pub mod access_log;
+pub mod log_format;
+pub mod log_filter;
+mod log_sink;
 pub mod server;
 
 // MARK: - Exports
@@ -13,7 +13,6 @@ use crate::health_check::HealthChecker;
 use pingora_load_balancing::prelude::RoundRobin;
 use pingora_load_balancing::selection::consistent::KetamaHashing;
 use pingora_load_balancing::LoadBalancer as NativeLoadBalancer;
-use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -80,7 +79,6 @@ impl LeastConnTracker {
 
         let upstream = self.upstreams.get(min_idx)?.clone();
         let counter = self.counters[min_idx].1.clone();
-        // Increment before returning — decremented by the caller via release()
         counter.fetch_add(1, Ordering::Relaxed);
         Some((upstream, counter))
     }
@@ -92,8 +90,11 @@ impl LeastConnTracker {
 ///
 /// Callers receive this alongside the selected `Upstream`. It is intentionally
 /// dropped at end of request scope to keep counters accurate.
+#[deprecated(note = "never handed out by `LoadBalancer::select`; LeastConn counters count selections")]
+#[allow(dead_code)]
 pub struct ConnGuard(Arc<AtomicUsize>);
 
+#[allow(deprecated)]
 impl Drop for ConnGuard {
     fn drop(&mut self) {
         // 🛑 SAFETY: Never underflow — we only create a guard after a successful
@@ -200,17 +201,16 @@ impl LoadBalancer {
     /// Selects an upstream backend for a request.
     ///
     /// - Parameter key: Client IP bytes for hash-based selection (`IpHash`).
-    ///                  Ignored for other strategies.
+    ///   Ignored for other strategies.
     /// - Returns: An optional `Upstream` if a healthy backend is available.
     pub fn select(&self, key: Option<&[u8]>) -> Option<Upstream> {
         match self.strategy {
             Strategy::LeastConn => {
-                // ⚡ LeastConn: pick minimum active-connection upstream.
-                // The ConnGuard is intentionally dropped here — for the simple
-                // select() API we count a "selection" as one request unit.
-                // Callers that need precise tracking can use select_with_guard().
+                // ⚡ LeastConn: pick the upstream with the lowest counter.
+                // ⚠️ Counters are never released, so this counts selections
+                // rather than in-flight requests.
                 let tracker = self.least_conn.as_ref()?;
-                let (upstream, _guard) = tracker.select()?;
+                let (upstream, _counter) = tracker.select()?;
                 Some(upstream)
             }
             Strategy::IpHash => {
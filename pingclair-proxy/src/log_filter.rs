@@ -0,0 +1,407 @@
+//! Access log filters: field redaction, skipped paths and sampling
+//!
+//! 🏗️ ARCHITECTURE: Filters run inside `AccessLogger::log`, before a line is
+//! handed to the writer, so redacted values never reach the destination.
+//!   - JSON entries are filtered on the rendered document: any `>`-separated
+//!     field path of the Caddy schema can be rewritten.
+//!   - Line formats (text, Common/Combined, templates) are filtered on the
+//!     entry itself, for the fields they can print: `request>remote_ip`,
+//!     `request>client_ip`, `request>host`, `request>uri`, `user_id`,
+//!     `request>headers>*` and `resp_headers>*`.
+//!
+//! Failed requests (`ERROR` level) are never sampled out, so errors stay
+//! visible on high-volume routes.
+
+use crate::access_log::AccessLogEntry;
+use pingclair_core::config::{LogFieldAction, LogFilterConfig, LogParamAction, LogParamFilter};
+use pingclair_core::server::path_glob_matches;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use tracing::Level;
+
+/// Compiled filters of one site's access log
+#[derive(Debug, Clone, Default)]
+pub struct LogFilters {
+    config: LogFilterConfig,
+}
+
+impl LogFilters {
+    pub fn new(config: &LogFilterConfig) -> Self {
+        Self { config: config.clone() }
+    }
+
+    /// Whether the entry should be written (skip rules, then sampling).
+    pub fn should_log(&self, entry: &AccessLogEntry) -> bool {
+        let path = entry.uri.split('?').next().unwrap_or_default();
+        if self.config.skip_paths.iter().any(|pattern| path_glob_matches(path, pattern)) {
+            return false;
+        }
+        if entry.level() == Level::ERROR {
+            return true;
+        }
+
+        let rule = self.config.sample.iter()
+            .find(|rule| rule.paths.is_empty() || rule.paths.iter().any(|pattern| path_glob_matches(path, pattern)));
+        match rule {
+            Some(rule) => fastrand::f64() < rule.ratio,
+            None => true,
+        }
+    }
+
+    /// Rewrite a rendered JSON entry in place.
+    pub fn apply_json(&self, value: &mut serde_json::Value) {
+        for filter in &self.config.fields {
+            let path: Vec<&str> = filter.field.split('>').map(str::trim).collect();
+            filter_json_path(value, &path, &filter.action);
+        }
+    }
+
+    /// Owned, filtered copies of the fields line formats print.
+    ///
+    /// - Returns: `None` when no field filter is configured.
+    pub fn redact(&self, entry: &AccessLogEntry) -> Option<RedactedFields> {
+        if self.config.fields.is_empty() {
+            return None;
+        }
+
+        let mut fields = RedactedFields {
+            remote_ip: entry.remote_ip.to_string(),
+            client_ip: entry.client().to_string(),
+            host: entry.host.to_string(),
+            uri: entry.uri.to_string(),
+            user_id: entry.user_id.clone(),
+            request_headers: entry.request_headers.cloned(),
+            response_headers: entry.response_headers.cloned(),
+        };
+
+        for filter in &self.config.fields {
+            let action = &filter.action;
+            let path: Vec<&str> = filter.field.split('>').map(str::trim).collect();
+            match path.as_slice() {
+                ["request", "remote_ip"] => apply_string(&mut fields.remote_ip, action),
+                ["request", "client_ip"] => apply_string(&mut fields.client_ip, action),
+                ["request", "host"] => apply_string(&mut fields.host, action),
+                ["request", "uri"] => apply_string(&mut fields.uri, action),
+                ["user_id"] => {
+                    fields.user_id = fields.user_id.as_deref().and_then(|user| apply(user, action));
+                }
+                ["request", "headers"] => {
+                    if matches!(action, LogFieldAction::Delete) {
+                        fields.request_headers = None;
+                    }
+                }
+                ["resp_headers"] => {
+                    if matches!(action, LogFieldAction::Delete) {
+                        fields.response_headers = None;
+                    }
+                }
+                ["request", "headers", name] => {
+                    if let Some(headers) = fields.request_headers.as_mut() {
+                        filter_header(headers, name, action);
+                    }
+                }
+                ["resp_headers", name] => {
+                    if let Some(headers) = fields.response_headers.as_mut() {
+                        filter_header(headers, name, action);
+                    }
+                }
+                // Other fields only exist in the JSON schema
+                _ => {}
+            }
+        }
+
+        Some(fields)
+    }
+}
+
+/// Filtered values backing a redacted `AccessLogEntry`
+#[derive(Debug, Clone)]
+pub struct RedactedFields {
+    remote_ip: String,
+    client_ip: String,
+    host: String,
+    uri: String,
+    user_id: Option<String>,
+    request_headers: Option<http::HeaderMap>,
+    response_headers: Option<http::HeaderMap>,
+}
+
+impl RedactedFields {
+    /// The entry with its filtered fields replaced
+    pub fn entry<'a>(&'a self, entry: &AccessLogEntry<'a>) -> AccessLogEntry<'a> {
+        AccessLogEntry {
+            remote_ip: &self.remote_ip,
+            client_ip: &self.client_ip,
+            host: &self.host,
+            uri: &self.uri,
+            user_id: self.user_id.clone(),
+            request_headers: self.request_headers.as_ref(),
+            response_headers: self.response_headers.as_ref(),
+            ..entry.clone()
+        }
+    }
+}
+
+// MARK: - Actions
+
+/// Apply an action to a value (`None`: the value is deleted)
+fn apply(value: &str, action: &LogFieldAction) -> Option<String> {
+    match action {
+        LogFieldAction::Delete => None,
+        LogFieldAction::Replace(replacement) => Some(replacement.clone()),
+        LogFieldAction::Hash => Some(hash(value)),
+        LogFieldAction::IpMask { ipv4, ipv6 } => Some(mask_ip(value, *ipv4, *ipv6)),
+        LogFieldAction::Query(params) => Some(filter_query(value, params)),
+        LogFieldAction::Cookie(params) => Some(filter_cookies(value, params)),
+    }
+}
+
+/// Apply an action to a string field; deleted fields become empty
+fn apply_string(field: &mut String, action: &LogFieldAction) {
+    *field = apply(field, action).unwrap_or_default();
+}
+
+fn filter_header(headers: &mut http::HeaderMap, name: &str, action: &LogFieldAction) {
+    let Ok(name) = http::HeaderName::from_bytes(name.to_ascii_lowercase().as_bytes()) else {
+        return;
+    };
+    let values: Vec<_> = headers.get_all(&name)
+        .iter()
+        .filter_map(|v| apply(&String::from_utf8_lossy(v.as_bytes()), action))
+        .filter_map(|v| http::HeaderValue::from_str(&v).ok())
+        .collect();
+
+    headers.remove(&name);
+    for value in values {
+        headers.append(name.clone(), value);
+    }
+}
+
+fn filter_json_path(value: &mut serde_json::Value, path: &[&str], action: &LogFieldAction) {
+    let Some((last, parents)) = path.split_last() else {
+        return;
+    };
+
+    let mut current = value;
+    for segment in parents {
+        let Some(next) = json_key(current, segment).and_then(|key| current.get_mut(&key)) else {
+            return;
+        };
+        current = next;
+    }
+
+    let Some(key) = json_key(current, last) else {
+        return;
+    };
+    let Some(object) = current.as_object_mut() else {
+        return;
+    };
+    if matches!(action, LogFieldAction::Delete) {
+        object.remove(&key);
+        return;
+    }
+    if let Some(field) = object.get_mut(&key) {
+        filter_json_value(field, action);
+    }
+}
+
+/// Rewrite a JSON value: strings directly, arrays (header values) element-wise
+fn filter_json_value(value: &mut serde_json::Value, action: &LogFieldAction) {
+    match value {
+        serde_json::Value::Array(items) => {
+            for item in items.iter_mut() {
+                filter_json_value(item, action);
+            }
+        }
+        serde_json::Value::String(s) => {
+            *s = apply(s, action).unwrap_or_default();
+        }
+        other => {
+            if let Some(rewritten) = apply(&other.to_string(), action) {
+                *other = serde_json::Value::String(rewritten);
+            }
+        }
+    }
+}
+
+/// Exact key, else a case-insensitive match (header names are canonicalised)
+fn json_key(value: &serde_json::Value, key: &str) -> Option<String> {
+    let object = value.as_object()?;
+    if object.contains_key(key) {
+        return Some(key.to_string());
+    }
+    object.keys().find(|k| k.eq_ignore_ascii_case(key)).cloned()
+}
+
+/// First 4 bytes of the SHA-256 digest, hex encoded (as Caddy's `hash` filter)
+fn hash(value: &str) -> String {
+    Sha256::digest(value.as_bytes())[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Zero the host bits of an IP address; values that are not IPs are unchanged
+fn mask_ip(value: &str, ipv4: u8, ipv6: u8) -> String {
+    match value.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            let bits = u32::from(ip) & u32::MAX.checked_shl(32 - ipv4.min(32) as u32).unwrap_or(0);
+            std::net::Ipv4Addr::from(bits).to_string()
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let bits = u128::from(ip) & u128::MAX.checked_shl(128 - ipv6.min(128) as u32).unwrap_or(0);
+            std::net::Ipv6Addr::from(bits).to_string()
+        }
+        Err(_) => value.to_string(),
+    }
+}
+
+fn apply_param(value: &str, action: &LogParamAction) -> Option<String> {
+    match action {
+        LogParamAction::Delete => None,
+        LogParamAction::Replace(replacement) => Some(replacement.clone()),
+        LogParamAction::Hash => Some(hash(value)),
+    }
+}
+
+/// Filter the query parameters of a URI (or of a bare query string)
+fn filter_query(uri: &str, params: &[LogParamFilter]) -> String {
+    let (prefix, query) = match uri.split_once('?') {
+        Some((path, query)) => (Some(path), query),
+        None if uri.starts_with('/') => return uri.to_string(),
+        None => (None, uri),
+    };
+
+    let filtered: Vec<String> = query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match params.iter().find(|p| p.name == name) {
+                Some(param) => apply_param(value, &param.action).map(|v| format!("{}={}", name, v)),
+                None => Some(pair.to_string()),
+            }
+        })
+        .collect();
+
+    match prefix {
+        Some(path) if filtered.is_empty() => path.to_string(),
+        Some(path) => format!("{}?{}", path, filtered.join("&")),
+        None => filtered.join("&"),
+    }
+}
+
+/// Filter the cookies of a `Cookie` header value
+fn filter_cookies(header: &str, params: &[LogParamFilter]) -> String {
+    header.split(';')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match params.iter().find(|p| p.name == name) {
+                Some(param) => apply_param(value, &param.action).map(|v| format!("{}={}", name, v)),
+                None => Some(pair.to_string()),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// MARK: - Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingclair_core::config::{LogFieldFilter, LogSampleConfig};
+
+    fn field(field: &str, action: LogFieldAction) -> LogFieldFilter {
+        LogFieldFilter { field: field.to_string(), action }
+    }
+
+    fn param(name: &str, action: LogParamAction) -> LogParamFilter {
+        LogParamFilter { name: name.to_string(), action }
+    }
+
+    fn filters() -> LogFilters {
+        LogFilters::new(&LogFilterConfig {
+            fields: vec![
+                field("request>headers>Authorization", LogFieldAction::Delete),
+                field("request>headers>Cookie", LogFieldAction::Cookie(vec![param("session", LogParamAction::Replace("REDACTED".into()))])),
+                field("request>uri", LogFieldAction::Query(vec![
+                    param("token", LogParamAction::Delete),
+                    param("email", LogParamAction::Hash),
+                ])),
+                field("request>remote_ip", LogFieldAction::IpMask { ipv4: 24, ipv6: 32 }),
+                field("duration", LogFieldAction::Delete),
+            ],
+            skip_paths: vec!["/health".into(), "/status/*".into()],
+            sample: vec![LogSampleConfig { ratio: 0.0, paths: vec!["/noisy*".into()] }],
+        })
+    }
+
+    #[test]
+    fn test_value_filters() {
+        assert_eq!(hash("secret"), "2bb80d53");
+        assert_eq!(mask_ip("203.0.113.77", 24, 32), "203.0.113.0");
+        assert_eq!(mask_ip("2001:db8:abcd:12::1", 24, 32), "2001:db8::");
+        assert_eq!(mask_ip("unix", 24, 32), "unix");
+
+        let params = [param("token", LogParamAction::Delete), param("email", LogParamAction::Replace("x".into()))];
+        assert_eq!(filter_query("/a?token=1&q=2&email=me", &params), "/a?q=2&email=x");
+        assert_eq!(filter_query("/a?token=1", &params), "/a");
+        assert_eq!(filter_query("token=1&q=2", &params), "q=2");
+        assert_eq!(filter_cookies("token=abc; theme=dark", &params), "theme=dark");
+    }
+
+    #[test]
+    fn test_redact_entry_and_json() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("authorization", "Bearer abc".parse().unwrap());
+        headers.insert("cookie", "session=s3cr3t; theme=dark".parse().unwrap());
+        let entry = AccessLogEntry {
+            remote_ip: "203.0.113.77",
+            uri: "/search?token=abc&q=rust",
+            request_headers: Some(&headers),
+            ..Default::default()
+        };
+
+        let filters = filters();
+        let redacted = filters.redact(&entry).unwrap();
+        let entry = redacted.entry(&entry);
+        assert_eq!(entry.remote_ip, "203.0.113.0");
+        assert_eq!(entry.uri, "/search?q=rust");
+        let headers = entry.request_headers.unwrap();
+        assert!(headers.get("authorization").is_none());
+        assert_eq!(headers.get("cookie").unwrap(), "session=REDACTED; theme=dark");
+
+        let mut json = serde_json::json!({
+            "duration": 0.5,
+            "request": {
+                "remote_ip": "203.0.113.77",
+                "uri": "/search?token=abc&email=me",
+                "headers": { "Authorization": ["Bearer abc"], "Cookie": ["session=s3cr3t"] },
+            },
+        });
+        filters.apply_json(&mut json);
+        assert!(json.get("duration").is_none());
+        assert_eq!(json["request"]["remote_ip"], "203.0.113.0");
+        assert_eq!(json["request"]["uri"], format!("/search?email={}", hash("me")));
+        assert!(json["request"]["headers"].get("Authorization").is_none());
+        assert_eq!(json["request"]["headers"]["Cookie"][0], "session=REDACTED");
+    }
+
+    #[test]
+    fn test_skip_and_sample() {
+        let filters = filters();
+        let entry = |uri, status| AccessLogEntry { uri, status, ..Default::default() };
+
+        assert!(!filters.should_log(&entry("/health", 200)));
+        assert!(!filters.should_log(&entry("/status/live?x=1", 200)));
+        assert!(filters.should_log(&entry("/healthz", 200)));
+        // `/status/*` stops at the segment boundary
+        assert!(!filters.should_log(&entry("/status", 200)));
+        assert!(filters.should_log(&entry("/statuses", 200)));
+        // Ratio 0: sampled out, except errors
+        assert!(!filters.should_log(&entry("/noisy/feed", 200)));
+        assert!(filters.should_log(&entry("/noisy/feed", 502)));
+    }
+}
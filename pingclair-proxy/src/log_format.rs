@@ -0,0 +1,477 @@
+//! User-defined access log lines
+//!
+//! 🏗️ ARCHITECTURE: A `LogTemplate` is compiled once per site from the `log`
+//! block and rendered for every request. Two placeholder syntaxes are accepted
+//! so existing configurations can be reused as-is:
+//!   - Caddy placeholders: `{http.request.header.User-Agent}`, `{http.response.status}`
+//!   - nginx variables: `$remote_addr`, `${request_time}`, `$http_user_agent`
+//!
+//! Caddy placeholders the log registry does not know fall back to the shared
+//! registry (`{query.page}`, `{time.now.unix}`, ...).
+//!
+//! The Common and Combined Log Formats are predefined templates. Values that
+//! are not available for a request (no upstream, no TLS, ...) render as `-`.
+//! Like nginx, `"`, `\` and non-printable bytes in values are escaped as `\xHH`
+//! so quoted fields cannot be broken out of.
+
+use crate::access_log::AccessLogEntry;
+use http::HeaderName;
+use pingclair_core::server::{Placeholder, PlaceholderContext};
+use std::fmt::Write as _;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// NCSA Common Log Format
+pub const COMMON: &str = r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent"#;
+
+/// NCSA Combined Log Format
+pub const COMBINED: &str = r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent "$http_referer" "$http_user_agent""#;
+
+// MARK: - Template
+
+/// Compiled log line template
+#[derive(Debug, Clone)]
+pub struct LogTemplate {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Var(Var),
+}
+
+/// Unit used to render a duration
+#[derive(Debug, Clone, Copy)]
+enum Unit {
+    /// Seconds with millisecond resolution (`0.012`, nginx)
+    Secs,
+    /// Whole milliseconds (`12`, Caddy `*_ms`)
+    Millis,
+}
+
+#[derive(Debug, Clone)]
+enum Var {
+    /// Direct peer address
+    RemoteAddr,
+    /// Client address resolved through trusted proxies
+    ClientIp,
+    RemotePort,
+    RemoteUser,
+    TimeLocal,
+    TimeIso8601,
+    Msec,
+    Request,
+    Method,
+    RequestUri,
+    Path,
+    Query,
+    Proto,
+    Scheme,
+    Host,
+    Status,
+    BytesOut,
+    BytesIn,
+    RequestTime(Unit),
+    RequestHeader(HeaderName),
+    ResponseHeader(HeaderName),
+    UpstreamAddr,
+    UpstreamConnect(Unit),
+    UpstreamHeader(Unit),
+    UpstreamResponse(Unit),
+    TlsVersion,
+    TlsCipher,
+    Route,
+    RateLimit,
+    RequestId,
+    TraceId,
+    SpanId,
+    /// Any other placeholder of the shared registry
+    Shared(Placeholder),
+}
+
+impl LogTemplate {
+    /// Compile a template.
+    ///
+    /// - Parameter source: Literal text mixed with `{placeholders}` and `$variables`.
+    /// - Returns: The template, or an error naming the first unknown placeholder.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = source;
+
+        while let Some(c) = rest.chars().next() {
+            let parsed = match c {
+                '$' => parse_dollar(rest),
+                '{' => parse_brace(rest),
+                _ => None,
+            };
+
+            match parsed {
+                Some((name, consumed, nginx)) => {
+                    let var = if nginx { nginx_var(name) } else { caddy_var(name) }
+                        .ok_or_else(|| format!("unknown log placeholder '{}'", &rest[..consumed]))?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Var(var));
+                    rest = &rest[consumed..];
+                }
+                None => {
+                    literal.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Render one log line (without the trailing newline).
+    pub fn render(&self, entry: &AccessLogEntry) -> String {
+        let mut line = String::with_capacity(256);
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => line.push_str(text),
+                Segment::Var(var) => match value(var, entry) {
+                    Some(value) if !value.is_empty() => escape_into(&mut line, &value),
+                    _ => line.push('-'),
+                },
+            }
+        }
+        line
+    }
+}
+
+/// `$name` / `${name}` → (name, bytes consumed, nginx syntax)
+fn parse_dollar(s: &str) -> Option<(&str, usize, bool)> {
+    if let Some(inner) = s.strip_prefix("${") {
+        let end = inner.find('}')?;
+        let name = &inner[..end];
+        return is_ident(name, false).then_some((name, end + 3, true));
+    }
+    let len = s[1..].bytes().take_while(|b| b.is_ascii_alphanumeric() || *b == b'_').count();
+    (len > 0).then(|| (&s[1..1 + len], 1 + len, true))
+}
+
+/// `{name.with.dots}` → (name, bytes consumed, nginx syntax); other braces are literal
+fn parse_brace(s: &str) -> Option<(&str, usize, bool)> {
+    let end = s.find('}')?;
+    let name = &s[1..end];
+    is_ident(name, true).then_some((name, end + 1, false))
+}
+
+fn is_ident(name: &str, dotted: bool) -> bool {
+    !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || (dotted && (b == b'.' || b == b'-')))
+}
+
+// MARK: - Placeholder Names
+
+fn nginx_var(name: &str) -> Option<Var> {
+    if let Some(header) = name.strip_prefix("http_") {
+        return header_name(&header.replace('_', "-")).map(Var::RequestHeader);
+    }
+    if let Some(header) = name.strip_prefix("sent_http_") {
+        return header_name(&header.replace('_', "-")).map(Var::ResponseHeader);
+    }
+
+    Some(match name {
+        // Like nginx's realip module: `$remote_addr` is the resolved client
+        "remote_addr" => Var::ClientIp,
+        "realip_remote_addr" => Var::RemoteAddr,
+        "remote_port" => Var::RemotePort,
+        "remote_user" => Var::RemoteUser,
+        "time_local" => Var::TimeLocal,
+        "time_iso8601" => Var::TimeIso8601,
+        "msec" => Var::Msec,
+        "request" => Var::Request,
+        "request_method" => Var::Method,
+        "request_uri" => Var::RequestUri,
+        "uri" | "document_uri" => Var::Path,
+        "args" | "query_string" => Var::Query,
+        "server_protocol" => Var::Proto,
+        "scheme" => Var::Scheme,
+        "host" => Var::Host,
+        "status" => Var::Status,
+        "body_bytes_sent" => Var::BytesOut,
+        "request_length" => Var::BytesIn,
+        "request_time" => Var::RequestTime(Unit::Secs),
+        "upstream_addr" => Var::UpstreamAddr,
+        "upstream_connect_time" => Var::UpstreamConnect(Unit::Secs),
+        "upstream_header_time" => Var::UpstreamHeader(Unit::Secs),
+        "upstream_response_time" => Var::UpstreamResponse(Unit::Secs),
+        "ssl_protocol" => Var::TlsVersion,
+        "ssl_cipher" => Var::TlsCipher,
+        "limit_req_status" => Var::RateLimit,
+        "request_id" => Var::RequestId,
+        "trace_id" => Var::TraceId,
+        "span_id" => Var::SpanId,
+        "pingclair_route" => Var::Route,
+        _ => return None,
+    })
+}
+
+fn caddy_var(name: &str) -> Option<Var> {
+    if let Some(header) = name.strip_prefix("http.request.header.") {
+        return header_name(header).map(Var::RequestHeader);
+    }
+    if let Some(header) = name.strip_prefix("http.response.header.") {
+        return header_name(header).map(Var::ResponseHeader);
+    }
+
+    Some(match name {
+        "http.request.remote.host" => Var::RemoteAddr,
+        "http.vars.client_ip" | "client_ip" => Var::ClientIp,
+        "http.request.remote.port" => Var::RemotePort,
+        "http.auth.user.id" => Var::RemoteUser,
+        "time.now.common_log" => Var::TimeLocal,
+        "time.now.iso" => Var::TimeIso8601,
+        "http.request.method" => Var::Method,
+        "http.request.uri" => Var::RequestUri,
+        "http.request.uri.path" => Var::Path,
+        "http.request.uri.query" => Var::Query,
+        "http.request.proto" => Var::Proto,
+        "http.request.scheme" => Var::Scheme,
+        "http.request.host" => Var::Host,
+        "http.request.size" => Var::BytesIn,
+        "http.response.status" | "http.response.status_code" => Var::Status,
+        "http.response.size" => Var::BytesOut,
+        "http.response.duration_ms" => Var::RequestTime(Unit::Millis),
+        "http.request.tls.version" => Var::TlsVersion,
+        "http.request.tls.cipher_suite" => Var::TlsCipher,
+        "http.request.uuid" | "pingclair.request_id" => Var::RequestId,
+        "http.reverse_proxy.upstream.hostport" | "upstream.address" => Var::UpstreamAddr,
+        "upstream.connect_ms" => Var::UpstreamConnect(Unit::Millis),
+        "http.reverse_proxy.upstream.latency_ms" | "upstream.header_ms" => Var::UpstreamHeader(Unit::Millis),
+        "http.reverse_proxy.upstream.duration_ms" | "upstream.response_ms" => Var::UpstreamResponse(Unit::Millis),
+        "pingclair.route" => Var::Route,
+        "pingclair.rate_limit" => Var::RateLimit,
+        "pingclair.trace_id" => Var::TraceId,
+        "pingclair.span_id" => Var::SpanId,
+        _ => return Placeholder::parse(name).ok().flatten().map(Var::Shared),
+    })
+}
+
+fn header_name(name: &str) -> Option<HeaderName> {
+    HeaderName::from_bytes(name.to_ascii_lowercase().as_bytes()).ok()
+}
+
+// MARK: - Values
+
+fn value(var: &Var, entry: &AccessLogEntry) -> Option<String> {
+    let time = || entry.time.unwrap_or_else(SystemTime::now);
+    let timing = &entry.upstream_timing;
+
+    Some(match var {
+        Var::RemoteAddr => entry.remote_ip.to_string(),
+        Var::ClientIp => entry.client().to_string(),
+        Var::RemotePort => entry.remote_port?.to_string(),
+        Var::RemoteUser => entry.user_id.clone()?,
+        Var::TimeLocal => common_log_time(time()),
+        Var::TimeIso8601 => iso8601(time()),
+        Var::Msec => {
+            let since_epoch = time().duration_since(UNIX_EPOCH).unwrap_or_default();
+            format!("{}.{:03}", since_epoch.as_secs(), since_epoch.subsec_millis())
+        }
+        Var::Request => format!("{} {} {}", entry.method, entry.uri, entry.proto),
+        Var::Method => entry.method.to_string(),
+        Var::RequestUri => entry.uri.to_string(),
+        Var::Path => entry.uri.split('?').next().unwrap_or_default().to_string(),
+        Var::Query => entry.uri.split_once('?')?.1.to_string(),
+        Var::Proto => entry.proto.to_string(),
+        Var::Scheme => entry.scheme.to_string(),
+        Var::Host => entry.host.to_string(),
+        Var::Status => entry.status.to_string(),
+        Var::BytesOut => entry.bytes.to_string(),
+        Var::BytesIn => entry.bytes_in.to_string(),
+        Var::RequestTime(unit) => duration(entry.duration, *unit),
+        Var::RequestHeader(name) => header_value(entry.request_headers?, name)?,
+        Var::ResponseHeader(name) => header_value(entry.response_headers?, name)?,
+        Var::UpstreamAddr => entry.upstream.clone()?,
+        Var::UpstreamConnect(unit) => duration(timing.connect?, *unit),
+        Var::UpstreamHeader(unit) => duration(timing.header?, *unit),
+        Var::UpstreamResponse(unit) => duration(timing.response?, *unit),
+        Var::TlsVersion => entry.tls.as_ref()?.version.clone(),
+        Var::TlsCipher => entry.tls.as_ref()?.cipher.clone(),
+        Var::Route => entry.route?.to_string(),
+        Var::RateLimit => match entry.rate_limited? {
+            true => "REJECTED".to_string(),
+            false => "PASSED".to_string(),
+        },
+        Var::RequestId => entry.request_id.to_string(),
+        Var::TraceId => entry.trace_id.to_string(),
+        Var::SpanId => entry.span_id.to_string(),
+        Var::Shared(placeholder) => placeholder.resolve(&placeholder_context(entry)).into_owned(),
+    })
+}
+
+/// Shared placeholder context of a logged request
+fn placeholder_context<'a>(entry: &'a AccessLogEntry) -> PlaceholderContext<'a> {
+    let (path, query) = entry.uri.split_once('?').unwrap_or((entry.uri, ""));
+    PlaceholderContext {
+        method: entry.method,
+        host: entry.host,
+        path,
+        query,
+        scheme: entry.scheme,
+        proto: entry.proto,
+        remote_ip: entry.remote_ip,
+        remote_port: entry.remote_port,
+        client_ip: entry.client(),
+        request_id: entry.request_id,
+        headers: entry.request_headers,
+        tls_version: entry.tls.as_ref().map(|t| t.version.as_str()),
+        tls_cipher: entry.tls.as_ref().map(|t| t.cipher.as_str()),
+        upstream: entry.upstream.as_deref(),
+        status: Some(entry.status),
+        response_headers: entry.response_headers,
+        time: entry.time,
+        ..Default::default()
+    }
+}
+
+/// All values of a header, comma-joined
+fn header_value(headers: &http::HeaderMap, name: &HeaderName) -> Option<String> {
+    let values: Vec<_> = headers.get_all(name)
+        .iter()
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+        .collect();
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+fn duration(d: Duration, unit: Unit) -> String {
+    match unit {
+        Unit::Secs => format!("{}.{:03}", d.as_secs(), d.subsec_millis()),
+        Unit::Millis => d.as_millis().to_string(),
+    }
+}
+
+/// Escape `"`, `\` and non-printable bytes as `\xHH` (nginx `escape=default`)
+fn escape_into(out: &mut String, value: &str) {
+    for b in value.bytes() {
+        if b == b'"' || b == b'\\' || !(0x20..0x7f).contains(&b) {
+            let _ = write!(out, "\\x{:02X}", b);
+        } else {
+            out.push(b as char);
+        }
+    }
+}
+
+// MARK: - Time
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Common Log Format timestamp: `10/Oct/2000:13:55:36 +0000`
+///
+/// ⚠️ Always UTC; nginx and Apache use the server's local time zone.
+pub fn common_log_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = crate::access_log::civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        day, MONTHS[month as usize - 1], year,
+        rem / 3600, (rem % 3600) / 60, rem % 60
+    )
+}
+
+/// ISO 8601 timestamp without fractions: `2000-10-10T13:55:36+00:00`
+fn iso8601(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = crate::access_log::civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+        year, month, day,
+        rem / 3600, (rem % 3600) / 60, rem % 60
+    )
+}
+
+// MARK: - Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access_log::{TlsInfo, UpstreamTiming};
+
+    fn entry<'a>(request: &'a http::HeaderMap, response: &'a http::HeaderMap) -> AccessLogEntry<'a> {
+        AccessLogEntry {
+            time: Some(UNIX_EPOCH + Duration::from_secs(971_186_136)),
+            remote_ip: "127.0.0.1",
+            method: "GET",
+            host: "example.com",
+            uri: "/apache_pb.gif?x=1",
+            proto: "HTTP/1.0",
+            status: 200,
+            bytes: 2326,
+            bytes_in: 87,
+            duration: Duration::from_millis(12),
+            request_headers: Some(request),
+            response_headers: Some(response),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_combined_log_format() {
+        let mut request = http::HeaderMap::new();
+        request.insert("referer", "http://www.example.com/start.html".parse().unwrap());
+        request.insert("user-agent", "Mozilla/4.08 \"x\"".parse().unwrap());
+        // frank:secret
+        request.insert("authorization", "Basic ZnJhbms6c2VjcmV0".parse().unwrap());
+        let response = http::HeaderMap::new();
+
+        let mut combined = entry(&request, &response);
+        combined.user_id = crate::access_log::basic_auth_user(&request);
+        let line = LogTemplate::parse(COMBINED).unwrap().render(&combined);
+        assert_eq!(
+            line,
+            r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] "GET /apache_pb.gif?x=1 HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08 \x22x\x22""#
+        );
+
+        // No credentials, no referer
+        let empty = http::HeaderMap::new();
+        let line = LogTemplate::parse(COMMON).unwrap().render(&entry(&empty, &response));
+        assert!(line.starts_with("127.0.0.1 - - [10/Oct/2000:13:55:36 +0000]"));
+    }
+
+    #[test]
+    fn test_custom_placeholders() {
+        let mut request = http::HeaderMap::new();
+        request.insert("x-tenant", "acme".parse().unwrap());
+        let mut response = http::HeaderMap::new();
+        response.insert("cache-status", "HIT".parse().unwrap());
+
+        let mut entry = entry(&request, &response);
+        entry.upstream = Some("10.0.0.5:8080".to_string());
+        entry.upstream_timing = UpstreamTiming {
+            connect: Some(Duration::from_millis(3)),
+            header: Some(Duration::from_millis(9)),
+            ..Default::default()
+        };
+        entry.tls = Some(TlsInfo { version: "TLSv1.3".into(), cipher: "TLS_AES_128_GCM_SHA256".into() });
+        entry.route = Some("/api/*");
+        entry.rate_limited = Some(false);
+        entry.scheme = "https";
+
+        let template = LogTemplate::parse(
+            "{http.request.header.X-Tenant} $sent_http_cache_status ${upstream_addr} \
+             $upstream_connect_time {upstream.header_ms} $upstream_response_time \
+             {http.request.tls.version}/$ssl_cipher {pingclair.route} $limit_req_status \
+             in=$request_length out={http.response.size} $scheme {\"literal\": $status}"
+        ).unwrap();
+        assert_eq!(
+            template.render(&entry),
+            "acme HIT 10.0.0.5:8080 0.003 9 - TLSv1.3/TLS_AES_128_GCM_SHA256 /api/* PASSED in=87 out=2326 https {\"literal\": 200}"
+        );
+
+        let shared = LogTemplate::parse("{query.x} {cookie.session} {time.now.unix} {method}").unwrap();
+        assert_eq!(shared.render(&entry), "1 - 971186136 GET");
+
+        assert!(LogTemplate::parse("$no_such_variable").is_err());
+        assert!(LogTemplate::parse("{http.nope}").is_err());
+    }
+}
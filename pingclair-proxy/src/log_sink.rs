@@ -0,0 +1,572 @@
+//! Log destinations behind a `LogWriter`
+//!
+//! 🏗️ ARCHITECTURE: Each `LogWriter` thread owns one `Sink` and hands it one
+//! line at a time:
+//!   - Files are appended to and, with a `LogRotation`, rolled by size and/or
+//!     UTC-aligned interval. Rolled files are renamed to
+//!     `<name>-<timestamp>.<ext>`, gzipped and pruned to the retention count on
+//!     a helper thread so the writer keeps draining its queue.
+//!   - Syslog sends one RFC 5424 message per line over UDP or a unix datagram
+//!     socket (`/dev/log`).
+//!   - Net sinks stream newline-delimited lines to a TCP or unix socket and
+//!     reconnect after failures; lines written while disconnected are dropped.
+//!
+//! `reopen` (triggered by SIGUSR1 through `access_log::reopen_all`) closes and
+//! reopens files so external tools such as logrotate can move them away.
+//! `set_rotation` applies a reloaded rotation policy to a file that is
+//! already open.
+
+use crate::access_log::{civil_from_days, rfc3339};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use pingclair_core::config::{LogOutput, LogRotation, SyslogConfig};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::Level;
+
+/// Delay before reconnecting a failed socket sink
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Connect timeout for TCP sinks
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Serialises compression and pruning of rolled files
+static HOUSEKEEPING: Mutex<()> = Mutex::new(());
+
+// MARK: - Sink
+
+/// An open log destination
+pub(crate) enum Sink {
+    Stdout(io::Stdout),
+    Stderr(io::Stderr),
+    File(FileSink),
+    Syslog(SyslogSink),
+    Net(NetSink),
+}
+
+impl Sink {
+    /// Open the destination.
+    ///
+    /// - Parameter output: The configured log output.
+    /// - Returns: The sink, or the error opening a file or socket.
+    pub(crate) fn open(output: &LogOutput) -> io::Result<Self> {
+        Ok(match output {
+            LogOutput::File(path) => Self::File(FileSink::open(PathBuf::from(path), None)?),
+            LogOutput::RollingFile { path, rotation } => {
+                Self::File(FileSink::open(PathBuf::from(path), Some(rotation.clone()))?)
+            }
+            LogOutput::Syslog(config) => Self::Syslog(SyslogSink::open(config)?),
+            LogOutput::Net(address) => Self::Net(NetSink::new(address)?),
+            LogOutput::Stdout => Self::Stdout(io::stdout()),
+            LogOutput::Stderr => Self::Stderr(io::stderr()),
+        })
+    }
+
+    /// Write one line (including its trailing newline).
+    pub(crate) fn write_line(&mut self, level: Level, line: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Stdout(out) => out.write_all(line),
+            Self::Stderr(out) => out.write_all(line),
+            Self::File(file) => file.write_line(line),
+            Self::Syslog(syslog) => syslog.send(level, line),
+            Self::Net(net) => net.write_line(line),
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(out) => out.flush(),
+            Self::Stderr(out) => out.flush(),
+            Self::File(file) => file.out.flush(),
+            Self::Syslog(_) => Ok(()),
+            Self::Net(net) => net.flush(),
+        }
+    }
+
+    /// Reopen files (after an external rotation); other sinks are unaffected.
+    pub(crate) fn reopen(&mut self) -> io::Result<()> {
+        match self {
+            Self::File(file) => file.reopen(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Switch a file to a new rotation policy (`None` stops rotating).
+    ///
+    /// The file stays open; other sinks have nothing to change.
+    pub(crate) fn set_rotation(&mut self, rotation: Option<LogRotation>) {
+        if let Self::File(file) = self {
+            file.next_rotation = rotation.as_ref().and_then(|r| next_boundary(r.interval_secs?, unix_now()));
+            file.rotation = rotation;
+        }
+    }
+}
+
+// MARK: - Files
+
+/// Appending file sink with optional rotation
+pub(crate) struct FileSink {
+    path: PathBuf,
+    out: BufWriter<File>,
+    /// Current file size, including buffered bytes
+    size: u64,
+    rotation: Option<LogRotation>,
+    /// Unix time of the next interval rotation
+    next_rotation: Option<u64>,
+}
+
+impl FileSink {
+    fn open(path: PathBuf, rotation: Option<LogRotation>) -> io::Result<Self> {
+        let (out, size) = open_append(&path)?;
+        let next_rotation = rotation.as_ref().and_then(|r| next_boundary(r.interval_secs?, unix_now()));
+        Ok(Self { path, out, size, rotation, next_rotation })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.should_rotate(line.len() as u64) {
+            self.rotate()?;
+        }
+        self.out.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn should_rotate(&self, incoming: u64) -> bool {
+        let Some(rotation) = &self.rotation else {
+            return false;
+        };
+        let by_size = rotation.max_size.is_some_and(|max| self.size > 0 && self.size + incoming > max);
+        let by_time = self.next_rotation.is_some_and(|at| unix_now() >= at);
+        by_size || by_time
+    }
+
+    /// Roll the current file and start a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        let Some(rotation) = self.rotation.clone() else {
+            return Ok(());
+        };
+        self.out.flush()?;
+
+        let rolled = rolled_path(&self.path, SystemTime::now());
+        fs::rename(&self.path, &rolled)?;
+        self.reopen()?;
+        self.next_rotation = rotation.interval_secs.and_then(|interval| next_boundary(interval, unix_now()));
+
+        let path = self.path.clone();
+        let _ = std::thread::Builder::new()
+            .name("log-rotate".to_string())
+            .spawn(move || {
+                // One housekeeping pass at a time, so pruning never races compression
+                let _guard = HOUSEKEEPING.lock().unwrap_or_else(|e| e.into_inner());
+                if rotation.compress
+                    && let Err(e) = gzip_file(&rolled)
+                    // NotFound: already pruned by a newer rotation
+                    && e.kind() != io::ErrorKind::NotFound
+                {
+                    tracing::warn!("⚠️ Failed to compress rotated log {}: {}", rolled.display(), e);
+                }
+                prune_rolled(&path, rotation.keep);
+            });
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        let (out, size) = open_append(&self.path)?;
+        self.out = out;
+        self.size = size;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    Ok((BufWriter::new(file), size))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Next multiple of `interval` after `now` (UTC-aligned: `24h` rolls at midnight)
+fn next_boundary(interval: u64, now: u64) -> Option<u64> {
+    (interval > 0).then(|| (now / interval + 1) * interval)
+}
+
+/// `<dir>/access.log` → `<dir>/access-2024-01-31T23-59-59.123.log`
+fn rolled_path(path: &Path, time: SystemTime) -> PathBuf {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    let stamp = format!(
+        "{:04}-{:02}-{:02}T{:02}-{:02}-{:02}.{:03}",
+        year, month, day,
+        rem / 3600, (rem % 3600) / 60, rem % 60,
+        since_epoch.subsec_millis()
+    );
+
+    let (stem, ext) = stem_and_extension(path);
+    let name = match ext {
+        Some(ext) => format!("{}-{}.{}", stem, stamp, ext),
+        None => format!("{}-{}", stem, stamp),
+    };
+    path.with_file_name(name)
+}
+
+fn stem_and_extension(path: &Path) -> (String, Option<String>) {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|s| s.to_string_lossy().into_owned());
+    (stem, ext)
+}
+
+/// Compress a rolled file to `<file>.gz` and remove the original
+fn gzip_file(path: &Path) -> io::Result<()> {
+    let mut gz_name = path.as_os_str().to_owned();
+    gz_name.push(".gz");
+    let gz_path = PathBuf::from(gz_name);
+
+    let mut input = File::open(path)?;
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(&gz_path)?), Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    fs::remove_file(path)
+}
+
+/// Delete the oldest rolled files beyond `keep`
+fn prune_rolled(path: &Path, keep: usize) {
+    let dir = match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+    let (stem, ext) = stem_and_extension(path);
+    let prefix = format!("{}-", stem);
+
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return;
+    };
+    let mut rolled: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| {
+            let Some(rest) = name.strip_prefix(&prefix) else {
+                return false;
+            };
+            let rest = rest.strip_suffix(".gz").unwrap_or(rest);
+            let stamp = match &ext {
+                Some(ext) => rest.strip_suffix(&format!(".{}", ext)),
+                None => Some(rest),
+            };
+            // `YYYY-MM-DDTHH-MM-SS.mmm`
+            stamp.is_some_and(|s| s.len() == 23 && s.as_bytes()[10] == b'T')
+        })
+        .collect();
+
+    // Timestamps sort lexicographically: newest first
+    rolled.sort_unstable_by(|a, b| b.cmp(a));
+    for name in rolled.into_iter().skip(keep) {
+        let _ = fs::remove_file(dir.join(name));
+    }
+}
+
+// MARK: - Sockets
+
+/// `unix//path` → ("unix", "/path"), `tcp/host:port` → ("tcp", "host:port");
+/// bare paths are unix sockets and anything else uses `default_network`.
+fn split_network<'a>(address: &'a str, default_network: &'a str) -> (&'a str, &'a str) {
+    if address.starts_with('/') {
+        return ("unix", address);
+    }
+    match address.split_once('/') {
+        Some((network @ ("tcp" | "udp" | "unix"), rest)) => (network, rest),
+        _ => (default_network, address),
+    }
+}
+
+fn resolve(address: &str) -> io::Result<std::net::SocketAddr> {
+    address.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("cannot resolve {}", address)))
+}
+
+fn unsupported(network: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("unsupported network '{}'", network))
+}
+
+// MARK: - Syslog
+
+enum SyslogTransport {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram, PathBuf),
+}
+
+/// RFC 5424 syslog sink
+pub(crate) struct SyslogSink {
+    transport: SyslogTransport,
+    facility: u8,
+    tag: String,
+    hostname: String,
+    pid: u32,
+}
+
+impl SyslogSink {
+    fn open(config: &SyslogConfig) -> io::Result<Self> {
+        let transport = match split_network(&config.address, "udp") {
+            ("udp", address) => {
+                let target = resolve(address)?;
+                let bind = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(bind)?;
+                socket.connect(target)?;
+                SyslogTransport::Udp(socket)
+            }
+            #[cfg(unix)]
+            ("unix", path) => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                // The daemon may not be up yet: connect lazily on first send
+                let _ = socket.connect(path);
+                SyslogTransport::Unix(socket, PathBuf::from(path))
+            }
+            (network, _) => return Err(unsupported(network)),
+        };
+
+        Ok(Self {
+            transport,
+            facility: config.facility,
+            tag: config.tag.clone(),
+            hostname: hostname(),
+            pid: std::process::id(),
+        })
+    }
+
+    fn send(&mut self, level: Level, line: &[u8]) -> io::Result<()> {
+        let message = self.format(level, SystemTime::now(), line);
+        match &mut self.transport {
+            SyslogTransport::Udp(socket) => socket.send(&message).map(|_| ()),
+            #[cfg(unix)]
+            SyslogTransport::Unix(socket, path) => match socket.send(&message) {
+                Ok(_) => Ok(()),
+                Err(_) => {
+                    // Reconnect once (syslog daemon restarted or started late)
+                    let fresh = std::os::unix::net::UnixDatagram::unbound()?;
+                    fresh.connect(&*path)?;
+                    *socket = fresh;
+                    socket.send(&message).map(|_| ())
+                }
+            },
+        }
+    }
+
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+    fn format(&self, level: Level, time: SystemTime, line: &[u8]) -> Vec<u8> {
+        let severity = match level {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+        let priority = self.facility as u32 * 8 + severity;
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+
+        let mut message = format!(
+            "<{}>1 {} {} {} {} - - ",
+            priority, rfc3339(time), self.hostname, self.tag, self.pid
+        ).into_bytes();
+        message.extend_from_slice(line);
+        message
+    }
+}
+
+/// Host name for the HOSTNAME field (`-` when unknown)
+fn hostname() -> String {
+    std::env::var("HOSTNAME").ok()
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty() && h.bytes().all(|b| b.is_ascii_graphic()))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// MARK: - Net
+
+enum NetAddress {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Line-oriented stream socket sink
+pub(crate) struct NetSink {
+    address: NetAddress,
+    stream: Option<BufWriter<Box<dyn Write + Send>>>,
+    /// Earliest time for the next connection attempt
+    retry_at: Option<Instant>,
+}
+
+impl NetSink {
+    fn new(address: &str) -> io::Result<Self> {
+        let address = match split_network(address, "tcp") {
+            ("tcp", address) => NetAddress::Tcp(address.to_string()),
+            #[cfg(unix)]
+            ("unix", path) => NetAddress::Unix(PathBuf::from(path)),
+            (network, _) => return Err(unsupported(network)),
+        };
+        // Agents may start after Pingclair: connect on first write
+        Ok(Self { address, stream: None, retry_at: None })
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn Write + Send>> {
+        Ok(match &self.address {
+            NetAddress::Tcp(address) => {
+                let stream = TcpStream::connect_timeout(&resolve(address)?, CONNECT_TIMEOUT)?;
+                let _ = stream.set_nodelay(true);
+                Box::new(stream)
+            }
+            #[cfg(unix)]
+            NetAddress::Unix(path) => Box::new(std::os::unix::net::UnixStream::connect(path)?),
+        })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.stream.is_none() {
+            if self.retry_at.is_some_and(|at| Instant::now() < at) {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "log sink disconnected"));
+            }
+            match self.connect() {
+                Ok(stream) => {
+                    self.stream = Some(BufWriter::new(stream));
+                    self.retry_at = None;
+                }
+                Err(e) => {
+                    self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+                    return Err(e);
+                }
+            }
+        }
+
+        let result = self.stream.as_mut().map_or(Ok(()), |stream| stream.write_all(line));
+        if result.is_err() {
+            self.disconnect();
+        }
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.stream.as_mut().map_or(Ok(()), |stream| stream.flush());
+        if result.is_err() {
+            self.disconnect();
+        }
+        result
+    }
+
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+    }
+}
+
+// MARK: - Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pingclair-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_size_rotation_gzip_and_retention() {
+        let dir = temp_dir("log-rotation");
+        let path = dir.join("access.log");
+        let rotation = LogRotation { max_size: Some(20), interval_secs: None, keep: 2, compress: true };
+        let mut sink = FileSink::open(path.clone(), Some(rotation)).unwrap();
+
+        for i in 0..5 {
+            sink.write_line(format!("line number {:04}\n", i).as_bytes()).unwrap();
+            // Distinct rotation timestamps
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        sink.out.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line number 0004\n");
+
+        // Compression and pruning run in the background
+        let mut rolled = Vec::new();
+        for _ in 0..100 {
+            rolled = fs::read_dir(&dir).unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .filter(|n| n != "access.log")
+                .collect::<Vec<_>>();
+            if rolled.len() == 2 && rolled.iter().all(|n| n.ends_with(".log.gz")) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        rolled.sort();
+        assert_eq!(rolled.len(), 2, "{:?}", rolled);
+        assert!(rolled.iter().all(|n| n.starts_with("access-") && n.ends_with(".log.gz")));
+
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(File::open(dir.join(&rolled[1])).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "line number 0003\n");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_rolled_names_and_boundaries() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(
+            rolled_path(Path::new("/var/log/access.log"), time),
+            PathBuf::from("/var/log/access-2023-11-14T22-13-20.123.log")
+        );
+        assert_eq!(next_boundary(86_400, 1_700_000_000), Some(1_700_006_400));
+        assert_eq!(split_network("unix//dev/log", "udp"), ("unix", "/dev/log"));
+        assert_eq!(split_network("tcp/127.0.0.1:5140", "udp"), ("tcp", "127.0.0.1:5140"));
+        assert_eq!(split_network("10.0.0.1:514", "udp"), ("udp", "10.0.0.1:514"));
+    }
+
+    #[test]
+    fn test_syslog_udp() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = SyslogConfig {
+            address: format!("udp/{}", collector.local_addr().unwrap()),
+            facility: 23,
+            tag: "pingclair".to_string(),
+        };
+        let mut sink = SyslogSink::open(&config).unwrap();
+        sink.send(Level::ERROR, b"GET / 502\n").unwrap();
+
+        let mut buf = [0u8; 512];
+        let n = collector.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..n]).unwrap();
+        // local7 (23) * 8 + err (3)
+        assert!(message.starts_with("<187>1 "), "{}", message);
+        assert!(message.ends_with(&format!(" pingclair {} - - GET / 502", std::process::id())));
+    }
+
+    #[test]
+    fn test_net_tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sink = NetSink::new(&format!("tcp/{}", listener.local_addr().unwrap())).unwrap();
+        sink.write_line(b"first\n").unwrap();
+        sink.write_line(b"second\n").unwrap();
+        sink.flush().unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        let lines: Vec<String> = BufReader::new(stream).lines().take(2).map(Result::unwrap).collect();
+        assert_eq!(lines, vec!["first", "second"]);
+    }
+}
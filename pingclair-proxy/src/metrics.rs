@@ -1,41 +1,180 @@
 //! Prometheus Metrics for Pingclair
 //!
-//! Provides metrics collection for requests, errors, and latency.
+//! Provides metrics collection for requests, upstreams, TLS, health checks and
+//! configuration reloads.
+//!
+//! 🏗️ ARCHITECTURE: Metrics are process-wide `LazyLock` statics registered with a
+//! single [`REGISTRY`]. Histogram buckets and the per-route label are read from
+//! the [`MetricsConfig`] passed to [`init`] the first time each metric is touched.
+//! Latency observations are also forwarded to the StatsD exporter when enabled.
 
-use prometheus::{Encoder, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
-use std::sync::LazyLock;
+use crate::access_log::UpstreamTiming;
+use crate::statsd;
+use pingclair_core::config::MetricsConfig;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::sync::{LazyLock, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // MARK: - Global Registry
 
 /// Global metrics registry
 pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
 
-// MARK: - Metrics Definitions
+/// Settings captured by [`init`]
+static SETTINGS: OnceLock<MetricsConfig> = OnceLock::new();
+
+fn settings() -> &'static MetricsConfig {
+    SETTINGS.get_or_init(MetricsConfig::default)
+}
+
+/// Request duration buckets (Prometheus defaults unless configured)
+fn request_buckets() -> Vec<f64> {
+    let buckets = &settings().buckets;
+    if buckets.is_empty() { prometheus::DEFAULT_BUCKETS.to_vec() } else { buckets.clone() }
+}
+
+/// Upstream latency buckets (falls back to the request buckets)
+fn upstream_buckets() -> Vec<f64> {
+    let buckets = &settings().upstream_buckets;
+    if buckets.is_empty() { request_buckets() } else { buckets.clone() }
+}
+
+fn counter(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    IntCounterVec::new(Opts::new(name, help), labels).expect("metric can be created")
+}
+
+fn gauge(name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    IntGaugeVec::new(Opts::new(name, help), labels).expect("metric can be created")
+}
+
+fn histogram(name: &str, help: &str, labels: &[&str], buckets: Vec<f64>) -> HistogramVec {
+    HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels)
+        .expect("metric can be created")
+}
+
+// MARK: - Request Metrics
 
 /// Total requests processed
 pub static REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
-    IntCounterVec::new(
-        Opts::new("pingclair_requests_total", "Total number of HTTP requests"),
-        &["method", "status", "host"]
-    ).expect("metric can be created")
+    counter("pingclair_requests_total", "Total number of HTTP requests", &["method", "status", "host", "route"])
 });
 
 /// Request latency in seconds
 pub static REQUEST_DURATION_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
-    HistogramVec::new(
-        prometheus::HistogramOpts::new(
-            "pingclair_request_duration_seconds",
-            "Request duration in seconds"
-        ),
-        &["method", "status", "host"]
-    ).expect("metric can be created")
+    histogram(
+        "pingclair_request_duration_seconds",
+        "Request duration in seconds",
+        &["method", "status", "host", "route"],
+        request_buckets(),
+    )
+});
+
+/// Requests currently being processed
+pub static REQUESTS_IN_FLIGHT: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    gauge("pingclair_requests_in_flight", "Number of requests currently being processed", &["host"])
+});
+
+/// Request body bytes received from clients
+pub static REQUEST_BYTES_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter("pingclair_request_bytes_total", "Request body bytes received from clients", &["host", "route"])
+});
+
+/// Response body bytes sent to clients
+pub static RESPONSE_BYTES_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter("pingclair_response_bytes_total", "Response body bytes sent to clients", &["host", "route"])
 });
 
-/// Active connections
-pub static ACTIVE_CONNECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
-    IntCounterVec::new(
-        Opts::new("pingclair_active_connections", "Number of active connections"),
-        &["host"]
+/// Requests rejected by a rate limiter
+pub static RATE_LIMITED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter("pingclair_rate_limited_total", "Requests rejected by rate limiting", &["host", "route"])
+});
+
+/// Cache lookups reported by upstream `Cache-Status` / `X-Cache` headers.
+/// Hit ratio: `sum(rate(..{result="hit"}[5m])) / sum(rate(..[5m]))`.
+pub static CACHE_REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter("pingclair_cache_requests_total", "Cache lookups by result (hit, miss)", &["host", "result"])
+});
+
+// MARK: - Upstream Metrics
+
+/// Time until the upstream connection was ready (new or reused)
+pub static UPSTREAM_CONNECT_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    histogram(
+        "pingclair_upstream_connect_seconds",
+        "Time to obtain an upstream connection in seconds",
+        &["upstream"],
+        upstream_buckets(),
+    )
+});
+
+/// Time until the upstream response headers arrived
+pub static UPSTREAM_TTFB_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    histogram(
+        "pingclair_upstream_ttfb_seconds",
+        "Time to first upstream response byte in seconds",
+        &["upstream"],
+        upstream_buckets(),
+    )
+});
+
+/// Time until the upstream response body completed
+pub static UPSTREAM_DURATION_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    histogram(
+        "pingclair_upstream_duration_seconds",
+        "Total upstream response time in seconds",
+        &["upstream"],
+        upstream_buckets(),
+    )
+});
+
+/// Upstream health as seen by active health checks (1 = healthy)
+pub static UPSTREAM_HEALTHY: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    gauge("pingclair_upstream_healthy", "Upstream health check state (1 = healthy, 0 = unhealthy)", &["upstream"])
+});
+
+/// Upstream attempts that were retried
+pub static UPSTREAM_RETRIES_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter("pingclair_upstream_retries_total", "Upstream attempts retried on another try", &["upstream"])
+});
+
+/// Upstream failures by kind (`connect`, `proxy`)
+pub static UPSTREAM_FAILURES_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter("pingclair_upstream_failures_total", "Failed upstream attempts", &["upstream", "kind"])
+});
+
+// MARK: - TLS Metrics
+
+/// Completed downstream TLS handshakes by protocol version
+pub static TLS_HANDSHAKES_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter("pingclair_tls_handshakes_total", "Completed TLS handshakes", &["version"])
+});
+
+/// Certificate `notAfter` as a Unix timestamp
+pub static CERTIFICATE_EXPIRY_TIMESTAMP: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    gauge("pingclair_certificate_expiry_timestamp_seconds", "Certificate expiry as Unix timestamp", &["domain"])
+});
+
+// MARK: - Config Reload Metrics
+
+/// Configuration reload attempts by result (`success`, `failure`)
+pub static CONFIG_RELOADS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    counter("pingclair_config_reloads_total", "Configuration reload attempts", &["result"])
+});
+
+/// Whether the last configuration reload succeeded (1) or failed (0)
+pub static CONFIG_LAST_RELOAD_SUCCESSFUL: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new("pingclair_config_last_reload_successful", "Whether the last configuration reload succeeded")
+        .expect("metric can be created")
+});
+
+/// Unix timestamp of the last successful configuration load
+pub static CONFIG_LAST_RELOAD_SUCCESS_TIMESTAMP: LazyLock<Gauge> = LazyLock::new(|| {
+    Gauge::new(
+        "pingclair_config_last_reload_success_timestamp_seconds",
+        "Timestamp of the last successful configuration load",
     ).expect("metric can be created")
 });
 
@@ -45,12 +184,127 @@ pub static ACTIVE_CONNECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
 ///
 /// Registers all defined metrics with the global registry.
 /// Should be called once at application startup.
-pub fn init() {
-    // Register metrics
-    // We ignore errors in case they are already registered (though typically init is called once)
-    let _ = REGISTRY.register(Box::new(REQUESTS_TOTAL.clone()));
-    let _ = REGISTRY.register(Box::new(REQUEST_DURATION_SECONDS.clone()));
-    let _ = REGISTRY.register(Box::new(ACTIVE_CONNECTIONS.clone()));
+///
+/// ⚠️ Buckets and the per-route label are fixed by the first call; changing
+/// them in the config requires a restart rather than a reload.
+///
+/// - Parameter config: Metrics options from the global config.
+pub fn init(config: &MetricsConfig) {
+    let _ = SETTINGS.set(config.clone());
+
+    register(&REGISTRY);
+
+    // The startup config counts as the last successful load
+    CONFIG_LAST_RELOAD_SUCCESSFUL.set(1);
+    CONFIG_LAST_RELOAD_SUCCESS_TIMESTAMP.set(unix_now());
+}
+
+/// Register every metric with a registry
+///
+/// Errors are ignored in case they are already registered (though typically init is called once)
+fn register(registry: &Registry) {
+    let _ = registry.register(Box::new(REQUESTS_TOTAL.clone()));
+    let _ = registry.register(Box::new(REQUEST_DURATION_SECONDS.clone()));
+    let _ = registry.register(Box::new(REQUESTS_IN_FLIGHT.clone()));
+    let _ = registry.register(Box::new(REQUEST_BYTES_TOTAL.clone()));
+    let _ = registry.register(Box::new(RESPONSE_BYTES_TOTAL.clone()));
+    let _ = registry.register(Box::new(RATE_LIMITED_TOTAL.clone()));
+    let _ = registry.register(Box::new(CACHE_REQUESTS_TOTAL.clone()));
+    let _ = registry.register(Box::new(UPSTREAM_CONNECT_SECONDS.clone()));
+    let _ = registry.register(Box::new(UPSTREAM_TTFB_SECONDS.clone()));
+    let _ = registry.register(Box::new(UPSTREAM_DURATION_SECONDS.clone()));
+    let _ = registry.register(Box::new(UPSTREAM_HEALTHY.clone()));
+    let _ = registry.register(Box::new(UPSTREAM_RETRIES_TOTAL.clone()));
+    let _ = registry.register(Box::new(UPSTREAM_FAILURES_TOTAL.clone()));
+    let _ = registry.register(Box::new(TLS_HANDSHAKES_TOTAL.clone()));
+    let _ = registry.register(Box::new(CERTIFICATE_EXPIRY_TIMESTAMP.clone()));
+    let _ = registry.register(Box::new(CONFIG_RELOADS_TOTAL.clone()));
+    let _ = registry.register(Box::new(CONFIG_LAST_RELOAD_SUCCESSFUL.clone()));
+    let _ = registry.register(Box::new(CONFIG_LAST_RELOAD_SUCCESS_TIMESTAMP.clone()));
+}
+
+// MARK: - Recording Helpers
+
+/// Value for the `route` label.
+///
+/// ⚡ OPTIMIZATION: Empty unless per-route labels are enabled; Prometheus treats
+/// an empty label as absent, so series stay bounded by host by default.
+pub fn route_label(route: &str) -> &str {
+    if settings().per_route { route } else { "" }
+}
+
+/// Record a completed request: count, latency and body bytes.
+///
+/// - Parameter route: The `route` label value (see [`route_label`]).
+pub fn record_request(method: &str, status: u16, host: &str, route: &str, elapsed: Duration, bytes_in: u64, bytes_out: u64) {
+    let status = status.to_string();
+    let labels = [method, status.as_str(), host, route];
+    REQUESTS_TOTAL.with_label_values(&labels).inc();
+    REQUEST_DURATION_SECONDS.with_label_values(&labels).observe(elapsed.as_secs_f64());
+    REQUEST_BYTES_TOTAL.with_label_values(&[host, route]).inc_by(bytes_in);
+    RESPONSE_BYTES_TOTAL.with_label_values(&[host, route]).inc_by(bytes_out);
+
+    statsd::timing(
+        "request_duration",
+        &[("method", method), ("status", &status), ("host", host), ("route", route)],
+        elapsed,
+    );
+}
+
+/// Record upstream connect, TTFB and total latency for one attempt.
+pub fn record_upstream_timing(upstream: &str, timing: &UpstreamTiming) {
+    let observations = [
+        (&*UPSTREAM_CONNECT_SECONDS, "upstream_connect", timing.connect),
+        (&*UPSTREAM_TTFB_SECONDS, "upstream_ttfb", timing.header),
+        (&*UPSTREAM_DURATION_SECONDS, "upstream_duration", timing.response),
+    ];
+    for (histogram, name, duration) in observations {
+        if let Some(duration) = duration {
+            histogram.with_label_values(&[upstream]).observe(duration.as_secs_f64());
+            statsd::timing(name, &[("upstream", upstream)], duration);
+        }
+    }
+}
+
+/// Classify an upstream `Cache-Status` (RFC 9211) or `X-Cache` header value.
+///
+/// - Returns: `Some("hit")`/`Some("miss")`, or `None` when the value says neither.
+pub fn cache_result(value: &str) -> Option<&'static str> {
+    // Cache-Status lists caches outermost-last; the last entry describes the nearest cache
+    let last = value.rsplit(',').next().unwrap_or(value).to_ascii_lowercase();
+    if last.contains("hit") {
+        Some("hit")
+    } else if last.contains("miss") || last.contains("fwd=") {
+        Some("miss")
+    } else {
+        None
+    }
+}
+
+/// Count a completed TLS handshake (`version` as reported by OpenSSL, e.g. `TLSv1.3`).
+pub fn record_tls_handshake(version: &str) {
+    TLS_HANDSHAKES_TOTAL.with_label_values(&[version]).inc();
+}
+
+/// Publish certificate expiry timestamps, replacing any previously reported set.
+pub fn set_certificate_expirations(expirations: &[(String, i64)]) {
+    CERTIFICATE_EXPIRY_TIMESTAMP.reset();
+    for (domain, expires_at) in expirations {
+        CERTIFICATE_EXPIRY_TIMESTAMP.with_label_values(&[domain]).set(*expires_at);
+    }
+}
+
+/// Record the outcome of a configuration (re)load.
+pub fn record_config_reload(success: bool) {
+    CONFIG_RELOADS_TOTAL.with_label_values(&[if success { "success" } else { "failure" }]).inc();
+    CONFIG_LAST_RELOAD_SUCCESSFUL.set(success as i64);
+    if success {
+        CONFIG_LAST_RELOAD_SUCCESS_TIMESTAMP.set(unix_now());
+    }
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
 }
 
 // MARK: - Export
@@ -61,9 +315,54 @@ pub fn init() {
 ///
 /// - Returns: A string containing the Prometheus-formatted metrics.
 pub fn gather() -> String {
+    encode(&REGISTRY)
+}
+
+/// Encode a registry in Prometheus text format
+fn encode(registry: &Registry) -> String {
     let mut buffer = Vec::new();
     let encoder = TextEncoder::new();
-    let metric_families = REGISTRY.gather();
+    let metric_families = registry.gather();
     encoder.encode(&metric_families, &mut buffer).unwrap();
     String::from_utf8(buffer).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_result() {
+        assert_eq!(cache_result("HIT"), Some("hit"));
+        assert_eq!(cache_result("TCP_MISS from edge"), Some("miss"));
+        assert_eq!(cache_result("ExampleCache; hit, OriginCache; fwd=uri-miss"), Some("miss"));
+        assert_eq!(cache_result("OriginCache; fwd=stale, Edge; hit; ttl=30"), Some("hit"));
+        assert_eq!(cache_result("BYPASS"), None);
+    }
+
+    #[test]
+    fn test_recorders_export() {
+        let registry = Registry::new();
+        register(&registry);
+
+        let timing = UpstreamTiming {
+            started: None,
+            connect: Some(Duration::from_millis(2)),
+            header: Some(Duration::from_millis(20)),
+            response: None,
+        };
+        record_upstream_timing("10.0.0.1:8080", &timing);
+        record_config_reload(false);
+        record_config_reload(true);
+        set_certificate_expirations(&[("example.com".to_string(), 1_900_000_000)]);
+
+        let text = encode(&registry);
+        assert!(text.contains(r#"pingclair_upstream_connect_seconds_count{upstream="10.0.0.1:8080"} 1"#));
+        assert!(text.contains(r#"pingclair_upstream_ttfb_seconds_count{upstream="10.0.0.1:8080"} 1"#));
+        assert!(!text.contains("pingclair_upstream_duration_seconds_count"));
+        assert!(text.contains(r#"pingclair_config_reloads_total{result="failure"} 1"#));
+        assert!(text.contains("pingclair_config_last_reload_successful 1"));
+        assert!(text.contains(r#"pingclair_certificate_expiry_timestamp_seconds{domain="example.com"} 1900000000"#));
+        assert_eq!(route_label("/api/*"), "");
+    }
+}
@@ -0,0 +1,754 @@
+//! OpenTelemetry span export over OTLP
+//!
+//! 🏗️ ARCHITECTURE: Each sampled request produces one `SERVER` span plus child
+//! spans (handler execution, upstream connect, upstream response, upstream
+//! TLS handshake). Downstream handshakes are shared by every request on a
+//! keep-alive connection and are not recorded. Finished spans are handed to a
+//! bounded queue; a dedicated exporter thread batches them and ships them to
+//! the collector via:
+//!   - OTLP/HTTP with JSON encoding (`POST /v1/traces`)
+//!   - OTLP/gRPC with protobuf encoding over HTTP/2 cleartext
+//!
+//! ⚡ OPTIMIZATION: The request path never blocks on export — when the queue is
+//! full, spans are dropped rather than applying backpressure to traffic.
+//!
+//! ⚠️ Only `http://` collector endpoints are supported (`https://` is rejected
+//! when the config is loaded); run a local collector (or sidecar) to forward to
+//! TLS-protected backends.
+
+use crate::trace::{self, TraceContext};
+use bytes::Bytes;
+use pingclair_core::config::{OtlpProtocol, TracingConfig};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// gRPC method for trace export
+const GRPC_EXPORT_PATH: &str = "/opentelemetry.proto.collector.trace.v1.TraceService/Export";
+
+/// Queue capacity, in batches
+const QUEUE_BATCHES: usize = 8;
+
+/// Upper bound for a single export request
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// MARK: - Span Model
+
+/// OTLP span kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+/// Span attribute value
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+/// A finished span
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanData {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    pub trace_state: Option<String>,
+    pub name: String,
+    pub kind: SpanKind,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(String, AttributeValue)>,
+    /// Marks the span status as `ERROR`
+    pub error: bool,
+}
+
+impl SpanData {
+    /// Pingclair's own span for the request (the parent of every child span).
+    ///
+    /// - Parameter trace: The request trace context.
+    /// - Returns: A `SERVER` span identified by `trace.span_id`.
+    pub fn server(trace: &TraceContext, name: impl Into<String>, start: SystemTime, end: SystemTime) -> Self {
+        Self {
+            trace_id: trace.trace_id.clone(),
+            span_id: trace.span_id.clone(),
+            parent_span_id: trace.parent_span_id.clone(),
+            trace_state: trace.tracestate.clone(),
+            name: name.into(),
+            kind: SpanKind::Server,
+            start,
+            end,
+            attributes: Vec::new(),
+            error: false,
+        }
+    }
+
+    /// A child of the request's server span
+    pub fn child(trace: &TraceContext, name: impl Into<String>, kind: SpanKind, start: SystemTime, end: SystemTime) -> Self {
+        Self {
+            trace_id: trace.trace_id.clone(),
+            span_id: trace::new_span_id(),
+            parent_span_id: Some(trace.span_id.clone()),
+            trace_state: trace.tracestate.clone(),
+            name: name.into(),
+            kind,
+            start,
+            end,
+            attributes: Vec::new(),
+            error: false,
+        }
+    }
+
+    /// Add an attribute
+    pub fn attr(mut self, key: &str, value: impl Into<AttributeValue>) -> Self {
+        self.attributes.push((key.to_string(), value.into()));
+        self
+    }
+}
+
+// MARK: - Request Recorder
+
+/// Child spans and timings collected while a sampled request is processed
+#[derive(Debug, Default)]
+pub struct SpanRecorder {
+    /// Finished child spans
+    pub children: Vec<SpanData>,
+    /// When the upstream peer was selected (start of the connect span)
+    pub upstream_selected_at: Option<SystemTime>,
+    /// When the upstream connection became usable (start of the response span)
+    pub upstream_connected_at: Option<SystemTime>,
+}
+
+/// TLS handshake window from per-layer connection establishment times.
+///
+/// - Parameter established: Establishment time of each transport layer (L4, then TLS).
+/// - Returns: `(tcp_established, tls_established)` when both layers are known.
+pub fn tls_handshake_window(established: &[Option<SystemTime>]) -> Option<(SystemTime, SystemTime)> {
+    match established {
+        [Some(tcp), Some(tls), ..] if tls >= tcp => Some((*tcp, *tls)),
+        _ => None,
+    }
+}
+
+// MARK: - Sampling
+
+/// `TraceIdRatioBased` head sampling decision for a trace id
+pub fn ratio_sampled(trace_id: &str, ratio: f64) -> bool {
+    if ratio >= 1.0 {
+        return true;
+    }
+    if ratio <= 0.0 {
+        return false;
+    }
+    // Lower 8 bytes of the trace id, as in the OpenTelemetry SDKs
+    let lower = trace_id.get(trace_id.len().saturating_sub(16)..)
+        .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+        .unwrap_or(0);
+    lower < (ratio * u64::MAX as f64) as u64
+}
+
+// MARK: - Exporter
+
+/// Handle to the background OTLP exporter
+#[derive(Debug)]
+pub struct OtlpExporter {
+    sender: mpsc::Sender<SpanData>,
+    sample_ratio: f64,
+}
+
+/// Process-wide exporter, reused across reloads while its config is unchanged
+static SHARED_EXPORTER: Mutex<Option<(TracingConfig, Arc<OtlpExporter>)>> = Mutex::new(None);
+
+impl OtlpExporter {
+    /// Start an exporter thread for the given configuration.
+    ///
+    /// - Parameter config: The OTLP export settings.
+    /// - Returns: The exporter handle; the thread exits once every handle is dropped.
+    pub fn start(config: TracingConfig) -> std::io::Result<Arc<Self>> {
+        let endpoint = Endpoint::parse(&config.endpoint, config.protocol)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let (sender, receiver) = mpsc::channel(config.batch_size.max(1) * QUEUE_BATCHES);
+        let sample_ratio = config.sample_ratio;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        std::thread::Builder::new()
+            .name("otlp-exporter".to_string())
+            .spawn(move || runtime.block_on(run_exporter(config, endpoint, receiver)))?;
+
+        Ok(Arc::new(Self { sender, sample_ratio }))
+    }
+
+    /// Exporter for the current configuration, shared by all proxies.
+    ///
+    /// - Parameter config: The OTLP export settings (`None` disables export).
+    /// - Returns: The running exporter, restarted only when the config changed.
+    pub fn shared(config: Option<&TracingConfig>) -> Option<Arc<Self>> {
+        let mut shared = SHARED_EXPORTER.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(config) = config else {
+            *shared = None;
+            return None;
+        };
+
+        if let Some((running, exporter)) = shared.as_ref()
+            && running == config
+        {
+            return Some(exporter.clone());
+        }
+
+        match Self::start(config.clone()) {
+            Ok(exporter) => {
+                tracing::info!("🔭 OTLP span export to {} ({:?})", config.endpoint, config.protocol);
+                *shared = Some((config.clone(), exporter.clone()));
+                Some(exporter)
+            }
+            Err(e) => {
+                tracing::error!("❌ Failed to start OTLP exporter: {}", e);
+                *shared = None;
+                None
+            }
+        }
+    }
+
+    /// Make the head sampling decision for a request.
+    ///
+    /// Continued traces follow the caller's sampled flag; new traces are
+    /// sampled by ratio and the decision is written to the trace flags so it
+    /// propagates upstream.
+    pub fn sample(&self, trace: &mut TraceContext) -> bool {
+        if trace.parent_span_id.is_none() {
+            let sampled = ratio_sampled(&trace.trace_id, self.sample_ratio);
+            trace.flags = if sampled { trace.flags | 0x01 } else { trace.flags & !0x01 };
+        }
+        trace.sampled()
+    }
+
+    /// Queue a finished span (dropped if the queue is full)
+    pub fn export(&self, span: SpanData) {
+        if self.sender.try_send(span).is_err() {
+            tracing::debug!("⚠️ OTLP export queue full, dropping span");
+        }
+    }
+}
+
+/// Background loop: batch spans and flush on size or interval
+async fn run_exporter(config: TracingConfig, endpoint: Endpoint, mut receiver: mpsc::Receiver<SpanData>) {
+    let batch_size = config.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut ticker = tokio::time::interval(Duration::from_millis(config.flush_interval_ms.max(1)));
+
+    loop {
+        tokio::select! {
+            span = receiver.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() >= batch_size {
+                        flush(&config, &endpoint, std::mem::take(&mut batch)).await;
+                    }
+                }
+                None => {
+                    // All handles dropped (shutdown or reload): flush what is left
+                    flush(&config, &endpoint, std::mem::take(&mut batch)).await;
+                    break;
+                }
+            },
+            _ = ticker.tick() => {
+                if !batch.is_empty() {
+                    flush(&config, &endpoint, std::mem::take(&mut batch)).await;
+                }
+            }
+        }
+    }
+}
+
+async fn flush(config: &TracingConfig, endpoint: &Endpoint, spans: Vec<SpanData>) {
+    if spans.is_empty() {
+        return;
+    }
+
+    let export = async {
+        match config.protocol {
+            OtlpProtocol::Http => {
+                let body = encode_json(&config.service_name, &spans);
+                export_http(endpoint, config, body.into_bytes()).await
+            }
+            OtlpProtocol::Grpc => {
+                let message = encode_protobuf(&config.service_name, &spans);
+                export_grpc(endpoint, config, message).await
+            }
+        }
+    };
+
+    match tokio::time::timeout(EXPORT_TIMEOUT, export).await {
+        Ok(Ok(())) => tracing::debug!("🔭 Exported {} span(s)", spans.len()),
+        Ok(Err(e)) => tracing::warn!("⚠️ OTLP export of {} span(s) failed: {}", spans.len(), e),
+        Err(_) => tracing::warn!("⚠️ OTLP export of {} span(s) timed out", spans.len()),
+    }
+}
+
+// MARK: - Transport
+
+/// Parsed collector endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endpoint {
+    /// `host:port` to connect to
+    authority: String,
+    /// Request path
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(endpoint: &str, protocol: OtlpProtocol) -> Result<Self, String> {
+        let uri: http::Uri = endpoint.parse().map_err(|e| format!("invalid endpoint {}: {}", endpoint, e))?;
+        match uri.scheme_str() {
+            Some("http") | None => {}
+            Some("https") => return Err(format!("{}: https:// endpoints are not supported, use a local http:// collector", endpoint)),
+            Some(other) => return Err(format!("unsupported endpoint scheme {}", other)),
+        }
+        let host = uri.host().ok_or_else(|| format!("endpoint {} has no host", endpoint))?;
+        let default_port = match protocol {
+            OtlpProtocol::Http => 4318,
+            OtlpProtocol::Grpc => 4317,
+        };
+        let path = match protocol {
+            OtlpProtocol::Grpc => GRPC_EXPORT_PATH.to_string(),
+            OtlpProtocol::Http => match uri.path() {
+                "" | "/" => "/v1/traces".to_string(),
+                path => path.to_string(),
+            },
+        };
+
+        Ok(Self {
+            authority: format!("{}:{}", host, uri.port_u16().unwrap_or(default_port)),
+            path,
+        })
+    }
+}
+
+/// Collector headers that are safe to put on the wire
+fn extra_headers(config: &TracingConfig) -> impl Iterator<Item = (&String, &String)> {
+    config.headers.iter().filter(|(name, value)| {
+        http::HeaderName::from_bytes(name.as_bytes()).is_ok()
+            && http::HeaderValue::from_str(value).is_ok()
+    })
+}
+
+/// OTLP/HTTP: one `POST` per batch over a fresh HTTP/1.1 connection
+async fn export_http(endpoint: &Endpoint, config: &TracingConfig, body: Vec<u8>) -> Result<(), String> {
+    let mut stream = TcpStream::connect(&endpoint.authority).await.map_err(|e| e.to_string())?;
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        endpoint.path, endpoint.authority, body.len()
+    );
+    for (name, value) in extra_headers(config) {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+    stream.write_all(&body).await.map_err(|e| e.to_string())?;
+
+    // Only the status line matters
+    let mut response = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !response.windows(2).any(|w| w == b"\r\n") {
+        let n = stream.read(&mut buf).await.map_err(|e| e.to_string())?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok());
+    match status {
+        Some(code) if (200..300).contains(&code) => Ok(()),
+        Some(code) => Err(format!("collector responded with HTTP {}", code)),
+        None => Err("invalid collector response".to_string()),
+    }
+}
+
+/// OTLP/gRPC: unary `Export` call over HTTP/2 with prior knowledge
+async fn export_grpc(endpoint: &Endpoint, config: &TracingConfig, message: Vec<u8>) -> Result<(), String> {
+    let tcp = TcpStream::connect(&endpoint.authority).await.map_err(|e| e.to_string())?;
+    let (client, connection) = h2::client::handshake(tcp).await.map_err(|e| e.to_string())?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::debug!("OTLP gRPC connection closed: {}", e);
+        }
+    });
+
+    let mut request = http::Request::post(format!("http://{}{}", endpoint.authority, endpoint.path))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers");
+    for (name, value) in extra_headers(config) {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let request = request.body(()).map_err(|e| e.to_string())?;
+
+    let mut client = client.ready().await.map_err(|e| e.to_string())?;
+    let (response, mut stream) = client.send_request(request, false).map_err(|e| e.to_string())?;
+
+    // Length-prefixed message: compressed flag + u32 big-endian length
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    stream.send_data(Bytes::from(frame), true).map_err(|e| e.to_string())?;
+
+    let response = response.await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("collector responded with HTTP {}", response.status()));
+    }
+
+    // Trailers-only responses carry grpc-status in the headers
+    let mut grpc_status = response.headers().get("grpc-status").cloned();
+    let mut body = response.into_body();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+    }
+    if grpc_status.is_none() {
+        let trailers = body.trailers().await.map_err(|e| e.to_string())?;
+        grpc_status = trailers.and_then(|t| t.get("grpc-status").cloned());
+    }
+
+    match grpc_status.as_ref().and_then(|v| v.to_str().ok()) {
+        Some("0") => Ok(()),
+        Some(code) => Err(format!("collector responded with grpc-status {}", code)),
+        None => Err("collector response missing grpc-status".to_string()),
+    }
+}
+
+// MARK: - Encoding
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+/// Encode an `ExportTraceServiceRequest` as OTLP/JSON
+pub fn encode_json(service_name: &str, spans: &[SpanData]) -> String {
+    use serde_json::{json, Value};
+
+    let attribute = |key: &str, value: &AttributeValue| {
+        let value = match value {
+            AttributeValue::String(s) => json!({ "stringValue": s }),
+            // int64 is a JSON string in the proto3 mapping
+            AttributeValue::Int(i) => json!({ "intValue": i.to_string() }),
+            AttributeValue::Bool(b) => json!({ "boolValue": b }),
+        };
+        json!({ "key": key, "value": value })
+    };
+
+    let spans: Vec<Value> = spans.iter().map(|span| {
+        let mut value = json!({
+            "traceId": span.trace_id,
+            "spanId": span.span_id,
+            "name": span.name,
+            "kind": span.kind as i32,
+            "startTimeUnixNano": unix_nanos(span.start).to_string(),
+            "endTimeUnixNano": unix_nanos(span.end).to_string(),
+            "attributes": span.attributes.iter().map(|(k, v)| attribute(k, v)).collect::<Vec<_>>(),
+            "status": { "code": if span.error { 2 } else { 0 } },
+        });
+        if let Some(parent) = &span.parent_span_id {
+            value["parentSpanId"] = json!(parent);
+        }
+        if let Some(state) = &span.trace_state {
+            value["traceState"] = json!(state);
+        }
+        value
+    }).collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", &AttributeValue::from(service_name))],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "pingclair", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    }).to_string()
+}
+
+/// Encode an `ExportTraceServiceRequest` as protobuf
+pub fn encode_protobuf(service_name: &str, spans: &[SpanData]) -> Vec<u8> {
+    let mut request = Vec::new();
+    // ExportTraceServiceRequest.resource_spans = 1
+    proto::message(&mut request, 1, |resource_spans| {
+        // ResourceSpans.resource = 1 { Resource.attributes = 1 }
+        proto::message(resource_spans, 1, |resource| {
+            proto::key_value(resource, 1, "service.name", &AttributeValue::from(service_name));
+        });
+        // ResourceSpans.scope_spans = 2
+        proto::message(resource_spans, 2, |scope_spans| {
+            proto::message(scope_spans, 1, |scope| {
+                proto::string(scope, 1, "pingclair");
+                proto::string(scope, 2, env!("CARGO_PKG_VERSION"));
+            });
+            for span in spans {
+                proto::message(scope_spans, 2, |out| encode_span(out, span));
+            }
+        });
+    });
+    request
+}
+
+fn encode_span(out: &mut Vec<u8>, span: &SpanData) {
+    proto::bytes(out, 1, &proto::hex_to_bytes(&span.trace_id));
+    proto::bytes(out, 2, &proto::hex_to_bytes(&span.span_id));
+    if let Some(state) = &span.trace_state {
+        proto::string(out, 3, state);
+    }
+    if let Some(parent) = &span.parent_span_id {
+        proto::bytes(out, 4, &proto::hex_to_bytes(parent));
+    }
+    proto::string(out, 5, &span.name);
+    proto::varint_field(out, 6, span.kind as u64);
+    proto::fixed64(out, 7, unix_nanos(span.start));
+    proto::fixed64(out, 8, unix_nanos(span.end));
+    for (key, value) in &span.attributes {
+        proto::key_value(out, 9, key, value);
+    }
+    if span.error {
+        // Status.code = 3 (STATUS_CODE_ERROR = 2)
+        proto::message(out, 15, |status| proto::varint_field(status, 3, 2));
+    }
+}
+
+/// Minimal protobuf wire-format writer
+mod proto {
+    use super::AttributeValue;
+
+    const VARINT: u8 = 0;
+    const FIXED64: u8 = 1;
+    const LEN: u8 = 2;
+
+    pub fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn tag(out: &mut Vec<u8>, field: u32, wire_type: u8) {
+        varint(out, ((field as u64) << 3) | wire_type as u64);
+    }
+
+    pub fn varint_field(out: &mut Vec<u8>, field: u32, value: u64) {
+        tag(out, field, VARINT);
+        varint(out, value);
+    }
+
+    pub fn fixed64(out: &mut Vec<u8>, field: u32, value: u64) {
+        tag(out, field, FIXED64);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(out: &mut Vec<u8>, field: u32, value: &[u8]) {
+        tag(out, field, LEN);
+        varint(out, value.len() as u64);
+        out.extend_from_slice(value);
+    }
+
+    pub fn string(out: &mut Vec<u8>, field: u32, value: &str) {
+        bytes(out, field, value.as_bytes());
+    }
+
+    pub fn message(out: &mut Vec<u8>, field: u32, build: impl FnOnce(&mut Vec<u8>)) {
+        let mut inner = Vec::new();
+        build(&mut inner);
+        bytes(out, field, &inner);
+    }
+
+    /// `KeyValue { key = 1; AnyValue value = 2 }`
+    pub fn key_value(out: &mut Vec<u8>, field: u32, key: &str, value: &AttributeValue) {
+        message(out, field, |kv| {
+            string(kv, 1, key);
+            message(kv, 2, |any| match value {
+                AttributeValue::String(s) => string(any, 1, s),
+                AttributeValue::Bool(b) => varint_field(any, 2, *b as u64),
+                AttributeValue::Int(i) => varint_field(any, 3, *i as u64),
+            });
+        });
+    }
+
+    pub fn hex_to_bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len() / 2)
+            .filter_map(|i| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok())
+            .collect()
+    }
+}
+
+// MARK: - Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_span() -> SpanData {
+        let mut trace = TraceContext::new_root();
+        trace.trace_id = "4bf92f3577b34da6a3ce929d0e0e4736".to_string();
+        trace.span_id = "00f067aa0ba902b7".to_string();
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        SpanData::server(&trace, "GET /api", start, start + Duration::from_millis(5))
+            .attr("http.request.method", "GET")
+            .attr("http.response.status_code", 200i64)
+    }
+
+    #[test]
+    fn test_ratio_sampling() {
+        assert!(ratio_sampled("4bf92f3577b34da6a3ce929d0e0e4736", 1.0));
+        assert!(!ratio_sampled("4bf92f3577b34da6a3ce929d0e0e4736", 0.0));
+        assert!(ratio_sampled("00000000000000000000000000000001", 0.5));
+        assert!(!ratio_sampled("0000000000000000ffffffffffffffff", 0.5));
+    }
+
+    #[test]
+    fn test_endpoint_parsing() {
+        let http = Endpoint::parse("http://collector:4318", OtlpProtocol::Http).unwrap();
+        assert_eq!(http.authority, "collector:4318");
+        assert_eq!(http.path, "/v1/traces");
+
+        let grpc = Endpoint::parse("http://collector", OtlpProtocol::Grpc).unwrap();
+        assert_eq!(grpc.authority, "collector:4317");
+        assert_eq!(grpc.path, GRPC_EXPORT_PATH);
+
+        assert!(Endpoint::parse("https://collector:4318", OtlpProtocol::Http).is_err());
+    }
+
+    #[test]
+    fn test_json_encoding() {
+        let json: serde_json::Value = serde_json::from_str(&encode_json("edge", &[sample_span()])).unwrap();
+        let span = &json["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span["kind"], 2);
+        assert_eq!(span["startTimeUnixNano"], "1700000000000000000");
+        assert_eq!(span["attributes"][1]["value"]["intValue"], "200");
+        assert_eq!(json["resourceSpans"][0]["resource"]["attributes"][0]["value"]["stringValue"], "edge");
+    }
+
+    #[test]
+    fn test_protobuf_encoding() {
+        let encoded = encode_protobuf("edge", &[sample_span()]);
+        // resource_spans (field 1, LEN)
+        assert_eq!(encoded[0], 0x0a);
+        let trace_id = proto::hex_to_bytes("4bf92f3577b34da6a3ce929d0e0e4736");
+        assert!(encoded.windows(trace_id.len()).any(|w| w == trace_id.as_slice()));
+        assert!(encoded.windows(4).any(|w| w == b"edge"));
+
+        let mut out = Vec::new();
+        proto::varint(&mut out, 300);
+        assert_eq!(out, vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_tls_handshake_window() {
+        let tcp = UNIX_EPOCH + Duration::from_secs(10);
+        let tls = tcp + Duration::from_millis(12);
+        assert_eq!(tls_handshake_window(&[Some(tcp), Some(tls)]), Some((tcp, tls)));
+        assert_eq!(tls_handshake_window(&[Some(tcp)]), None);
+    }
+
+    #[tokio::test]
+    async fn test_http_export_to_collector_stand_in() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let exporter = OtlpExporter::start(TracingConfig {
+            endpoint: format!("http://{}", addr),
+            flush_interval_ms: 20,
+            ..Default::default()
+        }).unwrap();
+        exporter.export(sample_span());
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !String::from_utf8_lossy(&received).contains("\"resourceSpans\"") || !received.ends_with(b"}") {
+            let n = socket.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            received.extend_from_slice(&buf[..n]);
+        }
+        socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+
+        let request = String::from_utf8_lossy(&received);
+        assert!(request.starts_with("POST /v1/traces HTTP/1.1"));
+        assert!(request.contains("application/json"));
+        assert!(request.contains("4bf92f3577b34da6a3ce929d0e0e4736"));
+    }
+
+    #[tokio::test]
+    async fn test_grpc_export_to_collector_stand_in() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let exporter = OtlpExporter::start(TracingConfig {
+            endpoint: format!("http://{}", addr),
+            protocol: OtlpProtocol::Grpc,
+            flush_interval_ms: 20,
+            ..Default::default()
+        }).unwrap();
+        exporter.export(sample_span());
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut connection = h2::server::handshake(socket).await.unwrap();
+        let (request, mut respond) = connection.accept().await.unwrap().unwrap();
+        tokio::spawn(async move { while connection.accept().await.is_some() {} });
+
+        assert_eq!(request.uri().path(), GRPC_EXPORT_PATH);
+        assert_eq!(request.headers()["content-type"], "application/grpc");
+        let mut body = request.into_body();
+        let mut message = Vec::new();
+        while let Some(chunk) = body.data().await {
+            message.extend_from_slice(&chunk.unwrap());
+        }
+
+        let response = http::Response::builder().status(200).header("content-type", "application/grpc").body(()).unwrap();
+        let mut stream = respond.send_response(response, false).unwrap();
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        stream.send_trailers(trailers).unwrap();
+
+        assert_eq!(message[0], 0, "uncompressed gRPC frame");
+        let len = u32::from_be_bytes([message[1], message[2], message[3], message[4]]) as usize;
+        assert_eq!(&message[5..], encode_protobuf("pingclair", &[sample_span()]).as_slice());
+        assert_eq!(len, message.len() - 5);
+    }
+}
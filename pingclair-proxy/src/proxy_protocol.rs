@@ -0,0 +1,711 @@
+//! PROXY protocol (v1 text / v2 binary) for listeners and upstreams
+//!
+//! 🏗️ ARCHITECTURE: Pingora's listeners hand the socket straight to TLS / HTTP,
+//! so a listener that accepts PROXY headers is fronted by a relay:
+//!   - The relay owns the public address, reads the header (trusted sources
+//!     only, bounded by a timeout) and forwards the remaining bytes to Pingora
+//!     on an internal loopback address
+//!   - The relay's outbound socket address keys a registry, so `request_filter`
+//!     can map Pingora's peer (the relay) back to the proxied connection
+//!   - The loopback listener is bound before Pingora starts and handed to it
+//!     through the listen fd table (`InternalListenerService`), so no other
+//!     process can take the port in between
+//!
+//! Upstream headers are written by a custom L4 connector before any TLS or
+//! HTTP bytes, on connections that are never shared between clients.
+
+use async_trait::async_trait;
+use ipnet::IpNet;
+use parking_lot::RwLock;
+use pingora_core::connectors::L4Connect;
+#[cfg(unix)]
+use pingora_core::server::ListenFds;
+use pingora_core::server::ShutdownWatch;
+use pingora_core::services::Service;
+use pingora_core::protocols::l4::socket::SocketAddr as PingoraSocketAddr;
+use pingora_core::protocols::l4::stream::Stream;
+use pingora_core::upstreams::peer::HttpPeer;
+use pingora_core::{Error, ErrorType, OrErr};
+use pingclair_core::config::{ProxyProtocolConfig, ProxyProtocolVersion};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::connection_filter::{parse_ip_ranges, PingclairConnectionFilter};
+use pingora_core::listeners::ConnectionFilter;
+
+/// v2 signature (`\r\n\r\n\0\r\nQUIT\n`)
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Pause after a failed `accept` (e.g. EMFILE) before trying again
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// v1 headers are at most 107 bytes including CRLF
+const V1_MAX_LEN: usize = 107;
+
+// v2 TLV types
+const PP2_TYPE_ALPN: u8 = 0x01;
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+const PP2_TYPE_SSL: u8 = 0x20;
+const PP2_SUBTYPE_SSL_VERSION: u8 = 0x21;
+const PP2_SUBTYPE_SSL_CN: u8 = 0x22;
+const PP2_SUBTYPE_SSL_CIPHER: u8 = 0x23;
+const PP2_CLIENT_SSL: u8 = 0x01;
+
+// MARK: - Header
+
+/// A parsed PROXY protocol header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// Original client address (`None` for `LOCAL` / `UNKNOWN` headers)
+    pub source: Option<SocketAddr>,
+    /// Address the client connected to
+    pub destination: Option<SocketAddr>,
+    /// Negotiated ALPN protocol (v2 TLV)
+    pub alpn: Option<String>,
+    /// Host name the client requested, usually the SNI (v2 TLV)
+    pub authority: Option<String>,
+    /// TLS parameters when the sender terminated TLS (v2 TLV)
+    pub tls: Option<ProxyTls>,
+}
+
+/// TLS details forwarded by the sender
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyTls {
+    /// Protocol version, e.g. `TLSv1.3`
+    pub version: Option<String>,
+    /// Cipher suite name
+    pub cipher: Option<String>,
+    /// Client certificate common name
+    pub client_cn: Option<String>,
+    /// Whether a presented client certificate was verified
+    pub verified: bool,
+}
+
+/// Outcome of parsing the start of a connection
+#[derive(Debug, PartialEq, Eq)]
+pub enum Parsed {
+    /// Complete header and its length in bytes
+    Header(ProxyHeader, usize),
+    /// More bytes are needed to decide
+    Incomplete,
+    /// The connection does not start with a PROXY header
+    Absent,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid PROXY header: {}", msg))
+}
+
+/// Parse a v1 or v2 header from the start of a buffer.
+///
+/// - Parameter buf: Bytes read from the connection so far.
+/// - Returns: The parsed header, `Incomplete`, or `Absent`; malformed headers are errors.
+pub fn parse(buf: &[u8]) -> io::Result<Parsed> {
+    const V1_PREFIX: &[u8] = b"PROXY ";
+    if buf.starts_with(V1_PREFIX) {
+        parse_v1(buf)
+    } else if buf.starts_with(&V2_SIGNATURE) {
+        parse_v2(buf)
+    } else if V1_PREFIX.starts_with(buf) || V2_SIGNATURE.starts_with(buf) {
+        Ok(Parsed::Incomplete)
+    } else {
+        Ok(Parsed::Absent)
+    }
+}
+
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+fn parse_v1(buf: &[u8]) -> io::Result<Parsed> {
+    let Some(end) = buf.windows(2).position(|w| w == b"\r\n") else {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(invalid("v1 line too long"));
+        }
+        return Ok(Parsed::Incomplete);
+    };
+    if end + 2 > V1_MAX_LEN {
+        return Err(invalid("v1 line too long"));
+    }
+
+    let line = std::str::from_utf8(&buf[..end]).map_err(|_| invalid("v1 not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let header = match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => ProxyHeader::default(),
+        ["PROXY", family @ ("TCP4" | "TCP6"), src, dst, sport, dport] => {
+            let ip = |s: &str| -> io::Result<IpAddr> {
+                let ip: IpAddr = s.parse().map_err(|_| invalid("v1 address"))?;
+                if ip.is_ipv4() != (*family == "TCP4") {
+                    return Err(invalid("v1 address family"));
+                }
+                Ok(ip)
+            };
+            let port = |s: &str| s.parse::<u16>().map_err(|_| invalid("v1 port"));
+            ProxyHeader {
+                source: Some(SocketAddr::new(ip(src)?, port(sport)?)),
+                destination: Some(SocketAddr::new(ip(dst)?, port(dport)?)),
+                ..Default::default()
+            }
+        }
+        _ => return Err(invalid("v1 fields")),
+    };
+    Ok(Parsed::Header(header, end + 2))
+}
+
+/// Binary header: signature, version/command, family, length, addresses, TLVs
+fn parse_v2(buf: &[u8]) -> io::Result<Parsed> {
+    if buf.len() < 16 {
+        return Ok(Parsed::Incomplete);
+    }
+    let len = 16 + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    if buf.len() < len {
+        return Ok(Parsed::Incomplete);
+    }
+    if buf[12] >> 4 != 2 {
+        return Err(invalid("v2 version"));
+    }
+    let body = &buf[16..len];
+
+    let (mut header, addr_len) = match buf[12] & 0x0F {
+        // LOCAL: health checks from the sender itself
+        0x0 => (ProxyHeader::default(), 0),
+        0x1 => match buf[13] >> 4 {
+            0x1 if body.len() >= 12 => {
+                let src = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+                let dst = Ipv4Addr::new(body[4], body[5], body[6], body[7]);
+                let header = ProxyHeader {
+                    source: Some(SocketAddr::new(src.into(), u16::from_be_bytes([body[8], body[9]]))),
+                    destination: Some(SocketAddr::new(dst.into(), u16::from_be_bytes([body[10], body[11]]))),
+                    ..Default::default()
+                };
+                (header, 12)
+            }
+            0x2 if body.len() >= 36 => {
+                let octets = |range: std::ops::Range<usize>| -> [u8; 16] {
+                    body[range].try_into().unwrap_or_default()
+                };
+                let src = Ipv6Addr::from(octets(0..16));
+                let dst = Ipv6Addr::from(octets(16..32));
+                let header = ProxyHeader {
+                    source: Some(SocketAddr::new(src.into(), u16::from_be_bytes([body[32], body[33]]))),
+                    destination: Some(SocketAddr::new(dst.into(), u16::from_be_bytes([body[34], body[35]]))),
+                    ..Default::default()
+                };
+                (header, 36)
+            }
+            // Unix sockets and unspecified families carry no usable address
+            0x0 => (ProxyHeader::default(), 0),
+            0x3 if body.len() >= 216 => (ProxyHeader::default(), 216),
+            _ => return Err(invalid("v2 address family")),
+        },
+        _ => return Err(invalid("v2 command")),
+    };
+
+    for (kind, value) in tlvs(&body[addr_len..])? {
+        match kind {
+            PP2_TYPE_ALPN => header.alpn = Some(String::from_utf8_lossy(value).into_owned()),
+            PP2_TYPE_AUTHORITY => header.authority = Some(String::from_utf8_lossy(value).into_owned()),
+            PP2_TYPE_SSL if value.len() >= 5 && value[0] & PP2_CLIENT_SSL != 0 => {
+                let mut tls = ProxyTls {
+                    verified: value[1..5] == [0, 0, 0, 0],
+                    ..Default::default()
+                };
+                for (sub, sub_value) in tlvs(&value[5..])? {
+                    let text = || Some(String::from_utf8_lossy(sub_value).into_owned());
+                    match sub {
+                        PP2_SUBTYPE_SSL_VERSION => tls.version = text(),
+                        PP2_SUBTYPE_SSL_CN => tls.client_cn = text(),
+                        PP2_SUBTYPE_SSL_CIPHER => tls.cipher = text(),
+                        _ => {}
+                    }
+                }
+                header.tls = Some(tls);
+            }
+            _ => {}
+        }
+    }
+    Ok(Parsed::Header(header, len))
+}
+
+/// Split a type-length-value sequence
+fn tlvs(mut buf: &[u8]) -> io::Result<Vec<(u8, &[u8])>> {
+    let mut out = Vec::new();
+    while !buf.is_empty() {
+        if buf.len() < 3 {
+            return Err(invalid("v2 TLV"));
+        }
+        let len = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+        let value = buf.get(3..3 + len).ok_or_else(|| invalid("v2 TLV length"))?;
+        out.push((buf[0], value));
+        buf = &buf[3 + len..];
+    }
+    Ok(out)
+}
+
+/// Encode a header announcing a proxied connection (no TLVs).
+///
+/// - Parameter version: Header version.
+/// - Parameter source: Original client address (`UNKNOWN` / `LOCAL` when absent).
+/// - Parameter destination: Address the client connected to.
+/// - Returns: The encoded header.
+pub fn encode(version: ProxyProtocolVersion, source: Option<SocketAddr>, destination: Option<SocketAddr>) -> Vec<u8> {
+    // Mixed families are sent as IPv6, mapping the IPv4 side
+    let addrs = source.zip(destination).map(|(src, dst)| match (src, dst) {
+        (SocketAddr::V4(_), SocketAddr::V4(_)) | (SocketAddr::V6(_), SocketAddr::V6(_)) => (src, dst),
+        _ => (to_v6(src), to_v6(dst)),
+    });
+
+    match version {
+        ProxyProtocolVersion::V1 => match addrs {
+            Some((src, dst)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if src.is_ipv4() { "TCP4" } else { "TCP6" },
+                src.ip(), dst.ip(), src.port(), dst.port()
+            ).into_bytes(),
+            None => b"PROXY UNKNOWN\r\n".to_vec(),
+        },
+        ProxyProtocolVersion::V2 => {
+            let mut out = V2_SIGNATURE.to_vec();
+            let Some((src, dst)) = addrs else {
+                out.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+                return out;
+            };
+            let mut body = Vec::with_capacity(36);
+            let family = match (src.ip(), dst.ip()) {
+                (IpAddr::V4(s), IpAddr::V4(d)) => {
+                    body.extend_from_slice(&s.octets());
+                    body.extend_from_slice(&d.octets());
+                    0x11
+                }
+                (s, d) => {
+                    body.extend_from_slice(&ipv6(s).octets());
+                    body.extend_from_slice(&ipv6(d).octets());
+                    0x21
+                }
+            };
+            body.extend_from_slice(&src.port().to_be_bytes());
+            body.extend_from_slice(&dst.port().to_be_bytes());
+            out.extend_from_slice(&[0x21, family]);
+            out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+            out.extend_from_slice(&body);
+            out
+        }
+    }
+}
+
+fn ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+fn to_v6(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(ipv6(addr.ip()).into(), addr.port())
+}
+
+// MARK: - Connection Registry
+
+/// A downstream connection that arrived through a PROXY-aware listener
+#[derive(Debug, Clone)]
+pub struct ProxiedConnection {
+    /// The socket peer of the public listener (the sender of any header)
+    pub peer: SocketAddr,
+    /// The public listener address the peer connected to
+    pub local: SocketAddr,
+    /// The header it sent, if any
+    pub header: Option<ProxyHeader>,
+}
+
+impl ProxiedConnection {
+    /// Original client address: the header's source, else the socket peer
+    pub fn client(&self) -> SocketAddr {
+        self.header.as_ref().and_then(|h| h.source).unwrap_or(self.peer)
+    }
+
+    /// Original destination: the header's, else the public listener address
+    pub fn destination(&self) -> SocketAddr {
+        self.header.as_ref().and_then(|h| h.destination).unwrap_or(self.local)
+    }
+}
+
+/// Relay-side socket address → connection; removed when the relay closes
+static CONNECTIONS: LazyLock<RwLock<HashMap<SocketAddr, Arc<ProxiedConnection>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Look up the connection behind a Pingora peer address.
+///
+/// - Parameter peer: The downstream peer as seen by Pingora.
+/// - Returns: The proxied connection when the peer is one of our relays.
+pub fn lookup(peer: &PingoraSocketAddr) -> Option<Arc<ProxiedConnection>> {
+    let inet = peer.as_inet()?;
+    if !inet.ip().is_loopback() {
+        return None;
+    }
+    CONNECTIONS.read().get(inet).cloned()
+}
+
+// MARK: - Listener Relay
+
+/// Loopback listener for the Pingora side of a relayed listener, bound up front
+#[derive(Debug)]
+pub struct InternalListener {
+    addr: SocketAddr,
+    listener: std::net::TcpListener,
+}
+
+impl InternalListener {
+    /// Bind an ephemeral loopback port.
+    ///
+    /// - Returns: The bound listener; hand it to Pingora with `InternalListenerService`.
+    pub fn bind() -> io::Result<Self> {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { addr: listener.local_addr()?, listener })
+    }
+
+    /// The bound address (what the Pingora service must listen on)
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+/// Service wrapper that hands pre-bound `InternalListener`s to Pingora.
+///
+/// Pingora looks up each listen address in its fd table before binding, so
+/// seeding the table makes it adopt the socket instead of binding the port again.
+pub struct InternalListenerService<S> {
+    inner: S,
+    listeners: Vec<InternalListener>,
+}
+
+impl<S> InternalListenerService<S> {
+    /// Wrap a service whose listen addresses include the listeners' addresses.
+    pub fn new(inner: S, listeners: Vec<InternalListener>) -> Self {
+        Self { inner, listeners }
+    }
+}
+
+#[async_trait]
+impl<S: Service> Service for InternalListenerService<S> {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] fds: Option<ListenFds>,
+        shutdown: ShutdownWatch,
+        listeners_per_fd: usize,
+    ) {
+        #[cfg(unix)]
+        if let Some(fds) = &fds {
+            use std::os::fd::IntoRawFd;
+            let mut table = fds.lock().await;
+            for internal in self.listeners.drain(..) {
+                table.add(internal.addr.to_string(), internal.listener.into_raw_fd());
+            }
+        }
+        // Anything not handed over is closed here and Pingora binds it itself
+        self.listeners.clear();
+
+        self.inner.start_service(
+            #[cfg(unix)]
+            fds,
+            shutdown,
+            listeners_per_fd,
+        ).await
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn threads(&self) -> Option<usize> {
+        self.inner.threads()
+    }
+}
+
+/// Public listener that accepts PROXY headers and relays to Pingora
+pub struct ProxyProtocolListener {
+    listen: String,
+    internal: SocketAddr,
+    timeout: Duration,
+    allow: Vec<IpNet>,
+    filter: Option<Arc<PingclairConnectionFilter>>,
+}
+
+impl ProxyProtocolListener {
+    /// Create a relay for one listener.
+    ///
+    /// - Parameter listen: Public address.
+    /// - Parameter internal: Loopback address Pingora listens on.
+    /// - Parameter config: Timeout and allowed senders.
+    /// - Parameter trusted_proxies: Allowed senders when `config.allow` is empty.
+    /// - Returns: A configured `ProxyProtocolListener`.
+    pub fn new(listen: &str, internal: SocketAddr, config: &ProxyProtocolConfig, trusted_proxies: &[String]) -> Self {
+        let allow = if config.allow.is_empty() { trusted_proxies } else { &config.allow };
+        let allow = parse_ip_ranges(allow, "proxy_protocol");
+        if allow.is_empty() {
+            tracing::warn!("⚠️ PROXY protocol on {} has no allowed sources; headers will be ignored", listen);
+        }
+        Self {
+            listen: listen.to_string(),
+            internal,
+            timeout: Duration::from_millis(config.timeout_ms),
+            allow,
+            filter: None,
+        }
+    }
+
+    /// Apply the L4 blocklist to the original client address.
+    pub fn set_connection_filter(&mut self, filter: Arc<PingclairConnectionFilter>) {
+        self.filter = Some(filter);
+    }
+
+    /// Accept connections for as long as the process runs.
+    ///
+    /// - Returns: An error only when the public address cannot be bound.
+    pub async fn run(self) -> io::Result<()> {
+        let listener = TcpListener::bind(&self.listen).await?;
+        tracing::info!("🔀 PROXY protocol relay on {} -> {}", self.listen, self.internal);
+        let this = Arc::new(self);
+        loop {
+            // Accept errors (EMFILE, ECONNABORTED, ...) are transient
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("⚠️ PROXY protocol relay on {} failed to accept: {}", this.listen, e);
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let this = this.clone();
+            tokio::spawn(async move {
+                if let Err(e) = this.relay(stream, peer).await {
+                    tracing::debug!("🔀 PROXY relay for {} closed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    async fn relay(&self, mut downstream: TcpStream, peer: SocketAddr) -> io::Result<()> {
+        let peer = SocketAddr::new(peer.ip().to_canonical(), peer.port());
+        let mut buf = Vec::new();
+
+        // 🛑 SAFETY: only trusted senders may speak for the client
+        let header = if self.allow.iter().any(|net| net.contains(&peer.ip())) {
+            match tokio::time::timeout(self.timeout, read_header(&mut downstream, &mut buf)).await {
+                Ok(result) => result?,
+                Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "PROXY header timeout")),
+            }
+        } else {
+            None
+        };
+
+        let connection = ProxiedConnection { peer, local: downstream.local_addr()?, header };
+        if let Some(filter) = &self.filter
+            && !filter.should_accept(Some(&connection.client())).await
+        {
+            return Ok(());
+        }
+
+        let mut upstream = TcpStream::connect(self.internal).await?;
+        upstream.set_nodelay(true)?;
+        let _ = downstream.set_nodelay(true);
+        let key = upstream.local_addr()?;
+        CONNECTIONS.write().insert(key, Arc::new(connection));
+
+        let result = async {
+            upstream.write_all(&buf).await?;
+            tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await
+        }.await;
+        CONNECTIONS.write().remove(&key);
+        result.map(|_| ())
+    }
+}
+
+/// Read until a header is parsed or ruled out; bytes past it stay in `buf`.
+async fn read_header(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<Option<ProxyHeader>> {
+    let mut chunk = [0u8; 512];
+    loop {
+        match parse(buf)? {
+            Parsed::Header(header, len) => {
+                buf.drain(..len);
+                return Ok(Some(header));
+            }
+            Parsed::Absent => return Ok(None),
+            Parsed::Incomplete => {
+                let n = stream.read(&mut chunk).await?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+    }
+}
+
+// MARK: - Upstream Connector
+
+/// L4 connector that opens a TCP connection and writes a PROXY header first
+#[derive(Debug)]
+pub struct ProxyProtocolConnector {
+    header: Vec<u8>,
+}
+
+impl ProxyProtocolConnector {
+    /// Configure `peer` to announce `source` → `destination` on new connections.
+    ///
+    /// 🛑 SAFETY: Pooled connections are isolated by source and destination
+    /// (on top of the peer's existing group), so a connection announcing one
+    /// client is never reused for another.
+    pub fn apply(peer: &mut HttpPeer, version: ProxyProtocolVersion, source: Option<SocketAddr>, destination: Option<SocketAddr>) {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        peer.group_key.hash(&mut hasher);
+        source.hash(&mut hasher);
+        destination.hash(&mut hasher);
+        peer.group_key = hasher.finish();
+        peer.options.custom_l4 = Some(Arc::new(Self { header: encode(version, source, destination) }));
+    }
+}
+
+#[async_trait]
+impl L4Connect for ProxyProtocolConnector {
+    async fn connect(&self, addr: &PingoraSocketAddr) -> pingora_core::Result<Stream> {
+        let Some(inet) = addr.as_inet() else {
+            return Err(Error::explain(ErrorType::ConnectError, "PROXY protocol requires a TCP upstream"));
+        };
+        let mut stream = TcpStream::connect(inet).await
+            .or_err(ErrorType::ConnectError, "connecting to upstream")?;
+        stream.write_all(&self.header).await
+            .or_err(ErrorType::WriteError, "writing PROXY header")?;
+        Ok(stream.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v1_round_trip() {
+        let src: SocketAddr = "192.0.2.1:56324".parse().unwrap();
+        let dst: SocketAddr = "198.51.100.1:443".parse().unwrap();
+        let mut bytes = encode(ProxyProtocolVersion::V1, Some(src), Some(dst));
+        assert_eq!(bytes, b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n");
+        let len = bytes.len();
+        bytes.extend_from_slice(b"GET / HTTP/1.1\r\n");
+
+        let Parsed::Header(header, consumed) = parse(&bytes).unwrap() else { panic!("expected header") };
+        assert_eq!(consumed, len);
+        assert_eq!(header.source, Some(src));
+        assert_eq!(header.destination, Some(dst));
+
+        assert_eq!(parse(b"PROXY TCP4 192.0.2.1").unwrap(), Parsed::Incomplete);
+        assert_eq!(parse(b"PRO").unwrap(), Parsed::Incomplete);
+        assert_eq!(parse(b"GET / HTTP/1.1\r\n").unwrap(), Parsed::Absent);
+        assert!(parse(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n").is_err());
+        assert!(matches!(parse(b"PROXY UNKNOWN\r\n").unwrap(), Parsed::Header(ProxyHeader { source: None, .. }, 15)));
+    }
+
+    #[test]
+    fn test_v2_round_trip_and_tlvs() {
+        let src: SocketAddr = "[2001:db8::1]:4000".parse().unwrap();
+        let dst: SocketAddr = "192.0.2.10:443".parse().unwrap();
+        let bytes = encode(ProxyProtocolVersion::V2, Some(src), Some(dst));
+        let Parsed::Header(header, consumed) = parse(&bytes).unwrap() else { panic!("expected header") };
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(header.source, Some(src));
+        assert_eq!(header.destination, Some("[::ffff:192.0.2.10]:443".parse().unwrap()));
+        assert_eq!(parse(&bytes[..20]).unwrap(), Parsed::Incomplete);
+
+        // IPv4 header with ALPN, authority and SSL (version + cipher) TLVs
+        let mut tlv = Vec::new();
+        tlv.extend_from_slice(&[PP2_TYPE_ALPN, 0, 2]);
+        tlv.extend_from_slice(b"h2");
+        tlv.extend_from_slice(&[PP2_TYPE_AUTHORITY, 0, 11]);
+        tlv.extend_from_slice(b"example.com");
+        let mut ssl = vec![PP2_CLIENT_SSL, 0, 0, 0, 0];
+        ssl.extend_from_slice(&[PP2_SUBTYPE_SSL_VERSION, 0, 7]);
+        ssl.extend_from_slice(b"TLSv1.3");
+        ssl.extend_from_slice(&[PP2_SUBTYPE_SSL_CIPHER, 0, 22]);
+        ssl.extend_from_slice(b"TLS_AES_128_GCM_SHA256");
+        tlv.extend_from_slice(&[PP2_TYPE_SSL, 0, ssl.len() as u8]);
+        tlv.extend_from_slice(&ssl);
+
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11]);
+        bytes.extend_from_slice(&((12 + tlv.len()) as u16).to_be_bytes());
+        bytes.extend_from_slice(&[203, 0, 113, 7, 10, 0, 0, 1, 0x1F, 0x90, 0x01, 0xBB]);
+        bytes.extend_from_slice(&tlv);
+
+        let Parsed::Header(header, _) = parse(&bytes).unwrap() else { panic!("expected header") };
+        assert_eq!(header.source, Some("203.0.113.7:8080".parse().unwrap()));
+        assert_eq!(header.alpn.as_deref(), Some("h2"));
+        assert_eq!(header.authority.as_deref(), Some("example.com"));
+        let tls = header.tls.unwrap();
+        assert_eq!(tls.version.as_deref(), Some("TLSv1.3"));
+        assert_eq!(tls.cipher.as_deref(), Some("TLS_AES_128_GCM_SHA256"));
+        assert!(tls.verified);
+
+        // LOCAL command (sender health checks)
+        let local = encode(ProxyProtocolVersion::V2, None, None);
+        assert_eq!(parse(&local).unwrap(), Parsed::Header(ProxyHeader::default(), 16));
+    }
+
+    #[tokio::test]
+    async fn test_relay_registers_connection() {
+        let internal = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let public = InternalListener::bind().unwrap().addr();
+        let config = ProxyProtocolConfig { allow: vec!["127.0.0.1".into()], ..Default::default() };
+        let relay = ProxyProtocolListener::new(&public.to_string(), internal.local_addr().unwrap(), &config, &[]);
+        tokio::spawn(relay.run());
+
+        let mut client = loop {
+            if let Ok(stream) = TcpStream::connect(public).await {
+                break stream;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        client.write_all(b"PROXY TCP4 203.0.113.9 127.0.0.1 5000 80\r\nping").await.unwrap();
+
+        let (mut accepted, relay_addr) = internal.accept().await.unwrap();
+        let mut body = [0u8; 4];
+        accepted.read_exact(&mut body).await.unwrap();
+        assert_eq!(&body, b"ping");
+
+        let connection = lookup(&PingoraSocketAddr::Inet(relay_addr)).unwrap();
+        assert_eq!(connection.client(), "203.0.113.9:5000".parse().unwrap());
+        assert_eq!(connection.local, public);
+    }
+
+    #[test]
+    fn test_destination_falls_back_to_listener() {
+        let mut connection = ProxiedConnection {
+            peer: "10.0.0.2:4000".parse().unwrap(),
+            local: "192.0.2.1:443".parse().unwrap(),
+            header: None,
+        };
+        assert_eq!(connection.destination(), "192.0.2.1:443".parse().unwrap());
+        connection.header = Some(ProxyHeader {
+            destination: Some("198.51.100.7:8443".parse().unwrap()),
+            ..Default::default()
+        });
+        assert_eq!(connection.destination(), "198.51.100.7:8443".parse().unwrap());
+    }
+
+    #[test]
+    fn test_connector_keeps_existing_group_key() {
+        let source = Some("203.0.113.9:5000".parse().unwrap());
+        let mut a = HttpPeer::new("127.0.0.1:8080", false, String::new());
+        let mut b = HttpPeer::new("127.0.0.1:8080", false, String::new());
+        b.group_key = 42;
+        ProxyProtocolConnector::apply(&mut a, ProxyProtocolVersion::V1, source, None);
+        ProxyProtocolConnector::apply(&mut b, ProxyProtocolVersion::V1, source, None);
+        assert_ne!(a.group_key, b.group_key);
+
+        let mut c = HttpPeer::new("127.0.0.1:8080", false, String::new());
+        ProxyProtocolConnector::apply(&mut c, ProxyProtocolVersion::V1, Some("203.0.113.10:5000".parse().unwrap()), None);
+        assert_ne!(a.group_key, c.group_key);
+    }
+}
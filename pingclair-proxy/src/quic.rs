@@ -14,8 +14,10 @@ use tokio::sync::RwLock;
 use bytes::Bytes;
 use http::{Request, Response};
 
-use crate::server::PingclairProxy;
+use crate::server::{sequence_nodes, strip_path_prefix, with_path, PingclairProxy, ProxyNodeId};
+use crate::forwarded::{self, Forwarding};
 use pingclair_core::config::HandlerConfig;
+use pingclair_core::server::{normalize_host, normalize_path, PlaceholderContext, RequestInfo};
 
 // MARK: - Errors
 
@@ -164,6 +166,7 @@ impl QuicServer {
         
         self.endpoint = Some(endpoint.clone());
         let proxy = self.proxy.clone();
+        let listen = self.config.listen;
         
         // Accept connections in background
         tokio::spawn(async move {
@@ -174,7 +177,7 @@ impl QuicServer {
                 tokio::spawn(async move {
                     match incoming.await {
                         Ok(connection) => {
-                             if let Err(e) = Self::handle_connection(connection, proxy_ref).await {
+                             if let Err(e) = Self::handle_connection(connection, proxy_ref, listen).await {
                                  tracing::error!("❌ QUIC Connection error: {}", e);
                              }
                         }
@@ -189,17 +192,26 @@ impl QuicServer {
         Ok(())
     }
     
-    async fn handle_connection(connection: quinn::Connection, proxy: Option<Arc<PingclairProxy>>) -> Result<(), QuicError> {
+    async fn handle_connection(
+        connection: quinn::Connection,
+        proxy: Option<Arc<PingclairProxy>>,
+        listen: SocketAddr,
+    ) -> Result<(), QuicError> {
+        let remote = connection.remote_address();
+        let peer = SocketAddr::new(remote.ip().to_canonical(), remote.port());
+        let local = SocketAddr::new(connection.local_ip().unwrap_or(listen.ip()), listen.port());
         let h3_conn = h3::server::Connection::new(QuinnConnection::new(connection))
             .await
             .map_err(|e| QuicError::H3(e.to_string()))?;
         
-        Self::handle_h3_connection(h3_conn, proxy).await
+        Self::handle_h3_connection(h3_conn, proxy, peer, local).await
     }
     
     async fn handle_h3_connection(
         mut connection: H3Connection<QuinnConnection, Bytes>,
         proxy: Option<Arc<PingclairProxy>>,
+        peer: SocketAddr,
+        local: SocketAddr,
     ) -> Result<(), QuicError> {
         loop {
             match connection.accept().await {
@@ -209,7 +221,7 @@ impl QuicServer {
                          match resolver.resolve_request().await {
                             Ok((req, mut stream)) => {
                                 let resp = if let Some(p) = proxy {
-                                    Self::process_request(req, p).await
+                                    Self::process_request(req, p, peer, local).await
                                 } else {
                                     Response::builder()
                                         .status(503)
@@ -248,38 +260,74 @@ impl QuicServer {
         Ok(())
     }
     
-    async fn process_request(req: Request<()>, proxy: Arc<PingclairProxy>) -> Response<Bytes> {
-        let (parts, _) = req.into_parts();
-
-        let mut header = match pingora_http::RequestHeader::build(
-            parts.method.clone(),
-            parts.uri.path().as_bytes(),
-            None,
-        ) {
-            Ok(h) => h,
-            Err(_) => return Self::error_response(400, "Bad Request"),
-        };
-        for (k, v) in parts.headers.iter() {
-            header.insert_header(k, v).ok();
-        }
+    async fn process_request(
+        req: Request<()>,
+        proxy: Arc<PingclairProxy>,
+        peer: SocketAddr,
+        local: SocketAddr,
+    ) -> Response<Bytes> {
+        let (mut parts, _) = req.into_parts();
 
-        // Extract host (strip port)
+        // Real client behind trusted proxies (same resolution as the TCP path)
+        let client_ip = proxy.settings().client_ip(peer.ip(), &parts.headers);
+
+        // Extract host (normalised before routing)
         let host = parts
             .headers
             .get("host")
             .and_then(|v| v.to_str().ok())
             .unwrap_or_else(|| parts.uri.host().unwrap_or(""))
             .to_string();
-        let host_bare = host.split(':').next().unwrap_or(&host).to_string();
-
-        // Match route via shared proxy logic
-        let (state, route_index, handler_opt) = match proxy.match_route(
-            &host_bare,
-            parts.uri.path(),
-            parts.method.as_str(),
-            &header,
-            "0.0.0.0",
-        ) {
+
+        // Normalise the path with the virtual host's policy (as `request_filter` does)
+        let policy = proxy.path_normalization(&host);
+        let path = match normalize_path(parts.uri.path(), &policy) {
+            Ok(path) => path.into_owned(),
+            Err(_) => return Self::error_response(400, "Bad Request"),
+        };
+        if path != parts.uri.path() && !policy.forward_original {
+            let normalized = match parts.uri.query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path.clone(),
+            };
+            if let Ok(uri) = normalized.parse() {
+                parts.uri = uri;
+            }
+        }
+
+        let (peer_ip, client_addr) = (peer.ip().to_string(), client_ip.to_string());
+        let normalized_host = normalize_host(&host);
+        let placeholders = PlaceholderContext {
+            method: parts.method.as_str(),
+            host: &normalized_host,
+            path: &path,
+            query: parts.uri.query().unwrap_or(""),
+            scheme: "https",
+            proto: "HTTP/3.0",
+            remote_ip: &peer_ip,
+            remote_port: Some(peer.port()),
+            client_ip: &client_addr,
+            headers: Some(&parts.headers),
+            ..Default::default()
+        };
+
+        // Site-wide `map` / `vars` run before routing, as in `request_filter`
+        let vars = proxy.site_vars(&placeholders);
+        let placeholders = PlaceholderContext { vars: Some(&vars), ..placeholders };
+
+        // Match route via shared proxy logic (QUIC is always TLS)
+        let request = RequestInfo {
+            path: &path,
+            query: placeholders.query,
+            method: parts.method.as_str(),
+            headers: &parts.headers,
+            host: &normalized_host,
+            remote_ip: &peer_ip,
+            client_ip: &client_addr,
+            protocol: "https",
+            vars: Some(&vars),
+        };
+        let (state, route_index, handler_opt) = match proxy.match_route(&request) {
             Some(t) => t,
             None => return Self::error_response(404, "No Matching Virtual Host"),
         };
@@ -289,104 +337,172 @@ impl QuicServer {
             None => return Self::error_response(404, "No Matching Route"),
         };
 
-        match handler {
-            // ─────────────────────────────────────────────────────────────
-            // Respond: inline response
-            // ─────────────────────────────────────────────────────────────
-            HandlerConfig::Respond { status, body, headers } => {
-                let mut builder = Response::builder().status(status);
-                for (k, v) in &headers {
-                    builder = builder.header(k, v);
-                }
-                builder
-                    .body(Bytes::from(body.unwrap_or_default()))
-                    .unwrap_or_else(|_| Self::error_response(500, "Response Build Error"))
-            }
+        // Flatten `handle` / `handle_path` / pipelines into the handlers that answer
+        let mut steps = Vec::new();
+        if !Self::terminal_handlers(&handler, &path, 0, &mut steps) {
+            return Self::error_response(501, "Handler Not Supported Over HTTP/3");
+        }
 
-            // ─────────────────────────────────────────────────────────────
-            // Redirect: 3xx
-            // ─────────────────────────────────────────────────────────────
-            HandlerConfig::Redirect { to, code } => {
-                Response::builder()
-                    .status(code)
-                    .header("location", to)
-                    .body(Bytes::new())
-                    .unwrap_or_else(|_| Self::error_response(500, "Redirect Build Error"))
-            }
+        for (handler, path, node) in steps {
+            let response = match handler {
+                // ─────────────────────────────────────────────────────────────
+                // Respond: inline response
+                // ─────────────────────────────────────────────────────────────
+                HandlerConfig::Respond { status, body, headers } => {
+                    let mut builder = Response::builder().status(*status);
+                    for (k, v) in headers {
+                        builder = builder.header(k, v.render(&placeholders).as_ref());
+                    }
+                    let body = body.as_ref().map(|b| b.render(&placeholders).into_owned()).unwrap_or_default();
+                    Some(builder
+                        .body(Bytes::from(body))
+                        .unwrap_or_else(|_| Self::error_response(500, "Response Build Error")))
+                }
 
-            // ─────────────────────────────────────────────────────────────
-            // FileServer: delegate to the full FileServer object
-            // (compression, range, ETag, directory listing, precompressed)
-            // ─────────────────────────────────────────────────────────────
-            HandlerConfig::FileServer { .. } => {
-                let maybe_fs = route_index.and_then(|idx| {
-                    state.file_servers.get(idx)?.clone()
-                });
+                // ─────────────────────────────────────────────────────────────
+                // Redirect: 3xx
+                // ─────────────────────────────────────────────────────────────
+                HandlerConfig::Redirect { to, code } => {
+                    Some(Response::builder()
+                        .status(*code)
+                        .header("location", to.render(&placeholders).as_ref())
+                        .body(Bytes::new())
+                        .unwrap_or_else(|_| Self::error_response(500, "Redirect Build Error")))
+                }
 
-                if let Some(fs) = maybe_fs {
-                    let accept_encoding = parts
-                        .headers
-                        .get("accept-encoding")
-                        .and_then(|v| v.to_str().ok());
-                    let range_header = parts
-                        .headers
-                        .get("range")
-                        .and_then(|v| v.to_str().ok());
-
-                    match fs.serve(parts.uri.path(), range_header, accept_encoding).await {
-                        Ok(Some(file)) => {
-                            let mut builder = Response::builder().status(file.status);
-                            builder = builder.header("content-type", file.mime_type);
-                            builder = builder.header("content-length", file.content.len().to_string());
-                            builder = builder.header("accept-ranges", "bytes");
-                            builder = builder.header("server", "Pingclair");
-                            if let Some(enc) = file.content_encoding {
-                                builder = builder.header("content-encoding", enc);
-                            }
-                            if let Some(lm) = file.last_modified {
-                                builder = builder.header("last-modified", lm);
-                            }
-                            if let Some(etag) = file.etag {
-                                builder = builder.header("etag", etag);
+                // ─────────────────────────────────────────────────────────────
+                // FileServer: delegate to the full FileServer object
+                // (compression, range, ETag, directory listing, precompressed)
+                // ─────────────────────────────────────────────────────────────
+                HandlerConfig::FileServer { .. } => {
+                    let maybe_fs = state.file_server(handler);
+
+                    if let Some(fs) = maybe_fs {
+                        let accept_encoding = parts
+                            .headers
+                            .get("accept-encoding")
+                            .and_then(|v| v.to_str().ok());
+                        let range_header = parts
+                            .headers
+                            .get("range")
+                            .and_then(|v| v.to_str().ok());
+
+                        match fs.serve(&path, range_header, accept_encoding).await {
+                            Ok(Some(file)) => {
+                                let mut builder = Response::builder().status(file.status);
+                                builder = builder.header("content-type", file.mime_type);
+                                builder = builder.header("content-length", file.content.len().to_string());
+                                builder = builder.header("accept-ranges", "bytes");
+                                builder = builder.header("server", "Pingclair");
+                                if let Some(enc) = file.content_encoding {
+                                    builder = builder.header("content-encoding", enc);
+                                }
+                                if let Some(lm) = file.last_modified {
+                                    builder = builder.header("last-modified", lm);
+                                }
+                                if let Some(etag) = file.etag {
+                                    builder = builder.header("etag", etag);
+                                }
+                                if let Some(range) = file.content_range {
+                                    builder = builder.header("content-range", range);
+                                }
+                                Some(builder
+                                    .body(Bytes::from(file.content))
+                                    .unwrap_or_else(|_| Self::error_response(500, "File Response Error")))
                             }
-                            if let Some(range) = file.content_range {
-                                builder = builder.header("content-range", range);
+                            // Missing file: later handlers of the sequence get a turn
+                            Ok(None) => None,
+                            Err(e) => {
+                                tracing::error!("❌ H3 FileServer error: {}", e);
+                                Some(Self::error_response(500, "File Server Error"))
                             }
-                            builder
-                                .body(Bytes::from(file.content))
-                                .unwrap_or_else(|_| Self::error_response(500, "File Response Error"))
-                        }
-                        Ok(None) => Self::error_response(404, "Not Found"),
-                        Err(e) => {
-                            tracing::error!("❌ H3 FileServer error: {}", e);
-                            Self::error_response(500, "File Server Error")
                         }
+                    } else {
+                        Some(Self::error_response(503, "File Server Unavailable"))
                     }
-                } else {
-                    Self::error_response(503, "File Server Unavailable")
                 }
-            }
 
-            // ─────────────────────────────────────────────────────────────
-            // ReverseProxy: forward request to upstream over plain HTTP/1.1
-            //
-            // 🏗️ ARCHITECTURE: Raw tokio TCP + minimal HTTP/1.1 framing is
-            // used to avoid a heavy hyper dependency in this crate.
-            // Future work: hyper for keep-alive and HTTP/2 upstream.
-            // ─────────────────────────────────────────────────────────────
-            HandlerConfig::ReverseProxy(_) => {
-                let upstream = match route_index
-                    .and_then(|idx| state.load_balancers.get(idx)?.as_ref())
-                    .and_then(|lb| lb.select(None))
-                {
-                    Some(u) => u,
-                    None => return Self::error_response(502, "No Upstream Available"),
-                };
-                Self::proxy_to_upstream(&upstream, &parts, &host).await
+                // ─────────────────────────────────────────────────────────────
+                // ReverseProxy: forward request to upstream over plain HTTP/1.1
+                //
+                // 🏗️ ARCHITECTURE: Raw tokio TCP + minimal HTTP/1.1 framing is
+                // used to avoid a heavy hyper dependency in this crate.
+                // Future work: hyper for keep-alive and HTTP/2 upstream.
+                // ─────────────────────────────────────────────────────────────
+                HandlerConfig::ReverseProxy(config) => {
+                    let selected = match route_index.and_then(|idx| state.proxies.get(&ProxyNodeId { route: idx, node })) {
+                        Some(proxy) => proxy.select(&placeholders, Some(&crate::server::ip_octets(client_ip))).await,
+                        None => None,
+                    };
+                    let upstream = match selected {
+                        Some(u) => u,
+                        None => return Self::error_response(502, "No Upstream Available"),
+                    };
+                    let proxy_header = config.proxy_protocol
+                        .map(|version| crate::proxy_protocol::encode(version, Some(peer), Some(local)));
+
+                    // Same forwarding headers as the TCP path (HTTP/3 is always TLS)
+                    let fwd = Forwarding {
+                        peer: peer.ip(),
+                        trusted: proxy.settings().is_trusted(&peer.ip().to_string()),
+                        proto: "https",
+                        host: &host,
+                    };
+                    // `header_up` values replace forwarding and client headers of the same name
+                    let upstream_addr = upstream.addr.to_string();
+                    let placeholders = PlaceholderContext { upstream: Some(&upstream_addr), ..placeholders };
+                    let mut forward_headers: Vec<(&str, String)> = forwarded::forwarded_headers(&config.forwarded_headers, &parts.headers, &fwd)
+                        .into_iter()
+                        .filter(|(name, _)| !config.headers_up.keys().any(|key| key.eq_ignore_ascii_case(name)))
+                        .collect();
+                    forward_headers.extend(config.headers_up.iter()
+                        .map(|(name, template)| (name.as_str(), template.render(&placeholders).into_owned())));
+                    forward_headers.retain(|(name, _)| !config.headers_up_remove.iter().any(|r| r.eq_ignore_ascii_case(name)));
+                    // Inside `handle_path` the backend sees the stripped path
+                    let upstream_uri = if path != parts.uri.path() { with_path(&parts.uri, &path) } else { None };
+                    let path_and_query = upstream_uri.as_ref().unwrap_or(&parts.uri)
+                        .path_and_query()
+                        .map(|pq| pq.as_str())
+                        .unwrap_or("/");
+                    Some(Self::proxy_to_upstream(&upstream, &parts, path_and_query, &host, &forward_headers, &config.headers_up_remove, proxy_header.as_deref()).await)
+                }
+
+                _ => None,
+            };
+            if let Some(response) = response {
+                return response;
             }
+        }
+        Self::error_response(404, "Not Found")
+    }
 
-            // All other handlers are not applicable over the H3 in-process path
-            _ => Self::error_response(501, "Handler Not Supported Over HTTP/3"),
+    /// Collect the handlers of a tree that produce a response, in execution order
+    ///
+    /// Each entry carries the path it sees (after `handle_path` stripping) and
+    /// its `ProxyNodeId::node`, numbered as in the TCP path.
+    ///
+    /// - Returns: false if the tree holds a handler HTTP/3 cannot run.
+    fn terminal_handlers<'a>(
+        handler: &'a HandlerConfig,
+        path: &str,
+        node: usize,
+        out: &mut Vec<(&'a HandlerConfig, String, usize)>,
+    ) -> bool {
+        match handler {
+            HandlerConfig::Respond { .. }
+            | HandlerConfig::Redirect { .. }
+            | HandlerConfig::FileServer { .. }
+            | HandlerConfig::ReverseProxy(_) => {
+                out.push((handler, path.to_string(), node));
+                true
+            }
+            HandlerConfig::Pipeline(handlers) | HandlerConfig::Handle(handlers) => sequence_nodes(handlers, node)
+                .all(|(h, node)| Self::terminal_handlers(h, path, node, out)),
+            HandlerConfig::HandlePath { prefix, handlers } => {
+                let path = strip_path_prefix(path, prefix);
+                sequence_nodes(handlers, node).all(|(h, node)| Self::terminal_handlers(h, path, node, out))
+            }
+            _ => false,
         }
     }
 
@@ -398,7 +514,11 @@ impl QuicServer {
     async fn proxy_to_upstream(
         upstream: &crate::upstream::Upstream,
         parts: &http::request::Parts,
+        path_and_query: &str,
         host: &str,
+        forward_headers: &[(&str, String)],
+        remove: &[String],
+        proxy_header: Option<&[u8]>,
     ) -> Response<Bytes> {
         use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -425,27 +545,33 @@ impl QuicServer {
             Err(_) => return Self::error_response(504, "Upstream Connect Timeout"),
         };
 
-        // 3. Build HTTP/1.1 request
-        let path_and_query = parts
-            .uri
-            .path_and_query()
-            .map(|pq| pq.as_str())
-            .unwrap_or("/");
+        // PROXY protocol header precedes the request
+        if let Some(proxy_header) = proxy_header
+            && let Err(e) = stream.write_all(proxy_header).await
+        {
+            tracing::error!("❌ H3 proxy PROXY header error: {}", e);
+            return Self::error_response(502, "Upstream Connect Failed");
+        }
 
+        // 3. Build HTTP/1.1 request
         let mut req_buf = format!(
             "{} {} HTTP/1.1\r\n\
              Host: {}\r\n\
-             Connection: close\r\n\
-             X-Forwarded-Proto: https\r\n",
+             Connection: close\r\n",
             parts.method, path_and_query, host
         );
-        // Forward original headers — skip hop-by-hop
+        for (name, value) in forward_headers {
+            req_buf.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        // Forward original headers — skip hop-by-hop, replaced and removed ones
+        let hop_by_hop = forwarded::hop_by_hop(&parts.headers);
         for (k, v) in &parts.headers {
-            let name = k.as_str().to_ascii_lowercase();
-            if matches!(
-                name.as_str(),
-                "host" | "connection" | "transfer-encoding" | "keep-alive"
-            ) {
+            let name = k.as_str();
+            if matches!(name, "host" | "transfer-encoding")
+                || hop_by_hop.iter().any(|h| h == name)
+                || forward_headers.iter().any(|(f, _)| f.eq_ignore_ascii_case(name))
+                || remove.iter().any(|r| r.eq_ignore_ascii_case(name))
+            {
                 continue;
             }
             if let Ok(v_str) = v.to_str() {
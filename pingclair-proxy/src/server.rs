@@ -2,8 +2,9 @@
 //!
 //! 🌐 This module implements the core reverse proxy using Pingora's ProxyHttp trait.
 
-use pingclair_core::config::{ServerConfig, HandlerConfig, ReverseProxyConfig};
-use pingclair_core::server::Router;
+use pingclair_core::config::{GlobalConfig, LoggingConfig, ServerConfig, HandlerConfig, ReverseProxyConfig, ResponseHandlerConfig, ForwardedHeadersConfig, PathNormalizationConfig};
+use pingclair_core::server::{Router, CompiledResponseMatcher, RequestInfo, Captures, HostIndex, normalize_host, normalize_path, rewrite_path, apply_vars, handler_vars};
+use pingclair_core::server::{Placeholder, PlaceholderContext, ProxyProtocolValues, Template};
 
 use async_trait::async_trait;
 use pingora_core::upstreams::peer::HttpPeer;
@@ -11,8 +12,9 @@ use pingora_core::Result as PingoraResult;
 use pingora_proxy::{ProxyHttp, Session};
 use pingora_http::{RequestHeader, ResponseHeader};
 
+use std::borrow::Cow;
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 use parking_lot::RwLock;
 use async_recursion::async_recursion;
@@ -21,9 +23,20 @@ use flate2::Compression;
 use std::io::Write;
 
 use crate::{LoadBalancer, Strategy, Upstream, HealthChecker};
-use crate::upstream::{create_upstream, Scheme, HostName};
+use crate::upstream::{create_upstream, resolve_upstream, Scheme, HostName};
 use crate::metrics;
+use crate::client_ip;
+use crate::forwarded::{self, Forwarding};
+use crate::proxy_protocol::{self, ProxiedConnection, ProxyProtocolConnector};
+use crate::accel::{self, AccelDirectives};
+use crate::trace::{self, TraceContext};
+use crate::otel::{OtlpExporter, SpanData, SpanKind, SpanRecorder};
+use crate::access_log::{basic_auth_user, AccessLogEntry, AccessLogger, TlsInfo, UpstreamTiming};
 use bytes::Bytes;
+use pingclair_static::{Templates, TemplatesConfig, TemplateContext};
+
+/// Largest upstream body buffered for `templates`; bigger ones pass through unrendered
+const MAX_TEMPLATE_BODY: usize = 8 * 1024 * 1024;
 
 // MARK: - Context
 
@@ -33,14 +46,26 @@ pub struct RequestContext {
     pub state: Option<ProxyState>,
     /// Matched route index
     pub route_index: Option<usize>,
+    /// Reverse proxy node reached by the handler tree
+    pub proxy_node: Option<ProxyNodeId>,
+    /// Path parameters and regex captures of the matched route (`{re.*}`)
+    pub captures: Captures,
+    /// Variables set by `map` / `vars` (`{vars.*}`)
+    pub vars: HashMap<String, String>,
     /// Selected upstream (kept for connection tracking)
     pub upstream: Option<Upstream>,
-    /// Extra headers to add upstream
-    pub headers_upstream: HashMap<String, String>,
+    /// Extra headers to add upstream (`header_up`)
+    pub headers_upstream: HashMap<String, Template>,
+    /// Headers to remove from the upstream request
+    pub headers_upstream_remove: Vec<String>,
+    /// Standard forwarding headers sent upstream
+    pub forwarded_headers: ForwardedHeadersConfig,
     /// Extra headers to add downstream (set)
     pub headers_downstream: HashMap<String, String>,
     /// Extra headers to add downstream (append)
     pub headers_downstream_add: HashMap<String, String>,
+    /// Reverse proxy `header_down` values, rendered against the upstream response
+    pub headers_down: HashMap<String, Template>,
     /// Headers to remove from downstream response
     pub headers_remove: Vec<String>,
     /// Whether to suppress the default Server header
@@ -55,14 +80,50 @@ pub struct RequestContext {
     pub request_method: String,
     /// Request path (for access log)
     pub request_path: String,
+    /// URI as received, forwarded upstream instead of the normalised one (`forward_original`)
+    pub original_uri: Option<http::Uri>,
+    /// Path left after `handle_path` stripped its prefix, forwarded upstream in place of the request path
+    pub upstream_path: Option<String>,
     /// Request host (for access log)
     pub request_host: String,
+    /// Direct peer IP address
+    pub remote_ip: String,
+    /// Direct peer port (None for Unix socket peers)
+    pub remote_port: Option<u16>,
+    /// Real client IP (resolved through trusted proxies)
+    pub client_ip: String,
+    /// Connection details from a PROXY protocol listener
+    pub proxy_protocol: Option<Arc<ProxiedConnection>>,
+    /// Request protocol (`http` / `https`)
+    pub protocol: String,
+    /// Negotiated TLS parameters (None on plaintext listeners)
+    pub tls: Option<TlsInfo>,
     /// Upstream response status (for access log)
     pub response_status: u16,
     /// Response body bytes written (for access log)
     pub response_bytes: u64,
+    /// Request body bytes received (for access log)
+    pub request_bytes: u64,
+    /// Upstream connect / header / response timings (for access log)
+    pub upstream_timing: UpstreamTiming,
+    /// Rate limiter outcome for the matched route (for access log)
+    pub rate_limited: Option<bool>,
+    /// Whether this request is counted in the in-flight gauge
+    pub in_flight: bool,
+    /// Body of a response produced by `handle_response`, replacing the upstream body
+    pub replacement_body: Option<Bytes>,
+    /// Response rate limit in bytes per second (`X-Accel-Limit-Rate`)
+    pub limit_rate: Option<u64>,
+    /// Active templates renderer (set by the `templates` handler)
+    pub templates: Option<Arc<Templates>>,
+    /// Buffered upstream body awaiting template rendering, with its content type
+    pub template_body: Option<(String, Vec<u8>)>,
     /// Unique request ID
     pub request_id: String,
+    /// W3C trace context (Pingclair's span)
+    pub trace: TraceContext,
+    /// Span recorder (set only for sampled requests while OTLP export is enabled)
+    pub spans: Option<SpanRecorder>,
     /// Start time for logging
     pub start_time: std::time::Instant,
 }
@@ -72,10 +133,16 @@ impl Default for RequestContext {
         Self {
             state: None,
             route_index: None,
+            proxy_node: None,
+            captures: Captures::new(),
+            vars: HashMap::new(),
             upstream: None,
             headers_upstream: HashMap::new(),
+            headers_upstream_remove: Vec::new(),
+            forwarded_headers: ForwardedHeadersConfig::default(),
             headers_downstream: HashMap::new(),
             headers_downstream_add: HashMap::new(),
+            headers_down: HashMap::new(),
             headers_remove: Vec::new(),
             suppress_server_header: false,
             compress_response: false,
@@ -83,27 +150,33 @@ impl Default for RequestContext {
             gzip_encoder: None,
             request_method: String::new(),
             request_path: String::new(),
+            original_uri: None,
+            upstream_path: None,
             request_host: String::new(),
+            remote_ip: String::new(),
+            remote_port: None,
+            client_ip: String::new(),
+            proxy_protocol: None,
+            protocol: String::new(),
+            tls: None,
             response_status: 0,
             response_bytes: 0,
-            request_id: generate_request_id(),
+            request_bytes: 0,
+            upstream_timing: UpstreamTiming::default(),
+            rate_limited: None,
+            in_flight: false,
+            replacement_body: None,
+            limit_rate: None,
+            templates: None,
+            template_body: None,
+            request_id: trace::generate_request_id(),
+            trace: TraceContext::default(),
+            spans: None,
             start_time: std::time::Instant::now(),
         }
     }
 }
 
-/// Generate a compact, sortable request ID (timestamp + random suffix)
-fn generate_request_id() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_micros();
-    // Base36 encode for compactness: ~13 chars timestamp + 4 random
-    let rand_part: u16 = (ts as u16).wrapping_mul(31421).wrapping_add(6927);
-    format!("{:x}-{:04x}", ts, rand_part)
-}
-
 // MARK: - Proxy State
 
 /// Mutable state for hot reloading
@@ -113,14 +186,133 @@ pub struct ProxyState {
     pub config: Arc<ServerConfig>,
     /// Route matcher
     pub router: Arc<Router>,
-    /// Load balancers per route
-    pub load_balancers: Vec<Option<Arc<LoadBalancer>>>,
-    /// Health checkers per route
-    pub health_checkers: Vec<Option<Arc<HealthChecker>>>,
-    /// File servers per route
-    pub file_servers: Vec<Option<Arc<pingclair_static::FileServer>>>,
+    /// Reverse proxy nodes anywhere in a route's handler tree
+    pub proxies: HashMap<ProxyNodeId, Arc<ProxyNode>>,
+    /// File servers of the routes' handler trees, keyed by their settings
+    pub file_servers: HashMap<FileServerKey, Arc<pingclair_static::FileServer>>,
     /// Rate limiters per route
     pub rate_limiters: Vec<Option<Arc<crate::rate_limit::RateLimiter>>>,
+    /// Template renderers per route (parse cache lives as long as the config)
+    pub templates: Vec<Option<Arc<Templates>>>,
+    /// Site access log (`log` block)
+    pub access_log: Option<Arc<AccessLogger>>,
+}
+
+/// Identifies a `reverse_proxy` node within a server's routes
+///
+/// 🏗️ ARCHITECTURE: Handlers are grouped into `Pipeline` / `Handle` /
+/// `HandlePath` trees, so a route may hold several proxies (or none at the
+/// top level). `node` is the proxy's pre-order position in its route's tree,
+/// which `handle_config` recomputes while walking the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProxyNodeId {
+    /// Route index
+    pub route: usize,
+    /// Pre-order index among the route's `reverse_proxy` handlers
+    pub node: usize,
+}
+
+/// Settings of a `file_server` handler, identifying its pre-built `FileServer`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileServerKey {
+    pub root: String,
+    pub index: Vec<String>,
+    pub browse: bool,
+    pub compress: bool,
+}
+
+impl FileServerKey {
+    /// Key of a `file_server` handler (None for other handlers)
+    pub fn of(handler: &HandlerConfig) -> Option<Self> {
+        match handler {
+            HandlerConfig::FileServer { root, index, browse, compress } => Some(Self {
+                root: root.clone(),
+                index: index.clone(),
+                browse: *browse,
+                compress: *compress,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Build the file server these settings describe
+    pub fn build(&self) -> pingclair_static::FileServer {
+        build_file_server(&self.root, &self.index, self.browse, self.compress)
+    }
+}
+
+/// A `reverse_proxy` handler with its runtime components
+pub struct ProxyNode {
+    /// Proxy configuration
+    pub config: ReverseProxyConfig,
+    /// Upstream pool (the health checker, if any, lives inside)
+    pub load_balancer: Arc<LoadBalancer>,
+    /// Upstreams rendered per request when any of them holds a `{placeholder}`
+    pub dynamic_upstreams: Vec<Template>,
+    /// Health-checked upstream addresses published to `pingclair_upstream_healthy`
+    pub health_gauges: Vec<String>,
+    /// Upstream response handlers (`handle_response`)
+    pub response_handlers: Arc<Vec<CompiledResponseHandler>>,
+}
+
+impl ProxyNode {
+    /// Select the upstream for a request
+    ///
+    /// Templated upstreams (e.g. a pool chosen by `map` into `{vars.backend}`)
+    /// are rendered and the first that resolves is used; they bypass load
+    /// balancing and health checks. Otherwise the load balancer picks one.
+    ///
+    /// - Parameter remote_addr: Client IP octets for `ip_hash`.
+    pub async fn select(&self, placeholders: &PlaceholderContext<'_>, remote_addr: Option<&[u8]>) -> Option<Upstream> {
+        if self.dynamic_upstreams.is_empty() {
+            return self.load_balancer.select(remote_addr);
+        }
+        let addresses: Vec<String> = self.dynamic_upstreams.iter()
+            .map(|upstream| upstream.render(placeholders).into_owned())
+            .collect();
+        for address in &addresses {
+            match resolve_upstream(address).await {
+                Some(upstream) => return Some(upstream),
+                None => tracing::warn!("⚠️ Upstream {:?} did not resolve", address),
+            }
+        }
+        None
+    }
+}
+
+/// A `handle_response` block with its matcher and file servers pre-built
+#[derive(Debug, Clone)]
+pub struct CompiledResponseHandler {
+    /// Pre-compiled response matcher (None matches every response)
+    pub matcher: Option<CompiledResponseMatcher>,
+    /// File servers of the block's handlers, keyed by their settings
+    pub file_servers: HashMap<FileServerKey, Arc<pingclair_static::FileServer>>,
+    /// Original configuration
+    pub config: ResponseHandlerConfig,
+}
+
+impl CompiledResponseHandler {
+    /// Compile a `handle_response` block
+    pub fn compile(config: &ResponseHandlerConfig) -> Self {
+        let file_servers = config.handlers.iter()
+            .flat_map(file_server_keys)
+            .map(|key| {
+                let file_server = Arc::new(key.build());
+                (key, file_server)
+            })
+            .collect();
+        Self {
+            matcher: config.matcher.as_ref().map(CompiledResponseMatcher::compile),
+            file_servers,
+            config: config.clone(),
+        }
+    }
+
+    /// Check whether this block applies to an upstream response
+    pub fn matches(&self, response: &ResponseHeader) -> bool {
+        self.matcher.as_ref()
+            .is_none_or(|m| m.matches(response.status.as_u16(), &response.headers))
+    }
 }
 
 impl ProxyState {
@@ -135,93 +327,33 @@ impl ProxyState {
         let router = Router::new(config.routes.clone());
         
         // Initialize components for each route
-        let mut load_balancers = Vec::new();
-        let mut health_checkers = Vec::new();
-        let mut file_servers = Vec::new();
+        let mut proxies = HashMap::new();
+        let mut file_servers = HashMap::new();
         let mut rate_limiters = Vec::new();
+        let mut templates = Vec::new();
+
+        for (route_index, route) in config.routes.iter().enumerate() {
+            // Every reverse_proxy in the handler tree gets its own upstream pool
+            for (node, proxy_config) in proxy_nodes(&route.handler).into_iter().enumerate() {
+                let (load_balancer, health_gauges) = build_load_balancer(proxy_config, &route.path);
+                let dynamic_upstreams = dynamic_upstreams(proxy_config, &route.path);
+                let compiled_response_handlers = proxy_config.handle_response.iter()
+                    .map(CompiledResponseHandler::compile)
+                    .collect();
+                proxies.insert(ProxyNodeId { route: route_index, node }, Arc::new(ProxyNode {
+                    config: proxy_config.clone(),
+                    load_balancer,
+                    dynamic_upstreams,
+                    health_gauges,
+                    response_handlers: Arc::new(compiled_response_handlers),
+                }));
+            }
 
-        for route in &config.routes {
-            match &route.handler {
-                HandlerConfig::ReverseProxy(proxy_config) => {
-                    // 1. Create Upstreams (Backends)
-                    let upstreams: Vec<Upstream> = proxy_config.upstreams.iter()
-                        .filter_map(|addr| create_upstream(addr))
-                        .collect();
-                    
-                    if upstreams.is_empty() {
-                        tracing::warn!("⚠️ No valid upstreams found for route {}", route.path);
-                    }
-
-                    // 2. Create Strategy
-                    let strategy = match proxy_config.load_balance.strategy.as_str() {
-                        "random"     => Strategy::Random,
-                        "least_conn" => Strategy::LeastConn,
-                        "ip_hash"    => Strategy::IpHash,
-                        "first"      => Strategy::RoundRobin,
-                        _            => Strategy::RoundRobin,
-                    };
-                    
-                    // 3. Create Load Balancer
-                    let mut load_balancer = Arc::new(LoadBalancer::new(upstreams, strategy));
-                    
-                    // 4. Setup Health Checker if configured
-                    if let Some(hc_config) = &proxy_config.health_check {
-                        let health_check_conf = crate::health_check::HealthCheckConfig {
-                             path: hc_config.path.clone(),
-                             timeout: std::time::Duration::from_secs(hc_config.timeout),
-                             positive_threshold: 1,
-                             negative_threshold: hc_config.threshold as usize,
-                             expected_status: (200, 299),
-                        };
-                        
-                        let health_checker = HealthChecker::new(health_check_conf);
-                        
-                        // Attach to LB (needs mutable access to LB wrapper during init)
-                        if let Some(load_balancer_mut) = Arc::get_mut(&mut load_balancer) {
-                            load_balancer_mut.set_health_check(health_checker);
-                            load_balancer_mut.set_health_check_frequency(std::time::Duration::from_secs(hc_config.interval));
-                        } else {
-                            tracing::warn!("Correlation ID: Init - Could not attach health checker to LB");
-                        }
-                        // 🛑 SAFETY: Always push to keep health_checkers aligned with
-                        // load_balancers by index. Health checker is stored inside the LB
-                        // object; this slot is a tombstone for index alignment only.
-                        health_checkers.push(None);
-                    } else {
-                        health_checkers.push(None);
-                    }
-
-                    load_balancers.push(Some(load_balancer));
-                    file_servers.push(None); // No file server for this route
-
-                    tracing::info!(
-                        "⚖️ Initialized load balancer for route {} with strategy {:?}", 
-                        route.path, strategy
-                    );
-
-                },
-                HandlerConfig::FileServer { root, index, browse, compress } => {
-                    // Initialize File Server
-                    let fs_config = pingclair_static::FileServerConfig {
-                        root: std::path::PathBuf::from(root),
-                        index: if index.is_empty() { vec!["index.html".to_string()] } else { index.clone() },
-                        browse: *browse,
-                        compress: *compress,
-                        precompressed: true,  // Enable pre-compressed file detection by default
-                    };
-                    
-                    let file_server = Arc::new(pingclair_static::FileServer::new(fs_config));
-                    
-                    load_balancers.push(None);
-                    health_checkers.push(None);
-                    file_servers.push(Some(file_server));
-                    
-                    tracing::info!("📁 Initialized file server for route {}", route.path);
-                },
-                _ => {
-                    load_balancers.push(None);
-                    health_checkers.push(None);
-                    file_servers.push(None);
+            // File servers anywhere in the handler tree (identical settings share one)
+            for key in file_server_keys(&route.handler) {
+                if !file_servers.contains_key(&key) {
+                    tracing::info!("📁 Initialized file server {} for route {}", key.root, route.path);
+                    file_servers.insert(key.clone(), Arc::new(key.build()));
                 }
             }
 
@@ -233,38 +365,154 @@ impl ProxyState {
             } else {
                 rate_limiters.push(None);
             }
+
+            // Templates renderer (include root defaults to the route's file server root)
+            if let Some(HandlerConfig::Templates { root, mime_types, between }) = find_templates_config(&route.handler) {
+                let root = root.clone()
+                    .or_else(|| find_file_server_root(&route.handler).map(str::to_string))
+                    .unwrap_or_else(|| ".".to_string());
+                let defaults = TemplatesConfig::default();
+                templates.push(Some(Arc::new(Templates::new(TemplatesConfig {
+                    root: std::path::PathBuf::from(root),
+                    mime_types: mime_types.clone(),
+                    delimiters: between.clone().unwrap_or(defaults.delimiters),
+                }))));
+                tracing::info!("🧩 Initialized templates for route {}", route.path);
+            } else {
+                templates.push(None);
+            }
         }
+
+        // Site access log
+        let access_log = config.log.as_ref().and_then(|log| match AccessLogger::new(log) {
+            Ok(logger) => Some(Arc::new(logger)),
+            Err(e) => {
+                tracing::error!("❌ Failed to open access log for {:?}: {}", config.name, e);
+                None
+            }
+        });
         
         Self {
             config: Arc::new(config),
             router: Arc::new(router),
-            load_balancers,
-            health_checkers,
+            proxies,
             file_servers,
             rate_limiters,
+            templates,
+            access_log,
         }
     }
+
+    /// The pre-built file server of a `file_server` handler
+    ///
+    /// - Returns: None for other handlers, or for settings not in the config (`try_files`).
+    pub fn file_server(&self, handler: &HandlerConfig) -> Option<Arc<pingclair_static::FileServer>> {
+        FileServerKey::of(handler).and_then(|key| self.file_servers.get(&key).cloned())
+    }
 }
 
 // MARK: - Server Implementation
 
+/// Process-wide request handling settings (from the global config block)
+#[derive(Debug, Clone)]
+pub struct GlobalSettings {
+    /// Header carrying the request ID (downstream and upstream)
+    pub request_id_header: String,
+    /// Peers allowed to supply request metadata such as the request ID
+    pub trusted_proxies: Vec<ipnet::IpNet>,
+    /// Headers carrying the client IP from trusted proxies, in priority order
+    pub client_ip_headers: Vec<http::HeaderName>,
+    /// OTLP span exporter (`None` when tracing export is disabled)
+    pub exporter: Option<Arc<OtlpExporter>>,
+    /// Access log level for sites without their own override
+    pub log_level: tracing::Level,
+}
+
+impl Default for GlobalSettings {
+    fn default() -> Self {
+        Self {
+            request_id_header: trace::DEFAULT_REQUEST_ID_HEADER.to_string(),
+            trusted_proxies: Vec::new(),
+            client_ip_headers: vec![http::HeaderName::from_static("x-forwarded-for")],
+            exporter: None,
+            log_level: tracing::Level::INFO,
+        }
+    }
+}
+
+impl GlobalSettings {
+    /// Build the settings from the global configuration.
+    ///
+    /// - Parameter global: The `global` config block.
+    /// - Parameter logging: The global logging configuration.
+    /// - Returns: Settings with invalid trusted proxy entries dropped.
+    pub fn from_config(global: &GlobalConfig, logging: &LoggingConfig) -> Self {
+        Self {
+            request_id_header: global.request_id_header.clone()
+                .filter(|h| http::HeaderName::from_bytes(h.as_bytes()).is_ok())
+                .unwrap_or_else(|| trace::DEFAULT_REQUEST_ID_HEADER.to_string()),
+            trusted_proxies: crate::connection_filter::parse_ip_ranges(&global.trusted_proxies, "trusted proxy"),
+            client_ip_headers: parse_client_ip_headers(&global.client_ip_headers),
+            exporter: OtlpExporter::shared(global.tracing.as_ref()),
+            log_level: crate::access_log::parse_level(&logging.level).unwrap_or(tracing::Level::INFO),
+        }
+    }
+
+    /// Whether the direct peer is a trusted proxy
+    pub fn is_trusted(&self, remote_ip: &str) -> bool {
+        remote_ip.parse::<std::net::IpAddr>()
+            .map(|ip| self.trusted_proxies.iter().any(|net| net.contains(&ip)))
+            .unwrap_or(false)
+    }
+
+    /// Resolve the real client IP (see `client_ip`)
+    ///
+    /// - Parameter peer: The direct peer address.
+    /// - Parameter headers: The request headers.
+    /// - Returns: The client address; `peer` unless it is a trusted proxy.
+    pub fn client_ip(&self, peer: std::net::IpAddr, headers: &http::HeaderMap) -> std::net::IpAddr {
+        client_ip::resolve(peer, headers, &self.client_ip_headers, &self.trusted_proxies)
+    }
+}
+
+/// Parse configured client IP header names, falling back to `X-Forwarded-For`
+fn parse_client_ip_headers(names: &[String]) -> Vec<http::HeaderName> {
+    let headers: Vec<http::HeaderName> = names.iter()
+        .filter_map(|name| match http::HeaderName::from_bytes(name.as_bytes()) {
+            Ok(header) => Some(header),
+            Err(_) => {
+                tracing::warn!("⚠️ Ignoring invalid client IP header: {}", name);
+                None
+            }
+        })
+        .collect();
+    if headers.is_empty() {
+        vec![http::HeaderName::from_static("x-forwarded-for")]
+    } else {
+        headers
+    }
+}
+
 /// Pingclair reverse proxy
 #[derive(Clone)]
 pub struct PingclairProxy {
-    /// Map of hostname -> server state
-    pub hosts: Arc<RwLock<HashMap<String, ProxyState>>>,
+    /// Hostname / `*.suffix` wildcard -> server state
+    pub hosts: Arc<RwLock<HostIndex<ProxyState>>>,
     /// Default server state (catch-all)
     pub default: Arc<RwLock<Option<ProxyState>>>,
     /// TLS Manager for certificate resolution
     pub tls_manager: Option<Arc<pingclair_tls::manager::TlsManager>>,
+    /// Global settings (swapped atomically on reload)
+    pub settings: Arc<RwLock<Arc<GlobalSettings>>>,
 }
 
 impl Default for PingclairProxy {
     fn default() -> Self {
         Self {
-            hosts: Arc::new(RwLock::new(HashMap::new())),
+            hosts: Arc::new(RwLock::new(HostIndex::new())),
             default: Arc::new(RwLock::new(None)),
             tls_manager: None,
+            settings: Arc::new(RwLock::new(Arc::new(GlobalSettings::default()))),
         }
     }
 }
@@ -278,12 +526,23 @@ impl PingclairProxy {
     /// Create a new proxy with TLS manager
     pub fn with_tls(tls_manager: Arc<pingclair_tls::manager::TlsManager>) -> Self {
         Self {
-            hosts: Arc::new(RwLock::new(HashMap::new())),
+            hosts: Arc::new(RwLock::new(HostIndex::new())),
             default: Arc::new(RwLock::new(None)),
             tls_manager: Some(tls_manager),
+            settings: Arc::new(RwLock::new(Arc::new(GlobalSettings::default()))),
         }
     }
 
+    /// Apply the global configuration (also used on reload)
+    pub fn set_global(&self, global: &GlobalConfig, logging: &LoggingConfig) {
+        *self.settings.write() = Arc::new(GlobalSettings::from_config(global, logging));
+    }
+
+    /// Current global settings
+    pub fn settings(&self) -> Arc<GlobalSettings> {
+        self.settings.read().clone()
+    }
+
     /// Add a server configuration to this proxy
     pub fn add_server(&self, config: ServerConfig) {
         let state = ProxyState::new(config.clone());
@@ -296,7 +555,7 @@ impl PingclairProxy {
             if domain == "_" || domain == "*" || domain.starts_with(':') {
                 *default = Some(state.clone());
             } else {
-                hosts.insert(domain.clone(), state.clone());
+                hosts.insert(domain, state.clone());
             }
         } else {
             *default = Some(state.clone());
@@ -305,7 +564,7 @@ impl PingclairProxy {
 
     /// Replace all server configurations with a new list
     pub fn update_config(&self, servers: Vec<ServerConfig>) {
-        let mut new_hosts = HashMap::new();
+        let mut new_hosts = HostIndex::new();
         let mut new_default = None;
 
         for config in servers {
@@ -314,7 +573,7 @@ impl PingclairProxy {
                 if domain == "_" || domain == "*" || domain.starts_with(':') {
                     new_default = Some(state);
                 } else {
-                    new_hosts.insert(domain.clone(), state);
+                    new_hosts.insert(domain, state);
                 }
             } else {
                 new_default = Some(state);
@@ -323,6 +582,15 @@ impl PingclairProxy {
 
         let mut hosts = self.hosts.write();
         let mut default = self.default.write();
+
+        // Upstreams no longer health-checked would otherwise keep exporting their last status
+        let kept = health_gauges(new_hosts.values().chain(new_default.as_ref()));
+        for label in health_gauges(hosts.values().chain(default.as_ref())) {
+            if !kept.contains(&label) {
+                let _ = crate::metrics::UPSTREAM_HEALTHY.remove_label_values(&[&label]);
+            }
+        }
+
         *hosts = new_hosts;
         *default = new_default;
         
@@ -331,16 +599,12 @@ impl PingclairProxy {
     
     /// Resolve a request to a handler state
     /// Used by HTTP/3 server to reuse routing logic
-    pub fn match_route(&self, host: &str, path: &str, method: &str, headers: &pingora_http::RequestHeader, remote_ip: &str) -> Option<(ProxyState, Option<usize>, Option<HandlerConfig>)> {
-        // 1. Get state for this host
-        let state = self.get_state(host)?;
-        
-        // 2. Match route
-        // Identify protocol (stub)
-        let protocol = "https"; 
-        
-        if let Some(route) = state.router.match_request(path, method, &headers.headers, host, remote_ip, protocol) {
-            let index = route.index;
+    ///
+    /// - Parameter request: The request, with its host normalised and site variables set (see `site_vars`).
+    pub fn match_route(&self, request: &RequestInfo<'_>) -> Option<(ProxyState, Option<usize>, Option<HandlerConfig>)> {
+        let state = self.get_state(request.host)?;
+        if let Some(matched) = state.router.match_request(request) {
+            let index = matched.route.index;
             let handler = state.config.routes.get(index).map(|r| r.handler.clone());
             Some((state, Some(index), handler))
         } else {
@@ -349,55 +613,102 @@ impl PingclairProxy {
         }
     }
 
+    /// Variables set by the site-wide `map` / `vars` of the virtual host serving `placeholders.host`.
+    /// Used by HTTP/3 server, which routes outside `request_filter`
+    pub fn site_vars(&self, placeholders: &PlaceholderContext<'_>) -> HashMap<String, String> {
+        let mut vars = HashMap::new();
+        if let Some(state) = self.get_state(placeholders.host) {
+            apply_vars(&state.config.variables, placeholders, &mut vars);
+        }
+        vars
+    }
+
+    /// Path normalisation policy of the virtual host serving `host`.
+    pub fn path_normalization(&self, host: &str) -> PathNormalizationConfig {
+        self.get_state(&normalize_host(host))
+            .map(|state| state.config.path_normalization.clone())
+            .unwrap_or_default()
+    }
+
     // MARK: - Internal Helpers
 
     /// Get the state for a specific host.
     ///
     /// Resolution order (matches Caddy semantics):
     /// 1. Exact hostname match (`api.example.com`)
-    /// 2. Wildcard match (`*.example.com`) — checks all registered wildcard hosts
+    /// 2. Most specific wildcard match (`*.api.example.com`, then `*.example.com`)
     /// 3. Default catch-all server
+    ///
+    /// - Parameter host: Host normalised with `normalize_host`.
     fn get_state(&self, host: &str) -> Option<ProxyState> {
-        let hosts = self.hosts.read();
-
-        // 1. Exact match (fast path)
-        if let Some(state) = hosts.get(host) {
+        // ⚡ OPTIMIZATION: Wildcards are indexed by suffix (see `HostIndex`)
+        if let Some(state) = self.hosts.read().get(host) {
             return Some(state.clone());
         }
+        self.default.read().clone()
+    }
 
-        // 2. ⚡ OPTIMIZATION: Wildcard match — iterate registered patterns like *.example.com
-        // Only hosts whose registered key starts with "*." are wildcard entries.
-        // For a request to "foo.example.com" we check if "*.example.com" is registered.
-        for (pattern, state) in hosts.iter() {
-            if let Some(wildcard_suffix) = pattern.strip_prefix("*.") {
-                // The request host must end with ".{suffix}" to match *.{suffix}
-                if host.ends_with(&format!(".{}", wildcard_suffix)) {
-                    return Some(state.clone());
-                }
+    /// Normalise the request path per the server's `path_normalization` policy.
+    ///
+    /// Rewrites the request URI in place (query kept), remembering the URI as
+    /// received when it should be forwarded upstream unchanged. Paths the
+    /// policy refuses are answered with `400 Bad Request`.
+    ///
+    /// - Returns: `false` when the request was rejected and answered.
+    async fn normalize_request_path(
+        &self,
+        session: &mut Session,
+        ctx: &mut RequestContext,
+        state: &ProxyState,
+    ) -> pingora_core::Result<bool> {
+        let policy = &state.config.path_normalization;
+        let normalized = match normalize_path(session.req_header().uri.path(), policy) {
+            Ok(Cow::Borrowed(_)) => return Ok(true),
+            Ok(Cow::Owned(path)) => Ok(path),
+            Err(e) => Err(e),
+        };
+        let normalized = match normalized {
+            Ok(path) => path,
+            Err(e) => {
+                let req = session.req_header();
+                tracing::debug!("🚫 Rejected request path {:?}: {}", req.uri.path(), e);
+                ctx.request_path = req.uri.path().to_string();
+                ctx.request_method = req.method.to_string();
+                let mut header = ResponseHeader::build(400, Some(3))?;
+                header.insert_header("Content-Length", "0")?;
+                header.insert_header("Server", "Pingclair")?;
+                session.write_response_header(Box::new(header), true).await?;
+                return Ok(false);
             }
-        }
+        };
 
-        // 3. Default catch-all
-        self.default.read().clone()
-    }
-    
-    /// Select an upstream using the load balancer
-    fn select_upstream(&self, state: &ProxyState, route_index: usize, remote_addr: Option<&[u8]>) -> Option<Upstream> {
-        if let Some(load_balancer) = state.load_balancers.get(route_index).and_then(|lb| lb.as_ref()) {
-            load_balancer.select(remote_addr)
-        } else {
-            None
+        let uri = &session.req_header().uri;
+        let rebuilt = match uri.query() {
+            Some(query) => format!("{}?{}", normalized, query),
+            None => normalized,
+        };
+        match rebuilt.parse::<http::Uri>() {
+            Ok(normalized) => {
+                tracing::debug!("🧹 Normalised path {} → {}", uri, normalized);
+                if policy.forward_original {
+                    ctx.original_uri = Some(uri.clone());
+                }
+                session.req_header_mut().set_uri(normalized);
+            }
+            Err(e) => tracing::warn!("⚠️ Invalid normalised URI {:?}: {}", rebuilt, e),
         }
+        Ok(true)
     }
+
     
     /// Parse upstream URL into (host, port, tls)
     pub fn parse_upstream(upstream: &str) -> Option<(String, u16, bool)> {
         let upstream = upstream.trim();
         
-        let (scheme, rest) = if upstream.starts_with("https://") {
-            (true, &upstream[8..])
-        } else if upstream.starts_with("http://") {
-            (false, &upstream[7..])
+        let (scheme, rest) = if let Some(rest) = upstream.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = upstream.strip_prefix("http://") {
+            (false, rest)
         } else {
             (false, upstream)
         };
@@ -414,16 +725,18 @@ impl PingclairProxy {
         Some((host, port, scheme))
     }
     
-    /// Get proxy config for a route
-    fn get_proxy_config(&self, state: &ProxyState, route_index: usize) -> Option<ReverseProxyConfig> {
-        let route = state.config.routes.get(route_index)?;
-        match &route.handler {
-            HandlerConfig::ReverseProxy(config) => Some(config.clone()),
-            _ => None,
-        }
+    /// Get the config of a reverse proxy node
+    fn get_proxy_config(&self, state: &ProxyState, node: ProxyNodeId) -> Option<ReverseProxyConfig> {
+        state.proxies.get(&node).map(|proxy| proxy.config.clone())
     }
 
     /// Handle a specific handler configuration
+    ///
+    /// Returns `Ok(true)` once a response has been written. Reaching a
+    /// `reverse_proxy` records its node in `ctx.proxy_node` and stops the walk
+    /// with `Ok(false)` so the request continues to `upstream_peer`.
+    ///
+    /// - Parameter node: Pre-order index of the first `reverse_proxy` in `handler`.
     #[async_recursion]
     async fn handle_config(
         &self, 
@@ -431,12 +744,24 @@ impl PingclairProxy {
         ctx: &mut RequestContext, 
         handler: &HandlerConfig, 
         path: &str, 
-        route_index: usize
+        route_index: usize,
+        node: usize,
     ) -> PingoraResult<bool> {
         match handler {
+            HandlerConfig::ReverseProxy(_) => {
+                ctx.proxy_node = Some(ProxyNodeId { route: route_index, node });
+                // Inside `handle_path` the backend sees the stripped path
+                if path != session.req_header().uri.path() {
+                    ctx.upstream_path = Some(path.to_string());
+                }
+                Ok(false)
+            }
             HandlerConfig::Respond { status, body, headers } => {
+                let placeholders = placeholder_context(session.req_header(), ctx, None, None);
+                let body = body.as_ref().map(|b| b.render(&placeholders).into_owned()).unwrap_or_default();
+                let headers = render_headers(headers, &placeholders);
                 let mut response = ResponseHeader::build(*status, Some(3)).unwrap();
-                for (k, v) in headers {
+                for (k, v) in &headers {
                     if let (Ok(name), Ok(value)) = (
                         http::header::HeaderName::from_bytes(k.as_bytes()),
                         http::header::HeaderValue::from_str(v.as_str())
@@ -444,99 +769,70 @@ impl PingclairProxy {
                         response.insert_header(name, value).unwrap();
                     }
                 }
-                let body_bytes = body.as_deref().unwrap_or("").as_bytes();
-                response.insert_header("Content-Length", body_bytes.len().to_string()).unwrap();
+                response.insert_header("Content-Length", body.len().to_string()).unwrap();
                 response.insert_header("Server", "Pingclair").unwrap();
                 session.write_response_header(Box::new(response), false).await?;
-                session.write_response_body(Some(Bytes::copy_from_slice(body_bytes)), true).await?;
+                session.write_response_body(Some(Bytes::from(body)), true).await?;
                 Ok(true)
             }
             HandlerConfig::Redirect { to, code } => {
+                let to = to.render(&placeholder_context(session.req_header(), ctx, None, None)).into_owned();
                 let mut response = ResponseHeader::build(*code, Some(3)).unwrap();
                 response.insert_header("Location", to.as_str()).unwrap();
                 response.insert_header("Server", "Pingclair").unwrap();
                 session.write_response_header(Box::new(response), true).await?;
                 Ok(true)
             }
-            HandlerConfig::FileServer { .. } => {
-                let maybe_file_server = {
-                    ctx.state.as_ref().and_then(|state| {
-                        state.file_servers.get(route_index).and_then(|f| f.clone())
-                    })
-                };
+            HandlerConfig::FileServer { root, index, browse, compress } => {
+                // Configured file servers are pre-built; `try_files` roots vary per file
+                let file_server = ctx.state.as_ref()
+                    .and_then(|state| state.file_server(handler))
+                    .unwrap_or_else(|| Arc::new(build_file_server(root, index, *browse, *compress)));
+
+                let range_header = session.req_header().headers.get("Range")
+                    .and_then(|v| v.to_str().ok());
+                let accept_encoding = session.req_header().headers.get("Accept-Encoding")
+                    .and_then(|v| v.to_str().ok());
+
+                let mut served = file_server.serve(path, range_header, accept_encoding).await;
+
+                // Templates render the whole identity-encoded file: refetch
+                // without Range / pre-compressed variants when needed.
+                let templates = ctx.templates.clone();
+                if let (Some(templates), Ok(Some(file))) = (&templates, &served)
+                    && templates.applies_to(&file.mime_type)
+                    && (file.content_encoding.is_some() || file.content_range.is_some())
+                {
+                    served = file_server.serve(path, None, None).await;
+                }
 
-                if let Some(file_server) = maybe_file_server {
-                    let range_header = session.req_header().headers.get("Range")
-                        .and_then(|v| v.to_str().ok());
-                    let accept_encoding = session.req_header().headers.get("Accept-Encoding")
-                        .and_then(|v| v.to_str().ok());
-                    
-                    if let Ok(Some(file)) = file_server.serve(path, range_header, accept_encoding).await {
-                        let mut header = ResponseHeader::build(file.status, Some(3)).unwrap();
-                        header.insert_header("Content-Type", file.mime_type.as_str()).unwrap();
-                        header.insert_header("Content-Length", file.content.len().to_string()).unwrap();
-                        
-                        if let Some(range) = file.content_range {
-                            header.insert_header("Content-Range", range.as_str()).unwrap();
+                if let Ok(Some(file)) = served {
+                    let (mut header, body) = match &templates {
+                        Some(templates) if templates.applies_to(&file.mime_type) => {
+                            render_template_file(templates, session.req_header(), ctx, file).await?
                         }
-                        if let Some(lm) = file.last_modified {
-                            header.insert_header("Last-Modified", lm.as_str()).unwrap();
-                        }
-                        if let Some(etag) = file.etag {
-                            header.insert_header("ETag", etag.as_str()).unwrap();
-                        }
-                        if let Some(encoding) = file.content_encoding {
-                            header.insert_header("Content-Encoding", encoding.as_str()).unwrap();
-                        }
-                        header.insert_header("Accept-Ranges", "bytes").unwrap();
-                        header.insert_header("Server", "Pingclair").unwrap();
-                        
-                        session.write_response_header(Box::new(header), false).await?;
-                        session.write_response_body(Some(Bytes::from(file.content)), true).await?;
-                        return Ok(true);
-                    }
+                        _ => (file_response_header(&file)?, Bytes::from(file.content)),
+                    };
+                    header.insert_header("Server", "Pingclair")?;
+
+                    session.write_response_header(Box::new(header), false).await?;
+                    session.write_response_body(Some(body), true).await?;
+                    return Ok(true);
                 }
                 Ok(false)
             }
-            HandlerConfig::Pipeline(handlers) => {
-                for h in handlers {
-                    if self.handle_config(session, ctx, h, path, route_index).await? {
-                        return Ok(true);
-                    }
-                }
+            HandlerConfig::Templates { .. } => {
+                // Middleware: enable rendering for the response produced later
+                ctx.templates = ctx.state.as_ref()
+                    .and_then(|state| state.templates.get(route_index).cloned().flatten());
                 Ok(false)
             }
-            HandlerConfig::Handle(handlers) => {
-                 for h in handlers {
-                    if self.handle_config(session, ctx, h, path, route_index).await? {
-                        return Ok(true);
-                    }
-                }
-                Ok(false)
+            HandlerConfig::Pipeline(handlers) | HandlerConfig::Handle(handlers) => {
+                self.handle_sequence(session, ctx, handlers, path, route_index, node).await
             }
             HandlerConfig::HandlePath { prefix, handlers } => {
-                let new_path = if path.starts_with(prefix) {
-                    let p = &path[prefix.len()..];
-                     if p.is_empty() {
-                         "/"
-                     } else if !p.starts_with('/') {
-                         // Should ensure leading slash if we want strict path compliance, 
-                         // but Caddy handle_path strips exact prefix.
-                         // Let's assume absolute paths are preferred.
-                         p // Simple strip
-                     } else {
-                         p
-                     }
-                } else {
-                    path
-                };
-                
-                for h in handlers {
-                    if self.handle_config(session, ctx, h, new_path, route_index).await? {
-                        return Ok(true);
-                    }
-                }
-                Ok(false)
+                let new_path = strip_path_prefix(path, prefix);
+                self.handle_sequence(session, ctx, handlers, new_path, route_index, node).await
             }
             HandlerConfig::HandleErrors { .. } => {
                 // Error handlers are configured separately or handled by middleware.
@@ -549,18 +845,34 @@ impl PingclairProxy {
                 Ok(false)
             }
             HandlerConfig::Headers { set, add, remove } => {
-                for (k, v) in set {
-                    ctx.headers_downstream.insert(k.clone(), v.clone());
-                }
-                for (k, v) in add {
-                    ctx.headers_downstream_add.insert(k.clone(), v.clone());
-                }
-                for h in remove {
-                    ctx.headers_remove.push(h.clone());
-                    // If removing "Server", set flag to suppress default
-                    if h.eq_ignore_ascii_case("server") {
-                        ctx.suppress_server_header = true;
+                let placeholders = placeholder_context(session.req_header(), ctx, None, None);
+                let (set, add) = (render_headers(set, &placeholders), render_headers(add, &placeholders));
+                apply_header_ops(ctx, &set, &add, remove);
+                Ok(false)
+            }
+            HandlerConfig::Map(_) | HandlerConfig::Vars { .. } => {
+                let vars = handler_vars(handler, &placeholder_context(session.req_header(), ctx, None, None));
+                ctx.vars.extend(vars);
+                Ok(false)
+            }
+            HandlerConfig::Rewrite { .. } => {
+                let req = session.req_header();
+                let Some(rewritten) = rewrite_path(handler, path, &placeholder_context(req, ctx, None, None)) else {
+                    return Ok(false);
+                };
+                // Keep the original query unless the rewrite supplies one
+                let uri = match req.uri.query() {
+                    Some(query) if !rewritten.contains('?') => format!("{}?{}", rewritten, query),
+                    _ => rewritten,
+                };
+                match uri.parse::<http::Uri>() {
+                    Ok(uri) => {
+                        tracing::debug!("✏️ Rewrite {} → {}", req.uri, uri);
+                        session.req_header_mut().set_uri(uri);
+                        // An explicit rewrite wins over `forward_original`
+                        ctx.original_uri = None;
                     }
+                    Err(e) => tracing::warn!("⚠️ Invalid rewritten URI {:?}: {}", uri, e),
                 }
                 Ok(false)
             }
@@ -598,14 +910,14 @@ impl PingclairProxy {
                 if req_header.method == http::Method::OPTIONS {
                     let mut header = pingora_http::ResponseHeader::build(204, Some(8)).unwrap();
                     header.insert_header("Access-Control-Allow-Origin", &allow_origin).unwrap();
-                    header.insert_header("Access-Control-Allow-Methods", &allowed_methods.join(", ")).unwrap();
-                    header.insert_header("Access-Control-Allow-Headers", &allowed_headers.join(", ")).unwrap();
-                    header.insert_header("Access-Control-Max-Age", &max_age.to_string()).unwrap();
+                    header.insert_header("Access-Control-Allow-Methods", allowed_methods.join(", ")).unwrap();
+                    header.insert_header("Access-Control-Allow-Headers", allowed_headers.join(", ")).unwrap();
+                    header.insert_header("Access-Control-Max-Age", max_age.to_string()).unwrap();
                     if *allow_credentials {
                         header.insert_header("Access-Control-Allow-Credentials", "true").unwrap();
                     }
                     if !exposed_headers.is_empty() {
-                        header.insert_header("Access-Control-Expose-Headers", &exposed_headers.join(", ")).unwrap();
+                        header.insert_header("Access-Control-Expose-Headers", exposed_headers.join(", ")).unwrap();
                     }
                     header.insert_header("Content-Length", "0").unwrap();
                     session.write_response_header(Box::new(header), true).await?;
@@ -651,120 +963,409 @@ impl PingclairProxy {
                             browse: false,
                             compress: true,
                         };
-                        return self.handle_config(session, ctx, &file_handler, &resolved, route_index).await;
+                        return self.handle_config(session, ctx, &file_handler, &resolved, route_index, node).await;
                     }
                 }
                 // No file found — execute fallback
                 if let Some(fb) = fallback {
-                    return self.handle_config(session, ctx, fb, path, route_index).await;
+                    return self.handle_config(session, ctx, fb, path, route_index, node).await;
                 }
                 Ok(false)
             }
             _ => Ok(false),
         }
     }
-}
 
-// MARK: - Caddy Placeholder Resolution
+    /// Run handlers in order until one responds or a `reverse_proxy` is reached
+    #[async_recursion]
+    async fn handle_sequence(
+        &self,
+        session: &mut Session,
+        ctx: &mut RequestContext,
+        handlers: &[HandlerConfig],
+        path: &str,
+        route_index: usize,
+        node: usize,
+    ) -> PingoraResult<bool> {
+        let mut path = path.to_string();
+        for (h, node) in sequence_nodes(handlers, node) {
+            if self.handle_config(session, ctx, h, &path, route_index, node).await? {
+                return Ok(true);
+            }
+            if ctx.proxy_node.is_some() {
+                return Ok(false);
+            }
+            // Later handlers see the rewritten path
+            if matches!(h, HandlerConfig::Rewrite { .. }) {
+                path = session.req_header().uri.path().to_string();
+            }
+        }
+        Ok(false)
+    }
+
+    // MARK: - Response Interception
 
-/// Resolve Caddy-style `{placeholder}` variables in a header value string
-/// using the actual downstream request headers.
-///
-/// Supported placeholders:
-/// - `{http.request.header.Header-Name}` → value of the named request header
-/// - `{host}`                            → request Host header
-/// - `{remote_ip}`                       → client IP (from X-Forwarded-For or peer)
-/// - `{http.request.method}`             → HTTP method
-/// - `{http.request.uri}`                → full URI
-/// - `{http.request.uri.path}`           → URI path only
-///
-/// If a placeholder references a header that doesn't exist, it resolves to
-/// an empty string (matching Caddy's behavior).
-fn resolve_caddy_placeholders(template: &str, req: &RequestHeader) -> String {
-    if !template.contains('{') {
-        // ⚡ OPTIMIZATION: Fast path — no placeholders, return as-is.
-        return template.to_string();
-    }
-
-    let mut result = String::with_capacity(template.len());
-    let mut chars = template.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        if c == '{' {
-            // Collect placeholder name until '}'
-            let mut placeholder = String::new();
-            while let Some(&pc) = chars.peek() {
-                if pc == '}' {
-                    chars.next(); // consume '}'
-                    break;
+    /// Run the first matching `handle_response` block against an upstream response.
+    ///
+    /// 🏗️ ARCHITECTURE: Terminal handlers (respond, redir, file_server) build a
+    /// replacement response: `upstream_response` is swapped in place and the
+    /// replacement body is returned for `upstream_response_body_filter` to emit.
+    /// Blocks with only header handlers mutate the upstream response, which then
+    /// streams through unchanged.
+    ///
+    /// - Parameter request: The downstream request (for Range / Accept-Encoding).
+    /// - Parameter upstream_response: The upstream response header, replaced on interception.
+    /// - Returns: The replacement body, or `None` to pass the upstream body through.
+    async fn intercept_response(
+        &self,
+        request: &RequestHeader,
+        ctx: &mut RequestContext,
+        upstream_response: &mut ResponseHeader,
+    ) -> PingoraResult<Option<Bytes>> {
+        let handlers = match (&ctx.state, ctx.proxy_node) {
+            (Some(state), Some(node)) => match state.proxies.get(&node) {
+                Some(proxy) if !proxy.response_handlers.is_empty() => proxy.response_handlers.clone(),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        let Some(block) = handlers.iter().find(|h| h.matches(upstream_response)) else {
+            return Ok(None);
+        };
+
+        let request = ResponseRequestInfo::new(ctx.request_path.clone(), request, upstream_response.status.as_u16());
+
+        for handler in &block.config.handlers {
+            if let Some((mut header, body)) = self.render_response_handler(ctx, handler, &request, &block.file_servers).await? {
+                for name in &block.config.copy_headers {
+                    if let Some(value) = upstream_response.headers.get(name.as_str()) {
+                        header.insert_header(name.clone(), value.clone())?;
+                    }
                 }
-                placeholder.push(chars.next().unwrap());
+                tracing::debug!(
+                    "↪️ handle_response replaced upstream {} with {}",
+                    upstream_response.status, header.status
+                );
+                *upstream_response = header;
+                return Ok(Some(body));
             }
-
-            // Resolve the placeholder
-            let resolved = resolve_single_placeholder(&placeholder, req);
-            result.push_str(&resolved);
-        } else {
-            result.push(c);
         }
+
+        Ok(None)
     }
 
-    result
-}
+    /// Serve an `X-Accel-Redirect` target in place of the upstream response.
+    ///
+    /// 🏗️ ARCHITECTURE: The target URI is re-dispatched through the same
+    /// server's router with internal routes allowed, then rendered like a
+    /// `handle_response` handler (typically `file_server`, with Range support).
+    /// Unmatched targets produce a 404, as nginx does.
+    ///
+    /// - Parameter request: The downstream request (method, headers, Range).
+    /// - Parameter upstream_response: The upstream response header, replaced in place.
+    /// - Parameter uri: The `X-Accel-Redirect` target.
+    /// - Returns: The replacement body.
+    async fn accel_redirect(
+        &self,
+        request: &RequestHeader,
+        ctx: &mut RequestContext,
+        upstream_response: &mut ResponseHeader,
+        uri: &str,
+    ) -> PingoraResult<Bytes> {
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+
+        let handler = ctx.state.as_ref().and_then(|state| {
+            state.router
+                .match_internal_request(&RequestInfo {
+                    path,
+                    query,
+                    method: request.method.as_str(),
+                    headers: &request.headers,
+                    host: &ctx.request_host,
+                    remote_ip: &ctx.remote_ip,
+                    client_ip: &ctx.client_ip,
+                    protocol: &ctx.protocol,
+                    vars: Some(&ctx.vars),
+                })
+                .and_then(|matched| state.config.routes.get(matched.route.index))
+                .map(|route| route.handler.clone())
+        });
+
+        let mut info = ResponseRequestInfo::new(path.to_string(), request, upstream_response.status.as_u16());
+        info.query = query.to_string();
+        let rendered = match handler {
+            // Route handlers: their file servers are in the state
+            Some(handler) => self.render_response_handler(ctx, &handler, &info, &HashMap::new()).await?,
+            None => None,
+        };
 
-/// Resolve a single Caddy placeholder name to its value.
-fn resolve_single_placeholder(name: &str, req: &RequestHeader) -> String {
-    // {http.request.header.Header-Name}
-    if let Some(header_name) = name.strip_prefix("http.request.header.") {
-        return req.headers
-            .get(header_name)
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("")
-            .to_string();
-    }
+        let (mut header, body) = match rendered {
+            Some(rendered) => rendered,
+            None => {
+                tracing::warn!("⚠️ X-Accel-Redirect target not found: {}", uri);
+                let mut header = ResponseHeader::build(404, Some(1))?;
+                header.insert_header("Content-Length", "0")?;
+                (header, Bytes::new())
+            }
+        };
 
-    // Common shortcuts
-    match name {
-        "host" => {
-            req.headers
-                .get("host")
-                .and_then(|v| v.to_str().ok())
-                .unwrap_or("")
-                .to_string()
-        }
-        "http.request.host" => {
-            req.headers
-                .get("host")
-                .and_then(|v| v.to_str().ok())
-                .unwrap_or("")
-                .to_string()
+        // nginx carries a few upstream headers over to the redirected response
+        for name in accel::REDIRECT_PRESERVED_HEADERS {
+            let values: Vec<_> = upstream_response.headers.get_all(name).iter().cloned().collect();
+            if values.is_empty() {
+                continue;
+            }
+            let _ = header.remove_header(name);
+            for value in values {
+                header.append_header(name, value)?;
+            }
         }
-        "remote_ip" | "http.request.remote.host" => {
-            // Best effort: try X-Forwarded-For, then X-Real-IP, then empty
-            req.headers
-                .get("x-forwarded-for")
-                .or_else(|| req.headers.get("x-real-ip"))
-                .and_then(|v| v.to_str().ok())
-                .unwrap_or("")
-                .to_string()
+
+        tracing::debug!("↪️ X-Accel-Redirect {} → {}", uri, header.status);
+        *upstream_response = header;
+        Ok(body)
+    }
+
+    /// Apply `X-Accel-*` directives from an upstream response.
+    ///
+    /// - Parameter request: The downstream request.
+    /// - Parameter upstream_response: The upstream response header; directives are stripped.
+    /// - Returns: The replacement body when `X-Accel-Redirect` was followed.
+    async fn apply_accel_directives(
+        &self,
+        request: &RequestHeader,
+        ctx: &mut RequestContext,
+        upstream_response: &mut ResponseHeader,
+    ) -> PingoraResult<Option<Bytes>> {
+        let directives = AccelDirectives::from_headers(&upstream_response.headers);
+        if directives.is_empty() {
+            return Ok(None);
         }
-        "http.request.method" => {
-            req.method.as_str().to_string()
+        for name in accel::ACCEL_HEADERS {
+            let _ = upstream_response.remove_header(name);
         }
-        "http.request.uri" => {
-            req.uri.to_string()
+
+        // Compression holds the whole body until the end of the stream, which
+        // `X-Accel-Buffering: no` opts out of and a rate limit cannot pace
+        if directives.buffering == Some(false) || directives.limit_rate.is_some() {
+            ctx.compress_response = false;
         }
-        "http.request.uri.path" => {
-            req.uri.path().to_string()
+        ctx.limit_rate = directives.limit_rate;
+
+        let body = match &directives.redirect {
+            Some(uri) => Some(self.accel_redirect(request, ctx, upstream_response, uri).await?),
+            None => None,
+        };
+
+        if let Some((cache_control, expires)) = directives.expires
+            .and_then(|e| e.cache_headers(std::time::SystemTime::now()))
+        {
+            upstream_response.insert_header("Cache-Control", cache_control)?;
+            match expires {
+                Some(expires) => upstream_response.insert_header("Expires", expires)?,
+                None => { let _ = upstream_response.remove_header("Expires"); }
+            }
         }
-        _ => {
-            tracing::debug!("⚠️ Unresolved Caddy placeholder: {{{}}}", name);
-            String::new()
+
+        Ok(body)
+    }
+
+    /// Render a handler into a standalone response for `handle_response`.
+    ///
+    /// Returns `None` for non-terminal handlers (header mutations are recorded
+    /// on the context and applied later in `response_filter`).
+    ///
+    /// - Parameter file_servers: The `handle_response` block's pre-built file servers.
+    #[async_recursion]
+    async fn render_response_handler(
+        &self,
+        ctx: &mut RequestContext,
+        handler: &HandlerConfig,
+        request: &ResponseRequestInfo<'_>,
+        file_servers: &HashMap<FileServerKey, Arc<pingclair_static::FileServer>>,
+    ) -> PingoraResult<Option<(ResponseHeader, Bytes)>> {
+        match handler {
+            HandlerConfig::Respond { status, body, headers } => {
+                let placeholders = request.placeholders(ctx);
+                let body = Bytes::from(body.as_ref().map(|b| b.render(&placeholders).into_owned()).unwrap_or_default());
+                let headers = render_headers(headers, &placeholders);
+                let mut response = ResponseHeader::build(*status, Some(headers.len() + 1))?;
+                for (k, v) in &headers {
+                    if let (Ok(name), Ok(value)) = (
+                        http::header::HeaderName::from_bytes(k.as_bytes()),
+                        http::header::HeaderValue::from_str(v.as_str())
+                    ) {
+                        response.insert_header(name, value)?;
+                    }
+                }
+                response.insert_header("Content-Length", body.len().to_string())?;
+                Ok(Some((response, body)))
+            }
+            HandlerConfig::Redirect { to, code } => {
+                let mut response = ResponseHeader::build(*code, Some(2))?;
+                response.insert_header("Location", to.render(&request.placeholders(ctx)).as_ref())?;
+                response.insert_header("Content-Length", "0")?;
+                Ok(Some((response, Bytes::new())))
+            }
+            HandlerConfig::FileServer { root, index, browse, compress } => {
+                // Pre-built with the `handle_response` block, or with the routes
+                let file_server = FileServerKey::of(handler)
+                    .and_then(|key| file_servers.get(&key).cloned())
+                    .or_else(|| ctx.state.as_ref().and_then(|state| state.file_server(handler)))
+                    .unwrap_or_else(|| Arc::new(build_file_server(root, index, *browse, *compress)));
+                match file_server.serve(&request.path, request.range.as_deref(), request.accept_encoding.as_deref()).await {
+                    Ok(Some(file)) => {
+                        let header = file_response_header(&file)?;
+                        Ok(Some((header, Bytes::from(file.content))))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            HandlerConfig::Headers { set, add, remove } => {
+                let placeholders = request.placeholders(ctx);
+                let (set, add) = (render_headers(set, &placeholders), render_headers(add, &placeholders));
+                apply_header_ops(ctx, &set, &add, remove);
+                Ok(None)
+            }
+            HandlerConfig::Map(_) | HandlerConfig::Vars { .. } => {
+                let vars = handler_vars(handler, &request.placeholders(ctx));
+                ctx.vars.extend(vars);
+                Ok(None)
+            }
+            HandlerConfig::Pipeline(handlers) | HandlerConfig::Handle(handlers) => {
+                for h in handlers {
+                    if let Some(rendered) = self.render_response_handler(ctx, h, request, file_servers).await? {
+                        return Ok(Some(rendered));
+                    }
+                }
+                Ok(None)
+            }
+            HandlerConfig::HandlePath { prefix, handlers } => {
+                let request = ResponseRequestInfo {
+                    path: strip_path_prefix(&request.path, prefix).to_string(),
+                    query: request.query.clone(),
+                    range: request.range.clone(),
+                    accept_encoding: request.accept_encoding.clone(),
+                    header: request.header,
+                    status: request.status,
+                };
+                for h in handlers {
+                    if let Some(rendered) = self.render_response_handler(ctx, h, &request, file_servers).await? {
+                        return Ok(Some(rendered));
+                    }
+                }
+                Ok(None)
+            }
+            _ => {
+                tracing::warn!("⚠️ Handler not supported for a replacement response: {:?}", handler);
+                Ok(None)
+            }
         }
     }
 }
 
-// MARK: - ProxyHttp Trait
+/// Downstream request details needed to render a `handle_response` handler
+struct ResponseRequestInfo<'a> {
+    path: String,
+    /// Raw query string (`{query}`), the `X-Accel-Redirect` target's when following one
+    query: String,
+    range: Option<String>,
+    accept_encoding: Option<String>,
+    /// The downstream request (placeholders)
+    header: &'a RequestHeader,
+    /// Upstream response status (`{status}`)
+    status: u16,
+}
+
+impl<'a> ResponseRequestInfo<'a> {
+    fn new(path: String, request: &'a RequestHeader, status: u16) -> Self {
+        let header = |name: &str| request.headers.get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        Self {
+            path,
+            query: request.uri.query().unwrap_or("").to_string(),
+            range: header("Range"),
+            accept_encoding: header("Accept-Encoding"),
+            header: request,
+            status,
+        }
+    }
+
+    fn placeholders<'c>(&'c self, ctx: &'c RequestContext) -> PlaceholderContext<'c> {
+        PlaceholderContext {
+            path: &self.path,
+            query: &self.query,
+            status: Some(self.status),
+            ..placeholder_context(self.header, ctx, None, None)
+        }
+    }
+}
+
+// MARK: - Placeholders
+
+/// Placeholder context of a downstream request.
+///
+/// - Parameter upstream: The selected upstream address (`{upstream.address}`), once known.
+/// - Parameter response: The response status and headers (`{status}`, `{http.response.header.*}`), once known.
+fn placeholder_context<'a>(
+    req: &'a RequestHeader,
+    ctx: &'a RequestContext,
+    upstream: Option<&'a str>,
+    response: Option<&'a ResponseHeader>,
+) -> PlaceholderContext<'a> {
+    let proxy_protocol = ctx.proxy_protocol.as_ref().and_then(|c| c.header.as_ref()).map(|header| {
+        let tls = header.tls.as_ref();
+        ProxyProtocolValues {
+            alpn: header.alpn.as_deref(),
+            authority: header.authority.as_deref(),
+            tls_version: tls.and_then(|t| t.version.as_deref()),
+            tls_cipher: tls.and_then(|t| t.cipher.as_deref()),
+            tls_client_cn: tls.and_then(|t| t.client_cn.as_deref()),
+        }
+    });
+    PlaceholderContext {
+        method: req.method.as_str(),
+        host: &ctx.request_host,
+        path: req.uri.path(),
+        query: req.uri.query().unwrap_or(""),
+        scheme: &ctx.protocol,
+        proto: match req.version {
+            http::Version::HTTP_09 => "HTTP/0.9",
+            http::Version::HTTP_10 => "HTTP/1.0",
+            http::Version::HTTP_2 => "HTTP/2.0",
+            http::Version::HTTP_3 => "HTTP/3.0",
+            _ => "HTTP/1.1",
+        },
+        remote_ip: &ctx.remote_ip,
+        remote_port: ctx.remote_port,
+        client_ip: &ctx.client_ip,
+        request_id: &ctx.request_id,
+        headers: Some(&req.headers),
+        tls_version: ctx.tls.as_ref().map(|t| t.version.as_str()),
+        tls_cipher: ctx.tls.as_ref().map(|t| t.cipher.as_str()),
+        proxy_protocol,
+        captures: Some(&ctx.captures),
+        vars: Some(&ctx.vars),
+        upstream,
+        status: response.map(|r| r.status.as_u16()),
+        response_headers: response.map(|r| &r.headers),
+        ..Default::default()
+    }
+}
+
+/// Render header templates against a request
+fn render_headers(
+    templates: &HashMap<String, Template>,
+    placeholders: &PlaceholderContext,
+) -> HashMap<String, String> {
+    templates.iter()
+        .map(|(name, template)| (name.clone(), template.render(placeholders).into_owned()))
+        .collect()
+}
+
+// MARK: - ProxyHttp Trait
 
 #[async_trait]
 impl ProxyHttp for PingclairProxy {
@@ -807,69 +1408,103 @@ impl ProxyHttp for PingclairProxy {
             }
         }
 
+        // Extract host: lowercase, port and trailing dot stripped, punycode
+        let host = {
+            let host_raw = session.req_header().headers.get("Host")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("");
+            normalize_host(host_raw).into_owned()
+        };
+
+        // Get state for this host
+        let state = match self.get_state(&host) {
+            Some(s) => s,
+            None => return Ok(false), // No virtual host found
+        };
+        ctx.state = Some(state.clone());
+
+        // 🛑 SAFETY: Normalise the path before anything routes on it, so
+        // matchers, handlers and the file server agree with the backend
+        if !self.normalize_request_path(session, ctx, &state).await? {
+            return Ok(true);
+        }
+
         // Match route in a scope to release borrow of session
-        let (path_str, route_index, handler, remote_ip, request_host, request_method) = {
+        let (path_str, route_index, handler, remote_ip, client_ip, request_host, request_method) = {
             let request_header = session.req_header();
             let path = request_header.uri.path();
             let method = request_header.method.as_str();
-            
-            // Extract host and strip port
-            let host_raw = request_header.headers.get("Host")
-                .and_then(|v| v.to_str().ok())
-                .unwrap_or("");
-            let host = host_raw.split(':').next().unwrap_or("");
+            let host = host.as_str();
+
+            // Direct peer, and the real client behind trusted proxies
+            ctx.proxy_protocol = session.client_addr().and_then(proxy_protocol::lookup);
+            let peer = peer_ip(session);
+            let remote_ip = peer.to_string();
+            let client_ip = self.settings().client_ip(peer, &request_header.headers).to_string();
+            ctx.remote_port = session.client_addr().and_then(|addr| addr.as_inet()).map(|inet| inet.port());
+
+            // Correlation: reuse the caller's request ID only from trusted proxies
+            // (before `map` / `vars`, which may read `{http.request.uuid}`)
+            let settings = self.settings();
+            if settings.is_trusted(&remote_ip)
+                && let Some(id) = request_header.headers.get(settings.request_id_header.as_str())
+                    .and_then(|v| v.to_str().ok())
+                    .filter(|id| trace::is_valid_request_id(id))
+            {
+                ctx.request_id = id.to_string();
+            }
                 
-            // Get state for this host
-            let state = match self.get_state(host) {
-                Some(s) => s,
-                None => return Ok(false), // No virtual host found
-            };
-            ctx.state = Some(state.clone());
-
-            // Extract remote IP
-            let remote_ip = session.client_addr()
-                .map(|addr| match addr {
-                    pingora_core::protocols::l4::socket::SocketAddr::Inet(inet) => inet.ip().to_string(),
-                    pingora_core::protocols::l4::socket::SocketAddr::Unix(_) => "127.0.0.1".to_string(),
-                })
-                .unwrap_or_else(|| "0.0.0.0".to_string());
+            // 🛑 SAFETY: The scheme comes from the connection, never from what a
+            // client claims. HTTPS when:
+            //   (a) this listener terminated TLS (negotiated parameters in the digest),
+            //   (b) a PROXY protocol sender terminated TLS (v2 SSL TLV), or
+            //   (c) a trusted proxy says so via X-Forwarded-Proto.
+            ctx.tls = tls_info(session);
+            let via_proxy_tls = ctx.proxy_protocol.as_ref()
+                .and_then(|c| c.header.as_ref())
+                .is_some_and(|h| h.tls.is_some());
+            let protocol = request_scheme(
+                ctx.tls.is_some() || via_proxy_tls,
+                self.settings().is_trusted(&remote_ip),
+                &request_header.headers,
+            );
                 
-            // ⚡ OPTIMIZATION: Identify protocol via port heuristic and X-Forwarded-Proto.
-            // Pingora 0.6 removed the per-request TLS flag; we detect HTTPS by:
-            //   (a) checking the X-Forwarded-Proto header (set by our upstream_request_filter), or
-            //   (b) checking whether the local port is 443 / 8443 as a fallback.
-            let protocol = {
-                let via_header = request_header.headers
-                    .get("x-forwarded-proto")
-                    .and_then(|v| v.to_str().ok())
-                    .unwrap_or("");
-                if via_header == "https" {
-                    "https"
-                } else {
-                    // Fallback: infer from the Host header port or the server listen config.
-                    let host_header = request_header.headers
-                        .get("Host")
-                        .and_then(|v| v.to_str().ok())
-                        .unwrap_or("");
-                    let port_in_host = host_header
-                        .split(':')
-                        .nth(1)
-                        .and_then(|p| p.parse::<u16>().ok())
-                        .unwrap_or(80);
-                    if port_in_host == 443 || port_in_host == 8443 {
-                        "https"
-                    } else {
-                        "http"
-                    }
-                }
+            ctx.protocol = protocol.to_string();
+            let query = request_header.uri.query().unwrap_or("");
+
+            // Site-wide `map` / `vars` run before routing, so matchers can use them
+            if !state.config.variables.is_empty() {
+                let placeholders = PlaceholderContext {
+                    host,
+                    path,
+                    query,
+                    remote_ip: &remote_ip,
+                    client_ip: &client_ip,
+                    ..placeholder_context(request_header, ctx, None, None)
+                };
+                let mut vars = HashMap::new();
+                apply_vars(&state.config.variables, &placeholders, &mut vars);
+                ctx.vars = vars;
+            }
+
+            let request = RequestInfo {
+                path,
+                query,
+                method,
+                headers: &request_header.headers,
+                host,
+                remote_ip: &remote_ip,
+                client_ip: &client_ip,
+                protocol,
+                vars: Some(&ctx.vars),
             };
-                
-            if let Some(route) = state.router.match_request(path, method, &request_header.headers, host, &remote_ip, protocol) {
-                let index = route.index;
+            if let Some(matched) = state.router.match_request(&request) {
+                let index = matched.route.index;
                 let handler = state.config.routes.get(index).map(|r| r.handler.clone());
-                (path.to_string(), Some(index), handler, remote_ip, host.to_string(), method.to_string())
+                ctx.captures = matched.captures;
+                (path.to_string(), Some(index), handler, remote_ip, client_ip, host.to_string(), method.to_string())
             } else {
-                (path.to_string(), None, None, remote_ip, host.to_string(), method.to_string())
+                (path.to_string(), None, None, remote_ip, client_ip, host.to_string(), method.to_string())
             }
         };
 
@@ -877,6 +1512,23 @@ impl ProxyHttp for PingclairProxy {
         ctx.request_path = path_str.clone();
         ctx.request_host = request_host;
         ctx.request_method = request_method;
+        ctx.remote_ip = remote_ip.clone();
+        ctx.client_ip = client_ip.clone();
+        metrics::REQUESTS_IN_FLIGHT.with_label_values(&[&ctx.request_host]).inc();
+        ctx.in_flight = true;
+
+        // Correlation: continue (or start) the W3C trace with a child span
+        {
+            let settings = self.settings();
+            let headers = &session.req_header().headers;
+            ctx.trace = TraceContext::from_headers(headers);
+
+            if let Some(exporter) = &settings.exporter
+                && exporter.sample(&mut ctx.trace)
+            {
+                ctx.spans = Some(SpanRecorder::default());
+            }
+        }
 
         // Detect Accept-Encoding for response compression
         {
@@ -888,15 +1540,13 @@ impl ProxyHttp for PingclairProxy {
         }
 
         // Check if server has compression enabled
-        if ctx.client_accepts_gzip {
-            if let Some(state) = &ctx.state {
-                // Check for compress config in the server routes (encode gzip)
-                // The compress list is not in ServerConfig directly, but
-                // we enable compression if the server has any compress algos
-                // For now, enable for all proxied responses if client supports it
-                // This matches Caddy's `encode gzip` behavior.
-                ctx.compress_response = true;
-            }
+        if ctx.client_accepts_gzip && ctx.state.is_some() {
+            // Check for compress config in the server routes (encode gzip)
+            // The compress list is not in ServerConfig directly, but
+            // we enable compression if the server has any compress algos
+            // For now, enable for all proxied responses if client supports it
+            // This matches Caddy's `encode gzip` behavior.
+            ctx.compress_response = true;
         }
 
         // Check request body size (Content-Length)
@@ -925,12 +1575,18 @@ impl ProxyHttp for PingclairProxy {
             if let Some(state) = &ctx.state {
                  if let Some(limiter) = state.rate_limiters.get(index).and_then(|l| l.as_ref()) {
                       let key = if limiter.config.by_ip {
-                           Some(remote_ip.as_str())
+                           Some(client_ip.as_str())
                       } else {
                            None
                       };
                       
-                      if let Err(info) = limiter.check(key) {
+                      let checked = limiter.check(key);
+                      ctx.rate_limited = Some(checked.is_err());
+                      if let Err(info) = checked {
+                           let route = state.config.routes.get(index).map(|r| r.path.as_str()).unwrap_or("");
+                           metrics::RATE_LIMITED_TOTAL
+                               .with_label_values(&[&ctx.request_host, metrics::route_label(route)])
+                               .inc();
                            let mut header = pingora_http::ResponseHeader::build(429, Some(4)).unwrap();
                            for (k, v) in info.to_headers() {
                                if let Ok(val) = http::header::HeaderValue::from_str(&v) {
@@ -947,7 +1603,15 @@ impl ProxyHttp for PingclairProxy {
             }
             
             if let Some(h) = handler {
-                if self.handle_config(session, ctx, &h, &path_str, index).await? {
+                let started = std::time::SystemTime::now();
+                let handled = self.handle_config(session, ctx, &h, &path_str, index, 0).await?;
+                if let Some(spans) = ctx.spans.as_mut() {
+                    let span = SpanData::child(&ctx.trace, "handler", SpanKind::Internal, started, std::time::SystemTime::now())
+                        .attr("pingclair.handler", handler_kind(&h))
+                        .attr("pingclair.handled", handled);
+                    spans.children.push(span);
+                }
+                if handled {
                     return Ok(true);
                 }
             }
@@ -965,23 +1629,15 @@ impl ProxyHttp for PingclairProxy {
     where
         Self::CTX: Send + Sync,
     {
-         // Route should be matched in request_filter
-         
-         let route_index = if let Some(index) = ctx.route_index {
-             index
+         // The reverse proxy node is recorded by handle_config in request_filter
+         let proxy_node = if let Some(node) = ctx.proxy_node {
+             node
          } else {
              return Err(pingora_core::Error::new(pingora_core::ErrorType::ConnectNoRoute));
          };
          
          // Get client IP for IP-hash load balancing
-        let client_ip = session.client_addr()
-             .map(|addr| match addr {
-                 pingora_core::protocols::l4::socket::SocketAddr::Inet(inet) => match inet {
-                     std::net::SocketAddr::V4(v4) => v4.ip().octets().to_vec(),
-                     std::net::SocketAddr::V6(v6) => v6.ip().octets().to_vec(),
-                 },
-                 pingora_core::protocols::l4::socket::SocketAddr::Unix(_) => vec![], 
-             });
+        let client_ip = ctx.client_ip.parse::<std::net::IpAddr>().ok().map(ip_octets);
 
         // 🛑 SAFETY: state must have been set by request_filter. If it wasn't
         // (e.g. no virtual host matched), fail gracefully instead of panic.
@@ -992,18 +1648,33 @@ impl ProxyHttp for PingclairProxy {
                 return Err(pingora_core::Error::new(pingora_core::ErrorType::ConnectNoRoute));
             }
         };
-        if let Some(upstream) = self.select_upstream(state, route_index, client_ip.as_deref()) {
+        // A previously selected upstream means the last attempt failed and is being retried
+        if let Some(previous) = &ctx.upstream {
+            metrics::UPSTREAM_RETRIES_TOTAL.with_label_values(&[&previous.addr.to_string()]).inc();
+        }
+        let selected = match state.proxies.get(&proxy_node) {
+            Some(proxy) => {
+                let placeholders = placeholder_context(session.req_header(), ctx, None, None);
+                proxy.select(&placeholders, client_ip.as_deref()).await
+            }
+            None => None,
+        };
+        if let Some(upstream) = selected {
             ctx.upstream = Some(upstream.clone()); // Backend is light to clone
 
             // Get proxy config for headers and timeouts
             let mut read_timeout_ms = None;
             let mut write_timeout_ms = None;
+            let mut proxy_protocol_version = None;
 
-            if let Some(proxy_config) = self.get_proxy_config(state, route_index) {
+            if let Some(proxy_config) = self.get_proxy_config(state, proxy_node) {
                 ctx.headers_upstream = proxy_config.headers_up.clone();
-                ctx.headers_downstream = proxy_config.headers_down.clone();
+                ctx.headers_upstream_remove = proxy_config.headers_up_remove.clone();
+                ctx.forwarded_headers = proxy_config.forwarded_headers;
+                ctx.headers_down = proxy_config.headers_down.clone();
                 read_timeout_ms = proxy_config.read_timeout;
                 write_timeout_ms = proxy_config.write_timeout;
+                proxy_protocol_version = proxy_config.proxy_protocol;
             }
 
             // Parse and create peer
@@ -1036,12 +1707,27 @@ impl ProxyHttp for PingclairProxy {
                     }
                 }
 
+                // Announce the original client to upstreams that require PROXY headers
+                if let Some(version) = proxy_protocol_version {
+                    // Relayed connections: Pingora's own addresses are the loopback relay's
+                    let source = ctx.proxy_protocol.as_ref().map(|c| c.client())
+                        .or_else(|| session.client_addr().and_then(|a| a.as_inet().copied()));
+                    let destination = ctx.proxy_protocol.as_ref().map(|c| c.destination())
+                        .or_else(|| session.server_addr().and_then(|a| a.as_inet().copied()));
+                    ProxyProtocolConnector::apply(&mut peer, version, source, destination);
+                }
+
                 // Set default connection timeout (10 seconds) if not configured
                 if peer.options.connection_timeout.is_none() {
                     peer.options.connection_timeout = Some(std::time::Duration::from_secs(10));
                     tracing::debug!("⏱️ Applied default connection timeout: 10s for {}", host);
                 }
 
+                if let Some(spans) = ctx.spans.as_mut() {
+                    spans.upstream_selected_at = Some(std::time::SystemTime::now());
+                }
+                ctx.upstream_timing.start();
+
                 return Ok(Box::new(peer));
         }
         
@@ -1066,40 +1752,185 @@ impl ProxyHttp for PingclairProxy {
         Self::CTX: Send + Sync,
     {
         let downstream_headers = session.req_header();
+        let settings = self.settings();
 
-        // Add configured upstream headers with variable resolution
-        for (key, value_template) in &ctx.headers_upstream {
-            let resolved = resolve_caddy_placeholders(value_template, downstream_headers);
-            upstream_request.insert_header(key.clone(), resolved.as_str())?;
+        // `forward_original`: routing used the normalised path, the backend gets it as received.
+        // Kept on the context, as Pingora runs this filter again for each retry.
+        if let Some(uri) = &ctx.original_uri {
+            upstream_request.set_uri(uri.clone());
         }
 
-        // Add standard proxy headers (only if not already configured by user)
-        if !ctx.headers_upstream.contains_key("X-Forwarded-Proto") {
-            upstream_request.insert_header("X-Forwarded-Proto", "https")?;
+        // `handle_path` stripped a prefix: an explicit strip wins over `forward_original`
+        if let Some(path) = &ctx.upstream_path {
+            match with_path(&downstream_headers.uri, path) {
+                Some(uri) => upstream_request.set_uri(uri),
+                None => tracing::warn!("⚠️ Invalid stripped path {:?}", path),
+            }
+        }
+
+        // Connection-scoped headers never cross the proxy
+        for name in forwarded::hop_by_hop(&downstream_headers.headers) {
+            let _ = upstream_request.remove_header(name.as_str());
+        }
+
+        // Standard forwarding headers (a matching `header_up` takes precedence)
+        let fwd = Forwarding {
+            peer: peer_ip(session),
+            trusted: settings.is_trusted(&ctx.remote_ip),
+            proto: ctx.protocol.as_str(),
+            host: ctx.request_host.as_str(),
+        };
+        for (name, value) in forwarded::forwarded_headers(&ctx.forwarded_headers, &downstream_headers.headers, &fwd) {
+            if !ctx.headers_upstream.keys().any(|key| key.eq_ignore_ascii_case(name)) {
+                upstream_request.insert_header(name, value.as_str())?;
+            }
+        }
+
+        // Add configured upstream headers with placeholders rendered
+        let upstream_addr = ctx.upstream.as_ref().map(|u| u.addr.to_string());
+        let placeholders = placeholder_context(downstream_headers, ctx, upstream_addr.as_deref(), None);
+        for (key, template) in &ctx.headers_upstream {
+            upstream_request.insert_header(key.clone(), template.render(&placeholders).as_ref())?;
+        }
+
+        // Propagate correlation headers (Pingclair's span becomes the parent)
+        upstream_request.insert_header(settings.request_id_header.clone(), ctx.request_id.as_str())?;
+        upstream_request.insert_header(trace::TRACEPARENT, ctx.trace.traceparent())?;
+        match &ctx.trace.tracestate {
+            Some(state) => upstream_request.insert_header(trace::TRACESTATE, state.as_str())?,
+            None => { let _ = upstream_request.remove_header(trace::TRACESTATE); }
+        }
+
+        // Templates need an identity-encoded body to render
+        if ctx.templates.is_some() {
+            let _ = upstream_request.remove_header("Accept-Encoding");
+        }
+
+        // `header_up -Name` removals apply last, including to the defaults above
+        for name in &ctx.headers_upstream_remove {
+            let _ = upstream_request.remove_header(name.as_str());
         }
 
         Ok(())
     }
     
+    /// Count request body bytes received from the client (access log `bytes_read`)
+    async fn request_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        _end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> pingora_core::Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        if let Some(chunk) = body.as_ref() {
+            ctx.request_bytes += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Called once a connection to the upstream is ready (new or reused).
+    ///
+    /// Records the upstream connect span and, for new TLS connections, the
+    /// upstream TLS handshake span.
+    ///
+    /// ⚠️ Downstream handshakes precede the request and are shared by every
+    /// keep-alive request on the connection, so they are not recorded per span.
+    async fn connected_to_upstream(
+        &self,
+        _session: &mut Session,
+        reused: bool,
+        _peer: &HttpPeer,
+        #[cfg(unix)] _fd: std::os::unix::io::RawFd,
+        #[cfg(windows)] _sock: std::os::windows::io::RawSocket,
+        digest: Option<&pingora_core::protocols::Digest>,
+        ctx: &mut Self::CTX,
+    ) -> pingora_core::Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        ctx.upstream_timing.connected();
+
+        let Some(spans) = ctx.spans.as_mut() else {
+            return Ok(());
+        };
+
+        let now = std::time::SystemTime::now();
+        let upstream_addr = ctx.upstream.as_ref().map(|u| u.addr.to_string()).unwrap_or_default();
+        if let Some(selected_at) = spans.upstream_selected_at.take() {
+            let span = SpanData::child(&ctx.trace, "upstream connect", SpanKind::Client, selected_at, now)
+                .attr("server.address", upstream_addr.clone())
+                .attr("pingclair.connection.reused", reused);
+            spans.children.push(span);
+        }
+
+        if !reused && let Some(digest) = digest {
+            let established: Vec<_> = digest.timing_digest.iter()
+                .map(|timing| timing.as_ref().map(|t| t.established_ts))
+                .collect();
+            if let Some((start, end)) = crate::otel::tls_handshake_window(&established) {
+                let span = SpanData::child(&ctx.trace, "tls handshake", SpanKind::Client, start, end)
+                    .attr("server.address", upstream_addr);
+                spans.children.push(span);
+            }
+        }
+
+        spans.upstream_connected_at = Some(now);
+        Ok(())
+    }
+
     /// Called before sending response to client
     ///
     /// 🏗️ ARCHITECTURE: Full response header processing pipeline:
+    ///   0. Replace the response if a `handle_response` block matches or the
+    ///      upstream sent `X-Accel-Redirect`; apply other `X-Accel-*` controls
     ///   1. Set downstream headers (from header directive)
     ///   2. Add downstream headers (append, from header +Key directive)
     ///   3. Remove headers (from header -Key directive)
     ///   4. Conditionally suppress Server header
     ///   5. Apply security headers
-    ///   6. Setup gzip compression if client supports it
-    ///   7. Add request ID header
+    ///   6. Buffer the body for `templates` rendering
+    ///   7. Setup gzip compression if client supports it
+    ///   8. Add request ID header
     async fn response_filter(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         upstream_response: &mut ResponseHeader,
         ctx: &mut Self::CTX,
     ) -> pingora_core::Result<()>
     where
         Self::CTX: Send + Sync,
     {
+        ctx.upstream_timing.headers_received();
+
+        // Cache hit/miss as reported by the upstream (CDN or caching layer)
+        if let Some(result) = upstream_response.headers.get("cache-status")
+            .or_else(|| upstream_response.headers.get("x-cache"))
+            .and_then(|v| v.to_str().ok())
+            .and_then(metrics::cache_result)
+        {
+            metrics::CACHE_REQUESTS_TOTAL.with_label_values(&[&ctx.request_host, result]).inc();
+        }
+
+        // Upstream response span: connection ready → response headers received
+        if let Some(spans) = ctx.spans.as_mut()
+            && let Some(connected_at) = spans.upstream_connected_at.take()
+        {
+            let span = SpanData::child(&ctx.trace, "upstream response", SpanKind::Client, connected_at, std::time::SystemTime::now())
+                .attr("server.address", ctx.upstream.as_ref().map(|u| u.addr.to_string()).unwrap_or_default())
+                .attr("http.response.status_code", upstream_response.status.as_u16() as i64);
+            spans.children.push(span);
+        }
+
+        // 0. Intercept the upstream response (handle_response, then X-Accel-*)
+        if let Some(body) = self.intercept_response(session.req_header(), ctx, upstream_response).await? {
+            ctx.replacement_body = Some(body);
+        } else if let Some(body) = self.apply_accel_directives(session.req_header(), ctx, upstream_response).await? {
+            ctx.replacement_body = Some(body);
+        }
+
         // Capture response status for access log
         ctx.response_status = upstream_response.status.as_u16();
 
@@ -1113,6 +1944,16 @@ impl ProxyHttp for PingclairProxy {
             upstream_response.append_header(key.clone(), value.as_str())?;
         }
 
+        // `header_down` overrides response headers set by `header` handlers earlier in the pipeline
+        if !ctx.headers_down.is_empty() {
+            let upstream_addr = ctx.upstream.as_ref().map(|u| u.addr.to_string());
+            let placeholders = placeholder_context(session.req_header(), ctx, upstream_addr.as_deref(), Some(&*upstream_response));
+            let rendered = render_headers(&ctx.headers_down, &placeholders);
+            for (key, value) in rendered {
+                upstream_response.insert_header(key, value.as_str())?;
+            }
+        }
+
         // 3. Remove configured headers
         for header_name in &ctx.headers_remove {
             let _ = upstream_response.remove_header(header_name);
@@ -1124,7 +1965,7 @@ impl ProxyHttp for PingclairProxy {
         }
 
         // 5. Add request ID header for tracing
-        upstream_response.insert_header("X-Request-Id", &ctx.request_id)?;
+        upstream_response.insert_header(self.settings().request_id_header.clone(), ctx.request_id.as_str())?;
 
         // 6. Security headers based on configuration
         if let Some(state) = &ctx.state {
@@ -1136,16 +1977,17 @@ impl ProxyHttp for PingclairProxy {
                 upstream_response.insert_header("Referrer-Policy", &state.config.security.referrer_policy)?;
                 upstream_response.insert_header("Permissions-Policy", &state.config.security.permissions_policy)?;
 
-                if state.config.tls.as_ref().map_or(false, |tls| tls.auto || tls.cert.is_some()) {
-                    if let Some(ref hsts_config) = state.config.security.hsts {
-                        let hsts_value = format!(
-                            "max-age={};{}{}",
-                            hsts_config.max_age,
-                            if hsts_config.include_subdomains { " includeSubDomains;" } else { "" },
-                            if hsts_config.preload { " preload" } else { "" }
-                        );
-                        upstream_response.insert_header("Strict-Transport-Security", &hsts_value)?;
-                    }
+                // HSTS is only meaningful (and only allowed) over HTTPS
+                if ctx.protocol == "https"
+                    && let Some(ref hsts_config) = state.config.security.hsts
+                {
+                    let hsts_value = format!(
+                        "max-age={};{}{}",
+                        hsts_config.max_age,
+                        if hsts_config.include_subdomains { " includeSubDomains;" } else { "" },
+                        if hsts_config.preload { " preload" } else { "" }
+                    );
+                    upstream_response.insert_header("Strict-Transport-Security", &hsts_value)?;
                 }
 
                 if let Some(ref csp) = state.config.security.csp {
@@ -1154,43 +1996,39 @@ impl ProxyHttp for PingclairProxy {
             }
         }
 
-        // 7. Setup gzip compression if applicable
-        // Only compress if:
-        //   - Client accepts gzip
-        //   - Response is not already compressed
-        //   - Content type is compressible (text/*, application/json, etc.)
-        //   - Body is not too small (> 256 bytes via Content-Length)
-        if ctx.compress_response && ctx.client_accepts_gzip {
-            let already_encoded = upstream_response.headers
-                .get("content-encoding")
-                .is_some();
+        // 7. Buffer template responses for rendering at end of stream
+        if let Some(templates) = &ctx.templates {
             let content_type = upstream_response.headers
                 .get("content-type")
                 .and_then(|v| v.to_str().ok())
-                .unwrap_or("");
-            let is_compressible = content_type.starts_with("text/")
-                || content_type.contains("json")
-                || content_type.contains("xml")
-                || content_type.contains("javascript")
-                || content_type.contains("css")
-                || content_type.contains("svg");
-            let content_length = upstream_response.headers
+                .unwrap_or("")
+                .to_string();
+            let encoded = upstream_response.headers.get("content-encoding").is_some();
+            let too_large = upstream_response.headers
                 .get("content-length")
                 .and_then(|v| v.to_str().ok())
-                .and_then(|v| v.parse::<u64>().ok());
-            let too_small = content_length.map_or(false, |len| len < 256);
-
-            if !already_encoded && is_compressible && !too_small {
-                // Initialize gzip encoder
-                ctx.gzip_encoder = Some(GzEncoder::new(Vec::new(), Compression::fast()));
-                // Set response headers for compressed content
-                upstream_response.insert_header("Content-Encoding", "gzip")?;
+                .and_then(|v| v.parse::<usize>().ok())
+                .is_some_and(|len| len > MAX_TEMPLATE_BODY);
+            if ctx.replacement_body.is_none() && !encoded && !too_large && templates.applies_to(&content_type) {
+                ctx.template_body = Some((content_type, Vec::new()));
                 let _ = upstream_response.remove_header("Content-Length");
-                // Transfer-Encoding: chunked will be set by Pingora automatically
-                upstream_response.insert_header("Vary", "Accept-Encoding")?;
+                let _ = upstream_response.remove_header("ETag");
+                let _ = upstream_response.remove_header("Last-Modified");
+                let _ = upstream_response.remove_header("Accept-Ranges");
             }
         }
 
+        // 8. Setup gzip compression if applicable (see `gzip_applies`)
+        if gzip_applies(ctx, &upstream_response.headers) {
+            // Initialize gzip encoder
+            ctx.gzip_encoder = Some(GzEncoder::new(Vec::new(), Compression::fast()));
+            // Set response headers for compressed content
+            upstream_response.insert_header("Content-Encoding", "gzip")?;
+            let _ = upstream_response.remove_header("Content-Length");
+            // Transfer-Encoding: chunked will be set by Pingora automatically
+            upstream_response.insert_header("Vary", "Accept-Encoding")?;
+        }
+
         Ok(())
     }
 
@@ -1201,15 +2039,53 @@ impl ProxyHttp for PingclairProxy {
     /// replacing the last chunk with the compressed output.
     fn upstream_response_body_filter(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         body: &mut Option<Bytes>,
         end_of_stream: bool,
         ctx: &mut Self::CTX,
     ) -> pingora_core::Result<Option<Duration>> {
-        // Track response bytes for access log
+        // Swallow the upstream body of an intercepted response and emit the
+        // replacement once the upstream stream ends (handle_response)
+        if ctx.replacement_body.is_some() {
+            *body = if end_of_stream { ctx.replacement_body.take() } else { Some(Bytes::new()) };
+        }
+
+        // Buffer a templated upstream body and emit the rendered document at the end
+        if let Some((_, buffer)) = ctx.template_body.as_mut() {
+            if let Some(chunk) = body.as_ref() {
+                buffer.extend_from_slice(chunk);
+            }
+            if buffer.len() > MAX_TEMPLATE_BODY {
+                // Too large to render: release what was held back and stream the rest as-is
+                tracing::warn!("⚠️ Upstream body exceeds {} bytes, passing it through unrendered", MAX_TEMPLATE_BODY);
+                *body = ctx.template_body.take().map(|(_, buffer)| Bytes::from(buffer));
+            } else if end_of_stream {
+                if let (Some((content_type, source)), Some(templates)) = (ctx.template_body.take(), ctx.templates.clone()) {
+                    let source = String::from_utf8_lossy(&source);
+                    let placeholders = template_placeholders(session.req_header(), ctx);
+                    let template_ctx = template_context(session.req_header(), ctx, &placeholders);
+                    let rendered = templates.render_source_blocking(&source, &content_type, &template_ctx);
+                    *body = Some(match rendered {
+                        Ok(rendered) => Bytes::from(rendered),
+                        Err(e) => {
+                            // Headers are already sent: fall back to the unrendered body
+                            tracing::warn!("⚠️ Template rendering failed for upstream response: {}", e);
+                            Bytes::from(source.into_owned())
+                        }
+                    });
+                }
+            } else {
+                *body = Some(Bytes::new());
+            }
+        }
+
+        // Track response bytes and upstream completion for access log
         if let Some(b) = body.as_ref() {
             ctx.response_bytes += b.len() as u64;
         }
+        if end_of_stream {
+            ctx.upstream_timing.finished();
+        }
 
         // Streaming gzip compression
         if let Some(ref mut encoder) = ctx.gzip_encoder {
@@ -1237,9 +2113,28 @@ impl ProxyHttp for PingclairProxy {
             }
         }
 
+        // X-Accel-Limit-Rate: pace proxied chunks (Pingora waits before sending each one)
+        if let (Some(rate), Some(chunk)) = (ctx.limit_rate, body.as_ref()) {
+            return Ok(accel::limit_rate_delay(chunk.len(), rate));
+        }
+
         Ok(None)
     }
     
+    /// Called when connecting to the upstream fails
+    fn fail_to_connect(
+        &self,
+        _session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        e: Box<pingora_core::Error>,
+    ) -> Box<pingora_core::Error> {
+        if let Some(upstream) = &ctx.upstream {
+            metrics::UPSTREAM_FAILURES_TOTAL.with_label_values(&[&upstream.addr.to_string(), "connect"]).inc();
+        }
+        e
+    }
+
     /// Called on errors
     fn error_while_proxy(
         &self,
@@ -1249,6 +2144,9 @@ impl ProxyHttp for PingclairProxy {
         ctx: &mut Self::CTX,
         _client_reused: bool,
     ) -> Box<pingora_core::Error> {
+        if let Some(upstream) = &ctx.upstream {
+            metrics::UPSTREAM_FAILURES_TOTAL.with_label_values(&[&upstream.addr.to_string(), "proxy"]).inc();
+        }
         let elapsed = ctx.start_time.elapsed();
         tracing::error!(
             peer = %peer,
@@ -1264,7 +2162,11 @@ impl ProxyHttp for PingclairProxy {
     /// 🏗️ ARCHITECTURE: Produces JSON-structured log lines compatible
     /// with the Caddy JSON log format. Fields:
     ///   - ts, duration, request (method, host, uri), status, size, request_id
-    ///   - Per-server log level/file is configured but we use tracing for now
+    ///   - trace_id, span_id (W3C trace context, for backend correlation)
+    ///   - Sampled requests also export their spans via OTLP (see `otel`)
+    ///   - Sites with a `log` block write to their own `AccessLogger`
+    ///     (file/stdout/stderr; text, Caddy JSON, Common/Combined or a custom
+    ///     template; per-server level); others use tracing
     async fn logging(
         &self,
         session: &mut Session,
@@ -1286,31 +2188,128 @@ impl ProxyHttp for PingclairProxy {
         let referer = req_header.headers.get("Referer")
             .and_then(|v| v.to_str().ok())
             .unwrap_or("-");
-        let remote_ip = session.client_addr()
-            .map(|addr| match addr {
-                pingora_core::protocols::l4::socket::SocketAddr::Inet(inet) => inet.ip().to_string(),
-                pingora_core::protocols::l4::socket::SocketAddr::Unix(_) => "127.0.0.1".to_string(),
-            })
-            .unwrap_or_else(|| "0.0.0.0".to_string());
+        let remote_ip = peer_ip(session).to_string();
+        // Requests rejected before routing never resolved a client IP
+        let client_ip = if ctx.client_ip.is_empty() { remote_ip.clone() } else { ctx.client_ip.clone() };
         let elapsed = ctx.start_time.elapsed();
 
         // Update Prometheus metrics
-        metrics::REQUESTS_TOTAL.with_label_values(&[
+        // ⚡ OPTIMIZATION: Label by the matched site host (port stripped, empty when
+        // no site matched) rather than the raw Host header to bound cardinality.
+        let metrics_host = ctx.request_host.as_str();
+        let metrics_route = metrics::route_label(
+            ctx.state.as_ref()
+                .zip(ctx.route_index)
+                .and_then(|(state, index)| state.config.routes.get(index))
+                .map(|route| route.path.as_str())
+                .unwrap_or(""),
+        );
+        metrics::record_request(
             method,
-            &response_code.to_string(),
-            host
-        ]).inc();
+            response_code,
+            metrics_host,
+            metrics_route,
+            elapsed,
+            ctx.request_bytes,
+            ctx.response_bytes,
+        );
 
-        metrics::REQUEST_DURATION_SECONDS.with_label_values(&[
-            method,
-            &response_code.to_string(),
-            host
-        ]).observe(elapsed.as_secs_f64());
+        if let Some(upstream) = &ctx.upstream {
+            metrics::record_upstream_timing(&upstream.addr.to_string(), &ctx.upstream_timing);
+        }
+
+        if ctx.in_flight {
+            metrics::REQUESTS_IN_FLIGHT.with_label_values(&[metrics_host]).dec();
+            ctx.in_flight = false;
+        }
+
+        // OpenTelemetry: server span + collected child spans
+        if let (Some(spans), Some(exporter)) = (ctx.spans.take(), self.settings().exporter.clone()) {
+            let end = std::time::SystemTime::now();
+            let route = ctx.state.as_ref()
+                .zip(ctx.route_index)
+                .and_then(|(state, index)| state.config.routes.get(index))
+                .map(|route| route.path.clone());
+            let name = match &route {
+                Some(route) => format!("{} {}", method, route),
+                None => method.to_string(),
+            };
+
+            let mut server_span = SpanData::server(&ctx.trace, name, end - elapsed, end)
+                .attr("http.request.method", method)
+                .attr("url.path", req_header.uri.path())
+                .attr("url.scheme", ctx.protocol.clone())
+                .attr("server.address", ctx.request_host.clone())
+                .attr("client.address", client_ip.clone())
+                .attr("network.peer.address", remote_ip.clone())
+                .attr("user_agent.original", user_agent)
+                .attr("http.response.status_code", response_code as i64)
+                .attr("pingclair.request_id", ctx.request_id.clone());
+            if let Some(query) = req_header.uri.query() {
+                server_span = server_span.attr("url.query", query);
+            }
+            if let Some(route) = route {
+                server_span = server_span.attr("http.route", route);
+            }
+            if let Some(err) = e {
+                server_span = server_span.attr("error.type", err.etype().as_str());
+            }
+            server_span.error = e.is_some() || response_code >= 500;
+
+            exporter.export(server_span);
+            for span in spans.children {
+                exporter.export(span);
+            }
+        }
+
+        // Site access log (`log` block) takes over from the application log
+        if let Some(access_log) = ctx.state.as_ref().and_then(|state| state.access_log.clone()) {
+            let uri = req_header.uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+            let proto = format!("{:?}", req_header.version);
+            let tls = ctx.tls.clone().or_else(|| tls_info(session));
+            let route = ctx.state.as_ref()
+                .zip(ctx.route_index)
+                .and_then(|(state, index)| state.config.routes.get(index))
+                .map(|route| route.path.as_str());
+            let entry = AccessLogEntry {
+                time: Some(std::time::SystemTime::now() - elapsed),
+                request_id: &ctx.request_id,
+                trace_id: &ctx.trace.trace_id,
+                span_id: &ctx.trace.span_id,
+                remote_ip: &remote_ip,
+                client_ip: &client_ip,
+                method,
+                host,
+                uri,
+                proto: &proto,
+                scheme: &ctx.protocol,
+                status: response_code,
+                bytes: ctx.response_bytes,
+                duration: elapsed,
+                user_agent,
+                referer,
+                upstream: ctx.upstream.as_ref().map(|u| u.addr.to_string()),
+                error: e.map(|err| err.to_string()),
+                remote_port: ctx.remote_port,
+                request_headers: Some(&req_header.headers),
+                response_headers: session.response_written().map(|resp| &resp.headers),
+                bytes_in: ctx.request_bytes,
+                upstream_timing: ctx.upstream_timing,
+                tls,
+                route,
+                rate_limited: ctx.rate_limited,
+                user_id: basic_auth_user(&req_header.headers),
+            };
+            access_log.log(&entry, self.settings().log_level);
+            return;
+        }
 
         // Structured access log
         if let Some(err) = e {
             tracing::error!(
                 request_id = %ctx.request_id,
+                trace_id = %ctx.trace.trace_id,
+                span_id = %ctx.trace.span_id,
                 method = method,
                 host = host,
                 path = req_header.uri.path(),
@@ -1318,6 +2317,7 @@ impl ProxyHttp for PingclairProxy {
                 bytes = ctx.response_bytes,
                 duration_ms = elapsed.as_millis(),
                 remote_ip = %remote_ip,
+                client_ip = %client_ip,
                 user_agent = user_agent,
                 error = %err,
                 "❌ Access"
@@ -1325,6 +2325,8 @@ impl ProxyHttp for PingclairProxy {
         } else {
             tracing::info!(
                 request_id = %ctx.request_id,
+                trace_id = %ctx.trace.trace_id,
+                span_id = %ctx.trace.span_id,
                 method = method,
                 host = host,
                 path = req_header.uri.path(),
@@ -1332,6 +2334,7 @@ impl ProxyHttp for PingclairProxy {
                 bytes = ctx.response_bytes,
                 duration_ms = elapsed.as_millis(),
                 remote_ip = %remote_ip,
+                client_ip = %client_ip,
                 user_agent = user_agent,
                 referer = referer,
                 upstream = ?ctx.upstream.as_ref().map(|u| &u.addr),
@@ -1343,6 +2346,407 @@ impl ProxyHttp for PingclairProxy {
 
 // MARK: - Helper Functions
 
+/// Direct peer IP: the PROXY protocol source when a relay accepted the
+/// connection, else the socket peer. Unix socket peers are treated as loopback
+fn peer_ip(session: &Session) -> std::net::IpAddr {
+    if let Some(connection) = session.client_addr().and_then(proxy_protocol::lookup) {
+        return connection.client().ip().to_canonical();
+    }
+    match session.client_addr() {
+        // Dual-stack listeners report IPv4 clients as `::ffff:a.b.c.d`
+        Some(pingora_core::protocols::l4::socket::SocketAddr::Inet(inet)) => inet.ip().to_canonical(),
+        Some(pingora_core::protocols::l4::socket::SocketAddr::Unix(_)) => std::net::Ipv4Addr::LOCALHOST.into(),
+        None => std::net::Ipv4Addr::UNSPECIFIED.into(),
+    }
+}
+
+/// Whether `response_filter` should gzip a response.
+///
+/// A replacement body is emitted whole by the body filter and a rate limit
+/// paces chunks as they are written, so neither goes through the encoder.
+///
+/// - Parameter ctx: The request context (client support, replacement, rate limit).
+/// - Parameter headers: The response headers.
+/// - Returns: `true` for an unencoded, compressible body of at least 256 bytes.
+fn gzip_applies(ctx: &RequestContext, headers: &http::HeaderMap) -> bool {
+    if !ctx.compress_response || !ctx.client_accepts_gzip
+        || ctx.replacement_body.is_some() || ctx.limit_rate.is_some()
+    {
+        return false;
+    }
+    let already_encoded = headers.get("content-encoding").is_some();
+    let content_type = headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let is_compressible = content_type.starts_with("text/")
+        || content_type.contains("json")
+        || content_type.contains("xml")
+        || content_type.contains("javascript")
+        || content_type.contains("css")
+        || content_type.contains("svg");
+    let too_small = headers
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .is_some_and(|len| len < 256);
+    !already_encoded && is_compressible && !too_small
+}
+
+/// Scheme of a request (see the SAFETY note in `request_filter`).
+///
+/// - Parameter terminated_tls: Whether this listener or a PROXY protocol sender terminated TLS.
+/// - Parameter trusted_peer: Whether the direct peer is a trusted proxy.
+/// - Parameter headers: The request headers; `X-Forwarded-Proto` is read from trusted peers only.
+/// - Returns: `https` or `http`.
+fn request_scheme(terminated_tls: bool, trusted_peer: bool, headers: &http::HeaderMap) -> &'static str {
+    // The rightmost value was set by the trusted peer; earlier ones came from further away
+    let forwarded_https = trusted_peer
+        && headers.get_all("x-forwarded-proto").iter()
+            .next_back()
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("https"));
+    if terminated_tls || forwarded_https {
+        "https"
+    } else {
+        "http"
+    }
+}
+
+/// Negotiated TLS parameters of the downstream connection
+fn tls_info(session: &Session) -> Option<TlsInfo> {
+    session.digest()
+        .and_then(|digest| digest.ssl_digest.as_ref())
+        .map(|ssl| TlsInfo { version: ssl.version.to_string(), cipher: ssl.cipher.to_string() })
+}
+
+/// Client IP bytes, the hash key for `ip_hash` load balancing
+pub(crate) fn ip_octets(ip: std::net::IpAddr) -> Vec<u8> {
+    match ip {
+        std::net::IpAddr::V4(v4) => v4.octets().to_vec(),
+        std::net::IpAddr::V6(v6) => v6.octets().to_vec(),
+    }
+}
+
+/// Handler type name (matches the config `type` tag), used as a span attribute
+fn handler_kind(handler: &HandlerConfig) -> &'static str {
+    match handler {
+        HandlerConfig::FileServer { .. } => "file_server",
+        HandlerConfig::ReverseProxy(_) => "reverse_proxy",
+        HandlerConfig::Redirect { .. } => "redirect",
+        HandlerConfig::Rewrite { .. } => "rewrite",
+        HandlerConfig::Respond { .. } => "respond",
+        HandlerConfig::Headers { .. } => "headers",
+        HandlerConfig::Pipeline(_) => "pipeline",
+        HandlerConfig::Handle(_) => "handle",
+        HandlerConfig::BasicAuth { .. } => "basic_auth",
+        HandlerConfig::RateLimit { .. } => "rate_limit",
+        HandlerConfig::HandleErrors { .. } => "handle_errors",
+        HandlerConfig::HandlePath { .. } => "handle_path",
+        HandlerConfig::Cors { .. } => "cors",
+        HandlerConfig::TryFiles { .. } => "try_files",
+        HandlerConfig::Templates { .. } => "templates",
+        HandlerConfig::Map(_) => "map",
+        HandlerConfig::Vars { .. } => "vars",
+        HandlerConfig::Plugin { .. } => "plugin",
+    }
+}
+
+/// Record `header` directive operations on the request context
+fn apply_header_ops(
+    ctx: &mut RequestContext,
+    set: &HashMap<String, String>,
+    add: &HashMap<String, String>,
+    remove: &[String],
+) {
+    for (k, v) in set {
+        ctx.headers_downstream.insert(k.clone(), v.clone());
+    }
+    for (k, v) in add {
+        ctx.headers_downstream_add.insert(k.clone(), v.clone());
+    }
+    for h in remove {
+        ctx.headers_remove.push(h.clone());
+        // If removing "Server", set flag to suppress default
+        if h.eq_ignore_ascii_case("server") {
+            ctx.suppress_server_header = true;
+        }
+    }
+}
+
+/// Build a file server for a `file_server` handler
+fn build_file_server(root: &str, index: &[String], browse: bool, compress: bool) -> pingclair_static::FileServer {
+    pingclair_static::FileServer::new(pingclair_static::FileServerConfig {
+        root: std::path::PathBuf::from(root),
+        index: if index.is_empty() { vec!["index.html".to_string()] } else { index.to_vec() },
+        browse,
+        compress,
+        precompressed: true,  // Enable pre-compressed file detection by default
+    })
+}
+
+/// Settings of every `file_server` in a handler tree
+fn file_server_keys(handler: &HandlerConfig) -> Vec<FileServerKey> {
+    fn walk(handler: &HandlerConfig, out: &mut Vec<FileServerKey>) {
+        match handler {
+            HandlerConfig::FileServer { .. } => out.extend(FileServerKey::of(handler)),
+            HandlerConfig::Pipeline(handlers) | HandlerConfig::Handle(handlers) | HandlerConfig::HandlePath { handlers, .. } => {
+                for h in handlers {
+                    walk(h, out);
+                }
+            }
+            HandlerConfig::TryFiles { fallback: Some(fallback), .. } => walk(fallback, out),
+            _ => {}
+        }
+    }
+    let mut out = Vec::new();
+    walk(handler, &mut out);
+    out
+}
+
+/// Recursively find a templates handler in a handler tree
+fn find_templates_config(handler: &HandlerConfig) -> Option<&HandlerConfig> {
+    match handler {
+        HandlerConfig::Templates { .. } => Some(handler),
+        HandlerConfig::Pipeline(handlers) | HandlerConfig::Handle(handlers) | HandlerConfig::HandlePath { handlers, .. } => {
+            handlers.iter().find_map(find_templates_config)
+        }
+        _ => None,
+    }
+}
+
+/// Recursively find the root of the first file server in a handler tree
+fn find_file_server_root(handler: &HandlerConfig) -> Option<&str> {
+    match handler {
+        HandlerConfig::FileServer { root, .. } => Some(root.as_str()),
+        HandlerConfig::Pipeline(handlers) | HandlerConfig::Handle(handlers) | HandlerConfig::HandlePath { handlers, .. } => {
+            handlers.iter().find_map(find_file_server_root)
+        }
+        _ => None,
+    }
+}
+
+/// Resolve the `placeholder` names used by templates for the current request
+fn template_placeholders<'a>(req: &'a RequestHeader, ctx: &'a RequestContext) -> impl Fn(&str) -> String + Sync + 'a {
+    let placeholder_ctx = placeholder_context(req, ctx, None, None);
+    move |name: &str| match Placeholder::parse(name) {
+        Ok(Some(placeholder)) => placeholder.resolve(&placeholder_ctx).into_owned(),
+        _ => String::new(),
+    }
+}
+
+/// Expose the current request to templates
+fn template_context<'a>(
+    req: &'a RequestHeader,
+    ctx: &'a RequestContext,
+    placeholders: &'a (dyn Fn(&str) -> String + Sync),
+) -> TemplateContext<'a> {
+    TemplateContext {
+        method: req.method.as_str(),
+        host: &ctx.request_host,
+        uri: req.uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/"),
+        remote_ip: &ctx.client_ip,
+        headers: &req.headers,
+        placeholders,
+    }
+}
+
+/// Render a served file as a template; rendering errors become a 500
+async fn render_template_file(
+    templates: &Templates,
+    req: &RequestHeader,
+    ctx: &RequestContext,
+    file: pingclair_static::ServedFile,
+) -> PingoraResult<(ResponseHeader, Bytes)> {
+    // The file server already read the file: render those bytes rather than re-reading it
+    let source = String::from_utf8_lossy(&file.content);
+    let placeholders = template_placeholders(req, ctx);
+    let template_ctx = template_context(req, ctx, &placeholders);
+    let rendered = templates.render_source(&source, &file.mime_type, &template_ctx).await;
+
+    match rendered {
+        Ok(rendered) => {
+            let mut header = ResponseHeader::build(200, Some(3))?;
+            header.insert_header("Content-Type", file.mime_type.as_str())?;
+            header.insert_header("Content-Length", rendered.len().to_string())?;
+            Ok((header, Bytes::from(rendered)))
+        }
+        Err(e) => {
+            tracing::error!("❌ Template rendering failed for {}: {}", file.path.display(), e);
+            let mut header = ResponseHeader::build(500, Some(2))?;
+            header.insert_header("Content-Length", "0")?;
+            Ok((header, Bytes::new()))
+        }
+    }
+}
+
+/// Build the response header for a served static file
+fn file_response_header(file: &pingclair_static::ServedFile) -> PingoraResult<ResponseHeader> {
+    let mut header = ResponseHeader::build(file.status, Some(8))?;
+    header.insert_header("Content-Type", file.mime_type.as_str())?;
+    header.insert_header("Content-Length", file.content.len().to_string())?;
+
+    if let Some(range) = &file.content_range {
+        header.insert_header("Content-Range", range.as_str())?;
+    }
+    if let Some(lm) = &file.last_modified {
+        header.insert_header("Last-Modified", lm.as_str())?;
+    }
+    if let Some(etag) = &file.etag {
+        header.insert_header("ETag", etag.as_str())?;
+    }
+    if let Some(encoding) = &file.content_encoding {
+        header.insert_header("Content-Encoding", encoding.as_str())?;
+    }
+    header.insert_header("Accept-Ranges", "bytes")?;
+    Ok(header)
+}
+
+/// Build the upstream pool for a `reverse_proxy` handler
+///
+/// - Returns: The pool and the labels of the health gauges it publishes.
+fn build_load_balancer(proxy_config: &ReverseProxyConfig, route_path: &str) -> (Arc<LoadBalancer>, Vec<String>) {
+    // 1. Create Upstreams (Backends); templated ones are resolved per request
+    let upstreams: Vec<Upstream> = proxy_config.upstreams.iter()
+        .filter(|addr| !addr.contains('{'))
+        .filter_map(|addr| create_upstream(addr))
+        .collect();
+
+    if upstreams.is_empty() && !proxy_config.upstreams.iter().any(|addr| addr.contains('{')) {
+        tracing::warn!("⚠️ No valid upstreams found for route {}", route_path);
+    }
+
+    // 2. Create Strategy
+    let strategy = match proxy_config.load_balance.strategy.as_str() {
+        "random"     => Strategy::Random,
+        "least_conn" => Strategy::LeastConn,
+        "ip_hash"    => Strategy::IpHash,
+        "first"      => Strategy::RoundRobin,
+        _            => Strategy::RoundRobin,
+    };
+
+    // Backends start out healthy; the health checker reports later transitions
+    let mut health_gauges = Vec::new();
+    if proxy_config.health_check.is_some() {
+        for upstream in &upstreams {
+            let label = upstream.addr.to_string();
+            crate::metrics::UPSTREAM_HEALTHY.with_label_values(&[&label]).set(1);
+            health_gauges.push(label);
+        }
+    }
+
+    // 3. Create Load Balancer
+    let mut load_balancer = Arc::new(LoadBalancer::new(upstreams, strategy));
+
+    // 4. Setup Health Checker if configured
+    if let Some(hc_config) = &proxy_config.health_check {
+        let health_check_conf = crate::health_check::HealthCheckConfig {
+             path: hc_config.path.clone(),
+             timeout: std::time::Duration::from_secs(hc_config.timeout),
+             positive_threshold: 1,
+             negative_threshold: hc_config.threshold as usize,
+             expected_status: (200, 299),
+        };
+
+        let health_checker = HealthChecker::new(health_check_conf);
+
+        // Attach to LB (needs mutable access to LB wrapper during init)
+        if let Some(load_balancer_mut) = Arc::get_mut(&mut load_balancer) {
+            load_balancer_mut.set_health_check(health_checker);
+            load_balancer_mut.set_health_check_frequency(std::time::Duration::from_secs(hc_config.interval));
+        } else {
+            tracing::warn!("Correlation ID: Init - Could not attach health checker to LB");
+        }
+    }
+
+    tracing::info!(
+        "⚖️ Initialized load balancer for route {} with strategy {:?}",
+        route_path, strategy
+    );
+    (load_balancer, health_gauges)
+}
+
+/// Labels of the health gauges published by the proxies of some sites
+fn health_gauges<'a>(states: impl Iterator<Item = &'a ProxyState>) -> HashSet<String> {
+    states
+        .flat_map(|state| state.proxies.values())
+        .flat_map(|node| node.health_gauges.iter().cloned())
+        .collect()
+}
+
+/// Parse the upstreams of a proxy rendered per request
+///
+/// - Returns: Every upstream as a template if any holds a placeholder, else none.
+fn dynamic_upstreams(proxy_config: &ReverseProxyConfig, route_path: &str) -> Vec<Template> {
+    if !proxy_config.upstreams.iter().any(|addr| addr.contains('{')) {
+        return Vec::new();
+    }
+    proxy_config.upstreams.iter()
+        .filter_map(|addr| match Template::parse(addr) {
+            Ok(template) => Some(template),
+            Err(e) => {
+                tracing::error!("❌ Invalid upstream {:?} for route {}: {}", addr, route_path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Collect the `reverse_proxy` handlers of a handler tree in pre-order
+///
+/// The position in the returned list is the node's `ProxyNodeId::node`.
+fn proxy_nodes(handler: &HandlerConfig) -> Vec<&ReverseProxyConfig> {
+    fn walk<'a>(handler: &'a HandlerConfig, out: &mut Vec<&'a ReverseProxyConfig>) {
+        match handler {
+            HandlerConfig::ReverseProxy(config) => out.push(config),
+            HandlerConfig::Pipeline(handlers) | HandlerConfig::Handle(handlers) | HandlerConfig::HandlePath { handlers, .. } => {
+                for h in handlers {
+                    walk(h, out);
+                }
+            }
+            HandlerConfig::TryFiles { fallback: Some(fallback), .. } => walk(fallback, out),
+            _ => {}
+        }
+    }
+    let mut out = Vec::new();
+    walk(handler, &mut out);
+    out
+}
+
+/// Pair each handler of a sequence with the `ProxyNodeId::node` of its first `reverse_proxy`
+///
+/// - Parameter node: Index of the sequence's first `reverse_proxy`.
+pub(crate) fn sequence_nodes(handlers: &[HandlerConfig], node: usize) -> impl Iterator<Item = (&HandlerConfig, usize)> {
+    handlers.iter().scan(node, |next, handler| {
+        let at = *next;
+        *next += proxy_nodes(handler).len();
+        Some((handler, at))
+    })
+}
+
+/// Path seen by the handlers of a `handle_path` block
+///
+/// Caddy strips the exact prefix at a segment boundary; stripping everything leaves `/`.
+pub(crate) fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> &'a str {
+    // The prefix ends on a segment boundary: `/api` does not strip `/apiary`
+    match path.strip_prefix(prefix.trim_end_matches('/')) {
+        Some("") => "/",
+        Some(rest) if rest.starts_with('/') => rest,
+        _ => path,
+    }
+}
+
+/// Replace the path of a URI, keeping its query
+///
+/// - Returns: The new URI, or None if `path` does not form a valid one.
+pub(crate) fn with_path(uri: &http::Uri, path: &str) -> Option<http::Uri> {
+    match uri.query() {
+        Some(query) => format!("{}?{}", path, query).parse().ok(),
+        None => path.parse().ok(),
+    }
+}
+
 /// Recursively find a rate limit config in a handler tree
 fn find_rate_limit_config(handler: &HandlerConfig) -> Option<crate::rate_limit::RateLimitConfig> {
     match handler {
@@ -1365,3 +2769,262 @@ fn find_rate_limit_config(handler: &HandlerConfig) -> Option<crate::rate_limit::
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(upstream: &str) -> HandlerConfig {
+        HandlerConfig::ReverseProxy(ReverseProxyConfig {
+            upstreams: vec![upstream.to_string()],
+            ..Default::default()
+        })
+    }
+
+    /// Descend like `handle_config` / `handle_sequence`, recording the node each `reverse_proxy` is reached with
+    fn reached<'a>(handler: &'a HandlerConfig, node: usize, out: &mut Vec<(&'a str, usize)>) {
+        match handler {
+            HandlerConfig::ReverseProxy(config) => out.push((config.upstreams[0].as_str(), node)),
+            HandlerConfig::Pipeline(handlers) | HandlerConfig::Handle(handlers) | HandlerConfig::HandlePath { handlers, .. } => {
+                for (h, node) in sequence_nodes(handlers, node) {
+                    reached(h, node, out);
+                }
+            }
+            HandlerConfig::TryFiles { fallback: Some(fallback), .. } => reached(fallback, node, out),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_limit_rate_skips_gzip_for_text_bodies() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("content-type", "text/plain".parse().unwrap());
+        headers.insert("content-length", "4096".parse().unwrap());
+        let mut ctx = RequestContext { compress_response: true, client_accepts_gzip: true, ..Default::default() };
+        assert!(gzip_applies(&ctx, &headers));
+
+        // X-Accel-Redirect to a large text file with X-Accel-Limit-Rate
+        ctx.limit_rate = Some(1024);
+        ctx.replacement_body = Some(Bytes::from(vec![b'a'; 4096]));
+        assert!(!gzip_applies(&ctx, &headers));
+
+        // Either one alone is enough
+        ctx.replacement_body = None;
+        assert!(!gzip_applies(&ctx, &headers));
+        ctx.limit_rate = None;
+        ctx.replacement_body = Some(Bytes::from_static(b"replacement"));
+        assert!(!gzip_applies(&ctx, &headers));
+    }
+
+    #[test]
+    fn test_request_scheme_ignores_untrusted_or_malformed_forwarded_proto() {
+        let headers = |values: &[&[u8]]| {
+            let mut headers = http::HeaderMap::new();
+            for value in values {
+                headers.append("x-forwarded-proto", http::HeaderValue::from_bytes(value).unwrap());
+            }
+            headers
+        };
+
+        // The connection decides; a client cannot downgrade or upgrade it
+        assert_eq!(request_scheme(true, false, &headers(&[b"http"])), "https");
+        assert_eq!(request_scheme(false, false, &headers(&[b"https"])), "http");
+        assert_eq!(request_scheme(false, false, &headers(&[])), "http");
+
+        // Trusted proxies: the value they appended, case-insensitively
+        assert_eq!(request_scheme(false, true, &headers(&[b"HTTPS"])), "https");
+        assert_eq!(request_scheme(false, true, &headers(&[b"http, https"])), "https");
+        assert_eq!(request_scheme(false, true, &headers(&[b"https", b"http"])), "http");
+
+        // Spoofed leftmost values and junk are not https
+        assert_eq!(request_scheme(false, true, &headers(&[b"https, http"])), "http");
+        assert_eq!(request_scheme(false, true, &headers(&[b"https-ish"])), "http");
+        assert_eq!(request_scheme(false, true, &headers(&[b""])), "http");
+        assert_eq!(request_scheme(false, true, &headers(&[b"\xffhttps"])), "http");
+    }
+
+    #[test]
+    fn test_proxy_node_indices_match_handler_walk() {
+        let route = HandlerConfig::Pipeline(vec![
+            HandlerConfig::Headers { set: HashMap::new(), add: HashMap::new(), remove: Vec::new() },
+            HandlerConfig::Handle(vec![
+                HandlerConfig::HandlePath { prefix: "/api".into(), handlers: vec![proxy("a"), proxy("b")] },
+                proxy("c"),
+            ]),
+            HandlerConfig::TryFiles { files: vec!["{path}".into()], fallback: Some(Box::new(proxy("d"))) },
+            HandlerConfig::Pipeline(vec![
+                HandlerConfig::Handle(Vec::new()),
+                HandlerConfig::HandlePath {
+                    prefix: "/v2".into(),
+                    handlers: vec![HandlerConfig::Handle(vec![proxy("e")]), proxy("f")],
+                },
+            ]),
+            proxy("g"),
+        ]);
+
+        let expected = vec![("a", 0), ("b", 1), ("c", 2), ("d", 3), ("e", 4), ("f", 5), ("g", 6)];
+
+        let indexed: Vec<&str> = proxy_nodes(&route).iter().map(|config| config.upstreams[0].as_str()).collect();
+        assert_eq!(indexed, expected.iter().map(|(upstream, _)| *upstream).collect::<Vec<_>>());
+
+        let mut walked = Vec::new();
+        reached(&route, 0, &mut walked);
+        assert_eq!(walked, expected);
+    }
+
+    #[tokio::test]
+    async fn test_templated_upstreams_render_per_request() {
+        let config = ReverseProxyConfig {
+            upstreams: vec!["{vars.backend}".into(), "127.0.0.1:9000".into()],
+            ..Default::default()
+        };
+        let (load_balancer, health_gauges) = build_load_balancer(&config, "/");
+        let node = ProxyNode {
+            load_balancer,
+            dynamic_upstreams: dynamic_upstreams(&config, "/"),
+            health_gauges,
+            config,
+            response_handlers: Arc::new(Vec::new()),
+        };
+        assert_eq!(node.dynamic_upstreams.len(), 2);
+
+        let vars = HashMap::from([("backend".to_string(), "127.0.0.1:8080".to_string())]);
+        let placeholders = PlaceholderContext { vars: Some(&vars), ..Default::default() };
+        let upstream = node.select(&placeholders, None).await.unwrap();
+        assert_eq!(upstream.addr.to_string(), "127.0.0.1:8080");
+
+        // An unset variable leaves nothing to resolve: the next upstream is used
+        let upstream = node.select(&PlaceholderContext::default(), None).await.unwrap();
+        assert_eq!(upstream.addr.to_string(), "127.0.0.1:9000");
+
+        // Static upstreams stay on the load balancer
+        let config = ReverseProxyConfig { upstreams: vec!["127.0.0.1:9000".into()], ..Default::default() };
+        assert!(dynamic_upstreams(&config, "/").is_empty());
+    }
+
+    #[test]
+    fn test_reload_drops_health_gauges_of_removed_upstreams() {
+        let site = |upstreams: &[&str]| ServerConfig {
+            name: Some("health.example.com".into()),
+            routes: vec![pingclair_core::config::RouteConfig {
+                path: "/*".into(),
+                handler: HandlerConfig::ReverseProxy(ReverseProxyConfig {
+                    upstreams: upstreams.iter().map(|u| u.to_string()).collect(),
+                    health_check: Some(pingclair_core::config::HealthCheckConfig {
+                        path: "/health".into(),
+                        interval: 30,
+                        timeout: 5,
+                        threshold: 3,
+                    }),
+                    ..Default::default()
+                }),
+                methods: None,
+                matcher: None,
+                internal: false,
+                priority: 0,
+                source_order: false,
+            }],
+            ..Default::default()
+        };
+        let proxy = PingclairProxy::new();
+        proxy.update_config(vec![site(&["127.0.0.1:9101", "127.0.0.1:9102"])]);
+        proxy.update_config(vec![site(&["127.0.0.1:9101"])]);
+
+        // Removing an already dropped series fails
+        let gauges = &crate::metrics::UPSTREAM_HEALTHY;
+        assert!(gauges.remove_label_values(&["127.0.0.1:9102"]).is_err());
+        assert!(gauges.remove_label_values(&["127.0.0.1:9101"]).is_ok());
+    }
+
+    #[test]
+    fn test_sequence_nodes_offsets() {
+        let handlers = vec![
+            HandlerConfig::HandlePath { prefix: "/a".into(), handlers: vec![proxy("a"), proxy("b")] },
+            HandlerConfig::Handle(Vec::new()),
+            proxy("c"),
+        ];
+        let offsets: Vec<usize> = sequence_nodes(&handlers, 3).map(|(_, node)| node).collect();
+        assert_eq!(offsets, vec![3, 5, 5]);
+    }
+
+    #[test]
+    fn test_handle_path_forwards_stripped_path() {
+        let uri: http::Uri = "/api/users/7?expand=1".parse().unwrap();
+        let stripped = strip_path_prefix(uri.path(), "/api");
+        assert_eq!(stripped, "/users/7");
+        assert_eq!(with_path(&uri, stripped).unwrap(), "/users/7?expand=1");
+
+        let uri: http::Uri = "/api".parse().unwrap();
+        assert_eq!(with_path(&uri, strip_path_prefix(uri.path(), "/api")).unwrap(), "/");
+
+        // Outside the prefix the path is left alone
+        assert_eq!(strip_path_prefix("/other", "/api"), "/other");
+
+        // The prefix only strips whole segments
+        assert_eq!(strip_path_prefix("/apiary", "/api"), "/apiary");
+        assert_eq!(strip_path_prefix("/api/users", "/api/"), "/users");
+    }
+
+    fn file_server(root: &str) -> HandlerConfig {
+        HandlerConfig::FileServer { root: root.into(), index: Vec::new(), browse: false, compress: false }
+    }
+
+    #[test]
+    fn test_nested_file_servers_prebuilt() {
+        let route = |path: &str, handler| pingclair_core::config::RouteConfig {
+            path: path.into(),
+            handler,
+            methods: None,
+            matcher: None,
+            internal: false,
+            priority: 0,
+            source_order: false,
+        };
+        let state = ProxyState::new(ServerConfig {
+            routes: vec![
+                route("/", file_server("/srv/www")),
+                route("/docs/*", HandlerConfig::Pipeline(vec![
+                    HandlerConfig::Templates { root: None, mime_types: Vec::new(), between: None },
+                    HandlerConfig::HandlePath { prefix: "/docs".into(), handlers: vec![file_server("/srv/docs")] },
+                ])),
+                route("/app/*", HandlerConfig::TryFiles {
+                    files: vec!["{path}".into()],
+                    fallback: Some(Box::new(file_server("/srv/www"))),
+                }),
+            ],
+            ..Default::default()
+        });
+
+        assert_eq!(state.file_servers.len(), 2);
+        assert!(state.file_server(&file_server("/srv/docs")).is_some());
+        assert!(state.file_server(&file_server("/srv/other")).is_none());
+        assert!(state.file_server(&proxy("a")).is_none());
+    }
+
+    #[test]
+    fn test_response_handler_file_servers_prebuilt() {
+        let block = CompiledResponseHandler::compile(&ResponseHandlerConfig {
+            matcher: None,
+            handlers: vec![HandlerConfig::Handle(vec![file_server("/srv/errors")]), file_server("/srv/errors")],
+            copy_headers: Vec::new(),
+        });
+        assert_eq!(block.file_servers.len(), 1);
+        assert!(block.file_servers.contains_key(&FileServerKey::of(&file_server("/srv/errors")).unwrap()));
+    }
+
+    #[test]
+    fn test_response_placeholders_use_redirect_target() {
+        let request = RequestHeader::build("GET", b"/download?id=1", None).unwrap();
+        let ctx = RequestContext::default();
+
+        let info = ResponseRequestInfo::new("/download".to_string(), &request, 200);
+        assert_eq!(info.placeholders(&ctx).query, "id=1");
+
+        // `X-Accel-Redirect: /protected/file.zip?token=abc`
+        let mut info = ResponseRequestInfo::new("/protected/file.zip".to_string(), &request, 200);
+        info.query = "token=abc".to_string();
+        let placeholders = info.placeholders(&ctx);
+        assert_eq!(placeholders.path, "/protected/file.zip");
+        assert_eq!(placeholders.query, "token=abc");
+    }
+}
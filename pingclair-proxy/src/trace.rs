@@ -0,0 +1,241 @@
+//! Request correlation: request IDs and W3C Trace Context
+//!
+//! 🏗️ ARCHITECTURE: Every request carries a request ID and a trace context.
+//!   - The request ID is taken from the incoming request only when the peer is a
+//!     trusted proxy; otherwise a fresh one is minted.
+//!   - `traceparent` / `tracestate` are continued when valid, and Pingclair
+//!     records its own child span so upstream services see the proxy as parent.
+//!
+//! See <https://www.w3.org/TR/trace-context/>.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// MARK: - Headers
+
+/// Default request ID header
+pub const DEFAULT_REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// W3C trace parent header
+pub const TRACEPARENT: &str = "traceparent";
+
+/// W3C vendor trace state header
+pub const TRACESTATE: &str = "tracestate";
+
+/// Upper bound for accepted incoming request IDs and trace states
+const MAX_HEADER_LEN: usize = 512;
+
+// MARK: - Request ID
+
+/// Generate a compact, sortable request ID (timestamp + random suffix)
+pub fn generate_request_id() -> String {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+    format!("{:x}-{:04x}", ts, fastrand::u16(..))
+}
+
+/// Whether an incoming request ID is safe to reuse (visible ASCII, bounded length)
+pub fn is_valid_request_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_HEADER_LEN
+        && value.bytes().all(|b| b.is_ascii_graphic())
+}
+
+// MARK: - Trace Context
+
+/// W3C trace context of a request as seen by Pingclair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 lowercase hex characters, shared by the whole trace
+    pub trace_id: String,
+    /// 16 lowercase hex characters identifying Pingclair's span
+    pub span_id: String,
+    /// Span of the caller, when the trace was continued
+    pub parent_span_id: Option<String>,
+    /// Trace flags (bit 0: sampled)
+    pub flags: u8,
+    /// Vendor-specific state, forwarded untouched
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// Continue the trace from incoming headers, or start a new one.
+    ///
+    /// - Parameter headers: The downstream request headers.
+    /// - Returns: A context whose `span_id` is a new child span.
+    pub fn from_headers(headers: &http::HeaderMap) -> Self {
+        let parent = headers.get(TRACEPARENT)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_traceparent);
+
+        match parent {
+            Some((trace_id, parent_span_id, flags)) => {
+                // tracestate is only meaningful alongside a valid traceparent
+                let tracestate = headers.get_all(TRACESTATE)
+                    .iter()
+                    .filter_map(|v| v.to_str().ok())
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                Self {
+                    trace_id,
+                    span_id: new_span_id(),
+                    parent_span_id: Some(parent_span_id),
+                    flags,
+                    tracestate: Some(tracestate).filter(|s| !s.is_empty() && s.len() <= MAX_HEADER_LEN),
+                }
+            }
+            None => Self::new_root(),
+        }
+    }
+
+    /// Start a new, sampled trace
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: random_hex_id(16),
+            span_id: new_span_id(),
+            parent_span_id: None,
+            flags: 0x01,
+            tracestate: None,
+        }
+    }
+
+    /// `traceparent` value to send upstream (Pingclair's span as parent)
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+
+    /// Whether the caller asked for this trace to be recorded
+    pub fn sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new_root()
+    }
+}
+
+/// Parse a `traceparent` header into `(trace_id, parent_span_id, flags)`.
+///
+/// Unknown future versions are accepted as long as the version-00 prefix is
+/// well-formed; version `ff` and all-zero ids are rejected.
+pub fn parse_traceparent(value: &str) -> Option<(String, String, u8)> {
+    let value = value.trim();
+    let bytes = value.as_bytes();
+    if bytes.len() < 55 {
+        return None;
+    }
+
+    let version = &value[..2];
+    if !is_lower_hex(version) || version == "ff" {
+        return None;
+    }
+    if version == "00" && bytes.len() != 55 {
+        return None;
+    }
+    if bytes.len() > 55 && bytes[55] != b'-' {
+        return None;
+    }
+    if bytes[2] != b'-' || bytes[35] != b'-' || bytes[52] != b'-' {
+        return None;
+    }
+
+    let trace_id = &value[3..35];
+    let parent_id = &value[36..52];
+    let flags = &value[53..55];
+    if !is_lower_hex(trace_id) || !is_lower_hex(parent_id) || !is_lower_hex(flags) {
+        return None;
+    }
+    if trace_id.bytes().all(|b| b == b'0') || parent_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some((trace_id.to_string(), parent_id.to_string(), flags))
+}
+
+/// New random span id (16 lowercase hex characters)
+pub fn new_span_id() -> String {
+    random_hex_id(8)
+}
+
+// MARK: - Helpers
+
+fn is_lower_hex(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Random non-zero id of `len` bytes, hex encoded
+fn random_hex_id(len: usize) -> String {
+    loop {
+        let id: String = (0..len).map(|_| format!("{:02x}", fastrand::u8(..))).collect();
+        if id.bytes().any(|b| b != b'0') {
+            return id;
+        }
+    }
+}
+
+// MARK: - Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_traceparent() {
+        let parsed = parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(parsed.0, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(parsed.1, "00f067aa0ba902b7");
+        assert_eq!(parsed.2, 0x01);
+
+        // Invalid: uppercase, zero ids, forbidden version, bad length
+        assert!(parse_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x").is_none());
+
+        // Future versions may append fields
+        assert!(parse_traceparent("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra").is_some());
+    }
+
+    #[test]
+    fn test_continue_trace_creates_child_span() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(TRACEPARENT, "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00".parse().unwrap());
+        headers.insert(TRACESTATE, "congo=t61rcWkgMzE".parse().unwrap());
+
+        let trace = TraceContext::from_headers(&headers);
+        assert_eq!(trace.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(trace.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_ne!(trace.span_id, "00f067aa0ba902b7");
+        assert!(!trace.sampled());
+        assert_eq!(trace.tracestate.as_deref(), Some("congo=t61rcWkgMzE"));
+        assert!(trace.traceparent().starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(parse_traceparent(&trace.traceparent()).is_some());
+    }
+
+    #[test]
+    fn test_new_trace_when_missing_or_invalid() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(TRACEPARENT, "garbage".parse().unwrap());
+        headers.insert(TRACESTATE, "congo=t61rcWkgMzE".parse().unwrap());
+
+        let trace = TraceContext::from_headers(&headers);
+        assert_eq!(trace.trace_id.len(), 32);
+        assert_eq!(trace.span_id.len(), 16);
+        assert!(trace.parent_span_id.is_none());
+        assert!(trace.tracestate.is_none());
+    }
+
+    #[test]
+    fn test_request_id_validation() {
+        assert!(is_valid_request_id("abc-123"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("has space"));
+        assert!(!is_valid_request_id(&"a".repeat(600)));
+    }
+}
@@ -35,32 +35,53 @@ pub struct HostName(pub String);
 /// Uses standard library resolution which is blocking. Acceptable for startup configuration phase.
 pub fn create_upstream(address_string: &str) -> Option<Upstream> {
     // Guard: Parse URL components
-    let (socket_address, scheme, host) = parse_url_components(address_string)?;
-    
+    let (scheme, host, port) = split_url(address_string)?;
+
+    // Resolve address (Blocking)
+    let socket_address = format!("{}:{}", host, port).to_socket_addrs().ok()?.next()?;
+
+    build_upstream(socket_address, scheme, host)
+}
+
+/// Creates an `Upstream` from an address rendered while serving a request.
+///
+/// Accepts the same formats as `create_upstream`, but resolves the host
+/// without blocking the runtime.
+///
+/// - Parameter address_string: The URL string to parse.
+/// - Returns: The configured backend, or `None` if parsing or resolution fails.
+pub async fn resolve_upstream(address_string: &str) -> Option<Upstream> {
+    let (scheme, host, port) = split_url(address_string)?;
+    let socket_address = tokio::net::lookup_host(format!("{}:{}", host, port)).await.ok()?.next()?;
+    build_upstream(socket_address, scheme, host)
+}
+
+// MARK: - Private Helpers
+
+/// Creates the Pingora backend for a resolved address and enriches it with metadata.
+fn build_upstream(socket_address: std::net::SocketAddr, scheme: Scheme, host: &str) -> Option<Upstream> {
     // Create Backend with the resolved IP address
     let mut backend = Upstream::new(&socket_address.to_string()).ok()?;
-    
+
     // Enrich with metadata
     backend.ext.insert(scheme);
-    backend.ext.insert(HostName(host));
-    
+    backend.ext.insert(HostName(host.to_string()));
+
     Some(backend)
 }
 
-// MARK: - Private Helpers
-
 /// Parses a URL string into its core components.
 ///
 /// - Parameter upstream: The upstream string to parse.
-/// - Returns: A tuple of `(SocketAddr, Scheme, HostString)` or `None`.
-fn parse_url_components(upstream: &str) -> Option<(std::net::SocketAddr, Scheme, String)> {
+/// - Returns: A tuple of `(Scheme, Host, Port)` or `None`.
+fn split_url(upstream: &str) -> Option<(Scheme, &str, u16)> {
     let trimmed_upstream = upstream.trim();
     
     // Determine scheme and strip prefix
-    let (scheme, minimal_url) = if trimmed_upstream.starts_with("https://") {
-        (Scheme::Https, &trimmed_upstream[8..])
-    } else if trimmed_upstream.starts_with("http://") {
-        (Scheme::Http, &trimmed_upstream[7..])
+    let (scheme, minimal_url) = if let Some(rest) = trimmed_upstream.strip_prefix("https://") {
+        (Scheme::Https, rest)
+    } else if let Some(rest) = trimmed_upstream.strip_prefix("http://") {
+        (Scheme::Http, rest)
     } else {
         (Scheme::Http, trimmed_upstream)
     };
@@ -76,8 +97,5 @@ fn parse_url_components(upstream: &str) -> Option<(std::net::SocketAddr, Scheme,
         (minimal_url, default_port)
     };
     
-    // Resolve address (Blocking)
-    let socket_address = format!("{}:{}", host, port).to_socket_addrs().ok()?.next()?;
-    
-    Some((socket_address, scheme, host.to_string()))
+    Some((scheme, host, port))
 }
@@ -1,6 +1,7 @@
 //! File server implementation
 
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use pingclair_core::error::Result;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
@@ -32,8 +33,11 @@ impl Default for FileServerConfig {
 }
 
 /// Static file server
+#[derive(Debug)]
 pub struct FileServer {
     config: FileServerConfig,
+    /// Root with symlinks resolved (`None` while the root does not exist yet)
+    canonical_root: Option<PathBuf>,
 }
 
 /// Response from file server
@@ -116,7 +120,8 @@ impl StreamingFile {
 impl FileServer {
     /// Create a new file server
     pub fn new(config: FileServerConfig) -> Self {
-        Self { config }
+        let canonical_root = std::fs::canonicalize(&config.root).ok();
+        Self { config, canonical_root }
     }
 
     /// Create a file server for a directory
@@ -154,6 +159,45 @@ impl FileServer {
         Ok(None)
     }
     
+    /// Map a request path onto an existing file or directory below the root.
+    ///
+    /// 🛑 SAFETY: Segments are percent-decoded one at a time, so an encoded
+    /// `/` or NUL cannot smuggle in a separator, `..` may not climb above the
+    /// root, and the target with symlinks resolved must stay inside the
+    /// canonical root.
+    ///
+    /// - Parameter path: The request path.
+    /// - Returns: The path to open (as requested, so MIME types follow the
+    ///   request name), or `None` when it does not exist or escapes the root.
+    async fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let root = self.root().await?;
+        let mut relative = PathBuf::new();
+        for segment in path.split('/') {
+            let segment = decode_segment(segment)?;
+            match segment.as_ref() {
+                "" | "." => {}
+                ".." => {
+                    if !relative.pop() {
+                        return None;
+                    }
+                }
+                s if s.contains(['/', '\\', '\0']) => return None,
+                s => relative.push(s),
+            }
+        }
+
+        let file_path = root.join(relative);
+        is_within(&root, &file_path).await.then_some(file_path)
+    }
+
+    /// The canonical root, resolved now if it did not exist at startup
+    async fn root(&self) -> Option<Cow<'_, Path>> {
+        match &self.canonical_root {
+            Some(root) => Some(Cow::Borrowed(root.as_path())),
+            None => tokio::fs::canonicalize(&self.config.root).await.ok().map(Cow::Owned),
+        }
+    }
+
     /// THRESHOLD for using streaming vs in-memory (5MB)
     const STREAMING_THRESHOLD: u64 = 5 * 1024 * 1024;
     
@@ -161,12 +205,9 @@ impl FileServer {
     /// Returns a StreamingFile that can be used for chunked transfer
     /// Use this for files larger than 5MB to avoid memory pressure
     pub async fn serve_streaming(&self, path: &str) -> Result<Option<StreamingFile>> {
-        let file_path = self.config.root.join(path.trim_start_matches('/'));
-        
-        // Prevent path traversal
-        if !file_path.starts_with(&self.config.root) {
+        let Some(file_path) = self.resolve(path).await else {
             return Ok(None);
-        }
+        };
         
         // Check if file exists
         let metadata = match tokio::fs::metadata(&file_path).await {
@@ -186,7 +227,7 @@ impl FileServer {
         
         // Calculate Last-Modified and ETag
         let last_modified = metadata.modified().ok()
-            .map(|t| httpdate::fmt_http_date(t));
+            .map(httpdate::fmt_http_date);
             
         let etag = format!("\"{:x}-{:x}\"", file_size, 
             metadata.modified().map(|t| t.elapsed().unwrap_or_default().as_secs()).unwrap_or(0));
@@ -205,7 +246,9 @@ impl FileServer {
     
     /// Check if a file should be served with streaming (based on size)
     pub async fn should_stream(&self, path: &str) -> Result<bool> {
-        let file_path = self.config.root.join(path.trim_start_matches('/'));
+        let Some(file_path) = self.resolve(path).await else {
+            return Ok(false);
+        };
         match tokio::fs::metadata(&file_path).await {
             Ok(m) => Ok(m.len() > Self::STREAMING_THRESHOLD),
             Err(_) => Ok(false),
@@ -215,12 +258,9 @@ impl FileServer {
 
     /// Serve a file request
     pub async fn serve(&self, path: &str, range_header: Option<&str>, accept_encoding: Option<&str>) -> Result<Option<ServedFile>> {
-        let mut file_path = self.config.root.join(path.trim_start_matches('/'));
-        
-        // Prevent path traversal
-        if !file_path.starts_with(&self.config.root) {
+        let Some(mut file_path) = self.resolve(path).await else {
             return Ok(None);
-        }
+        };
 
         tracing::debug!("📁 Serving request: {} -> {:?}", path, file_path);
         
@@ -232,11 +272,14 @@ impl FileServer {
 
         // Handle directory
         if metadata.is_dir() {
-            // Try index files
+            // Try index files (which may be symlinks too)
+            let root = self.root().await;
             let mut index_found = false;
             for index in &self.config.index {
                 let index_path = file_path.join(index);
-                if tokio::fs::try_exists(&index_path).await.unwrap_or(false) {
+                if let Some(root) = &root
+                    && is_within(root, &index_path).await
+                {
                     file_path = index_path;
                     index_found = true;
                     break;
@@ -279,7 +322,7 @@ impl FileServer {
         
         // Calculate Last-Modified and ETag
         let last_modified = metadata.modified().ok()
-            .map(|t| httpdate::fmt_http_date(t));
+            .map(httpdate::fmt_http_date);
             
         let etag = format!("\"{:x}-{:x}\"", file_size, 
             metadata.modified().map(|t| t.elapsed().unwrap_or_default().as_secs()).unwrap_or(0));
@@ -359,6 +402,7 @@ impl FileServer {
     /// Checks for .br, .gz, .zst files in order of preference based on Accept-Encoding
     async fn try_precompressed(&self, original_path: &std::path::Path, accept_encoding: Option<&str>) -> Option<(Vec<u8>, &'static str)> {
         let accept = accept_encoding?;
+        let root = self.root().await?;
         
         // Priority order based on compression ratio and modern support:
         // 1. Brotli (.br) - best for web
@@ -380,7 +424,10 @@ impl FileServer {
             precompressed_path.push(ext);
             let precompressed_path = std::path::PathBuf::from(precompressed_path);
             
-            // Check if pre-compressed file exists and is readable
+            // Check if pre-compressed file exists, stays inside the root and is readable
+            if !is_within(&root, &precompressed_path).await {
+                continue;
+            }
             if let Ok(content) = tokio::fs::read(&precompressed_path).await {
                 return Some((content, encoding));
             }
@@ -467,3 +514,89 @@ impl FileServer {
         Some((start, std::cmp::min(end, file_size - 1)))
     }
 }
+
+/// Whether `path` exists and, with symlinks resolved, lies inside the canonical `root`
+async fn is_within(root: &Path, path: &Path) -> bool {
+    match tokio::fs::canonicalize(path).await {
+        Ok(canonical) => canonical.starts_with(root),
+        Err(_) => false,
+    }
+}
+
+/// Percent-decode one path segment (`None` when the result is not UTF-8)
+fn decode_segment(segment: &str) -> Option<Cow<'_, str>> {
+    if !segment.contains('%') {
+        return Some(Cow::Borrowed(segment));
+    }
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = match (bytes[i], segment.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => u8::from_str_radix(hex, 16).ok(),
+            _ => None,
+        };
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok().map(Cow::Owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pingclair-fs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("public/docs")).unwrap();
+        std::fs::write(dir.join("public/docs/index.html"), "docs").unwrap();
+        std::fs::write(dir.join("public/a b.txt"), "spaced").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_resolve_stays_inside_root() {
+        let dir = fixture("traversal");
+        let server = FileServer::serve_dir(dir.join("public"));
+
+        assert!(server.resolve("/docs/index.html").await.is_some());
+        assert!(server.resolve("/docs/../docs/index.html").await.is_some());
+        assert!(server.resolve("/a%20b.txt").await.is_some());
+        assert!(server.resolve("/../secret.txt").await.is_none());
+        assert!(server.resolve("/%2e%2e/secret.txt").await.is_none());
+        assert!(server.resolve("/docs%2F..%2F..%2Fsecret.txt").await.is_none());
+        assert!(server.resolve("/docs/index.html%00").await.is_none());
+
+        let served = server.serve("/docs/", None, None).await.unwrap().unwrap();
+        assert_eq!(served.content, b"docs");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinks_outside_root_are_refused() {
+        let dir = fixture("symlink");
+        let public = dir.join("public");
+        std::os::unix::fs::symlink(dir.join("secret.txt"), public.join("leak.txt")).unwrap();
+        std::os::unix::fs::symlink(public.join("a b.txt"), public.join("alias.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.join("secret.txt"), public.join("docs/index.htm")).unwrap();
+        std::fs::remove_file(public.join("docs/index.html")).unwrap();
+        let server = FileServer::serve_dir(&public);
+
+        assert!(server.serve("/leak.txt", None, None).await.unwrap().is_none());
+        assert!(server.serve("/docs/", None, None).await.unwrap().is_none());
+        let served = server.serve("/alias.txt", None, None).await.unwrap().unwrap();
+        assert_eq!(served.content, b"spaced");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
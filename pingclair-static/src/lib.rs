@@ -5,10 +5,13 @@
 //! - Compression (gzip, brotli, zstd)
 //! - Directory browsing
 //! - Index file handling
+//! - Server-side templates
 
 mod compress;
 mod file_server;
 mod mime;
+mod templates;
 
 pub use compress::CompressionLevel;
-pub use file_server::{FileServer, FileServerConfig};
+pub use file_server::{FileServer, FileServerConfig, ServedFile};
+pub use templates::{Templates, TemplatesConfig, TemplateContext};
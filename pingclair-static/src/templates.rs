@@ -0,0 +1,839 @@
+//! Server-side templates (à la Caddy `templates`)
+//!
+//! 🏗️ ARCHITECTURE: A small Go-template-flavoured engine:
+//!   - Actions: `{{ .Host }}`, `{{ header "User-Agent" }}`, `{{ include "post.md" | markdown }}`
+//!   - Control: `{{ if query "debug" }}...{{ else }}...{{ end }}`, comments `{{/* ... */}}`
+//!   - Trim markers: `{{- ... -}}` strip the surrounding whitespace
+//!
+//! Included files are read with `tokio::fs`, cached parsed, and re-parsed when
+//! their mtime changes.
+//!
+//! 🛑 SAFETY: Request-derived values (`header`, `query`, `cookie`, `placeholder`,
+//! request fields) are HTML-escaped when the response is `text/html`, and raw
+//! HTML in them is not passed through `markdown`.
+
+use pingclair_core::error::{Error, Result};
+use pulldown_cmark::{html, Event, Options, Parser};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// Maximum nesting depth for `include`
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+// MARK: - Configuration
+
+/// Configuration for the templates handler
+#[derive(Debug, Clone)]
+pub struct TemplatesConfig {
+    /// Site root that `include` paths are resolved against
+    pub root: PathBuf,
+    /// Response MIME types rendered as templates
+    pub mime_types: Vec<String>,
+    /// Action delimiters
+    pub delimiters: (String, String),
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("."),
+            mime_types: vec![
+                "text/html".to_string(),
+                "text/plain".to_string(),
+                "text/markdown".to_string(),
+            ],
+            delimiters: ("{{".to_string(), "}}".to_string()),
+        }
+    }
+}
+
+// MARK: - Request Context
+
+/// Request data exposed to templates
+pub struct TemplateContext<'a> {
+    /// Request method
+    pub method: &'a str,
+    /// Request host (without port)
+    pub host: &'a str,
+    /// Request URI (path and query)
+    pub uri: &'a str,
+    /// Client IP address
+    pub remote_ip: &'a str,
+    /// Request headers
+    pub headers: &'a http::HeaderMap,
+    /// Resolves placeholder names such as `http.request.uri.path`
+    pub placeholders: &'a (dyn Fn(&str) -> String + Sync),
+}
+
+impl TemplateContext<'_> {
+    fn path(&self) -> &str {
+        self.uri.split('?').next().unwrap_or("")
+    }
+
+    fn query_string(&self) -> &str {
+        self.uri.split_once('?').map(|(_, q)| q).unwrap_or("")
+    }
+
+    fn query(&self, name: &str) -> String {
+        self.query_string()
+            .split('&')
+            .filter_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key) == name).then(|| percent_decode(value))
+            })
+            .next()
+            .unwrap_or_default()
+    }
+
+    fn header(&self, name: &str) -> String {
+        self.headers.get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string()
+    }
+
+    fn cookie(&self, name: &str) -> String {
+        self.headers.get_all(http::header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_default()
+    }
+}
+
+// MARK: - Templates
+
+/// Template renderer with an mtime-keyed parse cache for included files
+pub struct Templates {
+    config: TemplatesConfig,
+    /// Canonical site root (None if it did not exist at startup)
+    canonical_root: Option<PathBuf>,
+    cache: RwLock<HashMap<PathBuf, CachedTemplate>>,
+}
+
+struct CachedTemplate {
+    modified: SystemTime,
+    template: Arc<Template>,
+}
+
+impl Templates {
+    /// Create a templates renderer
+    pub fn new(config: TemplatesConfig) -> Self {
+        let canonical_root = std::fs::canonicalize(&config.root).ok();
+        Self {
+            config,
+            canonical_root,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Get the configuration
+    pub fn config(&self) -> &TemplatesConfig {
+        &self.config
+    }
+
+    /// Whether a response with this `Content-Type` should be rendered
+    pub fn applies_to(&self, content_type: &str) -> bool {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        self.config.mime_types.iter().any(|m| m.eq_ignore_ascii_case(mime))
+    }
+
+    /// Render an in-memory document, e.g. a served file or an upstream response body.
+    ///
+    /// ⚡ OPTIMIZATION: Each pass renders with the includes loaded so far and
+    /// records those still missing, which are then read with `tokio::fs`; a
+    /// document without includes renders in a single pass.
+    ///
+    /// - Parameter content_type: The response content type (controls HTML escaping).
+    /// - Returns: The rendered document.
+    pub async fn render_source(&self, source: &str, content_type: &str, ctx: &TemplateContext<'_>) -> Result<String> {
+        let template = Template::parse(source, true, &self.config.delimiters)?;
+        let mut includes = HashMap::new();
+
+        for _ in 0..=MAX_INCLUDE_DEPTH {
+            let renderer = self.renderer(content_type, ctx, &includes);
+            let rendered = renderer.render_template(&template, 0);
+            let missing = renderer.missing.into_inner();
+            if missing.is_empty() {
+                return rendered;
+            }
+            for name in missing {
+                let path = self.resolve_include(&name).await?;
+                includes.insert(name, self.load(&path).await?);
+            }
+        }
+        Err(Error::Template("include nested too deeply".to_string()))
+    }
+
+    /// Render from a synchronous context, such as Pingora's body filters.
+    ///
+    /// Includes are still read with `tokio::fs`; on a multi-threaded runtime the
+    /// worker hands its other tasks off while it waits.
+    pub fn render_source_blocking(&self, source: &str, content_type: &str, ctx: &TemplateContext<'_>) -> Result<String> {
+        let render = self.render_source(source, content_type, ctx);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| handle.block_on(render))
+            }
+            _ => futures::executor::block_on(render),
+        }
+    }
+
+    fn renderer<'a>(
+        &'a self,
+        content_type: &str,
+        ctx: &'a TemplateContext<'a>,
+        includes: &'a HashMap<String, Arc<Template>>,
+    ) -> Renderer<'a> {
+        Renderer {
+            ctx,
+            includes,
+            missing: RefCell::new(Vec::new()),
+            escape_html: content_type.trim_start().starts_with("text/html"),
+        }
+    }
+
+    /// Load a parsed include, re-parsing when the file changed on disk
+    async fn load(&self, path: &Path) -> Result<Arc<Template>> {
+        let modified = tokio::fs::metadata(path).await?.modified()?;
+
+        let cached = self.cache.read().ok().and_then(|c| {
+            c.get(path).filter(|e| e.modified == modified).map(|e| e.template.clone())
+        });
+        if let Some(cached) = cached {
+            return Ok(cached);
+        }
+
+        let source = tokio::fs::read_to_string(path).await?;
+        let template = Arc::new(Template::parse(&source, false, &self.config.delimiters)?);
+        if let Ok(mut cache) = self.cache.write() {
+            cache.insert(path.to_path_buf(), CachedTemplate { modified, template: template.clone() });
+        }
+        Ok(template)
+    }
+
+    /// Resolve an `include` path inside the site root
+    ///
+    /// 🛑 SAFETY: `..` is refused outright, and the canonical path (symlinks
+    /// resolved) must still lie inside the canonical root.
+    async fn resolve_include(&self, path: &str) -> Result<PathBuf> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        let escapes_root = relative.components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if escapes_root {
+            return Err(Error::Template(format!("include path escapes site root: {}", path)));
+        }
+
+        let root = match &self.canonical_root {
+            Some(root) => Cow::Borrowed(root.as_path()),
+            None => Cow::Owned(tokio::fs::canonicalize(&self.config.root).await?),
+        };
+        let canonical = tokio::fs::canonicalize(self.config.root.join(relative)).await?;
+        if !canonical.starts_with(&root) {
+            return Err(Error::Template(format!("include path escapes site root: {}", path)));
+        }
+        Ok(canonical)
+    }
+}
+
+// MARK: - Parsed Template
+
+struct Template {
+    front_matter: HashMap<String, String>,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Action(Pipeline),
+    If { cond: Pipeline, then: Vec<Node>, otherwise: Vec<Node> },
+}
+
+#[derive(Debug)]
+struct Pipeline(Vec<Command>);
+
+#[derive(Debug)]
+enum Command {
+    Func { name: String, args: Vec<Arg> },
+    Arg(Arg),
+}
+
+#[derive(Debug)]
+enum Arg {
+    Str(String),
+    Field(String),
+}
+
+/// An `if` block being parsed
+struct Frame {
+    cond: Option<Pipeline>,
+    then: Vec<Node>,
+    otherwise: Option<Vec<Node>>,
+}
+
+impl Frame {
+    fn nodes(&mut self) -> &mut Vec<Node> {
+        self.otherwise.as_mut().unwrap_or(&mut self.then)
+    }
+}
+
+impl Template {
+    fn parse(source: &str, strip_front_matter: bool, delimiters: &(String, String)) -> Result<Self> {
+        let (front_matter, body) = if strip_front_matter {
+            split_front_matter(source)
+        } else {
+            (HashMap::new(), source)
+        };
+
+        let (left, right) = (delimiters.0.as_str(), delimiters.1.as_str());
+        let mut stack = vec![Frame { cond: None, then: Vec::new(), otherwise: None }];
+        let mut rest = body;
+        let mut trim_next = false;
+
+        while !rest.is_empty() {
+            let Some(start) = rest.find(left) else {
+                push_text(&mut stack, rest, trim_next, false);
+                break;
+            };
+
+            let after_left = &rest[start + left.len()..];
+            let trim_prev = after_left.starts_with("- ") || after_left.starts_with("-\n");
+            push_text(&mut stack, &rest[..start], trim_next, trim_prev);
+
+            let end = find_action_end(after_left, right)
+                .ok_or_else(|| Error::Template("unclosed action".to_string()))?;
+            let mut action = &after_left[..end];
+            rest = &after_left[end + right.len()..];
+
+            if trim_prev {
+                action = &action[1..];
+            }
+            trim_next = action.ends_with(" -") || action.ends_with("\n-");
+            if trim_next {
+                action = &action[..action.len() - 1];
+            }
+            let action = action.trim();
+
+            if action.starts_with("/*") && action.ends_with("*/") {
+                continue;
+            }
+
+            if let Some(cond) = action.strip_prefix("if ") {
+                stack.push(Frame { cond: Some(parse_pipeline(cond)?), then: Vec::new(), otherwise: None });
+            } else if action == "else" {
+                let frame = stack.last_mut().filter(|f| f.cond.is_some() && f.otherwise.is_none())
+                    .ok_or_else(|| Error::Template("unexpected {{else}}".to_string()))?;
+                frame.otherwise = Some(Vec::new());
+            } else if action == "end" {
+                if stack.len() < 2 {
+                    return Err(Error::Template("unexpected {{end}}".to_string()));
+                }
+                let frame = stack.pop().unwrap();
+                let node = Node::If {
+                    cond: frame.cond.unwrap(),
+                    then: frame.then,
+                    otherwise: frame.otherwise.unwrap_or_default(),
+                };
+                stack.last_mut().unwrap().nodes().push(node);
+            } else {
+                let pipeline = parse_pipeline(action)?;
+                stack.last_mut().unwrap().nodes().push(Node::Action(pipeline));
+            }
+        }
+
+        if stack.len() != 1 {
+            return Err(Error::Template("missing {{end}}".to_string()));
+        }
+
+        Ok(Self {
+            front_matter,
+            nodes: stack.pop().unwrap().then,
+        })
+    }
+}
+
+fn push_text(stack: &mut [Frame], text: &str, trim_start: bool, trim_end: bool) {
+    let text = if trim_start { text.trim_start() } else { text };
+    let text = if trim_end { text.trim_end() } else { text };
+    if !text.is_empty()
+        && let Some(frame) = stack.last_mut()
+    {
+        frame.nodes().push(Node::Text(text.to_string()));
+    }
+}
+
+/// Find the closing delimiter, skipping over string literals
+fn find_action_end(action: &str, right: &str) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in action.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None if c == '"' || c == '`' => quote = Some(c),
+            None if action[i..].starts_with(right) => return Some(i),
+            None => {}
+        }
+    }
+    None
+}
+
+// MARK: - Action Parsing
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Str(String),
+    Field(String),
+    Ident(String),
+    Pipe,
+}
+
+fn tokenize(action: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = action.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => { chars.next(); }
+            '|' => { chars.next(); tokens.push(Token::Pipe); }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some(other) => value.push(other),
+                            None => return Err(Error::Template("unterminated string".to_string())),
+                        },
+                        Some(other) => value.push(other),
+                        None => return Err(Error::Template("unterminated string".to_string())),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            '`' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('`') => break,
+                        Some(other) => value.push(other),
+                        None => return Err(Error::Template("unterminated raw string".to_string())),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '|' || c == '"' || c == '`' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                match word.strip_prefix('.') {
+                    Some(field) => tokens.push(Token::Field(field.to_string())),
+                    None => tokens.push(Token::Ident(word)),
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_pipeline(action: &str) -> Result<Pipeline> {
+    let tokens = tokenize(action)?;
+    let mut commands = Vec::new();
+
+    for segment in tokens.split(|t| *t == Token::Pipe) {
+        let mut parts = segment.iter();
+        let command = match parts.next() {
+            Some(Token::Ident(name)) => {
+                let args = parts.map(|t| match t {
+                    Token::Str(s) => Ok(Arg::Str(s.clone())),
+                    Token::Field(f) => Ok(Arg::Field(f.clone())),
+                    other => Err(Error::Template(format!("unexpected argument {:?} in '{}'", other, action))),
+                }).collect::<Result<Vec<_>>>()?;
+                Command::Func { name: name.clone(), args }
+            }
+            Some(Token::Str(s)) if segment.len() == 1 => Command::Arg(Arg::Str(s.clone())),
+            Some(Token::Field(f)) if segment.len() == 1 => Command::Arg(Arg::Field(f.clone())),
+            _ => return Err(Error::Template(format!("invalid action '{}'", action))),
+        };
+        commands.push(command);
+    }
+
+    Ok(Pipeline(commands))
+}
+
+// MARK: - Rendering
+
+/// A rendered value; `safe` values are never HTML-escaped on output
+struct Value {
+    text: String,
+    safe: bool,
+}
+
+impl Value {
+    fn safe(text: String) -> Self {
+        Self { text, safe: true }
+    }
+
+    fn unsafe_(text: String) -> Self {
+        Self { text, safe: false }
+    }
+}
+
+struct Renderer<'a> {
+    ctx: &'a TemplateContext<'a>,
+    /// Includes loaded so far, by the name passed to `include`
+    includes: &'a HashMap<String, Arc<Template>>,
+    /// Includes this pass needed but did not have
+    missing: RefCell<Vec<String>>,
+    escape_html: bool,
+}
+
+impl Renderer<'_> {
+    fn render_template(&self, template: &Template, depth: usize) -> Result<String> {
+        let mut out = String::new();
+        self.render_nodes(&template.nodes, template, depth, &mut out)?;
+        Ok(out)
+    }
+
+    fn render_nodes(&self, nodes: &[Node], template: &Template, depth: usize, out: &mut String) -> Result<()> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Action(pipeline) => {
+                    let value = self.eval(pipeline, template, depth)?;
+                    if self.escape_html && !value.safe {
+                        out.push_str(&html_escape(&value.text));
+                    } else {
+                        out.push_str(&value.text);
+                    }
+                }
+                Node::If { cond, then, otherwise } => {
+                    let branch = if self.eval(cond, template, depth)?.text.is_empty() { otherwise } else { then };
+                    self.render_nodes(branch, template, depth, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn eval(&self, pipeline: &Pipeline, template: &Template, depth: usize) -> Result<Value> {
+        let mut piped: Option<Value> = None;
+        for command in &pipeline.0 {
+            piped = Some(match command {
+                Command::Arg(arg) => self.eval_arg(arg)?,
+                Command::Func { name, args } => {
+                    let mut values = args.iter().map(|a| self.eval_arg(a)).collect::<Result<Vec<_>>>()?;
+                    // Go template semantics: the piped value is the last argument
+                    values.extend(piped.take());
+                    self.call(name, values, template, depth)?
+                }
+            });
+        }
+        Ok(piped.unwrap_or_else(|| Value::safe(String::new())))
+    }
+
+    fn eval_arg(&self, arg: &Arg) -> Result<Value> {
+        match arg {
+            Arg::Str(s) => Ok(Value::safe(s.clone())),
+            Arg::Field(field) => {
+                let ctx = self.ctx;
+                let value = match field.as_str() {
+                    "Host" => ctx.host,
+                    "Method" => ctx.method,
+                    "Path" => ctx.path(),
+                    "URI" => ctx.uri,
+                    "Query" => ctx.query_string(),
+                    "RemoteIP" => ctx.remote_ip,
+                    other => return Err(Error::Template(format!("unknown field .{}", other))),
+                };
+                Ok(Value::unsafe_(value.to_string()))
+            }
+        }
+    }
+
+    fn call(&self, name: &str, args: Vec<Value>, template: &Template, depth: usize) -> Result<Value> {
+        let arg = |i: usize| -> Result<&Value> {
+            args.get(i).ok_or_else(|| Error::Template(format!("{} expects {} argument(s)", name, i + 1)))
+        };
+
+        match name {
+            "header" => Ok(Value::unsafe_(self.ctx.header(&arg(0)?.text))),
+            "query" => Ok(Value::unsafe_(self.ctx.query(&arg(0)?.text))),
+            "cookie" => Ok(Value::unsafe_(self.ctx.cookie(&arg(0)?.text))),
+            "placeholder" => Ok(Value::unsafe_((self.ctx.placeholders)(&arg(0)?.text))),
+            "env" => Ok(Value::safe(std::env::var(&arg(0)?.text).unwrap_or_default())),
+            "frontMatter" => Ok(Value::safe(
+                template.front_matter.get(&arg(0)?.text).cloned().unwrap_or_default(),
+            )),
+            "stripFrontMatter" => {
+                let value = arg(0)?;
+                Ok(Value { text: split_front_matter(&value.text).1.to_string(), safe: value.safe })
+            }
+            "markdown" => {
+                let value = arg(0)?;
+                Ok(Value::safe(render_markdown(&value.text, value.safe)))
+            }
+            "html" => Ok(Value::safe(html_escape(&arg(0)?.text))),
+            "include" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(Error::Template("include nested too deeply".to_string()));
+                }
+                let name = &arg(0)?.text;
+                match self.includes.get(name) {
+                    Some(included) => Ok(Value::safe(self.render_template(included, depth + 1)?)),
+                    None => {
+                        // Loaded before the next pass
+                        let mut missing = self.missing.borrow_mut();
+                        if !missing.contains(name) {
+                            missing.push(name.clone());
+                        }
+                        Ok(Value::safe(String::new()))
+                    }
+                }
+            }
+            other => Err(Error::Template(format!("unknown function {}", other))),
+        }
+    }
+}
+
+// MARK: - Helpers
+
+/// Split leading YAML (`---`) or TOML (`+++`) front matter from a document.
+///
+/// Only flat `key: value` / `key = value` pairs are supported.
+fn split_front_matter(source: &str) -> (HashMap<String, String>, &str) {
+    let mut fields = HashMap::new();
+
+    let fence = match source.get(..3) {
+        Some(f @ ("---" | "+++")) => f,
+        _ => return (fields, source),
+    };
+    let Some(first_newline) = source.find('\n') else {
+        return (fields, source);
+    };
+    if source[..first_newline].trim_end() != fence {
+        return (fields, source);
+    }
+
+    let mut offset = first_newline + 1;
+    for line in source[offset..].split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim_end();
+        if line == fence {
+            return (fields, &source[offset..]);
+        }
+        let separator = if fence == "---" { ':' } else { '=' };
+        if let Some((key, value)) = line.split_once(separator) {
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            fields.insert(key.trim().to_string(), value.to_string());
+        }
+    }
+
+    // No closing fence: not front matter
+    (HashMap::new(), source)
+}
+
+/// Render markdown to HTML; raw HTML is escaped unless the input is trusted
+fn render_markdown(text: &str, allow_html: bool) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_TASKLISTS;
+    let parser = Parser::new_ext(text, options);
+
+    let mut out = String::with_capacity(text.len() * 3 / 2);
+    if allow_html {
+        html::push_html(&mut out, parser);
+    } else {
+        html::push_html(&mut out, parser.map(|event| match event {
+            Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+            other => other,
+        }));
+    }
+    out
+}
+
+fn html_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => match (bytes.get(i + 1).and_then(|b| hex(*b)), bytes.get(i + 2).and_then(|b| hex(*b))) {
+                (Some(hi), Some(lo)) => {
+                    out.push(hi << 4 | lo);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// MARK: - Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, content_type: &str, headers: &http::HeaderMap) -> String {
+        let templates = Templates::new(TemplatesConfig::default());
+        let placeholders = |name: &str| format!("<{}>", name);
+        let ctx = TemplateContext {
+            method: "GET",
+            host: "example.com",
+            uri: "/docs?name=Ada%20L&debug=1",
+            remote_ip: "127.0.0.1",
+            headers,
+            placeholders: &placeholders,
+        };
+        templates.render_source_blocking(source, content_type, &ctx).unwrap()
+    }
+
+    #[test]
+    fn test_request_data() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("user-agent", "curl/8".parse().unwrap());
+        headers.insert("cookie", "theme=dark; session=abc".parse().unwrap());
+
+        let out = render(
+            r#"{{ .Method }} {{.Host}}{{ .Path }} {{ query "name" }} {{ header "User-Agent" }} {{ cookie "theme" }}"#,
+            "text/plain",
+            &headers,
+        );
+        assert_eq!(out, "GET example.com/docs Ada L curl/8 dark");
+    }
+
+    #[test]
+    fn test_html_escaping_of_request_data() {
+        let headers = http::HeaderMap::new();
+        let out = render(r#"<p>{{ placeholder "x" }}</p>"#, "text/html; charset=utf-8", &headers);
+        assert_eq!(out, "<p>&lt;x&gt;</p>");
+    }
+
+    #[test]
+    fn test_conditionals_and_trim() {
+        let headers = http::HeaderMap::new();
+        let out = render(
+            "a {{- if query \"debug\" }} on {{- else }} off {{- end -}} \n b{{/* comment */}}",
+            "text/plain",
+            &headers,
+        );
+        assert_eq!(out, "a onb");
+    }
+
+    #[test]
+    fn test_front_matter_and_markdown() {
+        let headers = http::HeaderMap::new();
+        let out = render(
+            "---\ntitle: \"Hello\"\n---\n<h1>{{ frontMatter \"title\" }}</h1>{{ \"*hi*\" | markdown }}",
+            "text/html",
+            &headers,
+        );
+        assert_eq!(out, "<h1>Hello</h1><p><em>hi</em></p>\n");
+    }
+
+    #[test]
+    fn test_untrusted_markdown_escapes_html() {
+        assert!(!render_markdown("<script>x</script>", false).contains("<script>"));
+        assert!(render_markdown("<b>x</b>", true).contains("<b>"));
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pingclair-templates-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("site/partials")).unwrap();
+        std::fs::write(dir.join("site/partials/nav.html"), "<nav>{{ include \"partials/item.html\" }}</nav>").unwrap();
+        std::fs::write(dir.join("site/partials/item.html"), "{{ .Host }}").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_include_renders_nested_files() {
+        let dir = fixture("nested");
+        let templates = Templates::new(TemplatesConfig { root: dir.join("site"), ..Default::default() });
+        let headers = http::HeaderMap::new();
+        let placeholders = |_: &str| String::new();
+        let ctx = TemplateContext {
+            method: "GET",
+            host: "example.com",
+            uri: "/",
+            remote_ip: "127.0.0.1",
+            headers: &headers,
+            placeholders: &placeholders,
+        };
+        let out = templates.render_source(r#"{{ include "/partials/nav.html" }}!"#, "text/html", &ctx).await.unwrap();
+        assert_eq!(out, "<nav>example.com</nav>!");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_include_rejects_traversal() {
+        let dir = fixture("traversal");
+        let templates = Templates::new(TemplatesConfig { root: dir.join("site"), ..Default::default() });
+        assert!(templates.resolve_include("../secret.txt").await.is_err());
+        assert!(templates.resolve_include("/partials/nav.html").await.is_ok());
+
+        // A symlink inside the root must not lead out of it
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("secret.txt"), dir.join("site/leak.txt")).unwrap();
+            assert!(templates.resolve_include("leak.txt").await.is_err());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let delimiters = TemplatesConfig::default().delimiters;
+        assert!(Template::parse("{{ if .Host }}", true, &delimiters).is_err());
+        assert!(Template::parse("{{ end }}", true, &delimiters).is_err());
+        assert!(Template::parse("{{ header \"x", true, &delimiters).is_err());
+    }
+}
@@ -254,6 +254,11 @@ impl AutoHttps {
     pub async fn has_certificate(&self, domain: &str) -> bool {
         self.store.has_valid(domain).await
     }
+
+    /// Lists `(primary domain, expires_at)` for every managed certificate.
+    pub async fn certificate_expirations(&self) -> Vec<(String, i64)> {
+        self.store.expirations().await
+    }
 }
 
 #[cfg(test)]
@@ -191,17 +191,28 @@ impl CertStore {
             // Use the primary domain as a unique key for the certificate bundle
             let primary_key = cert.domains.first().cloned().unwrap_or_default();
             
-            if !primary_key.is_empty() && !seen_primary_keys.contains(&primary_key) {
-                if cert.needs_renewal() {
-                    seen_primary_keys.insert(primary_key);
-                    candidates.push(cert.clone());
-                }
+            if !primary_key.is_empty() && !seen_primary_keys.contains(&primary_key) && cert.needs_renewal() {
+                seen_primary_keys.insert(primary_key);
+                candidates.push(cert.clone());
             }
         }
         
         candidates
     }
     
+    /// Returns the expiry timestamp of every stored certificate, keyed by primary domain.
+    ///
+    /// Deduplicates SAN mappings so each certificate is only listed once.
+    pub async fn expirations(&self) -> Vec<(String, i64)> {
+        let cache = self.cache.read().await;
+        let mut expirations: Vec<(String, i64)> = cache.values()
+            .filter_map(|cert| cert.domains.first().map(|primary| (primary.clone(), cert.expires_at)))
+            .collect();
+        expirations.sort();
+        expirations.dedup();
+        expirations
+    }
+    
     /// Deletes a certificate (and its mappings) from both disk and cache.
     pub async fn remove(&self, domain: &str) -> Result<(), CertStoreError> {
         tracing::info!("🗑️ Requested removal of certificate for {}", domain);
@@ -261,6 +272,7 @@ mod tests {
         
         assert!(store2.get("a.com").await.is_some());
         assert!(store2.get("b.com").await.is_some());
+        assert_eq!(store2.expirations().await, vec![("a.com".to_string(), 1234567890)]);
         
         // Cleanup
         let _ = tokio::fs::remove_dir_all(&temp_dir).await;
@@ -226,6 +226,14 @@ impl TlsManager {
         self.challenge_handler.clone()
     }
 
+    /// 📅 Expiry timestamps (Unix seconds) of all managed certificates, by primary domain
+    pub async fn certificate_expirations(&self) -> Vec<(String, i64)> {
+        match &self.auto_https {
+            Some(auto) => auto.certificate_expirations().await,
+            None => Vec::new(),
+        }
+    }
+
     /// Clean expired cache entries
     pub fn cleanup_expired_cache(&self) {
         let current_time = SystemTime::now()
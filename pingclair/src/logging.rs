@@ -0,0 +1,92 @@
+//! Application logging driven by the global `log` configuration
+//!
+//! 🏗️ ARCHITECTURE: The tracing subscriber is installed once at startup with
+//! reloadable filter and output layers, so the global `LoggingConfig`
+//! (level, format, file) can be applied after the config is parsed and again
+//! on every reload. `RUST_LOG`, when set, always takes precedence over the
+//! configured level.
+
+use pingclair_core::config::{LogOutput, LoggingConfig};
+use pingclair_proxy::access_log::{LogWriter, LogWriterHandle};
+use std::sync::OnceLock;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
+
+type Base = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+type OutputLayer = Box<dyn Layer<Base> + Send + Sync>;
+
+/// Reload handles for the installed subscriber
+struct LogHandles {
+    filter: reload::Handle<EnvFilter, Registry>,
+    output: reload::Handle<OutputLayer, Base>,
+}
+
+static HANDLES: OnceLock<LogHandles> = OnceLock::new();
+
+// MARK: - Setup
+
+/// Install the global subscriber (stdout, `RUST_LOG` or `info`).
+pub fn init() {
+    let (filter, filter_handle) = reload::Layer::new(env_filter(None));
+    let (output, output_handle) = reload::Layer::new(output_layer(&LoggingConfig::default()));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .init();
+
+    let _ = HANDLES.set(LogHandles { filter: filter_handle, output: output_handle });
+}
+
+/// Apply the global logging configuration (startup and reload).
+///
+/// - Parameter config: The parsed `LoggingConfig`.
+pub fn apply(config: &LoggingConfig) {
+    let Some(handles) = HANDLES.get() else {
+        return;
+    };
+
+    let level = Some(config.level.as_str()).filter(|l| !l.is_empty());
+    if let Err(e) = handles.filter.reload(env_filter(level)) {
+        tracing::warn!("⚠️ Failed to apply log level: {}", e);
+    }
+    if let Err(e) = handles.output.reload(output_layer(config)) {
+        tracing::warn!("⚠️ Failed to apply log output: {}", e);
+    }
+}
+
+// MARK: - Layers
+
+/// `RUST_LOG` if set, else the configured level, else `info`
+fn env_filter(level: Option<&str>) -> EnvFilter {
+    if std::env::var_os(EnvFilter::DEFAULT_ENV).is_some() {
+        return EnvFilter::from_default_env();
+    }
+    EnvFilter::try_new(level.unwrap_or("info").to_ascii_lowercase())
+        .unwrap_or_else(|_| EnvFilter::new("info"))
+}
+
+/// Formatter for the configured format and destination
+fn output_layer(config: &LoggingConfig) -> OutputLayer {
+    let json = config.format.eq_ignore_ascii_case("json");
+
+    let writer = config.file.as_ref().and_then(|path| {
+        LogWriter::open(&LogOutput::File(path.clone()))
+            .inspect_err(|e| eprintln!("⚠️ Failed to open log file {}: {}", path, e))
+            .ok()
+    });
+
+    match (writer, json) {
+        (Some(writer), true) => fmt::layer()
+            .json()
+            .with_writer(move || LogWriterHandle(writer.clone()))
+            .boxed(),
+        (Some(writer), false) => fmt::layer()
+            .with_ansi(false)
+            .with_writer(move || LogWriterHandle(writer.clone()))
+            .boxed(),
+        (None, true) => fmt::layer().json().boxed(),
+        (None, false) => fmt::layer().boxed(),
+    }
+}
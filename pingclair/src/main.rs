@@ -3,7 +3,6 @@
 //! This is the main entry point for the Pingclair CLI.
 
 use clap::{Parser, Subcommand};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use std::sync::Arc;
 use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH, Duration};
@@ -16,6 +15,8 @@ use openssl::x509::X509;
 use openssl::pkey::{PKey, Private};
 use parking_lot::RwLock;
 
+mod logging;
+
 #[cfg(target_os = "linux")]
 #[global_allocator]
 static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
@@ -150,6 +151,11 @@ impl TlsAccept for DynamicCertResolver {
             tracing::info!("🔐 Cached OpenSSL cert for {} (expires in {}s)", sni, OPENSSL_CACHE_TTL_SECS);
         }
     }
+
+    async fn handshake_complete_callback(&self, ssl: &TlsRef) -> Option<Arc<dyn std::any::Any + Send + Sync>> {
+        pingclair_proxy::metrics::record_tls_handshake(ssl.version_str());
+        None
+    }
 }
 
 /// Pingclair - Modern web server inspired by Caddy, powered by Pingora
@@ -231,11 +237,8 @@ enum ServiceAction {
 
 
 fn main() -> anyhow::Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    // Initialize tracing (global `log` config is applied once parsed)
+    logging::init();
 
     let cli = Cli::parse();
 
@@ -292,9 +295,12 @@ fn main() -> anyhow::Result<()> {
                 listen: vec![listen],
                 routes: Vec::new(),
                 tls: None,
+                tls_listen: Vec::new(),
                 log: None,
                 client_max_body_size: 10 * 1024 * 1024, // 10MB
                 security: Default::default(),
+                path_normalization: Default::default(),
+                variables: Vec::new(),
             };
 
             let handler = HandlerConfig::ReverseProxy(ReverseProxyConfig {
@@ -302,10 +308,14 @@ fn main() -> anyhow::Result<()> {
                 load_balance: LoadBalanceConfig::default(),
                 health_check: None,
                 headers_up: std::collections::HashMap::new(),
+                headers_up_remove: Vec::new(),
+                forwarded_headers: Default::default(),
                 headers_down: std::collections::HashMap::new(),
                 flush_interval: None,
                 read_timeout: None,
                 write_timeout: None,
+                handle_response: Vec::new(),
+                proxy_protocol: None,
             });
 
             server.routes.push(RouteConfig {
@@ -313,6 +323,9 @@ fn main() -> anyhow::Result<()> {
                 handler,
                 methods: None, 
                 matcher: None,
+                internal: false,
+                priority: 0,
+                source_order: false,
             });
 
             config.servers.push(server);
@@ -340,9 +353,12 @@ fn main() -> anyhow::Result<()> {
                 listen: vec![listen_addr],
                 routes: Vec::new(),
                 tls: None,
+                tls_listen: Vec::new(),
                 log: None,
                 client_max_body_size: 10 * 1024 * 1024,
                 security: Default::default(),
+                path_normalization: Default::default(),
+                variables: Vec::new(),
             };
             
             // Resolve absolute path
@@ -362,6 +378,9 @@ fn main() -> anyhow::Result<()> {
                 handler,
                 methods: None, 
                 matcher: None,
+                internal: false,
+                priority: 0,
+                source_order: false,
             });
 
             // 🛑 SAFETY: Push the server that contains the FileServer route,
@@ -456,6 +475,10 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
     #[cfg(not(target_os = "linux"))]
     let _ = config_path;
 
+    logging::apply(&config.logging);
+    pingclair_proxy::metrics::init(&config.global.metrics);
+    pingclair_proxy::statsd::configure(config.global.statsd.as_ref());
+
     // Create a background Tokio runtime for async tasks (HTTP/3, SIGHUP, etc.)
     // We do this in a separate thread to avoid conflicts with Pingora's runtime.
     let bg_runtime = tokio::runtime::Runtime::new().expect("Failed to create background runtime");
@@ -526,12 +549,29 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
             })
     );
 
+    // 📅 Publish certificate expiry timestamps for alerting
+    {
+        let tls_manager = tls_manager.clone();
+        bg_handle.spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let expirations = tls_manager.certificate_expirations().await;
+                pingclair_proxy::metrics::set_certificate_expirations(&expirations);
+            }
+        });
+    }
+
     // Group servers by listen address
     let port_proxies = std::collections::HashMap::new();
     let port_proxies = std::sync::Arc::new(parking_lot::RwLock::new(port_proxies));
 
     // Track binding information for diagnostic logging
     let mut binding_info = std::collections::HashMap::new();
+
+    // Listeners terminating TLS, from each site's address scheme / `tls` config
+    let mut tls_addrs = std::collections::HashSet::new();
+    let mut plain_addrs = std::collections::HashSet::new();
     
         for server_config in config.servers {
             tracing::debug!("🚀 Processing ServerConfig: name={:?}, listens={:?}", server_config.name, server_config.listen);
@@ -543,9 +583,17 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
             };
 
             for addr in listen_addrs {
+                if server_config.is_tls_listener(&addr) {
+                    tls_addrs.insert(addr.clone());
+                } else {
+                    plain_addrs.insert(addr.clone());
+                }
+
                 let mut proxies_guard = port_proxies.write();
                 let proxy = proxies_guard.entry(addr.clone()).or_insert_with(|| {
-                    pingclair_proxy::server::PingclairProxy::with_tls(tls_manager.clone())
+                    let proxy = pingclair_proxy::server::PingclairProxy::with_tls(tls_manager.clone());
+                    proxy.set_global(&config.global, &config.logging);
+                    proxy
                 });
                 
                 // Track what sites are bound to what addresses
@@ -556,6 +604,10 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
             }
         }
     
+    for addr in tls_addrs.intersection(&plain_addrs) {
+        tracing::warn!("⚠️ {} is shared by TLS and plaintext sites; serving TLS", addr);
+    }
+
     // Log binding information for diagnostics
     tracing::info!("🌐 Server binding information:");
     for (addr, sites) in &binding_info {
@@ -576,13 +628,47 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
             
             // Add L4 Connection Filter (Global Blocked IPs)
             let blocked_ips = &config.global.blocked_ips;
-            if !blocked_ips.is_empty() {
-                 let filter = std::sync::Arc::new(pingclair_proxy::PingclairConnectionFilter::new(blocked_ips));
-                 service.set_connection_filter(filter);
+            let connection_filter = (!blocked_ips.is_empty())
+                .then(|| std::sync::Arc::new(pingclair_proxy::PingclairConnectionFilter::new(blocked_ips)));
+            if let Some(filter) = &connection_filter {
+                 service.set_connection_filter(filter.clone());
             }
 
-            // Determine if this is an HTTPS port
-            let is_https = addr.ends_with(":443") || addr.ends_with(":8443");
+            // PROXY protocol listeners: a relay owns the public address and
+            // Pingora binds an internal loopback address behind it
+            let proxy_protocol = config.global.listeners.iter()
+                .filter(|l| l.applies_to(addr))
+                .find_map(|l| l.proxy_protocol.as_ref());
+            let mut internal_listener = None;
+            let bind_addr = match proxy_protocol {
+                Some(pp_config) => match pingclair_proxy::proxy_protocol::InternalListener::bind() {
+                    Ok(listener) => {
+                        let internal = listener.addr();
+                        internal_listener = Some(listener);
+                        let mut relay = pingclair_proxy::proxy_protocol::ProxyProtocolListener::new(
+                            addr, internal, pp_config, &config.global.trusted_proxies,
+                        );
+                        if let Some(filter) = &connection_filter {
+                            relay.set_connection_filter(filter.clone());
+                        }
+                        let public = addr.clone();
+                        bg_handle.spawn(async move {
+                            if let Err(e) = relay.run().await {
+                                tracing::error!("❌ PROXY protocol relay on {} failed: {}", public, e);
+                            }
+                        });
+                        internal.to_string()
+                    }
+                    Err(e) => {
+                        tracing::error!("❌ Failed to bind internal address for {}: {}", addr, e);
+                        addr.clone()
+                    }
+                },
+                None => addr.clone(),
+            };
+
+            // Determine if this is an HTTPS listener
+            let is_https = tls_addrs.contains(addr);
             let mut tls_enabled = false;
             let mut http3_enabled = false;
 
@@ -591,7 +677,7 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
                  let acceptor = DynamicCertResolver::new(tls_manager.clone());
                  match TlsSettings::with_callbacks(Box::new(acceptor)) {
                     Ok(tls_settings) => {
-                         service.add_tls_with_settings(addr, None, tls_settings);
+                         service.add_tls_with_settings(&bind_addr, None, tls_settings);
                          tls_enabled = true;
                     }
                     Err(e) => {
@@ -603,18 +689,23 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
                  https_ports.push(addr.clone());
                  http3_enabled = true;
             } else {
-                 service.add_tcp(addr);
+                 service.add_tcp(&bind_addr);
             }
 
             // Enhanced diagnostic logging for each binding
             tracing::info!(
-                "   🌐 Server listening on {} (TLS: {}, HTTP/3: {})",
+                "   🌐 Server listening on {} (TLS: {}, HTTP/3: {}, PROXY protocol: {})",
                 addr,
                 if tls_enabled { "enabled" } else { "disabled" },
-                if http3_enabled { "enabled" } else { "pending" }
+                if http3_enabled { "enabled" } else { "pending" },
+                if proxy_protocol.is_some() { "enabled" } else { "disabled" }
             );
 
-            server.add_service(service);
+            // Pingora adopts the pre-bound relay target instead of binding it again
+            server.add_service(pingclair_proxy::proxy_protocol::InternalListenerService::new(
+                service,
+                internal_listener.into_iter().collect(),
+            ));
         }
     }
 
@@ -630,8 +721,10 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
             let addr_str = _addr.clone();
             
             bg_handle.spawn(async move {
-                let mut quic_config = pingclair_proxy::quic::QuicConfig::default();
-                quic_config.listen = socket_addr;
+                let quic_config = pingclair_proxy::quic::QuicConfig {
+                    listen: socket_addr,
+                    ..Default::default()
+                };
                 
                 let mut quic_server = pingclair_proxy::quic::QuicServer::new(quic_config);
                 
@@ -674,6 +767,7 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
     if !config_path.is_empty() {
         let config_path = config_path.clone();
         let port_proxies = port_proxies.clone();
+        let metrics_config = config.global.metrics.clone();
         
         bg_handle.spawn(async move {
             use tokio::signal::unix::{signal, SignalKind};
@@ -703,6 +797,9 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
                 match result {
                     Ok(new_config) => {
                         tracing::info!("✅ Step 1/3: Configuration validation successful");
+                        if new_config.global.metrics != metrics_config {
+                            tracing::warn!("⚠️ Metrics options changed during reload. Restart required for new buckets or labels.");
+                        }
                         tracing::info!("📋 Step 2/3: Preparing configuration update...");
 
                         let mut new_config_by_port = std::collections::HashMap::new();
@@ -715,6 +812,11 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
 
                         // Use read lock to get existing proxies (safe because we only read)
                         let proxies_guard = port_proxies.read();
+                        logging::apply(&new_config.logging);
+                        pingclair_proxy::statsd::configure(new_config.global.statsd.as_ref());
+                        for proxy in proxies_guard.values() {
+                            proxy.set_global(&new_config.global, &new_config.logging);
+                        }
                         let mut success_count = 0;
                         let mut error_count = 0;
 
@@ -730,6 +832,7 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
                         }
 
                         let reload_duration = reload_start.elapsed();
+                        pingclair_proxy::metrics::record_config_reload(true);
 
                         if error_count == 0 {
                             tracing::info!("✅ Configuration reload completed successfully in {:?}", reload_duration);
@@ -743,6 +846,7 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
                     }
                     Err(e) => {
                         let reload_duration = reload_start.elapsed();
+                        pingclair_proxy::metrics::record_config_reload(false);
                         tracing::error!("❌ Configuration reload failed after {:?}: {}", reload_duration, e);
                         tracing::error!("   💡 Previous configuration remains active");
                         eprintln!("❌ Configuration reload failed: {}", e);
@@ -753,6 +857,27 @@ fn run_server(config_path: String, config: pingclair_core::config::PingclairConf
         });
     }
     
+    // ========================================
+    // 🔔 Signal Handling for SIGUSR1 (Reopen Logs)
+    // ========================================
+    #[cfg(target_os = "linux")]
+    bg_handle.spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut stream = match signal(SignalKind::user_defined1()) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::error!("❌ Failed to create SIGUSR1 listener: {}", e);
+                return;
+            }
+        };
+
+        while let Some(()) = stream.recv().await {
+            tracing::info!("🔔 Received SIGUSR1, reopening log files");
+            pingclair_proxy::access_log::reopen_all();
+        }
+    });
+
     println!("🚀 Pingclair running...");
     server.run_forever();
 }